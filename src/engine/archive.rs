@@ -0,0 +1,1603 @@
+mod builder;
+mod codepage;
+mod dumpdir;
+mod embedded;
+mod entry;
+mod exclude;
+mod extract;
+mod fflags;
+mod global;
+mod merge;
+mod nested;
+mod prune;
+mod repair;
+mod salvage;
+mod selinux;
+mod trailer;
+mod transform;
+
+pub use builder::{AppendOptions, ChangedFilePolicy, SymlinkMode};
+pub use codepage::Codepage;
+pub use dumpdir::{DumpDir, DumpMember, DumpMemberKind};
+pub use embedded::Embedded;
+pub use entry::{Entry, EntryKind, EntrySpec, Metadata, OwnerOverride, CREATIONTIME_KEY};
+pub use exclude::ExcludePattern;
+pub use extract::{ExtractOptions, ModeMask};
+pub use fflags::{FileFlags, FFLAGS_KEY};
+pub use global::{GlobalHeader, COMMENT_KEY, METADATA_PREFIX};
+pub use merge::ConflictPolicy;
+pub use nested::{MAX_NESTED_DEPTH, NESTED_SEPARATOR};
+pub use repair::RepairReport;
+pub use salvage::SalvageReport;
+pub use selinux::{SELINUX_KEY, SELINUX_XATTR_KEY};
+pub use trailer::{Trailer, TRAILER_MAGIC};
+pub use transform::{PathTransform, TransformFn};
+
+use anyhow::{bail, Result};
+use indexmap::IndexMap;
+use std::collections::HashSet;
+use std::io::{Read, Seek, SeekFrom, Take, Write};
+
+use crate::engine::DEFAULT_BUFFER_SIZE;
+use crate::engine::compression::{Compression, Decoded};
+use crate::engine::error::Error;
+use crate::engine::header::{GnuTypeFlag, PaxHeader, PaxTypeFlag, PosixViolation, TarHeader};
+use crate::engine::header::helper::parse_octal;
+use crate::engine::header::validate::{validate_block, validate_records};
+pub(crate) use entry::padded_size;
+pub(crate) use exclude::{is_excluded, parse_ignore_file, IgnoreRule};
+pub(crate) use global::pad_region;
+pub(crate) use transform::apply_transforms;
+use codepage::load_encoded;
+use dumpdir::MAX_DUMPDIR_SIZE;
+use global::PADDING_HEADER_NAME;
+use salvage::find_header;
+use trailer::read_trailer;
+
+/// Prefix used by whiteout entries to mark a path as deleted.
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Whiteout entry name used to mark a directory as opaque.
+pub const WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
+
+/// Environment variable holding the reproducible builds timestamp.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// Represents a TAR archive along with an in-memory index of its entries.
+pub struct Archive<T> {
+    /// Archive byte stream.
+    stream: T,
+    /// Indexed entries by path, keeps the archive order.
+    entries: IndexMap<String, Entry>,
+    /// PAX global headers in archive order.
+    globals: Vec<GlobalHeader>,
+    /// Offset of the end of archive marker.
+    end: u64,
+    /// Latest timestamp allowed on appended entries.
+    mtime_clamp: Option<u64>,
+    /// Ownership forced on appended entries.
+    owner: OwnerOverride,
+    /// Offset of the checksummed trailer, `None` when it isn't written.
+    trailer: Option<u64>,
+    /// Digest state of the bytes before the end of archive marker as
+    /// `(len, state)`, appends resume it instead of digesting the whole archive.
+    digest: Option<(u64, u64)>,
+    /// Maximum content size of appended entries.
+    max_entry_size: Option<u64>,
+    /// Length the archive can't grow past, set for archives embedded in
+    /// other containers.
+    capacity: Option<u64>,
+    /// Paths of the entries compaction can't move.
+    pinned: HashSet<String>,
+}
+
+impl<T: Read + Seek> Archive<T> {
+    /// Opens an uncompressed archive and indexes its entries.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to read the archive from.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The opened archive.
+    /// * `Err(e)` - If the archive is compressed, could not be read or parsed.
+    pub fn open(stream: T) -> Result<Self> {
+        Self::open_scan(stream, None, None)
+    }
+
+    /// Opens an uncompressed archive produced on an old system decoding the
+    /// entry names from a legacy codepage, the raw path bytes are kept on
+    /// the entries. Names given by PAX attributes are UTF-8 already.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to read the archive from.
+    /// * `codepage` - Encoding the names are decoded from.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The opened archive.
+    /// * `Err(e)` - If the archive is compressed, could not be read or parsed.
+    pub fn open_encoded(stream: T, codepage: Codepage) -> Result<Self> {
+        Self::open_scan(stream, None, Some(codepage))
+    }
+
+    /// Opens an uncompressed archive, damaged regions are skipped instead
+    /// of failing when a list to record them is given.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to read the archive from.
+    /// * `damaged` - Collects the offsets of the damaged headers, enables salvage scanning.
+    /// * `codepage` - Encoding the entry names are decoded from.
+    fn open_scan(mut stream: T, damaged: Option<&mut Vec<u64>>, codepage: Option<Codepage>) -> Result<Self> {
+        let compression = Compression::sniff(&mut stream)?;
+        if compression != Compression::None {
+            bail!("the archive is {:?} compressed, use Archive::open_auto to decompress it", compression);
+        }
+        let (entries, globals, end) = Self::scan(&mut stream, damaged, codepage)?;
+        let trailer = read_trailer(&mut stream, end)?;
+        Ok(Self {
+            stream,
+            entries,
+            globals,
+            end,
+            mtime_clamp: None,
+            owner: OwnerOverride::default(),
+            trailer,
+            digest: None,
+            max_entry_size: None,
+            capacity: None,
+            pinned: HashSet::new()
+        })
+    }
+
+    /// Scans the stream headers and indexes every entry by path. Padding
+    /// headers and zero blocks are skipped so soft deleted entries don't stop
+    /// the scan.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to scan.
+    /// * `damaged` - Collects the offsets of the damaged headers, when given the scan resumes at the next
+    ///   valid header instead of failing.
+    /// * `codepage` - Encoding the entry names are decoded from.
+    ///
+    /// # Returns
+    /// * `Ok((IndexMap<String, Entry>, Vec<GlobalHeader>, u64))` - Indexed entries, global headers and end offset.
+    /// * `Err(e)` - If a header could not be read or parsed.
+    fn scan(
+        stream: &mut T,
+        mut damaged: Option<&mut Vec<u64>>,
+        codepage: Option<Codepage>
+    ) -> Result<(IndexMap<String, Entry>, Vec<GlobalHeader>, u64)> {
+        let mut entries = IndexMap::new();
+        let mut globals = Vec::new();
+        let mut end = 0;
+        let mut pos = 0;
+        let mut start: Option<u64> = None;
+        let mut pax: Option<PaxHeader> = None;
+        loop {
+            let step = (|| -> Result<bool> {
+                stream.seek(SeekFrom::Start(pos))?;
+                let (header, raw_names) = match codepage {
+                    Some(_) => load_encoded(stream)?,
+                    None => (TarHeader::load(stream)?, None)
+                };
+                match header {
+                    TarHeader::Unknown(buf, size) => {
+                        if size < 512 || Trailer::parse(&buf).is_some() {
+                            return Ok(true);
+                        }
+                        if buf.iter().any(|b| *b != 0) {
+                            bail!("unrecognized header at offset {}", pos);
+                        }
+                        pos += 512;
+                    },
+                    TarHeader::Pax(h) if h.is_extended() => {
+                        start.get_or_insert(pos);
+                        pax = Some(h);
+                        pos = stream.stream_position()?;
+                    },
+                    TarHeader::Pax(h) if h.typeflag == PaxTypeFlag::Global && h.name == PADDING_HEADER_NAME => {
+                        pos = stream.stream_position()?;
+                    },
+                    TarHeader::Pax(h) if h.typeflag == PaxTypeFlag::Global => {
+                        let next = stream.stream_position()?;
+                        globals.push(GlobalHeader { offset: pos, len: next - pos, header: h });
+                        pos = next;
+                        end = pos;
+                    },
+                    header => {
+                        let data_offset = stream.stream_position()?;
+                        let mut meta = Metadata::from_headers(&header, pax.as_ref())?;
+                        let raw_path = match (raw_names, codepage) {
+                            (Some(names), Some(codepage)) => names.apply(codepage, &mut meta, pax.as_ref()),
+                            _ => None
+                        };
+                        let stored_size = match &header {
+                            TarHeader::Gnu(h) => h.size,
+                            _ => meta.size
+                        };
+                        let sparse = match &header {
+                            TarHeader::Gnu(h) => h.iter_sparse().cloned().collect(),
+                            _ => Vec::new()
+                        };
+
+                        // incremental directories list their members as content
+                        let dumpdir = match &header {
+                            TarHeader::Gnu(h) if h.typeflag == GnuTypeFlag::DirectoryDump => {
+                                if stored_size > MAX_DUMPDIR_SIZE {
+                                    bail!(
+                                        "dumpdir for '{}' is {} bytes, more than the {} bytes limit",
+                                        meta.path, stored_size, MAX_DUMPDIR_SIZE
+                                    );
+                                }
+                                let mut buf = Vec::new();
+                                (&mut *stream).take(stored_size).read_to_end(&mut buf)?;
+                                match DumpDir::parse(&buf) {
+                                    Ok(v) => Some(v),
+                                    Err(e) => bail!("invalid dumpdir for '{}': {}", meta.path, e)
+                                }
+                            },
+                            _ => None
+                        };
+                        let entry = Entry {
+                            meta,
+                            offset: start.take().unwrap_or(pos),
+                            data_offset,
+                            stored_size,
+                            sparse,
+                            physical: None,
+                            dumpdir,
+                            raw_path
+                        };
+                        pax = None;
+                        pos = entry.end();
+                        end = pos;
+
+                        // later entries override earlier ones with the same path
+                        entries.shift_remove(&entry.meta.path);
+                        entries.insert(entry.meta.path.clone(), entry);
+                    }
+                }
+                Ok(false)
+            })();
+            match step {
+                Ok(true) => break,
+                Ok(false) => {},
+                Err(e) => {
+                    let damaged = match damaged.as_deref_mut() {
+                        Some(v) => v,
+                        None => return Err(e)
+                    };
+
+                    // salvage: drop the pending extended header and resume at the next valid header
+                    damaged.push(pos);
+                    start = None;
+                    pax = None;
+                    match find_header(stream, pos + 512)? {
+                        Some(next) => pos = next,
+                        None => break
+                    }
+                }
+            }
+        }
+        Ok((entries, globals, end))
+    }
+
+    /// Gets the number of entries in the archive.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Tells whether the archive has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Gets an entry by path.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry to get.
+    ///
+    /// # Returns
+    /// * `Option<&Entry>` - The entry if found, otherwise None.
+    pub fn get(&self, path: &str) -> Option<&Entry> {
+        self.entries.get(path)
+    }
+
+    /// Returns an iterator over the entries in archive order.
+    pub fn entries(&self) -> indexmap::map::Values<'_, String, Entry> {
+        self.entries.values()
+    }
+
+    /// Returns the offset of the end of archive marker.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Returns the amount of bytes used by the archive, end of archive marker
+    /// and trailer included. The stream is never shrunk, callers owning it
+    /// can truncate it to this length, e.g. after compacting.
+    pub fn stored_len(&self) -> u64 {
+        match self.trailer {
+            Some(offset) => offset + 512,
+            None => self.end + 1024
+        }
+    }
+
+    /// Validates every entry raw headers against strict POSIX ustar and pax
+    /// rules, including the PAX extended records. Meant as a pre-flight
+    /// before shipping archives to picky consumers.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<(String, PosixViolation)>)` - The violations found by entry path, empty when the archive conforms.
+    /// * `Err(e)` - If the headers can't be read.
+    pub fn validate_posix(&mut self) -> Result<Vec<(String, PosixViolation)>> {
+        let ranges: Vec<(String, u64, u64)> = self.entries.values()
+            .map(|entry| (entry.meta.path.clone(), entry.offset, entry.data_offset))
+            .collect();
+        let mut violations = Vec::new();
+        for (path, mut pos, data_offset) in ranges {
+            while pos < data_offset {
+                let mut block = [0u8; 512];
+                self.stream.seek(SeekFrom::Start(pos))?;
+                self.stream.read_exact(&mut block)?;
+                violations.extend(validate_block(&block).into_iter().map(|v| (path.clone(), v)));
+                pos += 512;
+                let size = parse_octal::<u64>(&block[124..136]).unwrap_or(0);
+                match block[156] {
+                    b'x' | b'g' => {
+                        let mut data = vec![0u8; size as usize];
+                        self.stream.read_exact(&mut data)?;
+                        violations.extend(validate_records(&data).into_iter().map(|v| (path.clone(), v)));
+                        pos += padded_size(size);
+                    },
+                    b'L' | b'K' => pos += padded_size(size),
+
+                    // the main header is the last one, GNU sparse extension blocks may follow
+                    _ => break
+                }
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Returns a reader over the raw content stored for an entry. On archives
+    /// opened with `Archive::open_auto` the content is decompressed as it's
+    /// read, one chunk at a time.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry to read.
+    ///
+    /// # Returns
+    /// * `Ok(Take<&mut T>)` - Reader limited to the entry content.
+    /// * `Err(e)` - If the entry doesn't exists or the stream can't seek.
+    pub fn entry_reader(&mut self, path: &str) -> Result<Take<&mut T>> {
+        let (data_offset, stored_size) = match self.entries.get(path) {
+            Some(entry) => (entry.data_offset, entry.stored_size),
+            None => bail!("entry '{}' not found", path)
+        };
+        self.stream.seek(SeekFrom::Start(data_offset))?;
+        Ok((&mut self.stream).take(stored_size))
+    }
+
+    /// Streams an entry content into a writer, sparse files are expanded
+    /// with zeroes for their holes up to the entry logical size.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry to read.
+    /// * `writer` - The writer to stream the content to.
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - The amount of bytes written.
+    /// * `Err(e)` - If the entry doesn't exists, the content is truncated or the write fails.
+    pub fn read_to(&mut self, path: &str, writer: &mut impl Write) -> Result<u64> {
+        let entry = match self.entries.get(path) {
+            Some(entry) => entry.clone(),
+            None => bail!("entry '{}' not found", path)
+        };
+        self.stream.seek(SeekFrom::Start(entry.data_offset))?;
+        if entry.sparse.is_empty() {
+            let copied = std::io::copy(&mut (&mut self.stream).take(entry.stored_size), writer)?;
+            if copied != entry.stored_size {
+                bail!("truncated content for '{}', expected {} bytes but got {}", path, entry.stored_size, copied);
+            }
+            return Ok(copied);
+        }
+
+        // expand the sparse segments filling the holes with zeroes
+        let mut pos = 0;
+        for segment in entry.sparse.iter() {
+            if segment.offset < pos {
+                bail!("overlapping sparse segment at {} for '{}'", segment.offset, path);
+            }
+            write_zeroes(writer, segment.offset - pos)?;
+            let copied = std::io::copy(&mut (&mut self.stream).take(segment.numbytes), writer)?;
+            if copied != segment.numbytes {
+                bail!("truncated sparse segment at {} for '{}'", segment.offset, path);
+            }
+            pos = segment.offset + segment.numbytes;
+        }
+        if pos < entry.meta.size {
+            write_zeroes(writer, entry.meta.size - pos)?;
+            pos = entry.meta.size;
+        }
+        Ok(pos)
+    }
+
+    /// Reads a byte range of an entry content, sparse holes are read as
+    /// zeroes and the `.rhpart` partitions written by the index backed tar
+    /// engine continue the content, so offsets always refer to the logical
+    /// content. Ranges past the end are clipped, which suits serving HTTP
+    /// range requests.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry to read.
+    /// * `offset` - Logical offset to start reading at.
+    /// * `len` - Maximum amount of bytes to read.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<u8>)` - The range content, shorter than `len` when the range ends past the content.
+    /// * `Err(e)` - If the entry doesn't exists, the offset is past the content or the content is truncated.
+    pub fn read_range(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let parts = self.part_chain(path)?;
+        let size: u64 = parts.iter().map(Entry::logical_size).sum();
+        if offset > size {
+            bail!(Error::InvalidRange { path: path.to_string(), offset, len });
+        }
+        let end = offset + len.min(size - offset);
+        let mut buf = vec![0u8; (end - offset) as usize];
+        let mut start = 0;
+        for part in parts.iter() {
+            let part_end = start + part.logical_size();
+            let from = offset.max(start);
+            let to = end.min(part_end);
+            if from < to {
+                self.read_part(part, from - start, &mut buf[(from - offset) as usize..(to - offset) as usize])?;
+            }
+            start = part_end;
+        }
+        Ok(buf)
+    }
+
+    /// Gets an entry followed by the `.rhpart` partitions continuing its
+    /// content, as written by the index backed tar engine.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry.
+    fn part_chain(&self, path: &str) -> Result<Vec<Entry>> {
+        let mut parts = match self.entries.get(path) {
+            Some(entry) => vec![entry.clone()],
+            None => bail!("entry '{}' not found", path)
+        };
+        while let Some(entry) = self.entries.get(&part_path(path, parts.len())) {
+            parts.push(entry.clone());
+        }
+        Ok(parts)
+    }
+
+    /// Reads a slice of a single entry content, sparse holes are read as
+    /// zeroes.
+    ///
+    /// # Arguments
+    /// * `entry` - The entry to read.
+    /// * `offset` - Logical offset to start reading at.
+    /// * `buf` - Buffer to fill, it must not go past the entry content.
+    fn read_part(&mut self, entry: &Entry, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let path = &entry.meta.path;
+        if entry.sparse.is_empty() {
+            self.stream.seek(SeekFrom::Start(entry.data_offset + offset))?;
+            if let Err(e) = self.stream.read_exact(buf) {
+                bail!("truncated content for '{}': {}", path, e);
+            }
+            return Ok(());
+        }
+
+        // only the stored segments overlapping the range are read, holes stay zeroed
+        let end = offset + buf.len() as u64;
+        let mut stored = 0;
+        for segment in entry.sparse.iter() {
+            let start = segment.offset.max(offset);
+            let stop = (segment.offset + segment.numbytes).min(end);
+            if start < stop {
+                self.stream.seek(SeekFrom::Start(entry.data_offset + stored + start - segment.offset))?;
+                let target = &mut buf[(start - offset) as usize..(stop - offset) as usize];
+                if let Err(e) = self.stream.read_exact(target) {
+                    bail!("truncated sparse segment at {} for '{}': {}", segment.offset, path, e);
+                }
+            }
+            stored += segment.numbytes;
+        }
+        Ok(())
+    }
+
+    /// Writes a zstd compressed copy of the archive using the seekable format,
+    /// so it can still be opened with random access through `Archive::open_auto`.
+    ///
+    /// # Arguments
+    /// * `writer` - Compressed output.
+    /// * `frame_size` - Uncompressed size of each independent frame.
+    /// * `level` - Zstd compression level.
+    ///
+    /// # Returns
+    /// * `Ok(W)` - The compressed output.
+    /// * `Err(e)` - If read or write fails.
+    #[cfg(feature = "zstd")]
+    pub fn export_seekable_zstd<W: Write>(&mut self, writer: W, frame_size: usize, level: i32) -> Result<W> {
+        let mut encoder = crate::engine::compression::SeekableZstdWriter::new(writer, frame_size, level);
+        self.stream.seek(SeekFrom::Start(0))?;
+        let len = self.end + 1024;
+        let copied = std::io::copy(&mut (&mut self.stream).take(len), &mut encoder)?;
+        if copied != len {
+            bail!("truncated archive, expected {} bytes but got {}", len, copied);
+        }
+        encoder.finish()
+    }
+
+    /// Consumes the archive returning the inner stream.
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+}
+
+impl<T: Read + Seek> Archive<Decoded<T>> {
+    /// Opens an archive detecting its compression from the magic bytes
+    /// (gzip, zstd, xz, bzip2 or plain TAR). Compressed archives are
+    /// streamed, their content is decompressed on demand as it's read so
+    /// memory usage doesn't grow with the archive size, and can only be
+    /// read. Reading entries in archive order is the cheapest, going back
+    /// restarts the decompression unless the stream has a seek table.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to read the archive from.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The opened archive.
+    /// * `Err(e)` - If the compression isn't supported, the archive could not be read or parsed.
+    pub fn open_auto(stream: T) -> Result<Self> {
+        Self::open_decoded(Decoded::lazy(stream)?)
+    }
+
+    /// Opens an archive inflating a compressed stream into memory first, so
+    /// entries can be read in any order without restarting the
+    /// decompression.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to read the archive from.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The opened archive.
+    /// * `Err(e)` - If the compression isn't supported, the archive could not be read or parsed.
+    pub fn open_buffered(stream: T) -> Result<Self> {
+        Self::open_decoded(Decoded::new(stream)?)
+    }
+
+    /// Opens an already decoded archive mapping every entry content to its
+    /// compressed frame.
+    ///
+    /// # Arguments
+    /// * `stream` - The decoded stream.
+    fn open_decoded(stream: Decoded<T>) -> Result<Self> {
+        let mut archive = Archive::open(stream)?;
+        for entry in archive.entries.values_mut() {
+            entry.physical = archive.stream.locate(entry.data_offset);
+        }
+        Ok(archive)
+    }
+
+    /// Returns a reader over the raw content stored for an entry, seeking
+    /// the compressed stream straight to the entry frame when it's known.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry to read.
+    ///
+    /// # Returns
+    /// * `Ok(Take<&mut Decoded<T>>)` - Reader limited to the entry content.
+    /// * `Err(e)` - If the entry doesn't exists or the stream can't seek.
+    pub fn located_reader(&mut self, path: &str) -> Result<Take<&mut Decoded<T>>> {
+        let (data_offset, stored_size, physical) = match self.entries.get(path) {
+            Some(entry) => (entry.data_offset, entry.stored_size, entry.physical),
+            None => bail!("entry '{}' not found", path)
+        };
+        self.stream.seek_located(data_offset, physical.as_ref())?;
+        Ok((&mut self.stream).take(stored_size))
+    }
+
+    /// Returns the compression the archive was stored with.
+    pub fn compression(&self) -> Compression {
+        self.stream.compression()
+    }
+}
+
+impl<T: Read + Seek> Archive<Embedded<T>> {
+    /// Opens an uncompressed archive embedded inside other container, such
+    /// as a self extracting binary or a firmware image, without copying it
+    /// out. Offsets are relative to the archive start and the archive can't
+    /// grow past its length.
+    ///
+    /// # Arguments
+    /// * `stream` - The container stream.
+    /// * `base_offset` - Offset of the archive within the container.
+    /// * `len` - Space available to the archive, including the end of archive marker.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The opened archive.
+    /// * `Err(e)` - If the archive is compressed, could not be read or parsed.
+    pub fn open_at(stream: T, base_offset: u64, len: u64) -> Result<Self> {
+        let mut archive = Archive::open(Embedded::new(stream, base_offset, len)?)?;
+        archive.capacity = Some(len);
+        Ok(archive)
+    }
+}
+
+impl<T: Read + Write + Seek> Archive<T> {
+    /// Writes the end of archive marker at the end offset.
+    fn write_end(&mut self) -> Result<()> {
+        self.stream.seek(SeekFrom::Start(self.end))?;
+        self.stream.write_all(&[0u8; 1024])?;
+        self.write_trailer()
+    }
+
+    /// Fills a region of the stream with zeroes.
+    ///
+    /// # Arguments
+    /// * `offset` - Region start offset.
+    /// * `len` - Region length.
+    fn zero_fill(&mut self, offset: u64, len: u64) -> Result<()> {
+        self.touch(offset);
+        self.stream.seek(SeekFrom::Start(offset))?;
+        write_zeroes(&mut self.stream, len)
+    }
+
+    /// Clamps the timestamps of every entry appended from now on to an epoch,
+    /// used to produce reproducible archives.
+    ///
+    /// # Arguments
+    /// * `epoch` - Latest timestamp allowed (seconds since epoch), `None` disables clamping.
+    pub fn set_mtime_clamp(&mut self, epoch: Option<u64>) {
+        self.mtime_clamp = epoch;
+    }
+
+    /// Limits the content size of every entry appended from now on, larger
+    /// entries fail with a quota error instead of being written.
+    ///
+    /// # Arguments
+    /// * `limit` - Maximum content size in bytes, `None` removes the limit.
+    pub fn set_max_entry_size(&mut self, limit: Option<u64>) {
+        self.max_entry_size = limit;
+    }
+
+    /// Clamps appended entries timestamps to the `SOURCE_DATE_EPOCH`
+    /// environment variable when set.
+    ///
+    /// # Returns
+    /// * `Ok(Option<u64>)` - The epoch in use if any.
+    /// * `Err(e)` - If the environment variable isn't a valid timestamp.
+    pub fn clamp_to_source_date_epoch(&mut self) -> Result<Option<u64>> {
+        let epoch = match std::env::var(SOURCE_DATE_EPOCH) {
+            Ok(v) => match v.trim().parse::<u64>() {
+                Ok(v) => Some(v),
+                Err(_) => bail!("invalid {} value '{}'", SOURCE_DATE_EPOCH, v)
+            },
+            Err(_) => None
+        };
+        self.mtime_clamp = epoch;
+        Ok(epoch)
+    }
+
+    /// Returns the timestamp clamp applied to appended entries.
+    pub fn mtime_clamp(&self) -> Option<u64> {
+        self.mtime_clamp
+    }
+
+    /// Forces the ownership of every entry appended from now on.
+    ///
+    /// # Arguments
+    /// * `owner` - Ownership override, the default value disables it.
+    pub fn set_owner_override(&mut self, owner: OwnerOverride) {
+        self.owner = owner;
+    }
+
+    /// Returns the ownership override applied to appended entries.
+    pub fn owner_override(&self) -> &OwnerOverride {
+        &self.owner
+    }
+
+    /// Appends an entry at the end of the archive, an existing entry with the
+    /// same path is removed once the new one is written.
+    ///
+    /// # Arguments
+    /// * `meta` - The entry metadata, its size must match the reader content.
+    /// * `reader` - The entry content.
+    ///
+    /// # Returns
+    /// * `Ok(&Entry)` - The appended entry.
+    /// * `Err(e)` - If the content is shorter than expected or write fails.
+    pub fn append(&mut self, meta: Metadata, reader: &mut impl Read) -> Result<&Entry> {
+        self.append_inner(meta, reader, false)
+    }
+
+    /// Appends an entry copying the whole reader content, when the amount of
+    /// bytes read differs from the metadata size the headers are rewritten
+    /// with the actual size. Used for sources that may change while read.
+    ///
+    /// # Arguments
+    /// * `meta` - The entry metadata with the expected size.
+    /// * `reader` - The entry content.
+    ///
+    /// # Returns
+    /// * `Ok(&Entry)` - The appended entry with the actual size.
+    /// * `Err(e)` - If the headers can't be rewritten in place or write fails.
+    pub(crate) fn append_measured(&mut self, meta: Metadata, reader: &mut impl Read) -> Result<&Entry> {
+        self.append_inner(meta, reader, true)
+    }
+
+    /// Appends an entry at the end of the archive. A replaced entry is only
+    /// dropped once its replacement is fully written, a failed append wipes
+    /// the partial entry and keeps the old one.
+    ///
+    /// # Arguments
+    /// * `meta` - The entry metadata.
+    /// * `reader` - The entry content.
+    /// * `measure` - Whether the whole reader is copied and the size fixed afterwards.
+    fn append_inner(&mut self, mut meta: Metadata, reader: &mut impl Read, measure: bool) -> Result<&Entry> {
+        self.owner.apply(&mut meta);
+        if let Some(epoch) = self.mtime_clamp {
+            meta.mtime = meta.mtime.min(epoch);
+            meta.atime = meta.atime.map(|v| v.min(epoch));
+            meta.ctime = meta.ctime.map(|v| v.min(epoch));
+            meta.birthtime = meta.birthtime.map(|v| v.min(epoch));
+        }
+        if let Some(limit) = self.max_entry_size.filter(|limit| !measure && meta.size > *limit) {
+            bail!(Error::QuotaExceeded { path: meta.path, size: meta.size, limit });
+        }
+        if let Some(capacity) = self.capacity {
+            // embedded archives fail before a partial entry is written
+            let mut headers = Vec::new();
+            meta.save_headers(&mut headers)?;
+            let trailer = if self.trailer.is_some() { 512 } else { 0 };
+            let needed = self.end + headers.len() as u64 + padded_size(meta.size) + 1024 + trailer;
+            if needed > capacity {
+                bail!("'{}' needs the archive to grow to {} bytes, past its {} bytes", meta.path, needed, capacity);
+            }
+        }
+
+        // write headers and content, the end of archive marker is restored on failure
+        let offset = self.end;
+        let (meta, data_offset) = match self.write_entry(offset, meta, reader, measure) {
+            Ok(v) => v,
+            Err(e) => {
+                let written = self.stream.stream_position().unwrap_or(offset).max(offset);
+                self.zero_fill(offset, written - offset)?;
+                self.write_end()?;
+                self.stream.flush()?;
+                return Err(e);
+            }
+        };
+
+        // close the archive, index the entry and drop the replaced one
+        let entry = Entry {
+            stored_size: meta.size,
+            meta,
+            offset,
+            data_offset,
+            sparse: Vec::new(),
+            physical: None,
+            dumpdir: None,
+            raw_path: None
+        };
+        self.end = entry.end();
+        self.write_end()?;
+        let path = entry.meta.path.clone();
+        if let Some(old) = self.entries.shift_remove(&path) {
+            self.pinned.remove(&path);
+            self.write_padding(old.offset, old.end() - old.offset)?;
+            self.write_trailer()?;
+        }
+        self.stream.flush()?;
+        self.entries.insert(path.clone(), entry);
+        Ok(&self.entries[&path])
+    }
+
+    /// Writes an entry headers and block padded content.
+    ///
+    /// # Arguments
+    /// * `offset` - Offset to write the headers at.
+    /// * `meta` - The entry metadata.
+    /// * `reader` - The entry content.
+    /// * `measure` - Whether the whole reader is copied and the size fixed afterwards.
+    ///
+    /// # Returns
+    /// * `Ok((Metadata, u64))` - The metadata with the actual size and the content offset.
+    /// * `Err(e)` - If the content doesn't match the size, exceeds the quota or write fails.
+    fn write_entry(
+        &mut self,
+        offset: u64,
+        mut meta: Metadata,
+        reader: &mut impl Read,
+        measure: bool
+    ) -> Result<(Metadata, u64)> {
+        self.stream.seek(SeekFrom::Start(offset))?;
+        let data_offset = offset + meta.save_headers(&mut self.stream)?;
+        let copied = match (measure, self.max_entry_size) {
+            (true, Some(limit)) => std::io::copy(&mut reader.take(limit + 1), &mut self.stream)?,
+            (true, None) => std::io::copy(reader, &mut self.stream)?,
+            (false, _) => std::io::copy(&mut reader.take(meta.size), &mut self.stream)?
+        };
+        if let Some(limit) = self.max_entry_size.filter(|limit| copied > *limit) {
+            bail!(Error::QuotaExceeded { path: meta.path, size: copied, limit });
+        }
+        if copied != meta.size {
+            if !measure {
+                bail!("expected {} bytes of content for '{}' but got {}", meta.size, meta.path, copied);
+            }
+
+            // rewrite the headers with the actual size
+            meta.size = copied;
+            let mut headers = Vec::new();
+            meta.save_headers(&mut headers)?;
+            if offset + headers.len() as u64 != data_offset {
+                bail!("can't rewrite the headers of '{}' in place after its size changed", meta.path);
+            }
+            self.stream.seek(SeekFrom::Start(offset))?;
+            self.stream.write_all(&headers)?;
+            self.stream.seek(SeekFrom::Start(data_offset + copied))?;
+        }
+        let padding = padded_size(copied) - copied;
+        self.stream.write_all(&vec![0u8; padding as usize])?;
+        Ok((meta, data_offset))
+    }
+
+    /// Appends an entry from in-memory bytes, the metadata path and size are
+    /// set from the arguments.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `meta` - The entry metadata.
+    /// * `data` - The entry content.
+    ///
+    /// # Returns
+    /// * `Ok(&Entry)` - The appended entry.
+    /// * `Err(e)` - If write fails.
+    pub fn append_data(&mut self, path: &str, mut meta: Metadata, data: &[u8]) -> Result<&Entry> {
+        meta.path = path.to_string();
+        meta.size = data.len() as u64;
+        self.append(meta, &mut &data[..])
+    }
+
+    /// Soft deletes an entry by overwriting its headers and content with
+    /// padding headers, the space is reclaimed right away when it was the
+    /// last entry.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry to remove.
+    ///
+    /// # Returns
+    /// * `Ok(Entry)` - The removed entry.
+    /// * `Err(e)` - If the entry doesn't exists or write fails.
+    pub fn remove(&mut self, path: &str) -> Result<Entry> {
+        let entry = match self.entries.shift_remove(path) {
+            Some(entry) => entry,
+            None => bail!("entry '{}' not found", path)
+        };
+        self.pinned.remove(path);
+
+        // reclaim the space when it was the last entry, padding left before it included
+        if entry.end() >= self.end {
+            let entries_end = self.entries.values().map(|e| e.end()).max().unwrap_or(0);
+            self.end = entries_end.max(self.globals_end());
+            self.zero_fill(self.end, entry.end() - self.end)?;
+            self.write_end()?;
+        } else {
+            self.write_padding(entry.offset, entry.end() - entry.offset)?;
+            self.write_trailer()?;
+        }
+        self.stream.flush()?;
+        Ok(entry)
+    }
+
+    /// Applies a delta archive in place: regular entries add or overwrite the
+    /// target ones while whiteout entries (`.wh.<name>`) delete them and opaque
+    /// markers (`.wh..wh..opq`) clear their directory. Whiteouts are applied
+    /// before any addition so they only affect the previous content. An
+    /// overwritten entry is only dropped once its replacement is written.
+    ///
+    /// # Arguments
+    /// * `delta` - The delta archive to apply.
+    ///
+    /// # Returns
+    /// * `Ok(())` - On success.
+    /// * `Err(e)` - If the delta could not be read or the target written.
+    pub fn apply<D: Read + Seek>(&mut self, delta: &mut Archive<D>) -> Result<()> {
+        let entries: Vec<Entry> = delta.entries().cloned().collect();
+
+        // apply whiteouts
+        for key in whiteout_targets(&entries, self.entries.keys()) {
+            self.remove(&key)?;
+        }
+
+        // apply additions and overwrites, the same path under other spellings is dropped afterwards
+        for entry in entries {
+            if is_whiteout(&entry.meta.path) {
+                continue;
+            }
+            let path = normalize_path(&entry.meta.path).to_string();
+            let mut reader = delta.entry_reader(&entry.meta.path)?;
+            let key = self.append(entry.meta, &mut reader)?.meta.path.clone();
+            let stale: Vec<String> = self.entries.keys()
+                .filter(|k| **k != key && normalize_path(k) == path)
+                .cloned()
+                .collect();
+            for key in stale {
+                self.remove(&key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Gets the target paths deleted by the whiteout entries of a delta
+/// archive, a whiteout deletes its path and everything under it while an
+/// opaque marker deletes everything under its directory.
+///
+/// # Arguments
+/// * `delta` - The delta archive entries.
+/// * `present` - The target paths.
+pub(crate) fn whiteout_targets<'a>(delta: &[Entry], present: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let mut rules: Vec<(String, bool)> = Vec::new();
+    for entry in delta {
+        let path = normalize_path(&entry.meta.path);
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name == WHITEOUT_OPAQUE {
+            rules.push((dir.to_string(), false));
+        } else if let Some(name) = name.strip_prefix(WHITEOUT_PREFIX) {
+            let target = if dir.is_empty() { name.to_string() } else { format!("{}/{}", dir, name) };
+            rules.push((target, true));
+        }
+    }
+    present
+        .filter(|k| {
+            let k = normalize_path(k);
+            rules.iter().any(|(path, include_self)| match path.is_empty() {
+                true => *include_self || !k.is_empty(),
+                false => (*include_self && k == path.as_str()) || k.strip_prefix(path.as_str()).is_some_and(|rest| rest.starts_with('/'))
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+/// Builds the path of a file partition after the first one, as written by
+/// the index backed tar engine.
+///
+/// # Arguments
+/// * `path` - The path of the file.
+/// * `part` - Partition number, the first partition is 0.
+pub(crate) fn part_path(path: &str, part: usize) -> String {
+    format!("{}.{}.rhpart", path, part)
+}
+
+/// Tells whether a delta entry is a whiteout or an opaque marker.
+///
+/// # Arguments
+/// * `path` - The entry path.
+pub(crate) fn is_whiteout(path: &str) -> bool {
+    normalize_path(path).rsplit('/').next().unwrap_or_default().starts_with(WHITEOUT_PREFIX)
+}
+
+/// Writes an amount of zero bytes into a writer.
+///
+/// # Arguments
+/// * `writer` - The writer to write to.
+/// * `len` - Amount of zero bytes to write.
+fn write_zeroes(writer: &mut impl Write, len: u64) -> Result<()> {
+    let buf = [0u8; DEFAULT_BUFFER_SIZE];
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = remaining.min(DEFAULT_BUFFER_SIZE as u64) as usize;
+        writer.write_all(&buf[..n])?;
+        remaining -= n as u64;
+    }
+    Ok(())
+}
+
+/// Normalizes an entry path by removing the leading `./` and trailing `/`.
+///
+/// # Arguments
+/// * `path` - The path to normalize.
+pub(crate) fn normalize_path(path: &str) -> &str {
+    let mut path = path;
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path.trim_end_matches('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn add_file(archive: &mut Archive<Cursor<Vec<u8>>>, path: &str, content: &[u8]) {
+        let mut meta = Metadata::new(path, EntryKind::RegularFile);
+        meta.size = content.len() as u64;
+        archive.append(meta, &mut Cursor::new(content.to_vec())).unwrap();
+    }
+
+    fn read_file(archive: &mut Archive<Cursor<Vec<u8>>>, path: &str) -> Vec<u8> {
+        let mut buf = Vec::new();
+        archive.entry_reader(path).unwrap().read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn open_empty() {
+        let archive = match Archive::open(Cursor::new(Vec::new())) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open archive: {}", e);
+                return;
+            }
+        };
+        assert!(archive.is_empty());
+        assert_eq!(0, archive.end());
+    }
+
+    #[test]
+    fn append_and_reopen() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", b"hello");
+        add_file(&mut archive, &format!("dir/{}", "b".repeat(150)), b"world!");
+        assert_eq!(2, archive.len());
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(2, archive.len());
+        assert_eq!(b"hello".to_vec(), read_file(&mut archive, "a.txt"));
+        assert_eq!(b"world!".to_vec(), read_file(&mut archive, &format!("dir/{}", "b".repeat(150))));
+    }
+
+    #[test]
+    fn remove_soft_deletes() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", b"hello");
+        add_file(&mut archive, "b.txt", b"world");
+        let end = archive.end();
+        archive.remove("a.txt").unwrap();
+        assert_eq!(end, archive.end());
+        archive.remove("b.txt").unwrap();
+        assert_eq!(0, archive.end());
+        let archive = Archive::open(archive.into_inner()).unwrap();
+        assert!(archive.is_empty());
+    }
+
+    #[test]
+    fn remove_leaves_padding() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", &[b'a'; 3000]);
+        add_file(&mut archive, "b.txt", b"world");
+        let removed = match archive.remove("a.txt") {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to remove entry: {}", e);
+                return;
+            }
+        };
+
+        // other readers see a global header instead of an end of archive marker
+        let buf = archive.into_inner().into_inner();
+        let mut block = [0u8; 512];
+        block.copy_from_slice(&buf[removed.offset as usize..removed.offset as usize + 512]);
+        let header = crate::format::RawHeader::decode(&block).unwrap();
+        assert_eq!(b'g', header.typeflag);
+        assert_eq!(removed.end() - removed.offset - 512, header.size);
+
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        assert_eq!(vec!["b.txt"], archive.entries().map(|e| e.meta.path.as_str()).collect::<Vec<&str>>());
+        assert!(archive.global_headers().is_empty());
+        assert_eq!(b"world".to_vec(), read_file(&mut archive, "b.txt"));
+    }
+
+    #[test]
+    fn failed_replace_keeps_entry() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", b"hello");
+        add_file(&mut archive, "b.txt", b"world");
+        let end = archive.end();
+        let mut meta = Metadata::new("a.txt", EntryKind::RegularFile);
+        meta.size = 2000;
+        match archive.append(meta, &mut Cursor::new(vec![b'x'; 1000])) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(_) => {}
+        }
+        assert_eq!(end, archive.end());
+        assert_eq!(b"hello".to_vec(), read_file(&mut archive, "a.txt"));
+
+        // the partial entry isn't found once reopened
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(vec!["a.txt", "b.txt"], archive.entries().map(|e| e.meta.path.as_str()).collect::<Vec<&str>>());
+        add_file(&mut archive, "a.txt", b"replaced");
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(vec!["b.txt", "a.txt"], archive.entries().map(|e| e.meta.path.as_str()).collect::<Vec<&str>>());
+        assert_eq!(b"replaced".to_vec(), read_file(&mut archive, "a.txt"));
+    }
+
+    #[test]
+    fn apply_delta() {
+        let mut target = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut target, "keep.txt", b"keep");
+        add_file(&mut target, "change.txt", b"old");
+        add_file(&mut target, "gone.txt", b"gone");
+        add_file(&mut target, "dir/a.txt", b"a");
+        add_file(&mut target, "dir/b.txt", b"b");
+        let mut delta = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut delta, "change.txt", b"new");
+        add_file(&mut delta, ".wh.gone.txt", b"");
+        add_file(&mut delta, "dir/.wh..wh..opq", b"");
+        add_file(&mut delta, "dir/c.txt", b"c");
+        add_file(&mut delta, "new.txt", b"added");
+        if let Err(e) = target.apply(&mut delta) {
+            assert!(false, "Failed to apply delta: {}", e);
+            return;
+        }
+        let mut target = Archive::open(target.into_inner()).unwrap();
+        let paths: Vec<&str> = target.entries().map(|e| e.meta.path.as_str()).collect();
+        assert_eq!(vec!["keep.txt", "change.txt", "dir/c.txt", "new.txt"], paths);
+        assert_eq!(b"new".to_vec(), read_file(&mut target, "change.txt"));
+        assert_eq!(b"keep".to_vec(), read_file(&mut target, "keep.txt"));
+
+        // a failed overwrite keeps the previous entry
+        let mut delta = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut delta, "./keep.txt", b"too large");
+        target.set_max_entry_size(Some(4));
+        match target.apply(&mut delta) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(_) => assert_eq!(b"keep".to_vec(), read_file(&mut target, "keep.txt"))
+        }
+    }
+
+    #[test]
+    fn read_to_expands_sparse() {
+        use crate::engine::header::{GnuHeader, GnuTypeFlag};
+        use crate::engine::header::gnu::SparseEntry;
+
+        // sparse file of 3000 bytes with data at [1000, 1004) and [2048, 2051)
+        let mut header = GnuHeader::new(GnuTypeFlag::Sparse);
+        header.set_name("sparse.bin".to_string());
+        header.mode = 0o644;
+        header.size = 7;
+        header.realsize = Some(3000);
+        header.push_sparse(SparseEntry { offset: 1000, numbytes: 4 });
+        header.push_sparse(SparseEntry { offset: 2048, numbytes: 3 });
+        let mut buf = Vec::new();
+        header.save(&mut buf).unwrap();
+        let mut content = b"abcdxyz".to_vec();
+        content.resize(512, 0);
+        buf.extend_from_slice(&content);
+        buf.extend_from_slice(&[0u8; 1024]);
+
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        let mut out = Vec::new();
+        let written = match archive.read_to("sparse.bin", &mut out) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to read entry: {}", e);
+                return;
+            }
+        };
+        assert_eq!(3000, written);
+        assert_eq!(3000, out.len());
+        assert_eq!(b"abcd", &out[1000..1004]);
+        assert_eq!(b"xyz", &out[2048..2051]);
+        assert!(out[..1000].iter().all(|b| *b == 0));
+        assert!(out[2051..].iter().all(|b| *b == 0));
+
+        let mut out = Vec::new();
+        add_file(&mut archive, "plain.txt", b"plain");
+        assert_eq!(5, archive.read_to("plain.txt", &mut out).unwrap());
+        assert_eq!(b"plain".to_vec(), out);
+        assert!(archive.read_to("missing", &mut out).is_err());
+    }
+
+    #[test]
+    fn read_range_slices_content() {
+        use crate::engine::header::{GnuHeader, GnuTypeFlag};
+        use crate::engine::header::gnu::SparseEntry;
+
+        // sparse file of 3000 bytes with data at [1000, 1004) and [2048, 2051)
+        let mut header = GnuHeader::new(GnuTypeFlag::Sparse);
+        header.set_name("sparse.bin".to_string());
+        header.mode = 0o644;
+        header.size = 7;
+        header.realsize = Some(3000);
+        header.push_sparse(SparseEntry { offset: 1000, numbytes: 4 });
+        header.push_sparse(SparseEntry { offset: 2048, numbytes: 3 });
+        let mut buf = Vec::new();
+        header.save(&mut buf).unwrap();
+        let mut content = b"abcdxyz".to_vec();
+        content.resize(512, 0);
+        buf.extend_from_slice(&content);
+        buf.extend_from_slice(&[0u8; 1024]);
+
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        match archive.read_range("sparse.bin", 998, 8) {
+            Ok(v) => assert_eq!(b"\0\0abcd\0\0".to_vec(), v),
+            Err(e) => assert!(false, "Failed to read range: {}", e)
+        }
+        let range = archive.read_range("sparse.bin", 1003, 1047).unwrap();
+        assert_eq!(1047, range.len());
+        assert_eq!(b'd', range[0]);
+        assert_eq!(b"xy", &range[1045..]);
+        assert_eq!(2, archive.read_range("sparse.bin", 2998, 100).unwrap().len());
+
+        add_file(&mut archive, "plain.txt", b"plain text");
+        assert_eq!(b"in te".to_vec(), archive.read_range("plain.txt", 3, 5).unwrap());
+        assert_eq!(b"xt".to_vec(), archive.read_range("plain.txt", 8, 100).unwrap());
+        assert!(archive.read_range("plain.txt", 10, 1).unwrap().is_empty());
+        match archive.read_range("plain.txt", 11, 1) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert!(matches!(e.downcast_ref::<Error>(), Some(Error::InvalidRange { offset: 11, len: 1, .. })))
+        }
+        assert!(archive.read_range("missing", 0, 1).is_err());
+    }
+
+    #[test]
+    fn read_range_follows_parts() {
+        use crate::engine::header::{GnuHeader, GnuTypeFlag};
+        use crate::engine::header::gnu::SparseEntry;
+
+        // sparse first partition of 3000 bytes with data at [2048, 2051)
+        let mut header = GnuHeader::new(GnuTypeFlag::Sparse);
+        header.set_name("sparse.bin".to_string());
+        header.mode = 0o644;
+        header.size = 3;
+        header.realsize = Some(3000);
+        header.push_sparse(SparseEntry { offset: 2048, numbytes: 3 });
+        let mut buf = Vec::new();
+        header.save(&mut buf).unwrap();
+        let mut content = b"xyz".to_vec();
+        content.resize(512, 0);
+        buf.extend_from_slice(&content);
+        buf.extend_from_slice(&[0u8; 1024]);
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        add_file(&mut archive, "sparse.bin.1.rhpart", b"tail");
+        add_file(&mut archive, "a.bin", b"hello");
+        add_file(&mut archive, "a.bin.1.rhpart", b" wor");
+        add_file(&mut archive, "a.bin.2.rhpart", b"ld");
+
+        // ranges crossing partition boundaries
+        match archive.read_range("a.bin", 3, 6) {
+            Ok(v) => assert_eq!(b"lo wor".to_vec(), v),
+            Err(e) => assert!(false, "Failed to read range: {}", e)
+        }
+        assert_eq!(b"hello world".to_vec(), archive.read_range("a.bin", 0, 100).unwrap());
+        assert!(archive.read_range("a.bin", 11, 1).unwrap().is_empty());
+
+        // a range crossing a sparse hole into the next partition
+        let range = archive.read_range("sparse.bin", 2050, 954).unwrap();
+        assert_eq!(954, range.len());
+        assert_eq!(b'z', range[0]);
+        assert!(range[1..950].iter().all(|b| *b == 0));
+        assert_eq!(b"tail", &range[950..]);
+    }
+
+    #[test]
+    fn append_data_bytes() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        let meta = Metadata::new("ignored", EntryKind::RegularFile);
+        let entry = match archive.append_data("notes.txt", meta, b"some notes") {
+            Ok(v) => v.clone(),
+            Err(e) => {
+                assert!(false, "Failed to append data: {}", e);
+                return;
+            }
+        };
+        assert_eq!("notes.txt", entry.meta.path);
+        assert_eq!(10, entry.meta.size);
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert!(archive.get("ignored").is_none());
+        assert_eq!(b"some notes".to_vec(), read_file(&mut archive, "notes.txt"));
+    }
+
+    #[test]
+    fn clamp_mtime() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.set_mtime_clamp(Some(1_000));
+        let mut meta = Metadata::new("new.txt", EntryKind::RegularFile);
+        meta.mtime = 5_000;
+        meta.atime = Some(6_000);
+        archive.append_data("new.txt", meta, b"new").unwrap();
+        let mut meta = Metadata::new("old.txt", EntryKind::RegularFile);
+        meta.mtime = 500;
+        archive.append_data("old.txt", meta, b"old").unwrap();
+
+        let archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(None, archive.mtime_clamp());
+        let entry = archive.get("new.txt").unwrap();
+        assert_eq!(1_000, entry.meta.mtime);
+        assert_eq!(Some(1_000), entry.meta.atime);
+        assert_eq!(500, archive.get("old.txt").unwrap().meta.mtime);
+    }
+
+    #[test]
+    fn owner_override() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.set_owner_override(OwnerOverride::root());
+        let mut meta = Metadata::new("a.txt", EntryKind::RegularFile);
+        meta.uid = 1000;
+        meta.gid = 1000;
+        meta.uname = "user".to_string();
+        meta.gname = "users".to_string();
+        archive.append_data("a.txt", meta.clone(), b"a").unwrap();
+        archive.set_owner_override(OwnerOverride { gname: Some("staff".to_string()), ..Default::default() });
+        archive.append_data("b.txt", meta, b"b").unwrap();
+
+        let archive = Archive::open(archive.into_inner()).unwrap();
+        let a = &archive.get("a.txt").unwrap().meta;
+        assert_eq!((0, 0, "root", "root"), (a.uid, a.gid, a.uname.as_str(), a.gname.as_str()));
+        let b = &archive.get("b.txt").unwrap().meta;
+        assert_eq!((1000, 1000, "user", "staff"), (b.uid, b.gid, b.uname.as_str(), b.gname.as_str()));
+    }
+
+    #[test]
+    fn max_entry_size() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.set_max_entry_size(Some(4));
+        archive.append_data("a.txt", Metadata::new("a.txt", EntryKind::RegularFile), b"fits").unwrap();
+        let err = match archive.append_data("b.txt", Metadata::new("b.txt", EntryKind::RegularFile), b"too big") {
+            Ok(_) => {
+                assert!(false, "expected error but got success");
+                return;
+            },
+            Err(e) => e
+        };
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::QuotaExceeded { size: 7, limit: 4, .. })));
+        let meta = Metadata::new("c.txt", EntryKind::RegularFile);
+        assert!(archive.append_measured(meta, &mut Cursor::new(b"measured".to_vec())).is_err());
+
+        let archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(vec!["a.txt"], archive.entries().map(|v| v.meta.path.as_str()).collect::<Vec<&str>>());
+    }
+
+    #[test]
+    fn max_entry_size_replace() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.set_max_entry_size(Some(4));
+        add_file(&mut archive, "a.txt", b"old");
+
+        // an over quota replacement keeps the existing entry
+        match archive.append_data("a.txt", Metadata::new("a.txt", EntryKind::RegularFile), b"too big") {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert!(matches!(e.downcast_ref::<Error>(), Some(Error::QuotaExceeded { size: 7, limit: 4, .. })))
+        }
+        assert_eq!(b"old".to_vec(), read_file(&mut archive, "a.txt"));
+        let meta = Metadata::new("a.txt", EntryKind::RegularFile);
+        assert!(archive.append_measured(meta, &mut Cursor::new(b"measured".to_vec())).is_err());
+        assert_eq!(b"old".to_vec(), read_file(&mut archive, "a.txt"));
+
+        // a replacement within the quota succeeds
+        add_file(&mut archive, "a.txt", b"new");
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(1, archive.len());
+        assert_eq!(b"new".to_vec(), read_file(&mut archive, "a.txt"));
+    }
+
+    #[test]
+    fn append_measured_rewrites_size() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        let mut meta = Metadata::new("grown.txt", EntryKind::RegularFile);
+        meta.size = 3;
+        let entry = match archive.append_measured(meta.clone(), &mut Cursor::new(b"grown!".to_vec())) {
+            Ok(v) => v.clone(),
+            Err(e) => {
+                assert!(false, "Failed to append: {}", e);
+                return;
+            }
+        };
+        assert_eq!(6, entry.meta.size);
+        meta.path = "shrunk.txt".to_string();
+        archive.append_measured(meta, &mut Cursor::new(b"ab".to_vec())).unwrap();
+
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(b"grown!".to_vec(), read_file(&mut archive, "grown.txt"));
+        assert_eq!(b"ab".to_vec(), read_file(&mut archive, "shrunk.txt"));
+    }
+
+    #[test]
+    fn validate_posix() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", b"first");
+        add_file(&mut archive, &format!("{}/b.txt", "d".repeat(200)), b"second");
+        match archive.validate_posix() {
+            Ok(v) => assert_eq!(Vec::<(String, PosixViolation)>::new(), v),
+            Err(e) => assert!(false, "Failed to validate: {}", e)
+        }
+
+        // tamper the first header checksum
+        let mut buf = archive.into_inner().into_inner();
+        buf[148] = if buf[148] == b'1' { b'2' } else { b'1' };
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        let violations = archive.validate_posix().unwrap();
+        assert_eq!(1, violations.len());
+        assert_eq!("a.txt", violations[0].0);
+        assert!(matches!(violations[0].1, PosixViolation::ChecksumMismatch { .. }));
+    }
+
+    #[test]
+    fn open_detects_compression() {
+        let mut stream = vec![0x28, 0xb5, 0x2f, 0xfd];
+        stream.resize(1024, 0);
+        match Archive::open(Cursor::new(stream)) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(e.to_string(), "the archive is Zstd compressed, use Archive::open_auto to decompress it")
+        }
+
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", b"plain");
+        let mut archive = match Archive::open_auto(archive.into_inner()) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open archive: {}", e);
+                return;
+            }
+        };
+        assert_eq!(Compression::None, archive.compression());
+        let mut out = Vec::new();
+        archive.read_to("a.txt", &mut out).unwrap();
+        assert_eq!(b"plain".to_vec(), out);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn entry_reader_streams_gzip() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", &[b'a'; 5000]);
+        add_file(&mut archive, "b.txt", b"second");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(archive.into_inner().get_ref()).unwrap();
+        let mut archive = match Archive::open_auto(Cursor::new(encoder.finish().unwrap())) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open archive: {}", e);
+                return;
+            }
+        };
+        assert!(matches!(archive.stream, Decoded::Streaming(_)));
+        assert_eq!(Compression::Gzip, archive.compression());
+        let mut out = String::new();
+        archive.located_reader("b.txt").unwrap().read_to_string(&mut out).unwrap();
+        assert_eq!("second", out);
+
+        // the content is read back in small chunks going back to an earlier entry
+        let mut reader = archive.entry_reader("a.txt").unwrap();
+        let mut chunk = [0u8; 700];
+        let mut total = 0;
+        loop {
+            let read = reader.read(&mut chunk).unwrap();
+            if read < 1 {
+                break;
+            }
+            assert!(chunk[..read].iter().all(|b| *b == b'a'));
+            total += read;
+        }
+        assert_eq!(5000, total);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn open_auto_streams() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", &[b'a'; 5000]);
+        add_file(&mut archive, "b.txt", b"second");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(archive.into_inner().get_ref()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        // the compressed content isn't inflated into memory
+        let mut archive = match Archive::open_auto(Cursor::new(compressed.clone())) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open archive: {}", e);
+                return;
+            }
+        };
+        assert!(matches!(archive.stream, Decoded::Streaming(_)));
+        let mut out = Vec::new();
+        archive.read_to("b.txt", &mut out).unwrap();
+        assert_eq!(b"second".to_vec(), out);
+
+        let mut archive = Archive::open_buffered(Cursor::new(compressed)).unwrap();
+        assert!(matches!(archive.stream, Decoded::Buffered(..)));
+        let mut out = Vec::new();
+        archive.read_to("a.txt", &mut out).unwrap();
+        assert_eq!(vec![b'a'; 5000], out);
+    }
+
+    #[test]
+    fn open_at_offset() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", b"embedded");
+        let tar = archive.into_inner().into_inner();
+        let mut container = b"#!/bin/sh\nexit 0\n".to_vec();
+        let base = container.len() as u64;
+        container.extend_from_slice(&tar);
+        container.extend_from_slice(b"trailer");
+        let mut archive = match Archive::open_at(Cursor::new(container), base, tar.len() as u64) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open archive: {}", e);
+                return;
+            }
+        };
+        let mut out = Vec::new();
+        archive.read_to("a.txt", &mut out).unwrap();
+        assert_eq!(b"embedded".to_vec(), out);
+
+        // appends past the window fail before anything is written
+        match archive.append_data("b.txt", Metadata::new("b.txt", EntryKind::RegularFile), b"too large") {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(_) => {}
+        }
+        assert!(archive.get("b.txt").is_none());
+        let mut out = Vec::new();
+        archive.read_to("a.txt", &mut out).unwrap();
+        assert_eq!(b"embedded".to_vec(), out);
+
+        // the container content around the archive is kept
+        archive.remove("a.txt").unwrap();
+        assert!(archive.get("a.txt").is_none());
+        let container = archive.into_inner().into_inner().into_inner();
+        assert!(container.starts_with(b"#!/bin/sh\n"));
+        assert!(container.ends_with(b"trailer"));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn export_seekable_zstd() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", &[b'a'; 5000]);
+        add_file(&mut archive, "b.txt", b"second");
+        let compressed = match archive.export_seekable_zstd(Cursor::new(Vec::new()), 1024, 3) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to export: {}", e);
+                return;
+            }
+        };
+        let mut archive = Archive::open_auto(Cursor::new(compressed.into_inner())).unwrap();
+        assert_eq!(Compression::Zstd, archive.compression());
+        let mut out = Vec::new();
+        archive.read_to("b.txt", &mut out).unwrap();
+        assert_eq!(b"second".to_vec(), out);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn physical_offsets() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut archive, "a.txt", &[b'a'; 5000]);
+        add_file(&mut archive, "b.txt", b"second");
+        let compressed = archive.export_seekable_zstd(Cursor::new(Vec::new()), 1024, 3).unwrap();
+        let mut archive = Archive::open_auto(Cursor::new(compressed.into_inner())).unwrap();
+        let entry = match archive.get("b.txt") {
+            Some(v) => v.clone(),
+            None => {
+                assert!(false, "expected entry b.txt");
+                return;
+            }
+        };
+        let physical = match entry.physical {
+            Some(v) => v,
+            None => {
+                assert!(false, "expected a physical location");
+                return;
+            }
+        };
+        assert_eq!(entry.data_offset / 1024, physical.frame as u64);
+        assert_eq!(entry.data_offset % 1024, physical.frame_offset);
+        let mut out = String::new();
+        archive.located_reader("b.txt").unwrap().read_to_string(&mut out).unwrap();
+        assert_eq!("second", out);
+
+        // plain archives have no physical locations
+        let mut plain = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut plain, "a.txt", b"plain");
+        let archive = Archive::open_auto(plain.into_inner()).unwrap();
+        assert_eq!(None, archive.get("a.txt").unwrap().physical);
+    }
+}
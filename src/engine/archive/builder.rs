@@ -0,0 +1,615 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::{apply_transforms, is_excluded, parse_ignore_file, Archive, EntryKind, EntrySpec, ExcludePattern, IgnoreRule, Metadata, PathTransform};
+
+/// How symbolic links are archived when walking the source tree.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[non_exhaustive]
+pub enum SymlinkMode {
+    /// Archives symbolic links as links.
+    #[default]
+    Preserve,
+    /// Archives the content the links point to, same as tar `-h`.
+    Dereference,
+    /// Archives symbolic links as links skipping the dangling ones.
+    SkipDangling
+}
+
+/// What to do when a file size changes while it is being archived.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[non_exhaustive]
+pub enum ChangedFilePolicy {
+    /// Archives the bytes actually read rewriting the entry header size.
+    #[default]
+    Rewrite,
+    /// Fails with a "file changed as we read it" error, the partial entry is removed.
+    Error
+}
+
+/// Options used to append filesystem paths into an archive.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[non_exhaustive]
+pub struct AppendOptions {
+    /// Rename rules applied in order to every entry path.
+    pub transforms: Vec<PathTransform>,
+    /// Glob patterns of source paths to skip, excluded directories aren't walked.
+    pub exclude: Vec<ExcludePattern>,
+    /// How symbolic links are archived.
+    pub symlinks: SymlinkMode,
+    /// Name of the gitignore-style files honored while walking, e.g.
+    /// `.tarignore`. Rules apply to the paths under the file directory.
+    pub ignore_file: Option<String>,
+    /// What to do when a file size changes while it is being archived.
+    pub changed_files: ChangedFilePolicy,
+}
+
+/// State kept while walking a source tree.
+#[derive(Debug, Default)]
+struct WalkState {
+    /// Canonical paths of the directories being walked, used to detect
+    /// loops when dereferencing symbolic links.
+    ancestors: Vec<PathBuf>,
+    /// Archived path of the files with multiple links by (device, inode).
+    links: HashMap<(u64, u64), String>,
+    /// Ignore rules found while walking along with their directory entry path.
+    ignores: Vec<(String, Vec<IgnoreRule>)>,
+}
+
+impl WalkState {
+    /// Tells whether a path is ignored by the ignore files found so far, the
+    /// last matching rule wins.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `is_dir` - Whether the path is a directory.
+    fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for (base, rules) in self.ignores.iter() {
+            let relative = match base.as_str() {
+                "" => path,
+                base => match path.strip_prefix(base).and_then(|v| v.strip_prefix('/')) {
+                    Some(v) => v,
+                    None => continue
+                }
+            };
+            for rule in rules {
+                if let Some(v) = rule.matches(relative, is_dir) {
+                    ignored = v;
+                }
+            }
+        }
+        ignored
+    }
+}
+
+impl<T: Read + Write + Seek> Archive<T> {
+    /// Appends a filesystem path into the archive, directories are walked
+    /// recursively in name order. Files linked more than once are archived
+    /// as hard links to the first archived path.
+    ///
+    /// # Arguments
+    /// * `src` - Filesystem path to append.
+    /// * `name` - Entry path for `src` within the archive.
+    /// * `options` - Append options.
+    ///
+    /// # Returns
+    /// * `Ok(())` - On success.
+    /// * `Err(e)` - If the filesystem read or the archive write fails.
+    pub fn append_path(&mut self, src: &Path, name: &str, options: &AppendOptions) -> Result<()> {
+        self.walk(src, name, options, &mut WalkState::default())
+    }
+
+    /// Appends entries from generated content, e.g. database dumps or network
+    /// streams, without going through the filesystem. Contents with unknown
+    /// size are buffered in memory.
+    ///
+    /// # Arguments
+    /// * `entries` - Entries as (path, spec, content) items.
+    ///
+    /// # Returns
+    /// * `Ok(usize)` - The amount of appended entries.
+    /// * `Err(e)` - If a content read or the archive write fails.
+    pub fn append_iter<I, R>(&mut self, entries: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = (String, EntrySpec, R)>,
+        R: Read,
+    {
+        let mut count = 0;
+        for (path, spec, mut reader) in entries {
+            match spec.size {
+                Some(size) => {
+                    self.append(spec.to_metadata(&path, size), &mut reader)?;
+                },
+                None => {
+                    let mut buf = Vec::new();
+                    reader.read_to_end(&mut buf)?;
+                    self.append(spec.to_metadata(&path, buf.len() as u64), &mut buf.as_slice())?;
+                }
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Appends a filesystem path walking its directories recursively.
+    ///
+    /// # Arguments
+    /// * `src` - Filesystem path to append.
+    /// * `name` - Entry path for `src` within the archive.
+    /// * `options` - Append options.
+    /// * `state` - Walk state.
+    fn walk(&mut self, src: &Path, name: &str, options: &AppendOptions, state: &mut WalkState) -> Result<()> {
+        let mut fs_meta = fs::symlink_metadata(src)?;
+        if fs_meta.file_type().is_symlink() {
+            match options.symlinks {
+                SymlinkMode::Preserve => {},
+                SymlinkMode::Dereference => fs_meta = match fs::metadata(src) {
+                    Ok(v) => v,
+                    Err(e) => bail!("dangling symbolic link '{}': {}", src.display(), e)
+                },
+                SymlinkMode::SkipDangling => if fs::metadata(src).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        let file_type = fs_meta.file_type();
+        let kind = if file_type.is_dir() {
+            EntryKind::Directory
+        } else if file_type.is_symlink() {
+            EntryKind::SymbolicLink
+        } else if file_type.is_file() {
+            EntryKind::RegularFile
+        } else {
+            match special_kind(&fs_meta) {
+                Some(v) => v,
+                None => return Ok(())
+            }
+        };
+        let is_dir = kind == EntryKind::Directory;
+        if is_excluded(&options.exclude, name, is_dir) || state.is_ignored(name.trim_end_matches('/'), is_dir) {
+            return Ok(());
+        }
+
+        if let Some(path) = apply_transforms(&options.transforms, name) {
+            let mut meta = Metadata::new(&path, kind);
+            fill_metadata(&mut meta, &fs_meta);
+            match kind {
+                EntryKind::RegularFile => match link_key(&fs_meta).and_then(|key| state.links.get(&key)) {
+                    Some(target) => {
+                        meta.kind = EntryKind::HardLink;
+                        meta.linkname = target.clone();
+                        self.append(meta, &mut std::io::empty())?;
+                    },
+                    None => {
+                        if let Some(key) = link_key(&fs_meta) {
+                            state.links.insert(key, path.clone());
+                        }
+                        meta.size = fs_meta.len();
+                        let mut file = File::open(src)?;
+                        let size = self.append_measured(meta, &mut file)?.meta.size;
+                        if size != fs_meta.len() && options.changed_files == ChangedFilePolicy::Error {
+                            self.remove(&path)?;
+                            bail!("file '{}' changed as we read it", src.display());
+                        }
+                    }
+                },
+                EntryKind::SymbolicLink => {
+                    meta.linkname = match fs::read_link(src)?.to_str() {
+                        Some(v) => v.to_string(),
+                        None => bail!("non UTF-8 symbolic link target for '{}'", src.display())
+                    };
+                    self.append(meta, &mut std::io::empty())?;
+                },
+                EntryKind::Directory => {
+                    meta.path = format!("{}/", path.trim_end_matches('/'));
+                    self.append(meta, &mut std::io::empty())?;
+                },
+                _ => {
+                    (meta.devmajor, meta.devminor) = device_numbers(&fs_meta);
+                    self.append(meta, &mut std::io::empty())?;
+                }
+            }
+        }
+
+        if !is_dir {
+            return Ok(());
+        }
+        let canonical = fs::canonicalize(src)?;
+        if state.ancestors.contains(&canonical) {
+            bail!("symbolic link loop detected at '{}'", src.display());
+        }
+        state.ancestors.push(canonical);
+        let ignore_path = options.ignore_file.as_ref().map(|v| src.join(v));
+        let has_ignore = match ignore_path {
+            Some(path) if path.is_file() => {
+                let rules = parse_ignore_file(&fs::read_to_string(path)?);
+                state.ignores.push((name.trim_end_matches('/').to_string(), rules));
+                true
+            },
+            _ => false
+        };
+        let mut children: Vec<_> = fs::read_dir(src)?.collect::<std::io::Result<_>>()?;
+        children.sort_by_key(|v| v.file_name());
+        for child in children {
+            let child_name = match child.file_name().to_str() {
+                Some(v) => v.to_string(),
+                None => bail!("non UTF-8 path '{}'", child.path().display())
+            };
+            let child_path = match name.trim_end_matches('/') {
+                "" => child_name,
+                parent => format!("{}/{}", parent, child_name)
+            };
+            self.walk(&child.path(), &child_path, options, state)?;
+        }
+        if has_ignore {
+            state.ignores.pop();
+        }
+        state.ancestors.pop();
+        Ok(())
+    }
+}
+
+/// Gets the (device, inode) pair of files with more than one link.
+///
+/// # Arguments
+/// * `fs_meta` - The filesystem metadata.
+///
+/// # Returns
+/// * `Some((u64, u64))` - The link key when the file has multiple links.
+/// * `None` - When the file has a single link or the platform doesn't expose inodes.
+#[cfg(unix)]
+fn link_key(fs_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    if fs_meta.nlink() < 2 {
+        return None;
+    }
+    Some((fs_meta.dev(), fs_meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn link_key(_fs_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Gets the entry kind of special files: FIFOs and character or block devices.
+///
+/// # Arguments
+/// * `fs_meta` - The filesystem metadata.
+///
+/// # Returns
+/// * `Some(EntryKind)` - The special file entry kind.
+/// * `None` - When the file type isn't supported, e.g. sockets.
+#[cfg(unix)]
+fn special_kind(fs_meta: &fs::Metadata) -> Option<EntryKind> {
+    use std::os::unix::fs::FileTypeExt;
+    let file_type = fs_meta.file_type();
+    if file_type.is_fifo() {
+        return Some(EntryKind::FIFO);
+    }
+    if file_type.is_char_device() {
+        return Some(EntryKind::CharacterSpecial);
+    }
+    if file_type.is_block_device() {
+        return Some(EntryKind::BlockSpecial);
+    }
+    None
+}
+
+#[cfg(not(unix))]
+fn special_kind(_fs_meta: &fs::Metadata) -> Option<EntryKind> {
+    None
+}
+
+/// Gets the device (major, minor) numbers of a device file.
+///
+/// # Arguments
+/// * `fs_meta` - The filesystem metadata.
+#[cfg(unix)]
+fn device_numbers(fs_meta: &fs::Metadata) -> (u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+    split_rdev(fs_meta.rdev())
+}
+
+#[cfg(not(unix))]
+fn device_numbers(_fs_meta: &fs::Metadata) -> (u32, u32) {
+    (0, 0)
+}
+
+/// Splits a raw device number into its (major, minor) numbers.
+///
+/// # Arguments
+/// * `rdev` - Raw device number.
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd"))]
+fn split_rdev(rdev: u64) -> (u32, u32) {
+    (((rdev >> 24) & 0xff) as u32, (rdev & 0xffffff) as u32)
+}
+
+/// Splits a raw device number into its (major, minor) numbers.
+///
+/// # Arguments
+/// * `rdev` - Raw device number.
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd")))]
+fn split_rdev(rdev: u64) -> (u32, u32) {
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    (major as u32, minor as u32)
+}
+
+/// Fills the entry metadata from the filesystem metadata.
+///
+/// # Arguments
+/// * `meta` - The entry metadata to fill.
+/// * `fs_meta` - The filesystem metadata.
+fn fill_metadata(meta: &mut Metadata, fs_meta: &fs::Metadata) {
+    if let Ok(modified) = fs_meta.modified() {
+        meta.mtime = modified.duration_since(UNIX_EPOCH).map(|v| v.as_secs()).unwrap_or_default();
+    }
+    // not every platform or filesystem records it
+    if let Ok(created) = fs_meta.created() {
+        meta.birthtime = created.duration_since(UNIX_EPOCH).ok().map(|v| v.as_secs());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        meta.mode = fs_meta.mode() & 0o7777;
+        meta.uid = fs_meta.uid() as u64;
+        meta.gid = fs_meta.gid() as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn append_directory_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src/nested")).unwrap();
+        fs::write(dir.path().join("src/b.txt"), b"b").unwrap();
+        fs::write(dir.path().join("src/a.txt"), b"a").unwrap();
+        fs::write(dir.path().join("src/nested/c.txt"), b"c").unwrap();
+
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        if let Err(e) = archive.append_path(&dir.path().join("src"), "src", &AppendOptions::default()) {
+            assert!(false, "Failed to append path: {}", e);
+            return;
+        }
+        let paths: Vec<&str> = archive.entries().map(|e| e.meta.path.as_str()).collect();
+        assert_eq!(vec!["src/", "src/a.txt", "src/b.txt", "src/nested/", "src/nested/c.txt"], paths);
+        let mut out = Vec::new();
+        archive.read_to("src/nested/c.txt", &mut out).unwrap();
+        assert_eq!(b"c".to_vec(), out);
+
+        let created = fs::metadata(dir.path().join("src/a.txt")).unwrap().created().ok()
+            .map(|v| v.duration_since(UNIX_EPOCH).unwrap().as_secs());
+        assert_eq!(created, archive.get("src/a.txt").unwrap().meta.birthtime);
+    }
+
+    #[test]
+    fn append_with_transforms() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/a.txt"), b"a").unwrap();
+        fs::write(dir.path().join("src/a.o"), b"o").unwrap();
+
+        let options = AppendOptions {
+            transforms: vec![
+                PathTransform::parse("s/^src/pkg/").unwrap(),
+                PathTransform::custom(|path| if path.ends_with(".o") { String::new() } else { path.to_string() })
+            ],
+            ..Default::default()
+        };
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.append_path(&dir.path().join("src"), "src", &options).unwrap();
+        let paths: Vec<&str> = archive.entries().map(|e| e.meta.path.as_str()).collect();
+        assert_eq!(vec!["pkg/", "pkg/a.txt"], paths);
+    }
+
+    #[test]
+    fn append_with_excludes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("crate/target/debug")).unwrap();
+        fs::create_dir_all(dir.path().join("crate/src")).unwrap();
+        fs::write(dir.path().join("crate/target/debug/app"), b"app").unwrap();
+        fs::write(dir.path().join("crate/src/main.rs"), b"fn main() {}").unwrap();
+        fs::write(dir.path().join("crate/src/main.o"), b"o").unwrap();
+
+        let options = AppendOptions {
+            exclude: vec![ExcludePattern::new("target/"), ExcludePattern::new("*.o")],
+            ..Default::default()
+        };
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.append_path(&dir.path().join("crate"), "crate", &options).unwrap();
+        let paths: Vec<&str> = archive.entries().map(|e| e.meta.path.as_str()).collect();
+        assert_eq!(vec!["crate/", "crate/src/", "crate/src/main.rs"], paths);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn append_symlink_modes() {
+        use std::os::unix::fs::symlink;
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/a.txt"), b"hello").unwrap();
+        symlink("a.txt", dir.path().join("src/link")).unwrap();
+        symlink("missing", dir.path().join("src/dangling")).unwrap();
+
+        // preserve
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.append_path(&dir.path().join("src"), "src", &AppendOptions::default()).unwrap();
+        let link = archive.get("src/link").unwrap();
+        assert_eq!(EntryKind::SymbolicLink, link.meta.kind);
+        assert_eq!("a.txt", link.meta.linkname);
+        assert!(archive.get("src/dangling").is_some());
+
+        // skip dangling
+        let options = AppendOptions { symlinks: SymlinkMode::SkipDangling, ..Default::default() };
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.append_path(&dir.path().join("src"), "src", &options).unwrap();
+        assert_eq!(EntryKind::SymbolicLink, archive.get("src/link").unwrap().meta.kind);
+        assert!(archive.get("src/dangling").is_none());
+
+        // dereference fails on dangling links
+        let options = AppendOptions { symlinks: SymlinkMode::Dereference, ..Default::default() };
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        assert!(archive.append_path(&dir.path().join("src"), "src", &options).is_err());
+        fs::remove_file(dir.path().join("src/dangling")).unwrap();
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.append_path(&dir.path().join("src"), "src", &options).unwrap();
+        assert_eq!(EntryKind::RegularFile, archive.get("src/link").unwrap().meta.kind);
+        let mut out = Vec::new();
+        archive.read_to("src/link", &mut out).unwrap();
+        assert_eq!(b"hello".to_vec(), out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn append_dereference_loop() {
+        use std::os::unix::fs::symlink;
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        symlink("..", dir.path().join("src/parent")).unwrap();
+        let options = AppendOptions { symlinks: SymlinkMode::Dereference, ..Default::default() };
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        match archive.append_path(&dir.path().join("src"), "src", &options) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert!(e.to_string().starts_with("symbolic link loop detected"))
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn append_detects_hard_links() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/a.txt"), b"hello").unwrap();
+        fs::hard_link(dir.path().join("src/a.txt"), dir.path().join("src/b.txt")).unwrap();
+        fs::write(dir.path().join("src/c.txt"), b"other").unwrap();
+
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.append_path(&dir.path().join("src"), "src", &AppendOptions::default()).unwrap();
+        let first = archive.get("src/a.txt").unwrap();
+        assert_eq!(EntryKind::RegularFile, first.meta.kind);
+        assert_eq!(5, first.meta.size);
+        let link = archive.get("src/b.txt").unwrap();
+        assert_eq!(EntryKind::HardLink, link.meta.kind);
+        assert_eq!("src/a.txt", link.meta.linkname);
+        assert_eq!(0, link.meta.size);
+        assert_eq!(EntryKind::RegularFile, archive.get("src/c.txt").unwrap().meta.kind);
+    }
+
+    #[test]
+    fn append_honors_ignore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("proj/target")).unwrap();
+        fs::create_dir_all(dir.path().join("proj/logs")).unwrap();
+        fs::write(dir.path().join("proj/.tarignore"), b"target/\n*.log\n").unwrap();
+        fs::write(dir.path().join("proj/target/app"), b"app").unwrap();
+        fs::write(dir.path().join("proj/a.log"), b"log").unwrap();
+        fs::write(dir.path().join("proj/logs/.tarignore"), b"!keep.log\n").unwrap();
+        fs::write(dir.path().join("proj/logs/keep.log"), b"keep").unwrap();
+        fs::write(dir.path().join("proj/logs/drop.log"), b"drop").unwrap();
+
+        let options = AppendOptions { ignore_file: Some(".tarignore".to_string()), ..Default::default() };
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        if let Err(e) = archive.append_path(&dir.path().join("proj"), "proj", &options) {
+            assert!(false, "Failed to append path: {}", e);
+            return;
+        }
+        let paths: Vec<&str> = archive.entries().map(|e| e.meta.path.as_str()).collect();
+        assert_eq!(vec!["proj/", "proj/.tarignore", "proj/logs/", "proj/logs/.tarignore", "proj/logs/keep.log"], paths);
+
+        // ignore files are not honored unless requested
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.append_path(&dir.path().join("proj"), "proj", &AppendOptions::default()).unwrap();
+        assert!(archive.get("proj/target/app").is_some());
+    }
+
+    #[test]
+    fn append_from_iterator() {
+        let mut dir = EntrySpec::new(EntryKind::Directory);
+        dir.mtime = 1_700_000_000;
+        let mut dump = EntrySpec::file(None);
+        dump.uname = "db".to_string();
+        let entries: Vec<(String, EntrySpec, Box<dyn Read>)> = vec![
+            ("dumps/".to_string(), dir, Box::new(std::io::empty())),
+            ("dumps/users.sql".to_string(), dump, Box::new(Cursor::new(b"INSERT 1;".to_vec()))),
+            ("dumps/size.txt".to_string(), EntrySpec::file(Some(4)), Box::new(Cursor::new(b"1234extra".to_vec())))
+        ];
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        let count = match archive.append_iter(entries) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to append entries: {}", e);
+                return;
+            }
+        };
+        assert_eq!(3, count);
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(1_700_000_000, archive.get("dumps/").unwrap().meta.mtime);
+        let dump = archive.get("dumps/users.sql").unwrap();
+        assert_eq!(9, dump.meta.size);
+        assert_eq!("db", dump.meta.uname);
+        let mut out = Vec::new();
+        archive.read_to("dumps/size.txt", &mut out).unwrap();
+        assert_eq!(b"1234".to_vec(), out);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn split_linux_rdev() {
+        // /dev/null is 1:3 and /dev/sda1 is 8:1
+        assert_eq!((1, 3), split_rdev(0x103));
+        assert_eq!((8, 1), split_rdev(0x801));
+        assert_eq!((259, 300), split_rdev((259 << 8) | (300 & 0xff) | ((300 & !0xff) << 12)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn append_special_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        let fifo = dir.path().join("src/pipe");
+        let created = std::process::Command::new("mkfifo").arg(&fifo).status().map(|v| v.success()).unwrap_or(false);
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        if created {
+            archive.append_path(&dir.path().join("src"), "src", &AppendOptions::default()).unwrap();
+            let entry = archive.get("src/pipe").unwrap();
+            assert_eq!(EntryKind::FIFO, entry.meta.kind);
+            assert_eq!(0, entry.meta.size);
+        }
+        archive.append_path(Path::new("/dev/null"), "null", &AppendOptions::default()).unwrap();
+        let entry = archive.get("null").unwrap();
+        assert_eq!(EntryKind::CharacterSpecial, entry.meta.kind);
+        assert_eq!((1, 3), (entry.meta.devmajor, entry.meta.devminor));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn append_changed_files() {
+        // procfs files report an empty size but have content
+        let src = Path::new("/proc/self/status");
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        if let Err(e) = archive.append_path(src, "status", &AppendOptions::default()) {
+            assert!(false, "Failed to append path: {}", e);
+            return;
+        }
+        assert!(archive.get("status").unwrap().meta.size > 0);
+
+        let options = AppendOptions { changed_files: ChangedFilePolicy::Error, ..Default::default() };
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        match archive.append_path(src, "status", &options) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(e.to_string(), "file '/proc/self/status' changed as we read it")
+        }
+        assert!(archive.is_empty());
+        let archive = Archive::open(archive.into_inner()).unwrap();
+        assert!(archive.is_empty());
+    }
+}
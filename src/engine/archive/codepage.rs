@@ -0,0 +1,324 @@
+use anyhow::{bail, Result};
+#[cfg(feature = "codepage")]
+use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use super::Metadata;
+use super::repair::read_block;
+use crate::engine::header::{PaxHeader, TarHeader};
+use crate::format::{parse_octal, BLOCK_SIZE};
+
+/// Code page 437 characters for the bytes from 0x80 to 0xff.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}'
+];
+
+/// Legacy encodings the entry names of archives produced on old systems
+/// can be decoded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codepage {
+    /// Original IBM PC code page, used by DOS.
+    Cp437,
+    /// Windows western european code page.
+    #[cfg(feature = "codepage")]
+    Cp1252,
+    /// Japanese Shift JIS.
+    #[cfg(feature = "codepage")]
+    ShiftJis,
+}
+
+impl Codepage {
+    /// Decodes bytes into UTF-8, unmappable bytes become the replacement
+    /// character.
+    ///
+    /// # Arguments
+    /// * `bytes` - Encoded bytes.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Codepage::Cp437 => bytes.iter()
+                .map(|b| if *b < 0x80 { *b as char } else { CP437_HIGH[*b as usize - 0x80] })
+                .collect(),
+            #[cfg(feature = "codepage")]
+            Codepage::Cp1252 => WINDOWS_1252.decode_without_bom_handling(bytes).0.into_owned(),
+            #[cfg(feature = "codepage")]
+            Codepage::ShiftJis => SHIFT_JIS.decode_without_bom_handling(bytes).0.into_owned()
+        }
+    }
+}
+
+/// Raw name fields of a header block holding non ASCII bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct RawNames {
+    /// Path, the USTAR prefix is joined to the name.
+    pub path: Vec<u8>,
+    /// Link target.
+    pub linkname: Vec<u8>,
+    /// Owner user name.
+    pub uname: Vec<u8>,
+    /// Owner group name.
+    pub gname: Vec<u8>,
+}
+
+impl RawNames {
+    /// Sets the decoded names on the metadata, names given by PAX
+    /// attributes are already UTF-8 so they're kept.
+    ///
+    /// # Arguments
+    /// * `codepage` - Encoding the names are decoded from.
+    /// * `meta` - Metadata to update.
+    /// * `pax` - PAX extended header of the entry.
+    ///
+    /// # Returns
+    /// * `Some(Vec<u8>)` - The raw path when the path was decoded.
+    /// * `None` - When a PAX attribute gives the path.
+    pub fn apply(self, codepage: Codepage, meta: &mut Metadata, pax: Option<&PaxHeader>) -> Option<Vec<u8>> {
+        let has = |key: &str| pax.is_some_and(|v| v.get_attr(key).is_some());
+        if !has("linkpath") {
+            meta.linkname = codepage.decode(&self.linkname);
+        }
+        if !has("uname") {
+            meta.uname = codepage.decode(&self.uname);
+        }
+        if !has("gname") {
+            meta.gname = codepage.decode(&self.gname);
+        }
+        if has("path") {
+            return None;
+        }
+        meta.path = codepage.decode(&self.path);
+        Some(self.path)
+    }
+}
+
+/// Loads a header whose name fields may hold legacy encoded bytes, they're
+/// masked so the header parses and returned raw to be decoded.
+///
+/// # Arguments
+/// * `stream` - The stream positioned at the header.
+///
+/// # Returns
+/// * `Ok((TarHeader, Some(RawNames)))` - The header and its raw names when any holds non ASCII bytes.
+/// * `Ok((TarHeader, None))` - The header when its names are plain ASCII.
+/// * `Err(e)` - If the header can't be read or parsed.
+pub(super) fn load_encoded(stream: &mut (impl Read + Seek)) -> Result<(TarHeader, Option<RawNames>)> {
+    let pos = stream.stream_position()?;
+    let mut block = match read_block(stream, pos)? {
+        Some(v) => v,
+        None => {
+            stream.seek(SeekFrom::Start(pos))?;
+            return Ok((TarHeader::load(stream)?, None));
+        }
+    };
+    if is_long_record(&block) {
+        return load_long_encoded(stream, block);
+    }
+
+    // only USTAR and PAX headers have a prefix, GNU stores times there
+    let fields: &[(usize, usize)] = match &block[257..263] {
+        b"ustar\0" => &[(0, 100), (157, 257), (265, 297), (297, 329), (345, 500)],
+        b"ustar " => &[(0, 100), (157, 257), (265, 297), (297, 329)],
+        _ => &[(0, 100), (157, 257)]
+    };
+    let raw: Vec<Vec<u8>> = fields.iter()
+        .map(|(start, end)| block[*start..*end].iter().take_while(|b| **b != 0).copied().collect())
+        .collect();
+    if !raw.iter().flatten().any(|b| *b >= 0x80) {
+        return Ok((TarHeader::load_block(block, stream)?, None));
+    }
+    for (start, end) in fields {
+        block[*start..*end].iter_mut().filter(|b| **b >= 0x80).for_each(|b| *b = b'_');
+    }
+    let header = TarHeader::load_block(block, stream)?;
+
+    let mut path = raw[0].clone();
+    if let Some(prefix) = raw.get(4).filter(|v| !v.is_empty()) {
+        path = [prefix.as_slice(), b"/", path.as_slice()].concat();
+    }
+    let field = |i: usize| raw.get(i).cloned().unwrap_or_default();
+    let names = RawNames { path, linkname: field(1), uname: field(2), gname: field(3) };
+    Ok((header, Some(names)))
+}
+
+/// Tells whether a block is a GNU long name or long link record.
+///
+/// # Arguments
+/// * `block` - Raw header block.
+fn is_long_record(block: &[u8; BLOCK_SIZE]) -> bool {
+    &block[257..265] == b"ustar  \0" && matches!(block[156], b'L' | b'K')
+}
+
+/// Loads a GNU header led by long name records whose values may hold
+/// legacy encoded bytes, the records and the header are masked in memory
+/// so the header parses.
+///
+/// # Arguments
+/// * `stream` - The stream positioned after the first record block.
+/// * `block` - The first record block.
+///
+/// # Returns
+/// * `Ok((TarHeader, Some(RawNames)))` - The header and its raw names when any holds non ASCII bytes.
+/// * `Ok((TarHeader, None))` - The header when its names are plain ASCII.
+/// * `Err(e)` - If the header can't be read or parsed.
+fn load_long_encoded(stream: &mut (impl Read + Seek), mut block: [u8; BLOCK_SIZE]) -> Result<(TarHeader, Option<RawNames>)> {
+    let start = stream.stream_position()? - BLOCK_SIZE as u64;
+    let mut masked = Vec::new();
+    let mut long_name = None;
+    let mut long_link = None;
+    while is_long_record(&block) {
+        let size = parse_octal::<u64>(&block[124..136])?;
+        let padded = size.div_ceil(BLOCK_SIZE as u64) * BLOCK_SIZE as u64;
+        let mut data = Vec::new();
+        (&mut *stream).take(padded).read_to_end(&mut data)?;
+        if (data.len() as u64) < padded {
+            bail!("truncated GNU long name record at offset {}", start + masked.len() as u64);
+        }
+        let value: Vec<u8> = data[..size as usize].iter().take_while(|b| **b != 0).copied().collect();
+        if block[156] == b'L' {
+            long_name = Some(value);
+        } else {
+            long_link = Some(value);
+        }
+        data.iter_mut().filter(|b| **b >= 0x80).for_each(|b| *b = b'_');
+        masked.extend_from_slice(&block);
+        masked.extend_from_slice(&data);
+        block = match read_block(stream, start + masked.len() as u64)? {
+            Some(v) => v,
+            None => bail!("missing header after the GNU long name records at offset {}", start)
+        };
+    }
+
+    // the names the records don't give are on the header block
+    let fields = [(0, 100), (157, 257), (265, 297), (297, 329)];
+    let mut raw: Vec<Vec<u8>> = fields.iter()
+        .map(|(start, end)| block[*start..*end].iter().take_while(|b| **b != 0).copied().collect())
+        .collect();
+    if let Some(v) = long_name {
+        raw[0] = v;
+    }
+    if let Some(v) = long_link {
+        raw[1] = v;
+    }
+    for (start, end) in fields {
+        block[start..end].iter_mut().filter(|b| **b >= 0x80).for_each(|b| *b = b'_');
+    }
+    masked.extend_from_slice(&block);
+
+    // sparse extension blocks follow the header
+    if block[482] == b'1' {
+        loop {
+            let extension = match read_block(stream, start + masked.len() as u64)? {
+                Some(v) => v,
+                None => bail!("truncated GNU sparse header at offset {}", start)
+            };
+            masked.extend_from_slice(&extension);
+            if extension[504] != b'1' {
+                break;
+            }
+        }
+    }
+
+    let first: [u8; BLOCK_SIZE] = masked[..BLOCK_SIZE].try_into()?;
+    let mut reader = Cursor::new(&masked[BLOCK_SIZE..]);
+    let header = TarHeader::load_block(first, &mut reader)?;
+    stream.seek(SeekFrom::Start(start + BLOCK_SIZE as u64 + reader.position()))?;
+    if !raw.iter().flatten().any(|b| *b >= 0x80) {
+        return Ok((header, None));
+    }
+    let names = RawNames { path: raw[0].clone(), linkname: raw[1].clone(), uname: raw[2].clone(), gname: raw[3].clone() };
+    Ok((header, Some(names)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::{Archive, EntryKind};
+    use crate::engine::header::{GnuHeader, GnuTypeFlag, UstarTypeFlag};
+
+    #[test]
+    fn decode_codepages() {
+        assert_eq!("\u{c7}a\u{2591}", Codepage::Cp437.decode(b"\x80a\xb0"));
+        #[cfg(feature = "codepage")]
+        {
+            assert_eq!("caf\u{e9}", Codepage::Cp1252.decode(b"caf\xe9"));
+            assert_eq!("\u{65e5}\u{672c}", Codepage::ShiftJis.decode(b"\x93\xfa\x96\x7b"));
+        }
+    }
+
+    #[test]
+    fn open_encoded_names() {
+        let mut buf = Vec::new();
+        let mut meta = Metadata::new("cafe.txt", EntryKind::RegularFile);
+        meta.size = 4;
+        meta.save_headers(&mut buf).unwrap();
+        buf[3] = 0x82;
+        buf.extend_from_slice(b"menu");
+        buf.resize(2048, 0);
+        assert!(Archive::open(std::io::Cursor::new(buf.clone())).is_err());
+
+        let mut archive = match Archive::open_encoded(std::io::Cursor::new(buf), Codepage::Cp437) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open archive: {}", e);
+                return;
+            }
+        };
+        let entry = match archive.get("caf\u{e9}.txt") {
+            Some(v) => v,
+            None => {
+                assert!(false, "expected the decoded path");
+                return;
+            }
+        };
+        assert_eq!(Some(b"caf\x82.txt".to_vec()), entry.raw_path);
+        let mut out = Vec::new();
+        archive.read_to("caf\u{e9}.txt", &mut out).unwrap();
+        assert_eq!(b"menu".to_vec(), out);
+    }
+
+    #[test]
+    fn open_encoded_long_names() {
+        let plain = format!("{}/cafX.txt", "d".repeat(120));
+        let mut header = GnuHeader::new(GnuTypeFlag::Ustar(UstarTypeFlag::RegularFile));
+        header.name = plain.clone();
+        header.mode = 0o644;
+        header.size = 4;
+        let mut buf = Vec::new();
+        header.save(&mut buf).unwrap();
+        // re-encode the name on the long name record as code page 437
+        assert_eq!(b'L', buf[156]);
+        let at = 512 + plain.find('X').unwrap();
+        buf[at] = 0x82;
+        buf.extend_from_slice(b"menu");
+        buf.resize(buf.len().div_ceil(512) * 512 + 1024, 0);
+        assert!(Archive::open(std::io::Cursor::new(buf.clone())).is_err());
+
+        let mut archive = match Archive::open_encoded(std::io::Cursor::new(buf), Codepage::Cp437) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open archive: {}", e);
+                return;
+            }
+        };
+        let name = format!("{}/caf\u{e9}.txt", "d".repeat(120));
+        let entry = match archive.get(&name) {
+            Some(v) => v,
+            None => {
+                assert!(false, "expected the decoded long path");
+                return;
+            }
+        };
+        let mut raw = plain.into_bytes();
+        raw[at - 512] = 0x82;
+        assert_eq!(Some(raw), entry.raw_path);
+        let mut out = Vec::new();
+        archive.read_to(&name, &mut out).unwrap();
+        assert_eq!(b"menu".to_vec(), out);
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{bail, Result};
+
+/// Largest dumpdir content loaded, GNU tar lists a directory in far less
+/// so bigger ones are taken as corrupted.
+pub(super) const MAX_DUMPDIR_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Kind of a GNU dumpdir member, given by the control byte prefixing its name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum DumpMemberKind {
+    /// `Y`: file stored in the archive.
+    Dumped,
+    /// `N`: file unchanged since the previous dump, not stored in the archive.
+    Unchanged,
+    /// `D`: subdirectory.
+    Directory,
+    /// `R`: source of a rename, followed by its `T` target.
+    RenameFrom,
+    /// `T`: target of the previous `R` rename.
+    RenameTo,
+    /// `X`: temporary name used by rename cycles.
+    Temporary,
+    /// Unknown control byte.
+    Other(u8)
+}
+
+impl From<u8> for DumpMemberKind {
+    fn from(value: u8) -> Self {
+        match value {
+            b'Y' => Self::Dumped,
+            b'N' => Self::Unchanged,
+            b'D' => Self::Directory,
+            b'R' => Self::RenameFrom,
+            b'T' => Self::RenameTo,
+            b'X' => Self::Temporary,
+            v => Self::Other(v)
+        }
+    }
+}
+
+/// Member record of a GNU dumpdir.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpMember {
+    /// Member kind.
+    pub kind: DumpMemberKind,
+    /// Member name relative to the directory, rename records may hold full paths.
+    pub name: String,
+}
+
+/// Content of a GNU incremental directory entry (typeflag `D`), lists the
+/// directory members at dump time so a restore can delete the files that
+/// no longer exist.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DumpDir {
+    /// Members in dump order.
+    pub members: Vec<DumpMember>,
+}
+
+impl DumpDir {
+    /// Parses a dumpdir, a sequence of nul terminated records each prefixed
+    /// with its control byte and ended by an empty record.
+    ///
+    /// # Arguments
+    /// * `buf` - The entry content.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The parsed dumpdir.
+    /// * `Err(e)` - If a record isn't nul terminated or its name isn't valid UTF-8.
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let mut members = Vec::new();
+        let mut rest = buf;
+        while let Some((&control, tail)) = rest.split_first() {
+            if control == 0 {
+                break;
+            }
+            let end = match tail.iter().position(|b| *b == 0) {
+                Some(v) => v,
+                None => bail!("unterminated dumpdir record")
+            };
+            let name = match std::str::from_utf8(&tail[..end]) {
+                Ok(v) => v.to_string(),
+                Err(_) => bail!("invalid dumpdir member name")
+            };
+            members.push(DumpMember { kind: control.into(), name });
+            rest = &tail[end + 1..];
+        }
+        Ok(Self { members })
+    }
+
+    /// Tells whether a name was a directory member at dump time, rename and
+    /// temporary records aren't members.
+    ///
+    /// # Arguments
+    /// * `name` - Member name relative to the directory.
+    pub fn contains(&self, name: &str) -> bool {
+        self.members.iter().any(|member| member.name == name && matches!(
+            member.kind,
+            DumpMemberKind::Dumped | DumpMemberKind::Unchanged | DumpMemberKind::Directory
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_members() {
+        let dump = match DumpDir::parse(b"Ya.txt\0Nb.txt\0Dsub\0Rold\0Tnew\0\0garbage") {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to parse dumpdir: {}", e);
+                return;
+            }
+        };
+        assert_eq!(5, dump.members.len());
+        assert_eq!(DumpMember { kind: DumpMemberKind::Unchanged, name: "b.txt".to_string() }, dump.members[1]);
+        assert!(dump.contains("a.txt"));
+        assert!(dump.contains("sub"));
+        assert!(!dump.contains("old"));
+        assert!(!dump.contains("c.txt"));
+        assert!(DumpDir::parse(b"").unwrap().members.is_empty());
+        assert!(DumpDir::parse(b"Ya.txt").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{Error as IoError, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::io::Result as IoResult;
+
+/// Window over a region of a larger stream, offsets are relative to the
+/// window base and nothing is read or written past its length, so an
+/// archive embedded inside other container can be used in place.
+#[derive(Debug)]
+pub struct Embedded<T> {
+    /// Container stream.
+    stream: T,
+    /// Offset of the window within the container.
+    base: u64,
+    /// Window length.
+    len: u64,
+    /// Current position relative to the base.
+    pos: u64,
+}
+
+impl<T: Seek> Embedded<T> {
+    /// Creates a window over a container stream.
+    ///
+    /// # Arguments
+    /// * `stream` - Container stream.
+    /// * `base` - Offset of the window within the container.
+    /// * `len` - Window length.
+    ///
+    /// # Returns
+    /// * `IoResult<Self>` - The window positioned at its start.
+    pub fn new(mut stream: T, base: u64, len: u64) -> IoResult<Self> {
+        if base.checked_add(len).is_none() {
+            return Err(IoError::new(ErrorKind::InvalidInput, "embedded window overflows the stream"));
+        }
+        stream.seek(SeekFrom::Start(base))?;
+        Ok(Self { stream, base, len, pos: 0 })
+    }
+
+    /// Gets the offset of the window within the container.
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Gets the window length.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Tells whether the window is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len < 1
+    }
+
+    /// Consumes the window returning the container stream.
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+
+    /// Gets the amount of bytes left before the window end, capped to a buffer length.
+    ///
+    /// # Arguments
+    /// * `buf_len` - Buffer length.
+    fn available(&self, buf_len: usize) -> usize {
+        self.len.saturating_sub(self.pos).min(buf_len as u64) as usize
+    }
+}
+
+impl<T: Read + Seek> Read for Embedded<T> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let n = self.available(buf.len());
+        if n < 1 {
+            return Ok(0);
+        }
+        let n = self.stream.read(&mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<T: Write + Seek> Write for Embedded<T> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        // the container content after the window must be kept intact
+        let n = self.available(buf.len());
+        if n < 1 {
+            return Ok(0);
+        }
+        let n = self.stream.write(&buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.stream.flush()
+    }
+}
+
+impl<T: Seek> Seek for Embedded<T> {
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        let pos = match pos {
+            SeekFrom::Start(v) => v as i128,
+            SeekFrom::End(v) => self.len as i128 + v as i128,
+            SeekFrom::Current(v) => self.pos as i128 + v as i128
+        };
+        if pos < 0 {
+            return Err(IoError::new(ErrorKind::InvalidInput, "invalid seek to a negative position"));
+        }
+        let pos = pos as u64;
+        let physical = match self.base.checked_add(pos) {
+            Some(v) => v,
+            None => return Err(IoError::new(ErrorKind::InvalidInput, "seek position overflows the stream"))
+        };
+        self.stream.seek(SeekFrom::Start(physical))?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn window_bounds() {
+        let stream = Cursor::new(b"head0123456789tail".to_vec());
+        let mut window = match Embedded::new(stream, 4, 10) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to create window: {}", e);
+                return;
+            }
+        };
+        let mut buf = String::new();
+        window.read_to_string(&mut buf).unwrap();
+        assert_eq!("0123456789", buf);
+        assert_eq!(8, window.seek(SeekFrom::End(-2)).unwrap());
+        match window.write_all(b"abc") {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(ErrorKind::WriteZero, e.kind())
+        }
+        assert!(window.seek(SeekFrom::Current(-11)).is_err());
+        assert_eq!(b"head01234567abtail".to_vec(), window.into_inner().into_inner());
+    }
+}
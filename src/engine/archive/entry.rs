@@ -0,0 +1,655 @@
+use anyhow::{bail, Result};
+use indexmap::IndexMap;
+use std::io::Write;
+
+use crate::engine::header::{
+    CustomHeader, GnuTypeFlag, PaxAttribute, PaxHeader, PaxTypeFlag, TarHeader, UstarHeader, UstarTypeFlag
+};
+use crate::engine::compression::PhysicalOffset;
+use crate::engine::header::gnu::SparseEntry;
+use super::DumpDir;
+
+/// Biggest value that fits a 7 digits octal USTAR field (uid, gid).
+const MAX_OCTAL_7: u64 = 0o7777777;
+
+/// Biggest value that fits an 11 digits octal USTAR field (size, mtime).
+const MAX_OCTAL_11: u64 = 0o77777777777;
+
+/// PAX key used by bsdtar to store the creation time.
+pub const CREATIONTIME_KEY: &str = "LIBARCHIVE.creationtime";
+
+/// Kind of entry stored within the archive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum EntryKind {
+    RegularFile,
+    HardLink,
+    SymbolicLink,
+    CharacterSpecial,
+    BlockSpecial,
+    Directory,
+    FIFO,
+    ContiguousFile,
+    Other(u8)
+}
+
+impl From<u8> for EntryKind {
+    fn from(value: u8) -> Self {
+        match value {
+            b'0' | b'\0' => Self::RegularFile,
+            b'1' => Self::HardLink,
+            b'2' => Self::SymbolicLink,
+            b'3' => Self::CharacterSpecial,
+            b'4' => Self::BlockSpecial,
+            b'5' => Self::Directory,
+            b'6' => Self::FIFO,
+            b'7' => Self::ContiguousFile,
+            v => Self::Other(v),
+        }
+    }
+}
+
+impl From<EntryKind> for UstarTypeFlag {
+    fn from(value: EntryKind) -> Self {
+        match value {
+            EntryKind::RegularFile => Self::RegularFile,
+            EntryKind::HardLink => Self::HardLink,
+            EntryKind::SymbolicLink => Self::SymbolicLink,
+            EntryKind::CharacterSpecial => Self::CharacterSpecial,
+            EntryKind::BlockSpecial => Self::BlockSpecial,
+            EntryKind::Directory => Self::Directory,
+            EntryKind::FIFO => Self::FIFO,
+            EntryKind::ContiguousFile => Self::ContiguousFile,
+            EntryKind::Other(v) => Self::Unknown(v),
+        }
+    }
+}
+
+/// Unified entry metadata regardless of the header format it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    /// Full entry path.
+    pub path: String,
+    /// Entry kind.
+    pub kind: EntryKind,
+    /// File mode.
+    pub mode: u32,
+    /// Owner user ID.
+    pub uid: u64,
+    /// Owner group ID.
+    pub gid: u64,
+    /// Owner user name.
+    pub uname: String,
+    /// Owner group name.
+    pub gname: String,
+    /// Logical content size in bytes.
+    pub size: u64,
+    /// Modification time (seconds since epoch).
+    pub mtime: u64,
+    /// Access time (seconds since epoch).
+    pub atime: Option<u64>,
+    /// Change time (seconds since epoch).
+    pub ctime: Option<u64>,
+    /// Creation time (seconds since epoch).
+    pub birthtime: Option<u64>,
+    /// Name of the linked file.
+    pub linkname: String,
+    /// Device major number.
+    pub devmajor: u32,
+    /// Device minor number.
+    pub devminor: u32,
+    /// Extra PAX attributes not mapped into any other field.
+    pub attributes: IndexMap<String, PaxAttribute>,
+}
+
+impl Metadata {
+    /// Creates a new metadata with default values.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `kind` - Entry kind.
+    ///
+    /// # Returns
+    /// * `Self` - The created metadata.
+    pub fn new(path: &str, kind: EntryKind) -> Self {
+        Self {
+            path: path.to_string(),
+            kind,
+            mode: match kind {
+                EntryKind::Directory => 0o755,
+                _ => 0o644
+            },
+            uid: 0,
+            gid: 0,
+            uname: String::default(),
+            gname: String::default(),
+            size: 0,
+            mtime: 0,
+            atime: None,
+            ctime: None,
+            birthtime: None,
+            linkname: String::default(),
+            devmajor: 0,
+            devminor: 0,
+            attributes: IndexMap::new(),
+        }
+    }
+
+    /// Builds the metadata out of an entry header and its optional PAX extended header.
+    ///
+    /// # Arguments
+    /// * `header` - Entry header.
+    /// * `pax` - PAX extended header preceding the entry header.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The entry metadata.
+    /// * `Err(e)` - If the header doesn't describe an entry.
+    pub fn from_headers(header: &TarHeader, pax: Option<&PaxHeader>) -> Result<Self> {
+        let mut meta = match header {
+            TarHeader::Ustar(h) | TarHeader::Custom(CustomHeader { header: h, .. }) => Self {
+                path: join_prefix(&h.prefix, &h.name),
+                kind: u8::from(h.typeflag).into(),
+                mode: h.mode,
+                uid: h.uid as u64,
+                gid: h.gid as u64,
+                uname: h.uname.clone(),
+                gname: h.gname.clone(),
+                size: h.size,
+                mtime: h.mtime,
+                atime: None,
+                ctime: None,
+                birthtime: None,
+                linkname: h.linkname.clone(),
+                devmajor: h.devmajor,
+                devminor: h.devminor,
+                attributes: IndexMap::new(),
+            },
+            TarHeader::Gnu(h) => Self {
+                path: h.get_name().to_string(),
+                kind: match h.typeflag {
+                    GnuTypeFlag::Sparse => EntryKind::RegularFile,
+                    GnuTypeFlag::DirectoryDump => EntryKind::Directory,
+                    GnuTypeFlag::Ustar(v) => u8::from(v).into(),
+                    v => EntryKind::Other(v.into())
+                },
+                mode: h.mode,
+                uid: h.uid as u64,
+                gid: h.gid as u64,
+                uname: h.uname.clone(),
+                gname: h.gname.clone(),
+                size: match h.realsize {
+                    Some(realsize) if h.typeflag == GnuTypeFlag::Sparse => realsize,
+                    _ if h.typeflag == GnuTypeFlag::DirectoryDump => 0,
+                    _ => h.size
+                },
+                mtime: h.mtime,
+                atime: h.atime,
+                ctime: h.ctime,
+                birthtime: None,
+                linkname: h.get_linkname().to_string(),
+                devmajor: h.devmajor,
+                devminor: h.devminor,
+                attributes: IndexMap::new(),
+            },
+            TarHeader::Pax(h) => {
+                let kind = match h.typeflag {
+                    PaxTypeFlag::Ustar(v) => u8::from(v).into(),
+                    _ => bail!("PAX extended headers don't describe an entry")
+                };
+                Self {
+                    path: join_prefix(&h.prefix, &h.name),
+                    kind,
+                    mode: h.mode,
+                    uid: h.uid as u64,
+                    gid: h.gid as u64,
+                    uname: h.uname.clone(),
+                    gname: h.gname.clone(),
+                    size: h.size,
+                    mtime: h.mtime,
+                    atime: None,
+                    ctime: None,
+                    birthtime: None,
+                    linkname: h.linkname.clone(),
+                    devmajor: h.devmajor,
+                    devminor: h.devminor,
+                    attributes: IndexMap::new(),
+                }
+            },
+            TarHeader::V7(h) => Self {
+                path: h.name.clone(),
+                kind: u8::from(h.typeflag).into(),
+                mode: h.mode,
+                uid: h.uid as u64,
+                gid: h.gid as u64,
+                uname: String::default(),
+                gname: String::default(),
+                size: h.size,
+                mtime: h.mtime,
+                atime: None,
+                ctime: None,
+                birthtime: None,
+                linkname: h.linkname.clone(),
+                devmajor: 0,
+                devminor: 0,
+                attributes: IndexMap::new(),
+            },
+            TarHeader::Unknown(_, _) => bail!("unknown headers don't describe an entry")
+        };
+        if let Some(pax) = pax {
+            meta.apply_pax(pax);
+        }
+        Ok(meta)
+    }
+
+    /// Overrides the metadata fields with the values from a PAX extended header,
+    /// unmapped attributes are kept on the attributes map.
+    ///
+    /// # Arguments
+    /// * `pax` - PAX extended header.
+    pub fn apply_pax(&mut self, pax: &PaxHeader) {
+        for (key, attr) in pax.iter_attr() {
+            match key.as_str() {
+                "path" => self.path = attr.raw.clone(),
+                "linkpath" => self.linkname = attr.raw.clone(),
+                "uname" => self.uname = attr.raw.clone(),
+                "gname" => self.gname = attr.raw.clone(),
+                "uid" => if let Ok(v) = attr.raw.parse() { self.uid = v },
+                "gid" => if let Ok(v) = attr.raw.parse() { self.gid = v },
+                "size" => if let Ok(v) = attr.raw.parse() { self.size = v },
+                "mtime" => if let Some(v) = parse_time(&attr.raw) { self.mtime = v },
+                "atime" => self.atime = parse_time(&attr.raw),
+                "ctime" => self.ctime = parse_time(&attr.raw),
+                CREATIONTIME_KEY => self.birthtime = parse_time(&attr.raw),
+                _ => { self.attributes.insert(key.clone(), attr.clone()); }
+            }
+        }
+    }
+
+    /// Builds the PAX extended header required to store the values that don't
+    /// fit into the USTAR header fields.
+    ///
+    /// # Returns
+    /// * `Some(PaxHeader)` - When at least one value requires PAX.
+    /// * `None` - When all values fit into the USTAR header.
+    pub fn build_pax(&self) -> Option<PaxHeader> {
+        let mut pax = PaxHeader::new(PaxTypeFlag::Extended);
+        if split_path(&self.path).is_none() {
+            pax.set_attr_path(&self.path);
+        }
+        if self.linkname.len() > 100 {
+            pax.set_attr_linkpath(&self.linkname);
+        }
+        if self.uname.len() > 32 {
+            pax.set_attr_uname(&self.uname);
+        }
+        if self.gname.len() > 32 {
+            pax.set_attr_gname(&self.gname);
+        }
+        if self.uid > MAX_OCTAL_7 {
+            pax.set_attr_uid(self.uid);
+        }
+        if self.gid > MAX_OCTAL_7 {
+            pax.set_attr_gid(self.gid);
+        }
+        if self.size > MAX_OCTAL_11 {
+            pax.set_attr_size(self.size);
+        }
+        if self.mtime > MAX_OCTAL_11 {
+            pax.set_attr_mtime(self.mtime as f64);
+        }
+        if let Some(atime) = self.atime {
+            pax.set_attr_atime(atime as f64);
+        }
+        if let Some(ctime) = self.ctime {
+            pax.set_attr_ctime(ctime as f64);
+        }
+        if let Some(birthtime) = self.birthtime {
+            pax.set_attr(CREATIONTIME_KEY, PaxAttribute::from_u64(birthtime.to_string()));
+        }
+        for (key, attr) in self.attributes.iter() {
+            pax.set_attr(key, attr.clone());
+        }
+        if pax.iter_attr().len() < 1 {
+            return None;
+        }
+
+        // name the extended header after the entry the same way other tools do
+        let name = self.path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+        pax.name = format!("PaxHeaders/{}", name);
+        pax.mode = 0o644;
+        pax.mtime = self.mtime.min(MAX_OCTAL_11);
+        Some(pax)
+    }
+
+    /// Builds the USTAR header for this metadata, values that don't fit are left
+    /// empty since they are expected to be stored on the PAX extended header.
+    ///
+    /// # Returns
+    /// * `UstarHeader` - The entry header.
+    pub fn build_ustar(&self) -> UstarHeader {
+        let mut header = UstarHeader::new(self.kind.into());
+        match split_path(&self.path) {
+            Some((prefix, name)) => {
+                header.prefix = prefix;
+                header.name = name;
+            },
+            None => header.name = self.path.chars().take(100).collect()
+        }
+        if self.linkname.len() < 101 {
+            header.linkname = self.linkname.clone();
+        }
+        if self.uname.len() < 33 {
+            header.uname = self.uname.clone();
+        }
+        if self.gname.len() < 33 {
+            header.gname = self.gname.clone();
+        }
+        header.mode = self.mode;
+        header.uid = if self.uid > MAX_OCTAL_7 { 0 } else { self.uid as u32 };
+        header.gid = if self.gid > MAX_OCTAL_7 { 0 } else { self.gid as u32 };
+        header.size = if self.size > MAX_OCTAL_11 { 0 } else { self.size };
+        header.mtime = self.mtime.min(MAX_OCTAL_11);
+        header.devmajor = self.devmajor;
+        header.devminor = self.devminor;
+        header
+    }
+
+    /// Saves the headers describing this metadata into the writer, block padded.
+    ///
+    /// # Arguments
+    /// * `writer` - Byte writer.
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - The amount of bytes written.
+    /// * `Err(e)` - If write fails.
+    pub fn save_headers(&self, writer: &mut impl Write) -> Result<u64> {
+        let mut buf = Vec::with_capacity(1024);
+        if let Some(mut pax) = self.build_pax() {
+            pax.save(&mut buf)?;
+            let padding = buf.len() % 512;
+            if padding > 0 {
+                buf.resize(buf.len() + 512 - padding, 0);
+            }
+        }
+        self.build_ustar().save(&mut buf)?;
+        writer.write_all(&buf)?;
+        Ok(buf.len() as u64)
+    }
+}
+
+/// Ownership forced on created entries regardless of the source ownership,
+/// same as tar `--owner` and `--group`. Unset fields are left untouched.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OwnerOverride {
+    /// Forced owner user ID.
+    pub uid: Option<u64>,
+    /// Forced owner group ID.
+    pub gid: Option<u64>,
+    /// Forced owner user name.
+    pub uname: Option<String>,
+    /// Forced owner group name.
+    pub gname: Option<String>,
+}
+
+impl OwnerOverride {
+    /// Creates an override forcing `0/0/root/root` ownership.
+    pub fn root() -> Self {
+        Self {
+            uid: Some(0),
+            gid: Some(0),
+            uname: Some("root".to_string()),
+            gname: Some("root".to_string())
+        }
+    }
+
+    /// Applies the override to an entry metadata.
+    ///
+    /// # Arguments
+    /// * `meta` - The metadata to update.
+    pub fn apply(&self, meta: &mut Metadata) {
+        if let Some(uid) = self.uid {
+            meta.uid = uid;
+        }
+        if let Some(gid) = self.gid {
+            meta.gid = gid;
+        }
+        if let Some(uname) = &self.uname {
+            meta.uname = uname.clone();
+        }
+        if let Some(gname) = &self.gname {
+            meta.gname = gname.clone();
+        }
+    }
+}
+
+/// Entry description used to create entries from generated content, the
+/// path is provided apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntrySpec {
+    /// Entry kind.
+    pub kind: EntryKind,
+    /// File mode.
+    pub mode: u32,
+    /// Owner user ID.
+    pub uid: u64,
+    /// Owner group ID.
+    pub gid: u64,
+    /// Owner user name.
+    pub uname: String,
+    /// Owner group name.
+    pub gname: String,
+    /// Content size in bytes, when unknown the content is buffered in memory
+    /// to measure it.
+    pub size: Option<u64>,
+    /// Modification time (seconds since epoch).
+    pub mtime: u64,
+    /// Name of the linked file.
+    pub linkname: String,
+}
+
+impl EntrySpec {
+    /// Creates a new entry spec with default values.
+    ///
+    /// # Arguments
+    /// * `kind` - Entry kind.
+    pub fn new(kind: EntryKind) -> Self {
+        let meta = Metadata::new("", kind);
+        Self {
+            kind,
+            mode: meta.mode,
+            uid: 0,
+            gid: 0,
+            uname: String::default(),
+            gname: String::default(),
+            size: None,
+            mtime: 0,
+            linkname: String::default(),
+        }
+    }
+
+    /// Creates a regular file entry spec.
+    ///
+    /// # Arguments
+    /// * `size` - Content size in bytes if known.
+    pub fn file(size: Option<u64>) -> Self {
+        let mut spec = Self::new(EntryKind::RegularFile);
+        spec.size = size;
+        spec
+    }
+
+    /// Builds the entry metadata for a path.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `size` - Entry content size.
+    pub fn to_metadata(&self, path: &str, size: u64) -> Metadata {
+        let mut meta = Metadata::new(path, self.kind);
+        meta.mode = self.mode;
+        meta.uid = self.uid;
+        meta.gid = self.gid;
+        meta.uname = self.uname.clone();
+        meta.gname = self.gname.clone();
+        meta.size = size;
+        meta.mtime = self.mtime;
+        meta.linkname = self.linkname.clone();
+        meta
+    }
+}
+
+/// Represents an indexed archive entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Entry metadata.
+    pub meta: Metadata,
+    /// Offset of the first header block of the entry, including extended headers.
+    pub offset: u64,
+    /// Offset of the entry content.
+    pub data_offset: u64,
+    /// Content bytes stored within the archive, it differs from the metadata
+    /// size on sparse files.
+    pub stored_size: u64,
+    /// Sparse map for GNU sparse files.
+    pub sparse: Vec<SparseEntry>,
+    /// Physical location of the content within the compressed stream, only
+    /// set on compressed archives with independent frames.
+    pub physical: Option<PhysicalOffset>,
+    /// Directory members listed by GNU incremental directory entries.
+    pub dumpdir: Option<DumpDir>,
+    /// Raw path bytes when the path was decoded from a legacy codepage.
+    pub raw_path: Option<Vec<u8>>,
+}
+
+impl Entry {
+    /// Returns the offset right after the entry padded content.
+    pub fn end(&self) -> u64 {
+        self.data_offset + padded_size(self.stored_size)
+    }
+
+    /// Returns the logical content size, sparse holes included.
+    pub fn logical_size(&self) -> u64 {
+        match self.sparse.is_empty() {
+            true => self.stored_size,
+            false => self.meta.size
+        }
+    }
+}
+
+/// Rounds up a size to the next block boundary.
+///
+/// # Arguments
+/// * `size` - The size to round up.
+pub(crate) fn padded_size(size: u64) -> u64 {
+    size.div_ceil(512) * 512
+}
+
+/// Joins a USTAR prefix and name fields into a single path.
+fn join_prefix(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        return name.to_string();
+    }
+    format!("{}/{}", prefix, name)
+}
+
+/// Splits a path into USTAR prefix and name fields.
+///
+/// # Arguments
+/// * `path` - The path to split.
+///
+/// # Returns
+/// * `Some((prefix, name))` - When the path fits the USTAR fields.
+/// * `None` - When the path requires a PAX path attribute.
+fn split_path(path: &str) -> Option<(String, String)> {
+    if path.len() < 101 {
+        return Some((String::default(), path.to_string()));
+    }
+    for (index, char) in path.char_indices() {
+        if char != '/' || index > 155 {
+            continue;
+        }
+        let name = &path[index + 1..];
+        if !name.is_empty() && name.len() < 101 {
+            return Some((path[..index].to_string(), name.to_string()));
+        }
+    }
+    None
+}
+
+/// Parses a PAX timestamp, dropping the fraction of a second.
+fn parse_time(raw: &str) -> Option<u64> {
+    let secs = raw.split('.').next().unwrap_or_default();
+    secs.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_path_short() {
+        assert_eq!(Some(("".to_string(), "a/b.txt".to_string())), split_path("a/b.txt"));
+    }
+
+    #[test]
+    fn split_path_prefix() {
+        let path = format!("{}/{}", "a".repeat(120), "b".repeat(90));
+        assert_eq!(Some(("a".repeat(120), "b".repeat(90))), split_path(&path));
+    }
+
+    #[test]
+    fn split_path_too_long() {
+        assert_eq!(None, split_path(&"a".repeat(300)));
+    }
+
+    #[test]
+    fn build_pax_not_required() {
+        let meta = Metadata::new("a/b.txt", EntryKind::RegularFile);
+        assert!(meta.build_pax().is_none());
+    }
+
+    #[test]
+    fn build_pax_long_values() {
+        let mut meta = Metadata::new(&"a".repeat(300), EntryKind::RegularFile);
+        meta.uid = MAX_OCTAL_7 + 1;
+        let pax = match meta.build_pax() {
+            Some(v) => v,
+            None => {
+                assert!(false, "expected a PAX header");
+                return;
+            }
+        };
+        assert_eq!(Some(meta.path.as_str()), pax.get_attr_path());
+        assert_eq!(Some(MAX_OCTAL_7 + 1), pax.get_attr_uid());
+        let ustar = meta.build_ustar();
+        assert_eq!(0, ustar.uid);
+        assert_eq!(100, ustar.name.len());
+    }
+
+    #[test]
+    fn round_trip_headers() {
+        let mut meta = Metadata::new(&format!("dir/{}", "x".repeat(200)), EntryKind::RegularFile);
+        meta.size = 10;
+        meta.mtime = 1_600_000_000;
+        meta.birthtime = Some(1_500_000_000);
+        meta.attributes.insert("comment".to_string(), PaxAttribute::from_str("hello".to_string()));
+        let mut buf = Vec::new();
+        let written = match meta.save_headers(&mut buf) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to save headers: {}", e);
+                return;
+            }
+        };
+        assert_eq!(1536, written);
+        let mut stream = std::io::Cursor::new(buf);
+        let pax = match TarHeader::load(&mut stream).unwrap() {
+            TarHeader::Pax(h) => h,
+            _ => {
+                assert!(false, "expected a PAX header");
+                return;
+            }
+        };
+        let header = TarHeader::load(&mut stream).unwrap();
+        let loaded = Metadata::from_headers(&header, Some(&pax)).unwrap();
+        assert_eq!(meta, loaded);
+    }
+}
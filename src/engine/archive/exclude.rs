@@ -0,0 +1,318 @@
+use super::normalize_path;
+
+/// Glob pattern used to exclude entries on creation and extraction, follows
+/// GNU tar semantics: wildcards (`*`, `?`, `[...]`) match `/` too, patterns
+/// are unanchored unless they start with `/`, and a trailing `/` restricts
+/// the pattern to directories. Excluding a directory excludes its content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExcludePattern {
+    /// Glob pattern without the anchor and directory markers.
+    pattern: Vec<char>,
+    /// Only matches from the archive root.
+    anchored: bool,
+    /// Only matches directories.
+    dir_only: bool,
+}
+
+impl ExcludePattern {
+    /// Creates a new exclude pattern.
+    ///
+    /// # Arguments
+    /// * `pattern` - Glob pattern, e.g. `*.o`, `target/` or `/build`.
+    pub fn new(pattern: &str) -> Self {
+        let anchored = pattern.starts_with('/');
+        let dir_only = pattern.len() > 1 && pattern.ends_with('/');
+        let pattern = normalize_path(pattern.trim_start_matches('/'));
+        Self {
+            pattern: pattern.chars().collect(),
+            anchored,
+            dir_only
+        }
+    }
+
+    /// Tells whether the pattern matches a path, without checking its ancestors.
+    ///
+    /// # Arguments
+    /// * `path` - The path to check.
+    /// * `is_dir` - Whether the path is a directory.
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let path: Vec<char> = normalize_path(path).trim_start_matches('/').chars().collect();
+        if glob_match(&self.pattern, &path, true) {
+            return true;
+        }
+        if self.anchored {
+            return false;
+        }
+
+        // unanchored patterns may match after any directory separator
+        path.iter().enumerate()
+            .filter(|(_, c)| **c == '/')
+            .any(|(i, _)| glob_match(&self.pattern, &path[i + 1..], true))
+    }
+}
+
+/// Tells whether a path or any of its ancestor directories is excluded.
+///
+/// # Arguments
+/// * `patterns` - Exclude patterns.
+/// * `path` - The path to check.
+/// * `is_dir` - Whether the path is a directory.
+pub(crate) fn is_excluded(patterns: &[ExcludePattern], path: &str, is_dir: bool) -> bool {
+    if patterns.is_empty() {
+        return false;
+    }
+    let path = normalize_path(path);
+    if patterns.iter().any(|p| p.matches(path, is_dir)) {
+        return true;
+    }
+    path.match_indices('/')
+        .map(|(i, _)| &path[..i])
+        .filter(|v| !v.is_empty())
+        .any(|ancestor| patterns.iter().any(|p| p.matches(ancestor, true)))
+}
+
+/// Rule parsed from a gitignore-style ignore file.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct IgnoreRule {
+    /// Glob pattern without the negation, anchor and directory markers.
+    pattern: Vec<char>,
+    /// Whether the rule re-includes matching paths (`!` prefix).
+    negated: bool,
+    /// Whether the rule is relative to the ignore file directory, true when
+    /// the pattern contains a `/` other than a trailing one.
+    anchored: bool,
+    /// Only matches directories.
+    dir_only: bool,
+}
+
+impl IgnoreRule {
+    /// Parses an ignore file line.
+    ///
+    /// # Arguments
+    /// * `line` - The line to parse.
+    ///
+    /// # Returns
+    /// * `Some(Self)` - The parsed rule.
+    /// * `None` - When the line is blank or a comment.
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let mut line = line.trim_end_matches(['\r', '\n']);
+        if !line.ends_with("\\ ") {
+            line = line.trim_end();
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let negated = line.starts_with('!');
+        if negated {
+            line = &line[1..];
+        }
+        if line.starts_with("\\#") || line.starts_with("\\!") {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+        Some(Self {
+            pattern: line.chars().collect(),
+            negated,
+            anchored,
+            dir_only
+        })
+    }
+
+    /// Tells whether the rule matches a path relative to the ignore file directory.
+    ///
+    /// # Arguments
+    /// * `path` - Relative path.
+    /// * `is_dir` - Whether the path is a directory.
+    ///
+    /// # Returns
+    /// * `Some(true)` - When the path is ignored by the rule.
+    /// * `Some(false)` - When the path is re-included by the rule.
+    /// * `None` - When the rule doesn't match.
+    pub(crate) fn matches(&self, path: &str, is_dir: bool) -> Option<bool> {
+        if self.dir_only && !is_dir {
+            return None;
+        }
+        let path: Vec<char> = path.chars().collect();
+        let matched = match self.anchored {
+            true => glob_match(&self.pattern, &path, false),
+            false => {
+                let name = match path.iter().rposition(|c| *c == '/') {
+                    Some(i) => &path[i + 1..],
+                    None => &path[..]
+                };
+                glob_match(&self.pattern, name, false)
+            }
+        };
+        match matched {
+            true => Some(!self.negated),
+            false => None
+        }
+    }
+}
+
+/// Parses the content of a gitignore-style ignore file.
+///
+/// # Arguments
+/// * `content` - The ignore file content.
+pub(crate) fn parse_ignore_file(content: &str) -> Vec<IgnoreRule> {
+    content.lines().filter_map(IgnoreRule::parse).collect()
+}
+
+/// Matches a glob pattern against a text. Supports `*`, `**`, `?`, bracket
+/// expressions with ranges and negation (`[!...]` or `[^...]`) and `\` escapes.
+/// A `**/` prefix also matches zero directories.
+///
+/// # Arguments
+/// * `pattern` - Glob pattern.
+/// * `text` - Text to match.
+/// * `match_slash` - Whether single `*`, `?` and brackets match `/`, `**` always does.
+pub(crate) fn glob_match(pattern: &[char], text: &[char], match_slash: bool) -> bool {
+    let first = match pattern.first() {
+        Some(v) => *v,
+        None => return text.is_empty()
+    };
+    let slash_ok = |c: char| c != '/' || match_slash;
+    match first {
+        '*' => {
+            let double = pattern.get(1) == Some(&'*');
+            let rest = if double { &pattern[2..] } else { &pattern[1..] };
+            if double && rest.first() == Some(&'/') && glob_match(&rest[1..], text, match_slash) {
+                return true;
+            }
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..], match_slash) {
+                    return true;
+                }
+                if i < text.len() && !double && !slash_ok(text[i]) {
+                    break;
+                }
+            }
+            false
+        },
+        '?' => !text.is_empty() && slash_ok(text[0]) && glob_match(&pattern[1..], &text[1..], match_slash),
+        '[' => match match_bracket(pattern, 0, text.first().copied().unwrap_or_default()) {
+            Some((matched, next)) => !text.is_empty() && matched && slash_ok(text[0])
+                && glob_match(&pattern[next..], &text[1..], match_slash),
+            None => text.first() == Some(&'[') && glob_match(&pattern[1..], &text[1..], match_slash)
+        },
+        '\\' if pattern.len() > 1 => text.first() == Some(&pattern[1])
+            && glob_match(&pattern[2..], &text[1..], match_slash),
+        c => text.first() == Some(&c) && glob_match(&pattern[1..], &text[1..], match_slash)
+    }
+}
+
+/// Matches a char against the bracket expression starting at `start`.
+///
+/// # Returns
+/// * `Some((bool, usize))` - Whether it matched and the pattern index after the expression.
+/// * `None` - When the bracket is not closed, so it must be matched literally.
+fn match_bracket(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        let current = pattern[i];
+        if current == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|v| *v != ']') {
+            if current <= c && c <= pattern[i + 2] {
+                matched = true;
+            }
+            i += 3;
+            continue;
+        }
+        if current == c {
+            matched = true;
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, text: &str, match_slash: bool) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        glob_match(&pattern, &text, match_slash)
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob("*.o", "main.o", false));
+        assert!(!glob("*.o", "main.rs", false));
+        assert!(glob("*.o", "src/main.o", true));
+        assert!(!glob("*.o", "src/main.o", false));
+        assert!(glob("**/*.o", "src/a/main.o", false));
+        assert!(glob("**/*.o", "main.o", false));
+        assert!(glob("file?.txt", "file1.txt", false));
+        assert!(glob("file[0-9].txt", "file7.txt", false));
+        assert!(!glob("file[!0-9].txt", "file7.txt", false));
+        assert!(glob("file[!0-9].txt", "filex.txt", false));
+        assert!(glob("a\\*b", "a*b", false));
+        assert!(!glob("a\\*b", "axb", false));
+        assert!(glob("[ab", "[ab", false));
+    }
+
+    #[test]
+    fn exclude_semantics() {
+        assert!(ExcludePattern::new("*.o").matches("src/main.o", false));
+        assert!(ExcludePattern::new("target").matches("crate/target", true));
+        assert!(ExcludePattern::new("target/").matches("target", true));
+        assert!(!ExcludePattern::new("target/").matches("target", false));
+        assert!(ExcludePattern::new("/build").matches("./build/", true));
+        assert!(!ExcludePattern::new("/build").matches("src/build", true));
+        assert!(ExcludePattern::new("src/*.rs").matches("pkg/src/lib.rs", false));
+    }
+
+    #[test]
+    fn excluded_ancestors() {
+        let patterns = vec![ExcludePattern::new("target/"), ExcludePattern::new("*.o")];
+        assert!(is_excluded(&patterns, "target/debug/app", false));
+        assert!(is_excluded(&patterns, "src/a.o", false));
+        assert!(!is_excluded(&patterns, "src/main.rs", false));
+        assert!(!is_excluded(&[], "src/a.o", false));
+    }
+
+    #[test]
+    fn ignore_rules() {
+        let rules = parse_ignore_file("# comment\n\n*.log\n!keep.log\nbuild/\n/root.txt\ndocs/*.md\n\\#hash\n");
+        assert_eq!(6, rules.len());
+        let ignored = |path: &str, is_dir: bool| {
+            let mut result = false;
+            for rule in rules.iter() {
+                if let Some(v) = rule.matches(path, is_dir) {
+                    result = v;
+                }
+            }
+            result
+        };
+        assert!(ignored("a.log", false));
+        assert!(ignored("nested/a.log", false));
+        assert!(!ignored("nested/keep.log", false));
+        assert!(ignored("nested/build", true));
+        assert!(!ignored("nested/build", false));
+        assert!(ignored("root.txt", false));
+        assert!(!ignored("nested/root.txt", false));
+        assert!(ignored("docs/a.md", false));
+        assert!(!ignored("docs/nested/a.md", false));
+        assert!(ignored("#hash", false));
+    }
+}
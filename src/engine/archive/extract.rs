@@ -0,0 +1,828 @@
+use anyhow::{bail, Result};
+use std::fs::{self, File, FileTimes};
+use std::io::{Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(target_os = "linux")]
+use super::selinux::is_valid_selinux_context;
+use super::{
+    apply_transforms, is_excluded, normalize_path, Archive, DumpDir, Entry, EntryKind, ExcludePattern, FileFlags,
+    PathTransform
+};
+
+/// Umask used when the process umask can't be read.
+const DEFAULT_UMASK: u32 = 0o022;
+
+/// How the archived file modes are restored on extraction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModeMask {
+    /// Restores the exact archive modes, same as tar `-p`.
+    Exact,
+    /// Applies the process umask to the archive modes, tar default behavior.
+    ProcessUmask,
+    /// Applies an explicit mask to the archive modes.
+    Mask(u32)
+}
+
+impl ModeMask {
+    /// Applies the mask to a file mode.
+    ///
+    /// # Arguments
+    /// * `mode` - The archived file mode.
+    ///
+    /// # Returns
+    /// * `u32` - The mode to restore.
+    pub fn apply(&self, mode: u32) -> u32 {
+        let mode = mode & 0o7777;
+        match self {
+            Self::Exact => mode,
+            Self::ProcessUmask => mode & !process_umask(),
+            Self::Mask(mask) => mode & !mask
+        }
+    }
+}
+
+/// Options used to extract entries into the filesystem.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct ExtractOptions {
+    /// How file modes are restored.
+    pub mode_mask: ModeMask,
+    /// Amount of leading path components removed from each entry path, same
+    /// as tar `--strip-components`. Entries left without components are skipped.
+    pub strip_components: usize,
+    /// Rename rules applied in order to every entry path and hard link
+    /// target before stripping components.
+    pub transforms: Vec<PathTransform>,
+    /// Glob patterns of entry paths to skip, matched before transforms.
+    pub exclude: Vec<ExcludePattern>,
+    /// Deletes the files missing from the GNU incremental directory dumps,
+    /// same as tar `--incremental` on extraction.
+    pub incremental: bool,
+    /// Restores the `SCHILY.fflags` file flags of files and directories
+    /// where the platform supports them, ignored elsewhere.
+    pub restore_fflags: bool,
+    /// Restores the SELinux security contexts where the platform supports
+    /// them, ignored elsewhere.
+    pub restore_selinux: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            mode_mask: ModeMask::ProcessUmask,
+            strip_components: 0,
+            transforms: Vec::new(),
+            exclude: Vec::new(),
+            incremental: false,
+            restore_fflags: false,
+            restore_selinux: false,
+        }
+    }
+}
+
+impl<T: Read + Seek> Archive<T> {
+    /// Extracts every entry into a destination directory.
+    ///
+    /// # Arguments
+    /// * `dest` - Destination directory, created when missing.
+    /// * `options` - Extraction options.
+    ///
+    /// # Returns
+    /// * `Ok(())` - On success.
+    /// * `Err(e)` - If an entry path is unsafe or the filesystem write fails.
+    pub fn extract(&mut self, dest: &Path, options: &ExtractOptions) -> Result<()> {
+        self.extract_all(dest, options, None)
+    }
+
+    /// Extracts every entry into a destination directory, entries failing
+    /// to extract are recorded and skipped when a list is given.
+    ///
+    /// # Arguments
+    /// * `dest` - Destination directory, created when missing.
+    /// * `options` - Extraction options.
+    /// * `failed` - Collects the paths that failed to extract and their error.
+    pub(super) fn extract_all(
+        &mut self,
+        dest: &Path,
+        options: &ExtractOptions,
+        mut failed: Option<&mut Vec<(String, String)>>
+    ) -> Result<()> {
+        fs::create_dir_all(dest)?;
+        let entries: Vec<Entry> = self.entries().cloned().collect();
+        let mut dirs = Vec::new();
+        for entry in entries.iter() {
+            let target = match (self.extract_to(entry, dest, options), failed.as_deref_mut()) {
+                (Ok(v), _) => v,
+                (Err(e), Some(failed)) => {
+                    failed.push((entry.meta.path.clone(), e.to_string()));
+                    continue;
+                },
+                (Err(e), None) => return Err(e)
+            };
+            if let Some(target) = target {
+                if entry.meta.kind == EntryKind::Directory {
+                    dirs.push((target, entry));
+                }
+            }
+        }
+
+        // directory modes are applied last so read only directories can still be filled
+        for (target, entry) in dirs.iter().rev() {
+            let result = set_mode(target, options.mode_mask.apply(entry.meta.mode))
+                .and_then(|_| restore_selinux(target, entry, options))
+                .and_then(|_| restore_fflags(target, entry, options));
+            match (result, failed.as_deref_mut()) {
+                (Ok(_), _) => {},
+                (Err(e), Some(failed)) => failed.push((entry.meta.path.clone(), e.to_string())),
+                (Err(e), None) => return Err(e)
+            }
+        }
+        Ok(())
+    }
+
+    /// Extracts a single entry into a destination directory.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry to extract.
+    /// * `dest` - Destination directory, created when missing.
+    /// * `options` - Extraction options.
+    ///
+    /// # Returns
+    /// * `Ok(())` - On success.
+    /// * `Err(e)` - If the entry doesn't exists, its path is unsafe or the filesystem write fails.
+    pub fn extract_entry(&mut self, path: &str, dest: &Path, options: &ExtractOptions) -> Result<()> {
+        let entry = match self.get(path) {
+            Some(entry) => entry.clone(),
+            None => bail!("entry '{}' not found", path)
+        };
+        fs::create_dir_all(dest)?;
+        if let Some(target) = self.extract_to(&entry, dest, options)? {
+            if entry.meta.kind == EntryKind::Directory {
+                set_mode(&target, options.mode_mask.apply(entry.meta.mode))?;
+                restore_selinux(&target, &entry, options)?;
+                restore_fflags(&target, &entry, options)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Materializes an entry under the destination directory. Directory
+    /// modes are left to the caller.
+    ///
+    /// # Arguments
+    /// * `entry` - The entry to extract.
+    /// * `dest` - Destination directory.
+    /// * `options` - Extraction options.
+    ///
+    /// # Returns
+    /// * `Ok(Some(PathBuf))` - The extracted target path.
+    /// * `Ok(None)` - When the entry was skipped.
+    /// * `Err(e)` - If the entry path is unsafe or the filesystem write fails.
+    fn extract_to(&mut self, entry: &Entry, dest: &Path, options: &ExtractOptions) -> Result<Option<PathBuf>> {
+        if is_excluded(&options.exclude, &entry.meta.path, entry.meta.kind == EntryKind::Directory) {
+            return Ok(None);
+        }
+        let relative = match target_path(&entry.meta.path, options)? {
+            Some(v) => v,
+            None => return Ok(None)
+        };
+        let target = dest.join(&relative);
+        reject_symlinks(dest, &relative)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match entry.meta.kind {
+            EntryKind::Directory => {
+                if fs::symlink_metadata(&target).is_ok_and(|v| v.file_type().is_symlink()) {
+                    fs::remove_file(&target)?;
+                }
+                fs::create_dir_all(&target)?;
+                if let (true, Some(dumpdir)) = (options.incremental, &entry.dumpdir) {
+                    remove_missing(&target, dumpdir)?;
+                }
+                return Ok(Some(target));
+            },
+            EntryKind::RegularFile | EntryKind::ContiguousFile => {
+                // an existing link is replaced instead of being written through
+                remove_existing(&target)?;
+                let mut file = File::create_new(&target)?;
+                self.read_to(&entry.meta.path, &mut file)?;
+                let mut times = FileTimes::new().set_modified(UNIX_EPOCH + Duration::from_secs(entry.meta.mtime));
+                if let Some(birthtime) = entry.meta.birthtime {
+                    times = set_created(times, UNIX_EPOCH + Duration::from_secs(birthtime));
+                }
+                file.set_times(times)?;
+            },
+            EntryKind::HardLink => {
+                let source = match target_path(&entry.meta.linkname, options)? {
+                    Some(v) => {
+                        reject_symlinks(dest, &v)?;
+                        dest.join(v)
+                    },
+                    None => bail!("invalid hard link target for '{}'", entry.meta.path)
+                };
+                remove_existing(&target)?;
+                fs::hard_link(source, &target)?;
+                return Ok(Some(target));
+            },
+            EntryKind::SymbolicLink => {
+                remove_existing(&target)?;
+                symlink(&entry.meta.linkname, &target)?;
+                restore_selinux(&target, entry, options)?;
+                return Ok(Some(target));
+            },
+            _ => return Ok(None)
+        }
+        set_mode(&target, options.mode_mask.apply(entry.meta.mode))?;
+        restore_selinux(&target, entry, options)?;
+        restore_fflags(&target, entry, options)?;
+        Ok(Some(target))
+    }
+}
+
+/// Resolves the relative extraction path of an entry path by applying the
+/// transforms and stripping the leading components.
+///
+/// # Arguments
+/// * `path` - The entry path.
+/// * `options` - Extraction options.
+///
+/// # Returns
+/// * `Ok(Some(PathBuf))` - The relative path.
+/// * `Ok(None)` - When the entry should be skipped.
+/// * `Err(e)` - If the path escapes the destination.
+fn target_path(path: &str, options: &ExtractOptions) -> Result<Option<PathBuf>> {
+    let path = match apply_transforms(&options.transforms, path) {
+        Some(v) => v,
+        None => return Ok(None)
+    };
+    Ok(safe_path(&path)?.and_then(|v| strip_components(&v, options.strip_components)))
+}
+
+/// Converts an entry path into a relative filesystem path, rejecting
+/// absolute paths and parent directory components.
+///
+/// # Arguments
+/// * `path` - The entry path.
+///
+/// # Returns
+/// * `Ok(Some(PathBuf))` - The relative path.
+/// * `Ok(None)` - When the path points to the destination itself.
+/// * `Err(e)` - If the path escapes the destination.
+fn safe_path(path: &str) -> Result<Option<PathBuf>> {
+    let mut relative = PathBuf::new();
+    for component in Path::new(normalize_path(path)).components() {
+        match component {
+            Component::Normal(v) => relative.push(v),
+            Component::CurDir => {},
+            _ => bail!("unsafe entry path '{}'", path)
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(relative))
+}
+
+/// Fails when any existing parent component of a relative path is a
+/// symbolic link, so link entries extracted earlier can't redirect later
+/// writes outside of the destination.
+///
+/// # Arguments
+/// * `dest` - Destination directory.
+/// * `relative` - The relative path under the destination.
+fn reject_symlinks(dest: &Path, relative: &Path) -> Result<()> {
+    let mut current = dest.to_path_buf();
+    let parent = match relative.parent() {
+        Some(v) => v,
+        None => return Ok(())
+    };
+    for component in parent.components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                bail!("unsafe entry path '{}': '{}' is a symbolic link", relative.display(), current.display())
+            },
+            Ok(_) => {},
+            // nothing deeper exists either
+            Err(_) => break
+        }
+    }
+    Ok(())
+}
+
+/// Removes the leading components from a relative path.
+///
+/// # Arguments
+/// * `path` - The relative path.
+/// * `count` - Amount of leading components to remove.
+///
+/// # Returns
+/// * `Some(PathBuf)` - The stripped path.
+/// * `None` - When no components are left.
+fn strip_components(path: &Path, count: usize) -> Option<PathBuf> {
+    let stripped: PathBuf = path.components().skip(count).collect();
+    if stripped.as_os_str().is_empty() {
+        return None;
+    }
+    Some(stripped)
+}
+
+/// Removes an existing file or link so it can be replaced.
+fn remove_existing(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Deletes the directory children missing from its dumpdir, as GNU tar does
+/// when restoring incremental archives. Names that aren't valid UTF-8 are
+/// kept since dumpdir names always are.
+///
+/// # Arguments
+/// * `dir` - The extracted directory.
+/// * `dumpdir` - The directory members at dump time.
+fn remove_missing(dir: &Path, dumpdir: &DumpDir) -> Result<()> {
+    for child in fs::read_dir(dir)? {
+        let child = child?;
+        match child.file_name().to_str() {
+            Some(name) if !dumpdir.contains(name) => {},
+            _ => continue
+        }
+        if child.file_type()?.is_dir() {
+            fs::remove_dir_all(child.path())?;
+        } else {
+            fs::remove_file(child.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Restores the entry file flags when requested, they are applied last
+/// since immutable and append only files can't be modified afterwards.
+///
+/// # Arguments
+/// * `target` - The extracted path.
+/// * `entry` - The extracted entry.
+/// * `options` - Extraction options.
+fn restore_fflags(target: &Path, entry: &Entry, options: &ExtractOptions) -> Result<()> {
+    if !options.restore_fflags {
+        return Ok(());
+    }
+    match entry.meta.fflags() {
+        Some(flags) if !flags.is_empty() => set_fflags(target, &flags),
+        _ => Ok(())
+    }
+}
+
+/// Restores the entry SELinux security context when requested.
+///
+/// # Arguments
+/// * `target` - The extracted path.
+/// * `entry` - The extracted entry.
+/// * `options` - Extraction options.
+fn restore_selinux(target: &Path, entry: &Entry, options: &ExtractOptions) -> Result<()> {
+    if !options.restore_selinux {
+        return Ok(());
+    }
+    match entry.meta.selinux_context() {
+        Some(context) if !context.is_empty() => set_selinux(target, context),
+        _ => Ok(())
+    }
+}
+
+/// Labels the path itself by setting its `security.selinux` extended
+/// attribute, symbolic links aren't followed. Filesystems without extended
+/// attributes and callers not allowed to relabel are skipped.
+#[cfg(target_os = "linux")]
+fn set_selinux(path: &Path, context: &str) -> Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    if !is_valid_selinux_context(context) {
+        bail!("invalid SELinux context '{}' on '{}'", context.escape_default(), path.display());
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let value = CString::new(context)?;
+    let value = value.as_bytes_with_nul();
+    // SAFETY: both strings are NUL terminated and outlive the call
+    let result = unsafe {
+        libc::lsetxattr(c_path.as_ptr(), c"security.selinux".as_ptr(), value.as_ptr().cast(), value.len(), 0)
+    };
+    if result != 0 {
+        let e = std::io::Error::last_os_error();
+        if is_restore_unsupported(&e) {
+            return Ok(());
+        }
+        bail!("failed to restore SELinux context on '{}': {}", path.display(), e);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_selinux(_path: &Path, _context: &str) -> Result<()> {
+    Ok(())
+}
+
+/// Tells whether a restore error means the platform, filesystem or caller
+/// can't set the attribute, in which case restoring is skipped.
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd", target_os = "netbsd",
+    target_os = "openbsd", target_os = "dragonfly"))]
+fn is_restore_unsupported(e: &std::io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EPERM) | Some(libc::EACCES) | Some(libc::ENOTSUP) | Some(libc::EOPNOTSUPP) | Some(libc::ENOTTY)
+    )
+}
+
+/// Linux only supports the immutable, append only and no dump flags, they
+/// are added to the current inode flags through `FS_IOC_SETFLAGS`.
+#[cfg(target_os = "linux")]
+fn set_fflags(path: &Path, flags: &FileFlags) -> Result<()> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+    const FS_APPEND_FL: libc::c_int = 0x20;
+    const FS_NODUMP_FL: libc::c_int = 0x40;
+
+    let mut attrs = 0;
+    if flags.immutable() {
+        attrs |= FS_IMMUTABLE_FL;
+    }
+    if flags.append_only() {
+        attrs |= FS_APPEND_FL;
+    }
+    if flags.nodump() {
+        attrs |= FS_NODUMP_FL;
+    }
+    if attrs == 0 {
+        return Ok(());
+    }
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)?;
+    let mut current: libc::c_int = 0;
+    // SAFETY: the descriptor is open and both calls take a pointer to an int
+    let result = unsafe {
+        match libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut current) {
+            0 => {
+                current |= attrs;
+                libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &current)
+            },
+            v => v
+        }
+    };
+    if result != 0 {
+        let e = std::io::Error::last_os_error();
+        if is_restore_unsupported(&e) {
+            return Ok(());
+        }
+        bail!("failed to restore file flags on '{}': {}", path.display(), e);
+    }
+    Ok(())
+}
+
+/// BSD systems set the known flag names through `chflags(2)`, unknown
+/// names are ignored.
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd",
+    target_os = "dragonfly"))]
+fn set_fflags(path: &Path, flags: &FileFlags) -> Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let bits = flags.names().iter().fold(0, |bits, name| bits | bsd_flag(name));
+    if bits == 0 {
+        return Ok(());
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: the path is NUL terminated and outlives the call
+    if unsafe { libc::chflags(c_path.as_ptr(), bits as _) } != 0 {
+        let e = std::io::Error::last_os_error();
+        if is_restore_unsupported(&e) {
+            return Ok(());
+        }
+        bail!("failed to restore file flags on '{}': {}", path.display(), e);
+    }
+    Ok(())
+}
+
+/// Maps a BSD flag name into its `chflags(2)` bit, unknown names map to `0`.
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd",
+    target_os = "dragonfly"))]
+fn bsd_flag(name: &str) -> u32 {
+    match name {
+        "nodump" => 0x1,
+        "uchg" | "uchange" | "uimmutable" => 0x2,
+        "uappnd" | "uappend" => 0x4,
+        "opaque" => 0x8,
+        "arch" | "archived" => 0x10000,
+        "schg" | "schange" | "simmutable" => 0x20000,
+        "sappnd" | "sappend" => 0x40000,
+        _ => 0
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd", target_os = "netbsd",
+    target_os = "openbsd", target_os = "dragonfly")))]
+fn set_fflags(_path: &Path, _flags: &FileFlags) -> Result<()> {
+    Ok(())
+}
+
+/// Reads the process umask from `/proc/self/status`, falling back to the
+/// usual `022` when not available.
+fn process_umask() -> u32 {
+    let status = match fs::read_to_string("/proc/self/status") {
+        Ok(v) => v,
+        Err(_) => return DEFAULT_UMASK
+    };
+    status.lines()
+        .find_map(|line| line.strip_prefix("Umask:"))
+        .and_then(|v| u32::from_str_radix(v.trim(), 8).ok())
+        .unwrap_or(DEFAULT_UMASK)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+/// Adds the creation time to the file times on platforms that can set it,
+/// elsewhere it's ignored.
+#[cfg(target_os = "macos")]
+fn set_created(times: FileTimes, created: SystemTime) -> FileTimes {
+    use std::os::macos::fs::FileTimesExt;
+    times.set_created(created)
+}
+
+#[cfg(windows)]
+fn set_created(times: FileTimes, created: SystemTime) -> FileTimes {
+    use std::os::windows::fs::FileTimesExt;
+    times.set_created(created)
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn set_created(times: FileTimes, _created: SystemTime) -> FileTimes {
+    times
+}
+
+#[cfg(unix)]
+fn symlink(original: &str, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(original, link)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn symlink(_original: &str, link: &Path) -> Result<()> {
+    bail!("symbolic links are not supported on this platform: '{}'", link.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::Metadata;
+    use std::io::Cursor;
+
+    fn sample() -> Archive<Cursor<Vec<u8>>> {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        let mut meta = Metadata::new("dir", EntryKind::Directory);
+        meta.mode = 0o777;
+        archive.append(meta, &mut std::io::empty()).unwrap();
+        let mut meta = Metadata::new("dir/a.txt", EntryKind::RegularFile);
+        meta.mode = 0o666;
+        meta.size = 5;
+        meta.mtime = 1_600_000_000;
+        archive.append(meta, &mut Cursor::new(b"hello".to_vec())).unwrap();
+        archive
+    }
+
+    #[test]
+    fn mode_mask_apply() {
+        assert_eq!(0o4755, ModeMask::Exact.apply(0o104755));
+        assert_eq!(0o640, ModeMask::Mask(0o027).apply(0o666));
+        assert_eq!(0o644 & !process_umask(), ModeMask::ProcessUmask.apply(0o644));
+    }
+
+    #[test]
+    fn safe_path_rejects_escapes() {
+        assert_eq!(Some(PathBuf::from("a/b")), safe_path("./a/b/").unwrap());
+        assert_eq!(None, safe_path("./").unwrap());
+        assert!(safe_path("../a").is_err());
+        assert!(safe_path("a/../../b").is_err());
+        assert!(safe_path("/etc/passwd").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_with_mask() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = sample();
+        let options = ExtractOptions { mode_mask: ModeMask::Mask(0o027), ..Default::default() };
+        if let Err(e) = archive.extract(dir.path(), &options) {
+            assert!(false, "Failed to extract: {}", e);
+            return;
+        }
+        let file = dir.path().join("dir/a.txt");
+        assert_eq!(b"hello".to_vec(), fs::read(&file).unwrap());
+        assert_eq!(0o640, fs::metadata(&file).unwrap().permissions().mode() & 0o7777);
+        assert_eq!(0o750, fs::metadata(dir.path().join("dir")).unwrap().permissions().mode() & 0o7777);
+        let mtime = fs::metadata(&file).unwrap().modified().unwrap();
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(1_600_000_000), mtime);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_exact_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = sample();
+        let options = ExtractOptions { mode_mask: ModeMask::Exact, ..Default::default() };
+        archive.extract_entry("dir/a.txt", dir.path(), &options).unwrap();
+        let file = dir.path().join("dir/a.txt");
+        assert_eq!(0o666, fs::metadata(&file).unwrap().permissions().mode() & 0o7777);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_through_symlink_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        let mut meta = Metadata::new("dir", EntryKind::SymbolicLink);
+        meta.linkname = outside.path().to_str().unwrap().to_string();
+        archive.append(meta, &mut std::io::empty()).unwrap();
+        let mut meta = Metadata::new("dir/passwd", EntryKind::RegularFile);
+        meta.size = 4;
+        archive.append(meta, &mut Cursor::new(b"root".to_vec())).unwrap();
+        let mut meta = Metadata::new("link", EntryKind::HardLink);
+        meta.linkname = "dir/secret".to_string();
+        archive.append(meta, &mut std::io::empty()).unwrap();
+        fs::write(outside.path().join("secret"), b"secret").unwrap();
+
+        assert!(archive.extract(dir.path(), &ExtractOptions::default()).is_err());
+        assert!(!outside.path().join("passwd").exists());
+        let mut failed = Vec::new();
+        archive.extract_all(dir.path(), &ExtractOptions::default(), Some(&mut failed)).unwrap();
+        assert_eq!(vec!["dir/passwd", "link"], failed.iter().map(|v| v.0.as_str()).collect::<Vec<_>>());
+        assert!(!outside.path().join("passwd").exists());
+        assert!(!dir.path().join("link").exists());
+
+        // an existing symlink target is replaced, not written through
+        let dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret"), dir.path().join("a.txt")).unwrap();
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        let mut meta = Metadata::new("a.txt", EntryKind::RegularFile);
+        meta.size = 5;
+        archive.append(meta, &mut Cursor::new(b"hello".to_vec())).unwrap();
+        archive.extract(dir.path(), &ExtractOptions::default()).unwrap();
+        assert_eq!(b"secret".to_vec(), fs::read(outside.path().join("secret")).unwrap());
+        assert_eq!(b"hello".to_vec(), fs::read(dir.path().join("a.txt")).unwrap());
+        assert!(!fs::symlink_metadata(dir.path().join("a.txt")).unwrap().file_type().is_symlink());
+    }
+
+    #[test]
+    fn extract_restoring_fflags() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        let mut meta = Metadata::new("a.txt", EntryKind::RegularFile);
+        let mut flags = FileFlags::parse("nodump,--unknown");
+        flags.set_nodump(true);
+        meta.set_fflags(&flags);
+        archive.append_data("a.txt", meta, b"flags").unwrap();
+
+        // unsupported filesystems and unknown names are skipped
+        let options = ExtractOptions { restore_fflags: true, ..Default::default() };
+        if let Err(e) = archive.extract(dir.path(), &options) {
+            assert!(false, "Failed to extract: {}", e);
+            return;
+        }
+        assert_eq!(b"flags".to_vec(), fs::read(dir.path().join("a.txt")).unwrap());
+    }
+
+    #[test]
+    fn strip_leading_components() {
+        assert_eq!(Some(PathBuf::from("b/c")), strip_components(Path::new("a/b/c"), 1));
+        assert_eq!(Some(PathBuf::from("a/b/c")), strip_components(Path::new("a/b/c"), 0));
+        assert_eq!(None, strip_components(Path::new("a/b"), 2));
+    }
+
+    #[test]
+    fn extract_strip_components() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = sample();
+        let options = ExtractOptions { strip_components: 1, ..Default::default() };
+        if let Err(e) = archive.extract(dir.path(), &options) {
+            assert!(false, "Failed to extract: {}", e);
+            return;
+        }
+        assert_eq!(b"hello".to_vec(), fs::read(dir.path().join("a.txt")).unwrap());
+        assert!(!dir.path().join("dir").exists());
+    }
+
+    #[test]
+    fn extract_with_transforms() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = sample();
+        let options = ExtractOptions {
+            transforms: vec![PathTransform::parse("s/^dir/out/").unwrap()],
+            ..Default::default()
+        };
+        archive.extract(dir.path(), &options).unwrap();
+        assert_eq!(b"hello".to_vec(), fs::read(dir.path().join("out/a.txt")).unwrap());
+        assert!(!dir.path().join("dir").exists());
+
+        // transforms escaping the destination are rejected
+        let options = ExtractOptions {
+            transforms: vec![PathTransform::parse("s/^dir/../").unwrap()],
+            ..Default::default()
+        };
+        assert!(archive.extract(dir.path(), &options).is_err());
+    }
+
+    #[test]
+    fn extract_with_excludes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = sample();
+        let mut meta = Metadata::new("dir/b.o", EntryKind::RegularFile);
+        meta.size = 1;
+        archive.append(meta, &mut Cursor::new(b"o".to_vec())).unwrap();
+        let options = ExtractOptions { exclude: vec![ExcludePattern::new("*.o")], ..Default::default() };
+        archive.extract(dir.path(), &options).unwrap();
+        assert!(dir.path().join("dir/a.txt").exists());
+        assert!(!dir.path().join("dir/b.o").exists());
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = ExtractOptions { exclude: vec![ExcludePattern::new("/dir")], ..Default::default() };
+        archive.extract(dir.path(), &options).unwrap();
+        assert!(!dir.path().join("dir").exists());
+    }
+
+    #[test]
+    fn extract_incremental_dump() {
+        use crate::engine::archive::dumpdir::MAX_DUMPDIR_SIZE;
+        use crate::format::{put_field, put_octal, set_checksum};
+
+        // GNU incremental directory listing only a.txt
+        let content = b"Ya.txt\0\0";
+        let mut block = [0u8; 512];
+        put_field(&mut block[0..100], b"dir").unwrap();
+        put_octal(&mut block[100..108], 0o755).unwrap();
+        put_octal(&mut block[108..116], 0).unwrap();
+        put_octal(&mut block[116..124], 0).unwrap();
+        put_octal(&mut block[124..136], content.len() as u64).unwrap();
+        put_octal(&mut block[136..148], 0).unwrap();
+        block[156] = b'D';
+        block[257..265].copy_from_slice(b"ustar  \0");
+        set_checksum(&mut block);
+        let mut stream = block.to_vec();
+        stream.extend_from_slice(content);
+        stream.resize(2048, 0);
+        let mut archive = Archive::open(Cursor::new(stream)).unwrap();
+        let mut meta = Metadata::new("dir/a.txt", EntryKind::RegularFile);
+        meta.size = 5;
+        archive.append(meta, &mut Cursor::new(b"hello".to_vec())).unwrap();
+        let entry = archive.get("dir").unwrap();
+        assert_eq!(EntryKind::Directory, entry.meta.kind);
+        assert!(entry.dumpdir.as_ref().unwrap().contains("a.txt"));
+
+        // stale files are only removed on incremental extractions
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("dir/old")).unwrap();
+        fs::write(dir.path().join("dir/old.txt"), b"old").unwrap();
+        archive.extract(dir.path(), &ExtractOptions::default()).unwrap();
+        assert!(dir.path().join("dir/old.txt").exists());
+        let options = ExtractOptions { incremental: true, ..Default::default() };
+        if let Err(e) = archive.extract(dir.path(), &options) {
+            assert!(false, "Failed to extract: {}", e);
+            return;
+        }
+        assert!(!dir.path().join("dir/old.txt").exists());
+        assert!(!dir.path().join("dir/old").exists());
+        assert_eq!(b"hello".to_vec(), fs::read(dir.path().join("dir/a.txt")).unwrap());
+
+        // oversized listings aren't loaded
+        put_octal(&mut block[124..136], MAX_DUMPDIR_SIZE + 1).unwrap();
+        set_checksum(&mut block);
+        let mut stream = block.to_vec();
+        stream.resize(2048, 0);
+        match Archive::open(Cursor::new(stream)) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(_) => {}
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+
+use super::Metadata;
+use crate::engine::header::PaxAttribute;
+
+/// PAX key holding the BSD style file flags.
+pub const FFLAGS_KEY: &str = "SCHILY.fflags";
+
+/// Aliases of the immutable flag, the first one is used when setting it.
+const IMMUTABLE: [&str; 6] = ["schg", "uchg", "simmutable", "uimmutable", "schange", "uchange"];
+
+/// Aliases of the append only flag, the first one is used when setting it.
+const APPEND_ONLY: [&str; 4] = ["sappnd", "uappnd", "sappend", "uappend"];
+
+/// Name of the no dump flag.
+const NODUMP: &str = "nodump";
+
+/// File flags as stored on the `SCHILY.fflags` PAX key: comma separated
+/// BSD flag names. Unknown names are kept so they round trip untouched.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileFlags {
+    /// Flag names in archive order.
+    names: Vec<String>,
+}
+
+impl FileFlags {
+    /// Parses a comma separated list of flag names.
+    ///
+    /// # Arguments
+    /// * `value` - The `SCHILY.fflags` value.
+    pub fn parse(value: &str) -> Self {
+        let names = value.split(',')
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_string())
+            .collect();
+        Self { names }
+    }
+
+    /// Gets the flag names.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Tells whether no flag is set.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Tells whether the file can't be modified, renamed nor deleted.
+    pub fn immutable(&self) -> bool {
+        self.any(&IMMUTABLE)
+    }
+
+    /// Tells whether the file can only be appended to.
+    pub fn append_only(&self) -> bool {
+        self.any(&APPEND_ONLY)
+    }
+
+    /// Tells whether the file should be skipped by dumps.
+    pub fn nodump(&self) -> bool {
+        self.any(&[NODUMP])
+    }
+
+    /// Sets or clears the immutable flag.
+    ///
+    /// # Arguments
+    /// * `value` - Whether the flag is set.
+    pub fn set_immutable(&mut self, value: bool) {
+        self.set(&IMMUTABLE, value);
+    }
+
+    /// Sets or clears the append only flag.
+    ///
+    /// # Arguments
+    /// * `value` - Whether the flag is set.
+    pub fn set_append_only(&mut self, value: bool) {
+        self.set(&APPEND_ONLY, value);
+    }
+
+    /// Sets or clears the no dump flag.
+    ///
+    /// # Arguments
+    /// * `value` - Whether the flag is set.
+    pub fn set_nodump(&mut self, value: bool) {
+        self.set(&[NODUMP], value);
+    }
+
+    /// Tells whether any of the aliases is set.
+    fn any(&self, aliases: &[&str]) -> bool {
+        self.names.iter().any(|name| aliases.contains(&name.as_str()))
+    }
+
+    /// Sets a flag by its first alias or clears all of its aliases.
+    fn set(&mut self, aliases: &[&str], value: bool) {
+        if !value {
+            self.names.retain(|name| !aliases.contains(&name.as_str()));
+        } else if !self.any(aliases) {
+            self.names.push(aliases[0].to_string());
+        }
+    }
+}
+
+impl fmt::Display for FileFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.names.join(","))
+    }
+}
+
+impl Metadata {
+    /// Gets the file flags stored on the `SCHILY.fflags` attribute.
+    ///
+    /// # Returns
+    /// * `Some(FileFlags)` - The file flags.
+    /// * `None` - When the entry has no file flags.
+    pub fn fflags(&self) -> Option<FileFlags> {
+        self.attributes.get(FFLAGS_KEY).map(|attr| FileFlags::parse(&attr.raw))
+    }
+
+    /// Sets the file flags emitted as the `SCHILY.fflags` attribute, empty
+    /// flags remove the attribute.
+    ///
+    /// # Arguments
+    /// * `flags` - The file flags.
+    pub fn set_fflags(&mut self, flags: &FileFlags) {
+        if flags.is_empty() {
+            self.attributes.shift_remove(FFLAGS_KEY);
+            return;
+        }
+        self.attributes.insert(FFLAGS_KEY.to_string(), PaxAttribute::from_str(flags.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::{Archive, EntryKind};
+    use std::io::Cursor;
+
+    #[test]
+    fn parse_and_set() {
+        let mut flags = FileFlags::parse("uchg, nodump,hidden");
+        assert!(flags.immutable());
+        assert!(flags.nodump());
+        assert!(!flags.append_only());
+        flags.set_immutable(false);
+        flags.set_append_only(true);
+        flags.set_append_only(true);
+        assert_eq!("nodump,hidden,sappnd", flags.to_string());
+        assert!(FileFlags::parse(" , ").is_empty());
+    }
+
+    #[test]
+    fn metadata_roundtrip() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        let mut meta = Metadata::new("a.txt", EntryKind::RegularFile);
+        let mut flags = FileFlags::default();
+        flags.set_immutable(true);
+        flags.set_nodump(true);
+        meta.set_fflags(&flags);
+        archive.append_data("a.txt", meta, b"flags").unwrap();
+
+        let archive = Archive::open(archive.into_inner()).unwrap();
+        match archive.get("a.txt").and_then(|entry| entry.meta.fflags()) {
+            Some(v) => assert_eq!(flags, v),
+            None => assert!(false, "expected file flags")
+        }
+
+        let mut meta = Metadata::new("b.txt", EntryKind::RegularFile);
+        meta.set_fflags(&flags);
+        meta.set_fflags(&FileFlags::default());
+        assert_eq!(None, meta.fflags());
+    }
+}
@@ -0,0 +1,487 @@
+use anyhow::{bail, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::Archive;
+use crate::engine::DEFAULT_BUFFER_SIZE;
+use crate::engine::error::Error;
+use crate::engine::header::{PaxAttribute, PaxHeader, PaxTypeFlag, TarHeader};
+use crate::format::{put_record, RawHeader, BLOCK_SIZE};
+
+/// PAX key holding the archive comment.
+pub const COMMENT_KEY: &str = "comment";
+
+/// Prefix of the vendor PAX keys accepted as archive metadata, other keys
+/// would change how readers extract every entry.
+pub const METADATA_PREFIX: &str = "RTAR.";
+
+/// Name given to the global headers written by the archive, same as GNU tar.
+const GLOBAL_HEADER_NAME: &str = "pax_global_header";
+
+/// Name given to the global headers filling the space of removed entries,
+/// they are skipped when the archive is scanned.
+pub(super) const PADDING_HEADER_NAME: &str = "pax_padding_header";
+
+/// Largest content of a padding header, readers such as libarchive reject
+/// bigger extended headers.
+const MAX_PADDING_SIZE: u64 = 1024 * 1024;
+
+/// PAX global header found within the archive, its attributes apply to
+/// every entry after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalHeader {
+    /// Offset of the header block.
+    pub offset: u64,
+    /// Bytes used by the header and its records, block padded.
+    pub len: u64,
+    /// The global header.
+    pub header: PaxHeader,
+}
+
+impl GlobalHeader {
+    /// Returns the offset right after the header records.
+    pub fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+}
+
+impl<T: Read + Seek> Archive<T> {
+    /// Gets the archive level metadata: the global header leading the
+    /// archive, other global headers are ignored.
+    pub fn archive_header(&self) -> Option<&PaxHeader> {
+        self.globals.first().filter(|global| global.offset == 0).map(|global| &global.header)
+    }
+
+    /// Gets the archive comment.
+    pub fn comment(&self) -> Option<&str> {
+        self.archive_metadata(COMMENT_KEY)
+    }
+
+    /// Gets an archive level metadata value.
+    ///
+    /// # Arguments
+    /// * `key` - The metadata key.
+    pub fn archive_metadata(&self, key: &str) -> Option<&str> {
+        self.archive_header().and_then(|header| header.get_attr(key)).map(|attr| attr.raw.as_str())
+    }
+
+    /// Gets the PAX global headers in archive order.
+    pub fn global_headers(&self) -> &[GlobalHeader] {
+        &self.globals
+    }
+
+    /// Reads the archive level metadata straight from the first header,
+    /// without opening the archive nor scanning its entries.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to read the archive from.
+    ///
+    /// # Returns
+    /// * `Ok(Some(PaxHeader))` - The global header leading the archive.
+    /// * `Ok(None)` - When the archive doesn't start with a global header.
+    /// * `Err(e)` - If the header could not be read or parsed.
+    pub fn read_archive_header(stream: &mut T) -> Result<Option<PaxHeader>> {
+        stream.seek(SeekFrom::Start(0))?;
+        match TarHeader::load(stream)? {
+            TarHeader::Pax(h) if h.is_global() => Ok(Some(h)),
+            _ => Ok(None)
+        }
+    }
+
+    /// Returns the offset right after the last global header.
+    pub(crate) fn globals_end(&self) -> u64 {
+        self.globals.iter().map(|global| global.end()).max().unwrap_or(0)
+    }
+}
+
+impl<T: Read + Write + Seek> Archive<T> {
+    /// Sets the archive comment.
+    ///
+    /// # Arguments
+    /// * `comment` - The comment, `None` removes it.
+    ///
+    /// # Returns
+    /// * `Ok(())` - On success.
+    /// * `Err(e)` - If read or write fails.
+    pub fn set_comment(&mut self, comment: Option<&str>) -> Result<()> {
+        self.set_archive_metadata(COMMENT_KEY, comment)
+    }
+
+    /// Sets an archive level metadata value, stored on a global header
+    /// leading the archive. The archive content is shifted when the header
+    /// grows past its blocks, and the header is dropped once it's empty.
+    /// Pinned entries aren't shifted, a header can't grow in front of them.
+    ///
+    /// # Arguments
+    /// * `key` - The metadata key, either `comment` or a key under `RTAR.`.
+    /// * `value` - The metadata value, `None` removes it.
+    ///
+    /// # Returns
+    /// * `Ok(())` - On success.
+    /// * `Err(e)` - If the key isn't allowed or read or write fails.
+    pub fn set_archive_metadata(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        let vendor = key.strip_prefix(METADATA_PREFIX).is_some_and(|v| !v.is_empty());
+        if key != COMMENT_KEY && !vendor {
+            bail!("archive metadata key '{}' must be '{}' or start with '{}'", key, COMMENT_KEY, METADATA_PREFIX);
+        }
+        let (mut header, old_len) = match self.globals.first() {
+            Some(global) if global.offset == 0 => (global.header.clone(), global.len),
+            _ => {
+                let mut header = PaxHeader::new(PaxTypeFlag::Global);
+                header.name = GLOBAL_HEADER_NAME.to_string();
+                header.mode = 0o644;
+                (header, 0)
+            }
+        };
+        match value {
+            Some(v) => header.set_attr(key, PaxAttribute::from_str(v.to_string())),
+            None => {
+                header.remove_attr(key);
+            }
+        }
+
+        // an empty header is dropped entirely
+        if header.iter_attr().len() < 1 {
+            if old_len > 0 {
+                self.replace_region(0, old_len, &[])?;
+                self.globals.remove(0);
+            }
+            return Ok(());
+        }
+        let buf = encode_global(&mut header)?;
+        self.replace_region(0, old_len, &buf)?;
+        let global = GlobalHeader { offset: 0, len: buf.len() as u64, header };
+        if old_len > 0 {
+            self.globals[0] = global;
+        } else {
+            self.globals.insert(0, global);
+        }
+        Ok(())
+    }
+
+    /// Replaces a PAX global header rewriting the archive, the following
+    /// content is shifted when the header size changes.
+    ///
+    /// # Arguments
+    /// * `index` - Position of the header within the global headers.
+    /// * `header` - The new header, it's saved as a global header regardless of its type flag.
+    ///
+    /// # Returns
+    /// * `Ok(())` - On success.
+    /// * `Err(e)` - If the index is out of bounds, read or write fails.
+    pub fn set_global_header(&mut self, index: usize, mut header: PaxHeader) -> Result<()> {
+        let (offset, old_len) = match self.globals.get(index) {
+            Some(global) => (global.offset, global.len),
+            None => bail!(Error::OutOfBounds(index))
+        };
+        header.typeflag = PaxTypeFlag::Global;
+        let buf = encode_global(&mut header)?;
+        self.replace_region(offset, old_len, &buf)?;
+        self.globals[index] = GlobalHeader { offset, len: buf.len() as u64, header };
+        Ok(())
+    }
+
+    /// Removes a PAX global header rewriting the archive, the following
+    /// content is shifted back over it.
+    ///
+    /// # Arguments
+    /// * `index` - Position of the header within the global headers.
+    ///
+    /// # Returns
+    /// * `Ok(GlobalHeader)` - The removed header.
+    /// * `Err(e)` - If the index is out of bounds, read or write fails.
+    pub fn remove_global_header(&mut self, index: usize) -> Result<GlobalHeader> {
+        let (offset, len) = match self.globals.get(index) {
+            Some(global) => (global.offset, global.len),
+            None => bail!(Error::OutOfBounds(index))
+        };
+        self.replace_region(offset, len, &[])?;
+        Ok(self.globals.remove(index))
+    }
+
+    /// Overwrites a region with global headers holding a single `comment`
+    /// record, readers ignore them so the entries after the region are still
+    /// reachable by other tools, unlike zero blocks that read as the end of
+    /// the archive.
+    ///
+    /// # Arguments
+    /// * `offset` - Region start.
+    /// * `len` - Region length, block aligned.
+    ///
+    /// # Returns
+    /// * `Ok(())` - On success.
+    /// * `Err(e)` - If the region isn't block aligned or write fails.
+    pub(crate) fn write_padding(&mut self, offset: u64, len: u64) -> Result<()> {
+        self.touch(offset);
+        pad_region(&mut self.stream, offset, len)
+    }
+
+    /// Replaces a region of the archive with new content, shifting the
+    /// following entries and headers when the size changes. When pinned
+    /// entries follow the region a shrunk region is padded instead and a
+    /// grown one is refused.
+    ///
+    /// # Arguments
+    /// * `offset` - Region start.
+    /// * `old_len` - Region length, block aligned.
+    /// * `content` - New region content, block aligned.
+    pub(crate) fn replace_region(&mut self, offset: u64, old_len: u64, content: &[u8]) -> Result<()> {
+        let new_len = content.len() as u64;
+        let tail = offset + old_len;
+        let pinned = self.entries.values().any(|entry| entry.offset >= tail && self.pinned.contains(&entry.meta.path));
+        if pinned && new_len > old_len {
+            bail!("can't grow the region at offset {}, pinned entries follow it", offset);
+        }
+        self.touch(offset);
+        if pinned && new_len < old_len {
+            self.write_padding(offset + new_len, old_len - new_len)?;
+        } else if new_len != old_len {
+            let tail_len = (self.end + 1024).saturating_sub(tail);
+            move_bytes(&mut self.stream, tail, offset + new_len, tail_len)?;
+
+            // the vacated space is zeroed so it isn't mistaken for entries
+            if new_len < old_len {
+                self.zero_fill(offset + new_len + tail_len, old_len - new_len)?;
+            }
+            let shift = |v: &mut u64| if *v >= tail { *v = *v + new_len - old_len };
+            for entry in self.entries.values_mut() {
+                shift(&mut entry.offset);
+                shift(&mut entry.data_offset);
+            }
+            for global in self.globals.iter_mut() {
+                shift(&mut global.offset);
+            }
+            self.end = self.end + new_len - old_len;
+        }
+        self.stream.seek(SeekFrom::Start(offset))?;
+        self.stream.write_all(content)?;
+        self.write_end()?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+/// Overwrites a region of a stream with padding global headers, the scan
+/// skips them as if the region was never there.
+///
+/// # Arguments
+/// * `stream` - The stream.
+/// * `offset` - Region start.
+/// * `len` - Region length, block aligned.
+pub(crate) fn pad_region(stream: &mut (impl Write + Seek), offset: u64, len: u64) -> Result<()> {
+    let block = BLOCK_SIZE as u64;
+    if len % block != 0 {
+        bail!("can't pad {} bytes at offset {}, the region isn't block aligned", len, offset);
+    }
+    stream.seek(SeekFrom::Start(offset))?;
+    let mut remaining = len;
+    while remaining > 0 {
+        let size = (remaining - block).min(MAX_PADDING_SIZE);
+        stream.write_all(&encode_padding(size)?)?;
+        remaining -= block + size;
+    }
+    Ok(())
+}
+
+/// Encodes a global header with its records, block padded.
+///
+/// # Arguments
+/// * `header` - The global header.
+fn encode_global(header: &mut PaxHeader) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    header.save(&mut buf)?;
+    buf.resize(buf.len().div_ceil(512) * 512, 0);
+    Ok(buf)
+}
+
+/// Encodes a padding global header whose single `comment` record fills its
+/// content exactly.
+///
+/// # Arguments
+/// * `size` - Content size, block aligned.
+fn encode_padding(size: u64) -> Result<Vec<u8>> {
+    let header = RawHeader {
+        name: PADDING_HEADER_NAME.as_bytes().to_vec(),
+        mode: 0o644,
+        size,
+        typeflag: b'g',
+        magic: *b"ustar\0",
+        version: *b"00",
+        ..RawHeader::default()
+    };
+    let mut buf = header.encode()?.to_vec();
+    if size > 0 {
+        // "<len> comment=<value>\n" where the length counts its own digits
+        let size = size as usize;
+        let value_len = size - size.to_string().len() - COMMENT_KEY.len() - 3;
+        put_record(&mut buf, COMMENT_KEY.as_bytes(), &vec![b' '; value_len]);
+    }
+    Ok(buf)
+}
+
+/// Moves a region of a stream to another offset, the regions may overlap.
+/// Bytes past the stream end are read as zeroes.
+///
+/// # Arguments
+/// * `stream` - The stream.
+/// * `from` - Region start.
+/// * `to` - Destination offset.
+/// * `len` - Region length.
+pub(super) fn move_bytes(stream: &mut (impl Read + Write + Seek), from: u64, to: u64, len: u64) -> Result<()> {
+    let mut buf = vec![0u8; DEFAULT_BUFFER_SIZE];
+    let mut moved = 0;
+    while moved < len {
+        let n = (len - moved).min(buf.len() as u64);
+
+        // copy from the back when moving forward so the source isn't overwritten first
+        let start = if to > from { len - moved - n } else { moved };
+        let chunk = &mut buf[..n as usize];
+        chunk.fill(0);
+        stream.seek(SeekFrom::Start(from + start))?;
+        let mut read = 0;
+        while read < chunk.len() {
+            let count = stream.read(&mut chunk[read..])?;
+            if count < 1 {
+                break;
+            }
+            read += count;
+        }
+        stream.seek(SeekFrom::Start(to + start))?;
+        stream.write_all(chunk)?;
+        moved += n;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::{EntryKind, Metadata};
+    use std::io::Cursor;
+
+    fn global(key: &str, value: &str) -> PaxHeader {
+        let mut header = PaxHeader::new(PaxTypeFlag::Global);
+        header.name = GLOBAL_HEADER_NAME.to_string();
+        header.set_attr(key, PaxAttribute::from_str(value.to_string()));
+        header
+    }
+
+    fn add_raw_file(buf: &mut Vec<u8>, path: &str, content: &[u8]) {
+        let mut meta = Metadata::new(path, EntryKind::RegularFile);
+        meta.size = content.len() as u64;
+        meta.save_headers(buf).unwrap();
+        buf.extend_from_slice(content);
+        buf.resize(buf.len().div_ceil(512) * 512, 0);
+    }
+
+    #[test]
+    fn edit_global_headers() {
+        let mut buf = Vec::new();
+        add_raw_file(&mut buf, "a.txt", b"first");
+        buf.extend_from_slice(&encode_global(&mut global("charset", "BINARY")).unwrap());
+        add_raw_file(&mut buf, "b.txt", b"second");
+        buf.extend_from_slice(&encode_global(&mut global("comment", "tail")).unwrap());
+        buf.resize(buf.len() + 1024, 0);
+
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        assert_eq!(2, archive.len());
+        assert_eq!(2, archive.global_headers().len());
+        assert_eq!(1024, archive.global_headers()[0].offset);
+        assert!(archive.archive_header().is_none());
+
+        // growing the first header shifts the second entry and header
+        match archive.set_global_header(0, global("charset", &"x".repeat(700))) {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to set global header: {}", e);
+                return;
+            }
+        }
+        assert_eq!(1536, archive.global_headers()[0].len);
+        assert_eq!(archive.get("b.txt").unwrap().end(), archive.global_headers()[1].offset);
+        let removed = archive.remove_global_header(1).unwrap();
+        assert_eq!("tail", removed.header.get_attr("comment").unwrap().raw);
+        assert!(archive.remove_global_header(1).is_err());
+
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(1, archive.global_headers().len());
+        assert_eq!("x".repeat(700), archive.global_headers()[0].header.get_attr("charset").unwrap().raw);
+        let mut out = Vec::new();
+        archive.read_to("b.txt", &mut out).unwrap();
+        assert_eq!(b"second".to_vec(), out);
+    }
+
+    #[test]
+    fn archive_metadata_keeps_pinned() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.set_comment(Some(&"x".repeat(600))).unwrap();
+        archive.append_data("a.txt", Metadata::new("a.txt", EntryKind::RegularFile), b"first").unwrap();
+        archive.pin("a.txt").unwrap();
+        let offset = archive.get("a.txt").unwrap().offset;
+
+        // a grown header would shift the pinned entry
+        match archive.set_comment(Some(&"x".repeat(1200))) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(_) => {}
+        }
+
+        // a shrunk or dropped header is padded instead
+        match archive.set_comment(Some("short")) {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to set comment: {}", e);
+                return;
+            }
+        }
+        assert_eq!(offset, archive.get("a.txt").unwrap().offset);
+        archive.set_comment(None).unwrap();
+        assert_eq!(offset, archive.get("a.txt").unwrap().offset);
+
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert_eq!(None, archive.comment());
+        assert_eq!(offset, archive.get("a.txt").unwrap().offset);
+        let mut out = Vec::new();
+        archive.read_to("a.txt", &mut out).unwrap();
+        assert_eq!(b"first".to_vec(), out);
+    }
+
+    #[test]
+    fn archive_comment() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.append_data("a.txt", Metadata::new("a.txt", EntryKind::RegularFile), b"first").unwrap();
+        assert_eq!(None, archive.comment());
+        match archive.set_comment(Some("nightly build")) {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to set comment: {}", e);
+                return;
+            }
+        }
+        archive.set_archive_metadata("RTAR.build", Some(&"x".repeat(600))).unwrap();
+        for key in ["path", "size", "GNU.sparse.size", "RTAR."] {
+            match archive.set_archive_metadata(key, Some("1")) {
+                Ok(_) => assert!(false, "expected error but got success"),
+                Err(_) => {}
+            }
+        }
+        archive.append_data("b.txt", Metadata::new("b.txt", EntryKind::RegularFile), b"second").unwrap();
+
+        let mut stream = archive.into_inner();
+        let header = Archive::read_archive_header(&mut stream).unwrap().unwrap();
+        assert_eq!("nightly build", header.get_attr(COMMENT_KEY).unwrap().raw);
+        let mut archive = Archive::open(stream).unwrap();
+        assert_eq!(Some("nightly build"), archive.comment());
+        assert_eq!(2, archive.len());
+        let mut out = Vec::new();
+        archive.read_to("a.txt", &mut out).unwrap();
+        assert_eq!(b"first".to_vec(), out);
+
+        // removing every value drops the header
+        archive.set_comment(None).unwrap();
+        archive.set_archive_metadata("RTAR.build", None).unwrap();
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert!(archive.archive_header().is_none());
+        let mut out = Vec::new();
+        archive.read_to("b.txt", &mut out).unwrap();
+        assert_eq!(b"second".to_vec(), out);
+    }
+}
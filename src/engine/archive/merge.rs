@@ -0,0 +1,130 @@
+use anyhow::{bail, Result};
+use std::io::{Read, Seek, Write};
+
+use super::{normalize_path, Archive, Entry};
+
+/// Conflict resolution used when both archives contain the same path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum ConflictPolicy {
+    /// Keeps the entry with the newest modification time, left wins on ties.
+    NewerWins,
+    /// Always keeps the left (target) archive entry.
+    LeftWins,
+    /// Fails the merge without modifying the target archive.
+    Error
+}
+
+impl<T: Read + Write + Seek> Archive<T> {
+    /// Merges the entries of another archive into this one.
+    ///
+    /// # Arguments
+    /// * `other` - The archive to merge entries from.
+    /// * `policy` - Conflict resolution for paths present on both archives.
+    ///
+    /// # Returns
+    /// * `Ok(())` - On success.
+    /// * `Err(e)` - On conflict when using `ConflictPolicy::Error`, or if read/write fails.
+    pub fn merge<O: Read + Seek>(&mut self, other: &mut Archive<O>, policy: ConflictPolicy) -> Result<()> {
+        let entries: Vec<Entry> = other.entries().cloned().collect();
+
+        // validate conflicts up front so an error leaves the target untouched
+        if policy == ConflictPolicy::Error {
+            for entry in entries.iter() {
+                if self.find_normalized(&entry.meta.path).is_some() {
+                    bail!("conflicting entry '{}'", entry.meta.path);
+                }
+            }
+        }
+
+        for entry in entries {
+            if let Some(existing) = self.find_normalized(&entry.meta.path) {
+                let keep_left = match policy {
+                    ConflictPolicy::NewerWins => self.entries[&existing].meta.mtime >= entry.meta.mtime,
+                    ConflictPolicy::LeftWins => true,
+                    ConflictPolicy::Error => bail!("conflicting entry '{}'", entry.meta.path)
+                };
+                if keep_left {
+                    continue;
+                }
+                self.remove(&existing)?;
+            }
+            let mut reader = other.entry_reader(&entry.meta.path)?;
+            self.append(entry.meta, &mut reader)?;
+        }
+        Ok(())
+    }
+
+    /// Finds the key of an entry whose normalized path matches the given path.
+    ///
+    /// # Arguments
+    /// * `path` - The path to look for.
+    fn find_normalized(&self, path: &str) -> Option<String> {
+        let path = normalize_path(path);
+        self.entries.keys().find(|k| normalize_path(k) == path).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::{EntryKind, Metadata};
+    use std::io::Cursor;
+
+    fn add_file(archive: &mut Archive<Cursor<Vec<u8>>>, path: &str, mtime: u64, content: &[u8]) {
+        let mut meta = Metadata::new(path, EntryKind::RegularFile);
+        meta.size = content.len() as u64;
+        meta.mtime = mtime;
+        archive.append(meta, &mut Cursor::new(content.to_vec())).unwrap();
+    }
+
+    fn read_file(archive: &mut Archive<Cursor<Vec<u8>>>, path: &str) -> Vec<u8> {
+        let mut buf = Vec::new();
+        archive.entry_reader(path).unwrap().read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    fn sample() -> (Archive<Cursor<Vec<u8>>>, Archive<Cursor<Vec<u8>>>) {
+        let mut left = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut left, "a.txt", 10, b"left a");
+        add_file(&mut left, "b.txt", 30, b"left b");
+        let mut right = Archive::open(Cursor::new(Vec::new())).unwrap();
+        add_file(&mut right, "./a.txt", 20, b"right a");
+        add_file(&mut right, "b.txt", 20, b"right b");
+        add_file(&mut right, "c.txt", 20, b"right c");
+        (left, right)
+    }
+
+    #[test]
+    fn merge_newer_wins() {
+        let (mut left, mut right) = sample();
+        if let Err(e) = left.merge(&mut right, ConflictPolicy::NewerWins) {
+            assert!(false, "Failed to merge: {}", e);
+            return;
+        }
+        assert_eq!(3, left.len());
+        assert_eq!(b"right a".to_vec(), read_file(&mut left, "./a.txt"));
+        assert_eq!(b"left b".to_vec(), read_file(&mut left, "b.txt"));
+        assert_eq!(b"right c".to_vec(), read_file(&mut left, "c.txt"));
+    }
+
+    #[test]
+    fn merge_left_wins() {
+        let (mut left, mut right) = sample();
+        left.merge(&mut right, ConflictPolicy::LeftWins).unwrap();
+        assert_eq!(3, left.len());
+        assert_eq!(b"left a".to_vec(), read_file(&mut left, "a.txt"));
+        assert_eq!(b"left b".to_vec(), read_file(&mut left, "b.txt"));
+        assert_eq!(b"right c".to_vec(), read_file(&mut left, "c.txt"));
+    }
+
+    #[test]
+    fn merge_error() {
+        let (mut left, mut right) = sample();
+        match left.merge(&mut right, ConflictPolicy::Error) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(e.to_string(), "conflicting entry './a.txt'")
+        }
+        assert_eq!(2, left.len());
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::{bail, Result};
+use std::io::{Read, Seek, SeekFrom};
+
+use super::{Archive, Embedded, EntryKind};
+use crate::engine::header::PosixViolation;
+use crate::engine::header::validate::validate_block;
+
+/// Separator between an archive entry path and a path within it.
+pub const NESTED_SEPARATOR: &str = "!/";
+
+/// Deepest level of nested archives opened or listed, crafted archives
+/// could otherwise nest deep enough to overflow the stack.
+pub const MAX_NESTED_DEPTH: usize = 16;
+
+impl<T: Read + Seek> Archive<T> {
+    /// Tells whether an entry content is itself an uncompressed TAR
+    /// archive, sniffing its first header magic and checksum.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry to check.
+    ///
+    /// # Returns
+    /// * `Ok(bool)` - Whether the entry is a nested archive.
+    /// * `Err(e)` - If the entry doesn't exists or the stream can't be read.
+    pub fn is_nested(&mut self, path: &str) -> Result<bool> {
+        let entry = match self.entries.get(path) {
+            Some(v) => v,
+            None => bail!("entry '{}' not found", path)
+        };
+        if entry.meta.kind != EntryKind::RegularFile || !entry.sparse.is_empty() || entry.stored_size < 512 {
+            return Ok(false);
+        }
+        let data_offset = entry.data_offset;
+        sniff_tar(&mut self.stream, data_offset)
+    }
+
+    /// Opens a nested archive over the entry content in place, chained
+    /// paths like `outer.tar!/inner.tar` open archives nested deeper.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the nested archive.
+    ///
+    /// # Returns
+    /// * `Ok(Archive<Embedded<&mut T>>)` - The child archive borrowing this archive stream.
+    /// * `Err(e)` - If any of the entries doesn't exists, isn't an archive or can't be read, or the path nests too deep.
+    pub fn open_nested(&mut self, path: &str) -> Result<Archive<Embedded<&mut T>>> {
+        if path.matches(NESTED_SEPARATOR).count() >= MAX_NESTED_DEPTH {
+            bail!("nested path '{}' is deeper than {} levels", path, MAX_NESTED_DEPTH);
+        }
+        let mut parts = path.split(NESTED_SEPARATOR);
+        let first = parts.next().unwrap_or_default();
+        if !self.is_nested(first)? {
+            bail!("entry '{}' is not a TAR archive", first);
+        }
+        let (mut base, mut len) = (self.entries[first].data_offset, self.entries[first].stored_size);
+        let mut opened = first.to_string();
+
+        // every level is opened over the root stream so the types don't nest
+        for part in parts {
+            let mut child = Archive::open(Embedded::new(&mut self.stream, base, len)?)?;
+            if !child.is_nested(part)? {
+                bail!("entry '{}{}{}' is not a TAR archive", opened, NESTED_SEPARATOR, part);
+            }
+            let entry = &child.entries[part];
+            (base, len) = (base + entry.data_offset, entry.stored_size);
+            opened = format!("{}{}{}", opened, NESTED_SEPARATOR, part);
+        }
+        Archive::open_at(&mut self.stream, base, len)
+    }
+
+    /// Lists the entry paths recursing into nested archives, entries within
+    /// a nested archive are listed as `outer.tar!/inner/file`. Entry paths
+    /// holding the separator are rejected since they can't be told apart.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<String>)` - The paths in archive order, nested archives are listed before their content.
+    /// * `Err(e)` - If a nested archive can't be read or parsed, an entry path holds the separator or archives nest too deep.
+    pub fn nested_paths(&mut self) -> Result<Vec<String>> {
+        let mut paths = Vec::new();
+        let entries: Vec<(String, u64, u64)> = self.entries.values()
+            .map(|entry| (entry.meta.path.clone(), entry.data_offset, entry.stored_size))
+            .collect();
+        for (path, data_offset, stored_size) in entries {
+            check_member(&path)?;
+            let nested = self.is_nested(&path)?;
+            paths.push(path.clone());
+            if nested {
+                walk_nested(&mut self.stream, data_offset, stored_size, &path, 1, &mut paths)?;
+            }
+        }
+        Ok(paths)
+    }
+}
+
+/// Sniffs whether the block at an offset is a valid USTAR header.
+///
+/// # Arguments
+/// * `stream` - The stream to read from.
+/// * `offset` - Offset of the block.
+fn sniff_tar(stream: &mut (impl Read + Seek), offset: u64) -> Result<bool> {
+    stream.seek(SeekFrom::Start(offset))?;
+    let mut block = [0u8; 512];
+    stream.read_exact(&mut block)?;
+    if block[257..262] != *b"ustar" {
+        return Ok(false);
+    }
+    let valid = !validate_block(&block).iter().any(|v| matches!(v, PosixViolation::ChecksumMismatch { .. }));
+    Ok(valid)
+}
+
+/// Rejects entry paths holding the nested separator.
+///
+/// # Arguments
+/// * `path` - The entry path.
+fn check_member(path: &str) -> Result<()> {
+    if path.contains(NESTED_SEPARATOR) {
+        bail!("entry '{}' holds the nested separator '{}'", path, NESTED_SEPARATOR);
+    }
+    Ok(())
+}
+
+/// Lists the paths of an archive embedded in a stream recursing into its
+/// nested archives.
+///
+/// # Arguments
+/// * `stream` - The root stream.
+/// * `base` - Offset of the archive within the root stream.
+/// * `len` - Archive length.
+/// * `prefix` - Nested path of the archive.
+/// * `depth` - Nesting level of the archive, starting at 1.
+/// * `paths` - Collected paths.
+fn walk_nested<T: Read + Seek>(
+    stream: &mut T,
+    base: u64,
+    len: u64,
+    prefix: &str,
+    depth: usize,
+    paths: &mut Vec<String>
+) -> Result<()> {
+    if depth > MAX_NESTED_DEPTH {
+        bail!("nested archive '{}' is deeper than {} levels", prefix, MAX_NESTED_DEPTH);
+    }
+    let mut nested = Vec::new();
+    {
+        let mut child = Archive::open(Embedded::new(&mut *stream, base, len)?)?;
+        let entries: Vec<(String, u64, u64)> = child.entries.values()
+            .map(|entry| (entry.meta.path.clone(), entry.data_offset, entry.stored_size))
+            .collect();
+        for (path, data_offset, stored_size) in entries {
+            check_member(&path)?;
+            let full = format!("{}{}{}", prefix, NESTED_SEPARATOR, path);
+            if child.is_nested(&path)? {
+                nested.push((paths.len(), full.clone(), base + data_offset, stored_size));
+            }
+            paths.push(full);
+        }
+    }
+
+    // children are inserted right after their archive path, last first to keep the indexes valid
+    for (index, full, base, len) in nested.into_iter().rev() {
+        let mut children = Vec::new();
+        walk_nested(stream, base, len, &full, depth + 1, &mut children)?;
+        paths.splice(index + 1..index + 1, children);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::Metadata;
+    use std::io::Cursor;
+
+    fn tar_with(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        for (path, content) in files {
+            archive.append_data(path, Metadata::new(path, EntryKind::RegularFile), content).unwrap();
+        }
+        archive.into_inner().into_inner()
+    }
+
+    #[test]
+    fn nested_traversal() {
+        let deepest = tar_with(&[("deep.txt", b"deepest".as_slice())]);
+        let inner = tar_with(&[("inner/file", b"inner".as_slice()), ("deep.tar", deepest.as_slice())]);
+        let outer = tar_with(&[("outer.tar", inner.as_slice()), ("plain.txt", b"ustar".as_slice())]);
+        let mut archive = Archive::open(Cursor::new(outer)).unwrap();
+        assert!(archive.is_nested("outer.tar").unwrap());
+        assert!(!archive.is_nested("plain.txt").unwrap());
+        match archive.nested_paths() {
+            Ok(v) => assert_eq!(vec![
+                "outer.tar",
+                "outer.tar!/inner/file",
+                "outer.tar!/deep.tar",
+                "outer.tar!/deep.tar!/deep.txt",
+                "plain.txt"
+            ], v),
+            Err(e) => assert!(false, "Failed to list paths: {}", e)
+        }
+
+        let mut child = match archive.open_nested("outer.tar!/deep.tar") {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open nested archive: {}", e);
+                return;
+            }
+        };
+        let mut out = Vec::new();
+        child.read_to("deep.txt", &mut out).unwrap();
+        assert_eq!(b"deepest".to_vec(), out);
+        assert!(archive.open_nested("plain.txt").is_err());
+        assert!(archive.open_nested("outer.tar!/inner/file").is_err());
+    }
+
+    #[test]
+    fn nested_limits() {
+        let mut content = tar_with(&[("leaf.txt", b"leaf".as_slice())]);
+        for _ in 0..=MAX_NESTED_DEPTH {
+            content = tar_with(&[("n.tar", content.as_slice())]);
+        }
+        let mut archive = Archive::open(Cursor::new(content)).unwrap();
+        match archive.nested_paths() {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(_) => {}
+        }
+        let path = vec!["n.tar"; MAX_NESTED_DEPTH + 1].join(NESTED_SEPARATOR);
+        match archive.open_nested(&path) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(_) => {}
+        }
+
+        let mut archive = Archive::open(Cursor::new(tar_with(&[("a!/b.txt", b"b".as_slice())]))).unwrap();
+        match archive.nested_paths() {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(_) => {}
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::{bail, Result};
+use std::io::{Read, Seek, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::global::move_bytes;
+use super::{Archive, Entry, Metadata};
+
+/// Owner of a region moved by `Archive::compact`.
+enum Region {
+    /// Entry by path.
+    Entry(String),
+    /// Global header by index.
+    Global(usize),
+}
+
+impl<T: Read + Write + Seek> Archive<T> {
+    /// Deletes every entry matching a predicate and compacts the archive to
+    /// reclaim their space, meant for log or backup rotation. The freed
+    /// bytes are zeroed at the stream end, see `compact`.
+    ///
+    /// # Arguments
+    /// * `predicate` - Tells whether an entry should be deleted.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<Entry>)` - The deleted entries in archive order.
+    /// * `Err(e)` - If the archive can't be read or written.
+    pub fn prune(&mut self, mut predicate: impl FnMut(&Metadata) -> bool) -> Result<Vec<Entry>> {
+        let paths: Vec<String> = self.entries.values()
+            .filter(|entry| predicate(&entry.meta))
+            .map(|entry| entry.meta.path.clone())
+            .collect();
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut pruned = Vec::with_capacity(paths.len());
+        for path in paths.iter() {
+            self.pinned.remove(path);
+            if let Some(entry) = self.entries.shift_remove(path) {
+                pruned.push(entry);
+            }
+        }
+        self.compact()?;
+        Ok(pruned)
+    }
+
+    /// Deletes the entries modified longer ago than an age.
+    ///
+    /// # Arguments
+    /// * `age` - Maximum age, compared against the entries modification time.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<Entry>)` - The deleted entries in archive order.
+    /// * `Err(e)` - If the archive can't be read or written.
+    pub fn prune_older_than(&mut self, age: Duration) -> Result<Vec<Entry>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let cutoff = now.saturating_sub(age.as_secs());
+        self.prune(|meta| meta.mtime < cutoff)
+    }
+
+    /// Deletes the entries larger than a size.
+    ///
+    /// # Arguments
+    /// * `size` - Maximum content size in bytes.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<Entry>)` - The deleted entries in archive order.
+    /// * `Err(e)` - If the archive can't be read or written.
+    pub fn prune_larger_than(&mut self, size: u64) -> Result<Vec<Entry>> {
+        self.prune(|meta| meta.size > size)
+    }
+
+    /// Pins an entry so compaction leaves it at its offset, e.g. while its
+    /// content is memory mapped or read through an offset handed out.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry to pin.
+    ///
+    /// # Returns
+    /// * `Ok(())` - On success.
+    /// * `Err(e)` - If the entry doesn't exists.
+    pub fn pin(&mut self, path: &str) -> Result<()> {
+        if !self.entries.contains_key(path) {
+            bail!("entry '{}' not found", path);
+        }
+        self.pinned.insert(path.to_string());
+        Ok(())
+    }
+
+    /// Unpins an entry so compaction can move it again.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry to unpin.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the entry was pinned.
+    pub fn unpin(&mut self, path: &str) -> bool {
+        self.pinned.remove(path)
+    }
+
+    /// Tells whether an entry is pinned.
+    ///
+    /// # Arguments
+    /// * `path` - The entry path.
+    pub fn is_pinned(&self, path: &str) -> bool {
+        self.pinned.contains(path)
+    }
+
+    /// Moves the entries and global headers down over the gaps left by
+    /// deleted entries and moves the archive end back. The stream keeps its
+    /// length with the bytes past the new end zeroed, callers owning it can
+    /// truncate it to `stored_len` to give the space back.
+    /// Pinned entries keep their offsets, only the gaps after them are
+    /// filled and the gaps left before them are padded so they aren't read
+    /// as the archive end.
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - The amount of bytes the archive end moved back.
+    /// * `Err(e)` - If the archive can't be read or written.
+    pub fn compact(&mut self) -> Result<u64> {
+        let mut regions: Vec<(u64, u64, Region)> = self.entries.values()
+            .map(|entry| (entry.offset, entry.end() - entry.offset, Region::Entry(entry.meta.path.clone())))
+            .chain(self.globals.iter().enumerate().map(|(i, global)| (global.offset, global.len, Region::Global(i))))
+            .collect();
+        regions.sort_by_key(|region| region.0);
+
+        // the regions before a pinned entry always end before it so none is moved over it
+        let mut cursor = 0;
+        for (offset, len, owner) in regions {
+            if matches!(&owner, Region::Entry(path) if self.pinned.contains(path)) {
+                if cursor < offset {
+                    self.write_padding(cursor, offset - cursor)?;
+                }
+                cursor = offset + len;
+                continue;
+            }
+            if offset != cursor {
+                self.touch(cursor);
+                move_bytes(&mut self.stream, offset, cursor, len)?;
+                match owner {
+                    Region::Entry(path) => {
+                        let entry = self.entries.get_mut(&path).unwrap();
+                        entry.data_offset -= offset - cursor;
+                        entry.offset = cursor;
+                    },
+                    Region::Global(index) => self.globals[index].offset = cursor
+                }
+            }
+            cursor += len;
+        }
+
+        // the old end of archive marker is zeroed so it isn't mistaken for entries
+        let reclaimed = self.end - cursor;
+        if reclaimed > 0 {
+            self.zero_fill(cursor, reclaimed + 1024)?;
+            self.end = cursor;
+        }
+        self.write_end()?;
+        self.stream.flush()?;
+        Ok(reclaimed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::EntryKind;
+    use std::io::Cursor;
+
+    #[test]
+    fn prune_and_compact() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.set_comment(Some("rotated logs")).unwrap();
+        for (path, mtime, content) in [
+            ("old.log", 0, b"old".as_slice()),
+            ("big.log", now, [b'x'; 2000].as_slice()),
+            ("new.log", now, b"new".as_slice())
+        ] {
+            let mut meta = Metadata::new(path, EntryKind::RegularFile);
+            meta.mtime = mtime;
+            archive.append_data(path, meta, content).unwrap();
+        }
+
+        let pruned = match archive.prune_older_than(Duration::from_secs(86400)) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to prune archive: {}", e);
+                return;
+            }
+        };
+        assert_eq!(vec!["old.log"], pruned.iter().map(|v| v.meta.path.as_str()).collect::<Vec<&str>>());
+        assert_eq!(1, archive.prune_larger_than(1024).unwrap().len());
+        assert!(archive.prune(|_| false).unwrap().is_empty());
+
+        // the stream isn't shrunk but can be truncated to the stored length
+        let len = archive.stored_len();
+        let mut stream = archive.into_inner();
+        assert!(stream.get_ref().len() as u64 > len);
+        stream.get_mut().truncate(len as usize);
+        let archive = Archive::open(stream).unwrap();
+        assert_eq!(vec!["new.log"], archive.entries().map(|v| v.meta.path.as_str()).collect::<Vec<&str>>());
+        assert_eq!(Some("rotated logs"), archive.comment());
+        let new = archive.get("new.log").unwrap();
+        assert_eq!(archive.global_headers()[0].end(), new.offset);
+    }
+
+    #[test]
+    fn compact_keeps_pinned() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        for path in ["a.bin", "b.bin", "c.bin", "d.bin"] {
+            archive.append_data(path, Metadata::new(path, EntryKind::RegularFile), path.as_bytes()).unwrap();
+        }
+        assert!(archive.pin("missing").is_err());
+        archive.pin("c.bin").unwrap();
+        assert!(archive.is_pinned("c.bin"));
+        let pinned = archive.get("c.bin").unwrap().offset;
+        let b = archive.get("b.bin").unwrap().offset;
+
+        archive.remove("a.bin").unwrap();
+        match archive.compact() {
+            Ok(v) => assert_eq!(0, v),
+            Err(e) => {
+                assert!(false, "Failed to compact archive: {}", e);
+                return;
+            }
+        }
+        assert_eq!(0, archive.get("b.bin").unwrap().offset);
+        assert_eq!(pinned, archive.get("c.bin").unwrap().offset);
+        assert_eq!(pinned + 1024, archive.get("d.bin").unwrap().offset);
+
+        // the gap before the pinned entry is padded, neither an end marker nor a stale copy
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        let paths: Vec<String> = archive.entries().map(|v| v.meta.path.clone()).collect();
+        assert_eq!(vec!["b.bin", "c.bin", "d.bin"], paths);
+        archive.pin("c.bin").unwrap();
+
+        assert!(archive.unpin("c.bin"));
+        assert!(!archive.unpin("c.bin"));
+        assert_eq!(1024, archive.compact().unwrap());
+        assert_eq!(b, archive.get("c.bin").unwrap().offset);
+
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        let mut out = Vec::new();
+        archive.read_to("d.bin", &mut out).unwrap();
+        assert_eq!(b"d.bin".to_vec(), out);
+    }
+}
@@ -0,0 +1,287 @@
+use anyhow::{bail, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::global::move_bytes;
+use super::{write_zeroes, Archive, Trailer};
+use crate::engine::error::Error;
+use crate::engine::header::PosixViolation;
+use crate::engine::header::validate::validate_block;
+use crate::format::{checksum, parse_octal, set_checksum, BLOCK_SIZE};
+
+/// Fixes applied by `Archive::repair`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RepairReport {
+    /// Offsets of the headers whose checksum was rewritten.
+    pub checksums: Vec<u64>,
+    /// Offsets of the headers with a bad checksum left as they are, their
+    /// other fields are invalid too so they're corrupted rather than mangled.
+    pub corrupted: Vec<u64>,
+    /// Offsets the headers following unpadded content were moved to.
+    pub realigned: Vec<u64>,
+    /// Whether the end of archive marker was missing or truncated.
+    pub terminator: bool,
+}
+
+impl RepairReport {
+    /// Tells whether the archive didn't need any fix.
+    pub fn is_clean(&self) -> bool {
+        !self.is_fixed() && self.corrupted.is_empty()
+    }
+
+    /// Tells whether any fix was written.
+    fn is_fixed(&self) -> bool {
+        !self.checksums.is_empty() || !self.realigned.is_empty() || self.terminator
+    }
+}
+
+impl<T: Read + Write + Seek> Archive<T> {
+    /// Repairs an archive mangled by a buggy writer and opens it: bad header
+    /// checksums are recomputed when the other header fields are valid,
+    /// headers following unpadded content are
+    /// moved back to a block boundary and a missing or truncated end of
+    /// archive marker is rewritten. Content is never modified, archives with
+    /// truncated content or unrecognizable headers are left as they are.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to repair the archive in.
+    ///
+    /// # Returns
+    /// * `Ok((Self, RepairReport))` - The opened archive and the applied fixes.
+    /// * `Err(e)` - If the archive can't be repaired, read or written, or a corrupted header stops it from opening.
+    pub fn repair(mut stream: T) -> Result<(Self, RepairReport)> {
+        let mut report = RepairReport::default();
+        let mut len = stream.seek(SeekFrom::End(0))?;
+        let mut pos = 0;
+        let mut end = 0;
+        let mut unpadded: Option<u64> = None;
+        while let Some(mut block) = read_block(&mut stream, pos)? {
+            if block.iter().all(|b| *b == 0) {
+                pos += BLOCK_SIZE as u64;
+                continue;
+            }
+            if Trailer::parse(&block).is_some() {
+                break;
+            }
+            if !is_plausible(&block) {
+                // the previous content wasn't padded so the header starts right after it
+                let offset = match unpadded.take() {
+                    Some(offset) if read_block(&mut stream, offset)?.is_some_and(|v| is_plausible(&v)) => offset,
+                    _ => bail!(Error::Corrupted(format!("unrecognized header at offset {}", pos)))
+                };
+                move_bytes(&mut stream, offset, pos, len - offset)?;
+                stream.seek(SeekFrom::Start(offset))?;
+                write_zeroes(&mut stream, pos - offset)?;
+                len += pos - offset;
+                report.realigned.push(pos);
+                continue;
+            }
+            if !checksum_matches(&block) {
+                if fields_valid(&block) {
+                    set_checksum(&mut block);
+                    stream.seek(SeekFrom::Start(pos))?;
+                    stream.write_all(&block)?;
+                    report.checksums.push(pos);
+                } else {
+                    report.corrupted.push(pos);
+                }
+            }
+
+            // GNU sparse headers may be followed by extension blocks before the content
+            let mut data_offset = pos + BLOCK_SIZE as u64;
+            let mut extended = block[156] == b'S' && block[482] != 0;
+            while extended {
+                extended = match read_block(&mut stream, data_offset)? {
+                    Some(v) => v[504] != 0,
+                    None => false
+                };
+                data_offset += BLOCK_SIZE as u64;
+            }
+            let size = match block[156] {
+                b'1' | b'2' | b'3' | b'4' | b'6' => 0,
+                _ => field_size(&block, pos)?
+            };
+            let content_end = data_offset + size;
+            if content_end > len {
+                bail!(Error::Corrupted(format!("truncated content for the header at offset {}", pos)));
+            }
+            unpadded = if size % BLOCK_SIZE as u64 > 0 { Some(content_end) } else { None };
+            pos = content_end.div_ceil(BLOCK_SIZE as u64) * BLOCK_SIZE as u64;
+            end = pos;
+        }
+
+        // the end of archive marker must be two full zero blocks
+        let mut marker = Vec::with_capacity(1024);
+        stream.seek(SeekFrom::Start(end))?;
+        (&mut stream).take(1024).read_to_end(&mut marker)?;
+        if marker.len() < 1024 || marker.iter().any(|b| *b != 0) {
+            stream.seek(SeekFrom::Start(end))?;
+            stream.write_all(&[0u8; 1024])?;
+            report.terminator = true;
+        }
+        stream.flush()?;
+
+        // the trailer digest covers the fixed bytes too
+        let mut archive = match Archive::open(stream) {
+            Ok(v) => v,
+            Err(e) if !report.corrupted.is_empty() => bail!(Error::Corrupted(format!(
+                "headers at offsets {:?} have a bad checksum and invalid fields: {}", report.corrupted, e
+            ))),
+            Err(e) => return Err(e)
+        };
+        if archive.has_trailer() && report.is_fixed() {
+            archive.write_trailer()?;
+            archive.stream.flush()?;
+        }
+        Ok((archive, report))
+    }
+}
+
+/// Reads a full block at an offset.
+///
+/// # Arguments
+/// * `stream` - The stream to read from.
+/// * `offset` - Block offset.
+///
+/// # Returns
+/// * `Ok(Some([u8; 512]))` - The block.
+/// * `Ok(None)` - When the stream ends before a full block.
+pub(super) fn read_block(stream: &mut (impl Read + Seek), offset: u64) -> Result<Option<[u8; BLOCK_SIZE]>> {
+    stream.seek(SeekFrom::Start(offset))?;
+    let mut block = [0u8; BLOCK_SIZE];
+    let mut read = 0;
+    while read < BLOCK_SIZE {
+        let n = stream.read(&mut block[read..])?;
+        if n < 1 {
+            return Ok(None);
+        }
+        read += n;
+    }
+    Ok(Some(block))
+}
+
+/// Tells whether a checksum field matches the block, old writers summed
+/// the bytes as signed values so both sums are accepted.
+///
+/// # Arguments
+/// * `block` - Raw header block.
+pub(super) fn checksum_matches(block: &[u8; BLOCK_SIZE]) -> bool {
+    let stored = match parse_octal(&block[148..156]) {
+        Ok(v) => v,
+        Err(_) => return false
+    };
+    let signed: i64 = block.iter().enumerate()
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' as i64 } else { *b as i8 as i64 })
+        .sum();
+    stored == checksum(block) as u64 || stored as i64 == signed
+}
+
+/// Tells whether a block looks like a header: it has the USTAR magic or,
+/// for old V7 headers, a valid checksum.
+///
+/// # Arguments
+/// * `block` - Raw header block.
+fn is_plausible(block: &[u8; BLOCK_SIZE]) -> bool {
+    block[257..262] == *b"ustar" || checksum_matches(block)
+}
+
+/// Tells whether the fields of a header other than its checksum hold valid
+/// values, a bad checksum is only recomputed for those since it'd hide the
+/// corruption of any other block. GNU and V7 headers and their type flags
+/// are accepted, numeric fields may be base-256 encoded.
+///
+/// # Arguments
+/// * `block` - Raw header block.
+fn fields_valid(block: &[u8; BLOCK_SIZE]) -> bool {
+    let base256 = |field: &str| match field {
+        "uid" => block[108] & 0x80 != 0,
+        "gid" => block[116] & 0x80 != 0,
+        "size" => block[124] & 0x80 != 0,
+        "mtime" => block[136] & 0x80 != 0,
+        _ => false
+    };
+    validate_block(block).iter().all(|violation| match violation {
+        PosixViolation::InvalidMagic | PosixViolation::InvalidVersion => false,
+        PosixViolation::InvalidNumber(field) => *field == "chksum" || base256(field),
+        _ => true
+    })
+}
+
+/// Reads the size field of a header, both octal and base-256 encodings.
+///
+/// # Arguments
+/// * `block` - Raw header block.
+/// * `offset` - Header offset, used on errors.
+fn field_size(block: &[u8; BLOCK_SIZE], offset: u64) -> Result<u64> {
+    if block[124] & 0x80 != 0 {
+        return Ok(block[128..136].iter().fold(0u64, |acc, b| (acc << 8) | *b as u64));
+    }
+    match parse_octal(&block[124..136]) {
+        Ok(v) => Ok(v),
+        Err(e) => bail!(Error::Corrupted(format!("invalid size for the header at offset {}: {}", offset, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::{EntryKind, Metadata};
+    use std::io::Cursor;
+
+    fn raw_file(buf: &mut Vec<u8>, path: &str, content: &[u8], pad: bool) {
+        let mut meta = Metadata::new(path, EntryKind::RegularFile);
+        meta.size = content.len() as u64;
+        meta.save_headers(buf).unwrap();
+        buf.extend_from_slice(content);
+        if pad {
+            buf.resize(buf.len().div_ceil(512) * 512, 0);
+        }
+    }
+
+    #[test]
+    fn repair_mangled_archive() {
+        let mut buf = Vec::new();
+        raw_file(&mut buf, "a.txt", b"abc", false);
+        raw_file(&mut buf, "b.txt", b"second", true);
+        buf[148..156].copy_from_slice(b"0000000\0");
+
+        let (mut archive, report) = match Archive::repair(Cursor::new(buf)) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to repair archive: {}", e);
+                return;
+            }
+        };
+        assert_eq!(vec![0], report.checksums);
+        assert_eq!(vec![1024], report.realigned);
+        assert!(report.terminator);
+        let mut out = Vec::new();
+        archive.read_to("a.txt", &mut out).unwrap();
+        assert_eq!(b"abc".to_vec(), out);
+        let mut out = Vec::new();
+        archive.read_to("b.txt", &mut out).unwrap();
+        assert_eq!(b"second".to_vec(), out);
+
+        // a sound archive is left untouched
+        let (archive, report) = Archive::repair(archive.into_inner()).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(2, archive.len());
+        assert!(Archive::repair(Cursor::new(vec![b'x'; 1024])).is_err());
+    }
+
+    #[test]
+    fn repair_keeps_corrupted_checksums() {
+        let mut buf = Vec::new();
+        raw_file(&mut buf, "a.txt", b"abc", true);
+        buf[100] = b'z';
+        buf[148..156].copy_from_slice(b"0000000\0");
+        buf.resize(buf.len() + 1024, 0);
+
+        // the checksum isn't rewritten over the corrupted mode
+        let mut stream = Cursor::new(buf);
+        match Archive::repair(&mut stream) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert!(e.to_string().contains("[0]"))
+        }
+        assert_eq!(b"0000000\0", &stream.get_ref()[148..156]);
+    }
+}
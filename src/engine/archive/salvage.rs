@@ -0,0 +1,116 @@
+use anyhow::Result;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use super::repair::{checksum_matches, read_block};
+use super::{Archive, ExtractOptions};
+use crate::format::BLOCK_SIZE;
+
+/// Outcome of a salvage extraction.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SalvageReport {
+    /// Offsets of the damaged headers skipped while scanning.
+    pub skipped: Vec<u64>,
+    /// Paths of the entries that failed to extract along with the error.
+    pub failed: Vec<(String, String)>,
+}
+
+impl SalvageReport {
+    /// Tells whether the whole archive was recovered.
+    pub fn is_clean(&self) -> bool {
+        self.skipped.is_empty() && self.failed.is_empty()
+    }
+}
+
+impl<T: Read + Seek> Archive<T> {
+    /// Opens a partially damaged archive: whenever a header is corrupt the
+    /// scan moves forward block by block until the next header having both
+    /// the USTAR magic and a valid checksum, the entries in between are lost.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to read the archive from.
+    ///
+    /// # Returns
+    /// * `Ok((Self, Vec<u64>))` - The archive with the recovered entries and the damaged header offsets.
+    /// * `Err(e)` - If the stream can't be read.
+    pub fn open_salvage(stream: T) -> Result<(Self, Vec<u64>)> {
+        let mut damaged = Vec::new();
+        let archive = Self::open_scan(stream, Some(&mut damaged), None)?;
+        Ok((archive, damaged))
+    }
+
+    /// Recovers as many files as possible from a partially damaged archive,
+    /// corrupt headers are skipped as in `Archive::open_salvage` and entries
+    /// failing to extract, like those with truncated content, are reported
+    /// instead of aborting the extraction.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to read the archive from.
+    /// * `dest` - Destination directory, created when missing.
+    /// * `options` - Extraction options.
+    ///
+    /// # Returns
+    /// * `Ok(SalvageReport)` - The skipped headers and failed entries.
+    /// * `Err(e)` - If the stream can't be read or the destination can't be created.
+    pub fn salvage(stream: T, dest: &Path, options: &ExtractOptions) -> Result<SalvageReport> {
+        let (mut archive, skipped) = Self::open_salvage(stream)?;
+        let mut report = SalvageReport { skipped, failed: Vec::new() };
+        archive.extract_all(dest, options, Some(&mut report.failed))?;
+        Ok(report)
+    }
+}
+
+/// Finds the next plausible header, a block with the USTAR magic and a
+/// valid checksum.
+///
+/// # Arguments
+/// * `stream` - The stream to search.
+/// * `from` - Offset to start searching from, rounded up to a block boundary.
+///
+/// # Returns
+/// * `Ok(Some(u64))` - The header offset.
+/// * `Ok(None)` - When no header is left.
+pub(super) fn find_header(stream: &mut (impl Read + Seek), from: u64) -> Result<Option<u64>> {
+    let mut pos = from.div_ceil(BLOCK_SIZE as u64) * BLOCK_SIZE as u64;
+    while let Some(block) = read_block(stream, pos)? {
+        if block[257..262] == *b"ustar" && checksum_matches(&block) {
+            return Ok(Some(pos));
+        }
+        pos += BLOCK_SIZE as u64;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::{EntryKind, Metadata};
+    use std::fs;
+    use std::io::Cursor;
+
+    #[test]
+    fn salvage_damaged_archive() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        for (path, content) in [("a.txt", b"first"), ("b.txt", b"broke"), ("c.txt", b"third")] {
+            archive.append_data(path, Metadata::new(path, EntryKind::RegularFile), content).unwrap();
+        }
+        let mut buf = archive.into_inner().into_inner();
+        let offset = (buf.len() - 1024) / 3;
+        buf[offset..offset + 512].fill(b'#');
+        assert!(Archive::open(Cursor::new(buf.clone())).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let report = match Archive::salvage(Cursor::new(buf), dir.path(), &ExtractOptions::default()) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to salvage archive: {}", e);
+                return;
+            }
+        };
+        assert_eq!(vec![offset as u64], report.skipped);
+        assert!(report.failed.is_empty());
+        assert_eq!("first", fs::read_to_string(dir.path().join("a.txt")).unwrap());
+        assert_eq!("third", fs::read_to_string(dir.path().join("c.txt")).unwrap());
+        assert!(!dir.path().join("b.txt").exists());
+    }
+}
@@ -0,0 +1,98 @@
+use super::Metadata;
+use crate::engine::header::PaxAttribute;
+
+/// PAX key used by Red Hat tar to store the SELinux context.
+pub const SELINUX_KEY: &str = "RHT.security.selinux";
+
+/// PAX key used by GNU tar and libarchive to store the SELinux context
+/// as an extended attribute.
+pub const SELINUX_XATTR_KEY: &str = "SCHILY.xattr.security.selinux";
+
+impl Metadata {
+    /// Gets the SELinux security context, the Red Hat key is preferred
+    /// over the extended attribute one when both are present.
+    ///
+    /// # Returns
+    /// * `Some(&str)` - The security context, e.g. `system_u:object_r:container_file_t:s0`.
+    /// * `None` - When the entry has no security context.
+    pub fn selinux_context(&self) -> Option<&str> {
+        self.attributes.get(SELINUX_KEY)
+            .or_else(|| self.attributes.get(SELINUX_XATTR_KEY))
+            .map(|attr| attr.raw.trim_end_matches('\0'))
+    }
+
+    /// Sets the SELinux security context, it's emitted on the Red Hat key
+    /// and on the extended attribute key when the entry already had it so
+    /// both kinds of readers keep seeing it.
+    ///
+    /// # Arguments
+    /// * `context` - The security context, `None` removes it.
+    pub fn set_selinux_context(&mut self, context: Option<&str>) {
+        let context = match context {
+            Some(v) => v,
+            None => {
+                self.attributes.shift_remove(SELINUX_KEY);
+                self.attributes.shift_remove(SELINUX_XATTR_KEY);
+                return;
+            }
+        };
+        if self.attributes.contains_key(SELINUX_XATTR_KEY) {
+            self.attributes.insert(SELINUX_XATTR_KEY.to_string(), PaxAttribute::from_str(context.to_string()));
+        }
+        self.attributes.insert(SELINUX_KEY.to_string(), PaxAttribute::from_str(context.to_string()));
+    }
+}
+
+/// Tells whether a security context is safe to apply: printable ASCII
+/// without spaces made of at least `user:role:type`.
+///
+/// # Arguments
+/// * `context` - The security context.
+pub(super) fn is_valid_selinux_context(context: &str) -> bool {
+    context.bytes().all(|v| v.is_ascii_graphic())
+        && context.split(':').count() >= 3
+        && context.split(':').take(3).all(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::{Archive, EntryKind};
+    use std::io::Cursor;
+
+    #[test]
+    fn context_roundtrip() {
+        let context = "system_u:object_r:container_file_t:s0";
+        let mut meta = Metadata::new("a.txt", EntryKind::RegularFile);
+        meta.attributes.insert(SELINUX_XATTR_KEY.to_string(), PaxAttribute::from_str("old_t\0".to_string()));
+        assert_eq!(Some("old_t"), meta.selinux_context());
+        meta.set_selinux_context(Some(context));
+
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.append_data("a.txt", meta, b"label").unwrap();
+        let archive = Archive::open(archive.into_inner()).unwrap();
+        let meta = match archive.get("a.txt") {
+            Some(v) => v.meta.clone(),
+            None => {
+                assert!(false, "expected entry a.txt");
+                return;
+            }
+        };
+        assert_eq!(Some(context), meta.selinux_context());
+        assert_eq!(context, meta.attributes[SELINUX_XATTR_KEY].raw);
+
+        let mut meta = meta;
+        meta.set_selinux_context(None);
+        assert_eq!(None, meta.selinux_context());
+    }
+
+    #[test]
+    fn validate_context() {
+        assert!(is_valid_selinux_context("system_u:object_r:container_file_t:s0:c1,c2"));
+        assert!(is_valid_selinux_context("user_u:role_r:type_t"));
+        assert!(!is_valid_selinux_context("--reference=/etc/passwd"));
+        assert!(!is_valid_selinux_context("user_u::type_t"));
+        assert!(!is_valid_selinux_context("user_u:role_r:type t"));
+        assert!(!is_valid_selinux_context(""));
+    }
+}
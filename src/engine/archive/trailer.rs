@@ -0,0 +1,283 @@
+use anyhow::{bail, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::repair::read_block;
+use super::Archive;
+use crate::engine::DEFAULT_BUFFER_SIZE;
+use crate::engine::error::Error;
+use crate::format::BLOCK_SIZE;
+
+/// Magic opening the rtar trailer block.
+pub const TRAILER_MAGIC: &[u8; 8] = b"rtar-trl";
+
+/// FNV-1a 64 bits offset basis.
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// FNV-1a 64 bits prime.
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Rtar specific block written after the end of archive marker, other
+/// tools stop reading at the marker so they ignore it. The block holds the
+/// magic, the archive length and the archive digest as little endian
+/// integers, the rest is zeroed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trailer {
+    /// Archive length including the end of archive marker, also the trailer offset.
+    pub len: u64,
+    /// FNV-1a 64 bits digest of the archive bytes.
+    pub digest: u64,
+}
+
+impl Trailer {
+    /// Parses a trailer block.
+    ///
+    /// # Arguments
+    /// * `block` - Raw block.
+    ///
+    /// # Returns
+    /// * `Some(Trailer)` - The trailer.
+    /// * `None` - When the block isn't a trailer.
+    pub fn parse(block: &[u8; BLOCK_SIZE]) -> Option<Self> {
+        if block[..8] != *TRAILER_MAGIC {
+            return None;
+        }
+        let len = u64::from_le_bytes(block[8..16].try_into().unwrap());
+        let digest = u64::from_le_bytes(block[16..24].try_into().unwrap());
+        Some(Self { len, digest })
+    }
+
+    /// Encodes the trailer block.
+    pub fn encode(&self) -> [u8; BLOCK_SIZE] {
+        let mut block = [0u8; BLOCK_SIZE];
+        block[..8].copy_from_slice(TRAILER_MAGIC);
+        block[8..16].copy_from_slice(&self.len.to_le_bytes());
+        block[16..24].copy_from_slice(&self.digest.to_le_bytes());
+        block
+    }
+}
+
+impl<T: Read + Seek> Archive<T> {
+    /// Tells whether the archive keeps a checksummed trailer.
+    pub fn has_trailer(&self) -> bool {
+        self.trailer.is_some()
+    }
+
+    /// Recomputes the archive digest and compares it with the trailer one.
+    /// Opening only checks the trailer length, this reads the whole archive.
+    ///
+    /// # Returns
+    /// * `Ok(bool)` - Whether the digest matches.
+    /// * `Err(e)` - If the archive has no trailer or can't be read.
+    pub fn verify_trailer(&mut self) -> Result<bool> {
+        let offset = match self.trailer {
+            Some(v) => v,
+            None => bail!("the archive has no trailer")
+        };
+        let trailer = match read_block(&mut self.stream, offset)?.and_then(|v| Trailer::parse(&v)) {
+            Some(v) => v,
+            None => bail!(Error::Corrupted(format!("missing trailer at offset {}", offset)))
+        };
+        Ok(trailer.len == offset && digest(&mut self.stream, offset)? == trailer.digest)
+    }
+}
+
+impl<T: Read + Write + Seek> Archive<T> {
+    /// Drops the resumable digest when a change lands within the digested
+    /// bytes.
+    ///
+    /// # Arguments
+    /// * `offset` - Offset of the change.
+    pub(super) fn touch(&mut self, offset: u64) {
+        if self.digest.is_some_and(|(len, _)| offset < len) {
+            self.digest = None;
+        }
+    }
+
+    /// Enables or disables the checksummed trailer, once enabled it's
+    /// rewritten on every change. Appends only digest the new bytes while
+    /// changes within the archive read it whole.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether the trailer is written.
+    pub fn set_trailer(&mut self, enabled: bool) -> Result<()> {
+        match (enabled, self.trailer) {
+            (true, None) => {
+                self.trailer = Some(self.end + 1024);
+                self.write_end()?;
+            },
+            (false, Some(offset)) => {
+                self.trailer = None;
+                self.zero_fill(offset, BLOCK_SIZE as u64)?;
+            },
+            _ => return Ok(())
+        }
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Writes the trailer after the end of archive marker, a previous
+    /// trailer left past it after the archive shrank is zeroed. The digest
+    /// resumes from the last one when only bytes were appended since.
+    pub(super) fn write_trailer(&mut self) -> Result<()> {
+        let old = match self.trailer {
+            Some(v) => v,
+            None => return Ok(())
+        };
+        let offset = self.end + 1024;
+        if old > offset {
+            self.zero_fill(old, BLOCK_SIZE as u64)?;
+        }
+        let (start, state) = match self.digest {
+            Some((len, state)) if len <= self.end => (len, state),
+            _ => (0, FNV_OFFSET)
+        };
+        let state = digest_range(&mut self.stream, start, self.end, state)?;
+        self.digest = Some((self.end, state));
+
+        // the end of archive marker is made of zeroes
+        let digest = (0..1024).fold(state, |hash, _| hash.wrapping_mul(FNV_PRIME));
+        let trailer = Trailer { len: offset, digest };
+        self.stream.seek(SeekFrom::Start(offset))?;
+        self.stream.write_all(&trailer.encode())?;
+        self.trailer = Some(offset);
+        Ok(())
+    }
+}
+
+/// Reads the trailer following an end of archive marker and checks the
+/// archive wasn't truncated nor appended to after it was written.
+///
+/// # Arguments
+/// * `stream` - The stream to read from.
+/// * `end` - Offset of the end of archive marker.
+///
+/// # Returns
+/// * `Ok(Some(u64))` - The trailer offset.
+/// * `Ok(None)` - When the archive has no trailer.
+/// * `Err(e)` - If the trailer doesn't match the archive or the stream can't be read.
+pub(super) fn read_trailer(stream: &mut (impl Read + Seek), end: u64) -> Result<Option<u64>> {
+    let offset = end + 1024;
+    let trailer = match read_block(stream, offset)?.and_then(|v| Trailer::parse(&v)) {
+        Some(v) => v,
+        None => return Ok(None)
+    };
+    if trailer.len != offset {
+        bail!(Error::Corrupted(format!("the trailer expects {} bytes but the archive has {}", trailer.len, offset)));
+    }
+
+    // only zeroes may follow, a shrunk archive zeroes its old trailer
+    let mut pos = offset + BLOCK_SIZE as u64;
+    while let Some(block) = read_block(stream, pos)? {
+        if block.iter().any(|b| *b != 0) {
+            bail!(Error::Corrupted(format!("unexpected data after the trailer at offset {}", pos)));
+        }
+        pos += BLOCK_SIZE as u64;
+    }
+    Ok(Some(offset))
+}
+
+/// Computes the FNV-1a 64 bits digest of the stream start.
+///
+/// # Arguments
+/// * `stream` - The stream to digest.
+/// * `len` - Amount of bytes to digest.
+fn digest(stream: &mut (impl Read + Seek), len: u64) -> Result<u64> {
+    digest_range(stream, 0, len, FNV_OFFSET)
+}
+
+/// Resumes a FNV-1a 64 bits digest over a stream region.
+///
+/// # Arguments
+/// * `stream` - The stream to digest.
+/// * `start` - Region start, where the digest state was left.
+/// * `end` - Region end.
+/// * `state` - Digest state of the bytes before the region.
+fn digest_range(stream: &mut (impl Read + Seek), start: u64, end: u64, state: u64) -> Result<u64> {
+    stream.seek(SeekFrom::Start(start))?;
+    let mut hash = state;
+    let mut buf = [0u8; DEFAULT_BUFFER_SIZE];
+    let len = end - start;
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = stream.read(&mut buf[..remaining.min(DEFAULT_BUFFER_SIZE as u64) as usize])?;
+        if n < 1 {
+            bail!(Error::Corrupted(format!("truncated archive, expected {} bytes", len)));
+        }
+        for b in buf[..n].iter() {
+            hash = (hash ^ *b as u64).wrapping_mul(FNV_PRIME);
+        }
+        remaining -= n as u64;
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::{EntryKind, Metadata};
+    use std::io::Cursor;
+
+    #[test]
+    fn trailer_roundtrip() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.set_trailer(true).unwrap();
+        archive.append_data("a.txt", Metadata::new("a.txt", EntryKind::RegularFile), b"first").unwrap();
+        archive.append_data("b.txt", Metadata::new("b.txt", EntryKind::RegularFile), b"second").unwrap();
+        archive.remove("b.txt").unwrap();
+
+        let mut archive = match Archive::open(archive.into_inner()) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open archive: {}", e);
+                return;
+            }
+        };
+        assert!(archive.has_trailer());
+        assert!(archive.verify_trailer().unwrap());
+        assert_eq!(1, archive.len());
+
+        // tampered content fails the digest while appended data fails the open
+        let mut buf = archive.into_inner().into_inner();
+        buf[512] = b'F';
+        let mut archive = Archive::open(Cursor::new(buf.clone())).unwrap();
+        assert!(!archive.verify_trailer().unwrap());
+        buf.extend_from_slice(&[b'x'; 512]);
+        assert!(Archive::open(Cursor::new(buf)).is_err());
+
+        archive.set_trailer(false).unwrap();
+        let archive = Archive::open(archive.into_inner()).unwrap();
+        assert!(!archive.has_trailer());
+    }
+
+    #[test]
+    fn trailer_refreshed_on_every_change() {
+        let mut archive = Archive::open(Cursor::new(Vec::new())).unwrap();
+        archive.set_trailer(true).unwrap();
+        for path in ["a.txt", "b.txt", "c.txt"] {
+            archive.append_data(path, Metadata::new(path, EntryKind::RegularFile), path.as_bytes()).unwrap();
+
+            // the resumed digest matches a full read
+            match archive.verify_trailer() {
+                Ok(v) => assert!(v),
+                Err(e) => {
+                    assert!(false, "Failed to verify trailer: {}", e);
+                    return;
+                }
+            }
+        }
+
+        // removing or replacing entries before the end refreshes the trailer too
+        archive.remove("a.txt").unwrap();
+        assert!(archive.verify_trailer().unwrap());
+        archive.append_data("b.txt", Metadata::new("b.txt", EntryKind::RegularFile), b"replaced").unwrap();
+        assert!(archive.verify_trailer().unwrap());
+        archive.set_comment(Some("refreshed")).unwrap();
+        assert!(archive.verify_trailer().unwrap());
+        archive.append_data("d.txt", Metadata::new("d.txt", EntryKind::RegularFile), b"last").unwrap();
+        assert!(archive.verify_trailer().unwrap());
+
+        let mut archive = Archive::open(archive.into_inner()).unwrap();
+        assert!(archive.verify_trailer().unwrap());
+        assert_eq!(3, archive.len());
+    }
+}
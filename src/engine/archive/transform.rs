@@ -0,0 +1,233 @@
+use anyhow::{bail, Result};
+use std::sync::Arc;
+
+/// Closure used by custom path transforms.
+pub type TransformFn = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Rename rule applied to entry paths on extraction and creation, same as
+/// tar `--transform`. An empty path result skips the entry.
+#[derive(Clone)]
+pub enum PathTransform {
+    /// Sed-like literal replacement.
+    Replace {
+        /// Literal text to look for.
+        pattern: String,
+        /// Replacement text.
+        replacement: String,
+        /// Only matches at the start of the path.
+        anchor_start: bool,
+        /// Only matches at the end of the path.
+        anchor_end: bool,
+        /// Replaces every match instead of only the first one.
+        global: bool
+    },
+    /// Closure based transform.
+    Custom(TransformFn)
+}
+
+impl PathTransform {
+    /// Parses a sed-like `s/pattern/replacement/[g]` expression. The pattern
+    /// is matched literally except for the `^` and `$` anchors, any character
+    /// can be used as delimiter and escaped with `\`.
+    ///
+    /// # Arguments
+    /// * `expr` - The expression to parse.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The parsed transform.
+    /// * `Err(e)` - If the expression is malformed.
+    pub fn parse(expr: &str) -> Result<Self> {
+        let mut chars = expr.chars();
+        if chars.next() != Some('s') {
+            bail!("invalid transform '{}', expected 's/pattern/replacement/'", expr);
+        }
+        let delimiter = match chars.next() {
+            Some(v) => v,
+            None => bail!("invalid transform '{}', missing delimiter", expr)
+        };
+
+        // split the expression parts handling escaped delimiters
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            if escaped {
+                // anchors keep the escape so they can be told apart later
+                if c != delimiter && (c == '^' || c == '$') {
+                    parts.last_mut().unwrap().push('\\');
+                }
+                parts.last_mut().unwrap().push(c);
+                escaped = false;
+                continue;
+            }
+            match c {
+                '\\' => escaped = true,
+                c if c == delimiter => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c)
+            }
+        }
+        if parts.len() != 3 {
+            bail!("invalid transform '{}', expected 's/pattern/replacement/'", expr);
+        }
+        let flags = parts.pop().unwrap();
+        let replacement = parts.pop().unwrap().replace("\\$", "$").replace("\\^", "^");
+        let mut pattern = parts.pop().unwrap();
+        let global = match flags.as_str() {
+            "" => false,
+            "g" => true,
+            v => bail!("invalid transform flags '{}'", v)
+        };
+        let anchor_start = pattern.starts_with('^');
+        if anchor_start {
+            pattern.remove(0);
+        }
+        let anchor_end = pattern.ends_with('$') && !pattern.ends_with("\\$");
+        if anchor_end {
+            pattern.pop();
+        }
+        if pattern.is_empty() && !anchor_start && !anchor_end {
+            bail!("invalid transform '{}', empty pattern", expr);
+        }
+        Ok(Self::Replace {
+            pattern: pattern.replace("\\$", "$").replace("\\^", "^"),
+            replacement,
+            anchor_start,
+            anchor_end,
+            global
+        })
+    }
+
+    /// Creates a closure based transform.
+    ///
+    /// # Arguments
+    /// * `f` - Closure receiving the current path and returning the new one.
+    pub fn custom(f: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(f))
+    }
+
+    /// Applies the transform to a path.
+    ///
+    /// # Arguments
+    /// * `path` - The path to transform.
+    ///
+    /// # Returns
+    /// * `String` - The transformed path.
+    pub fn apply(&self, path: &str) -> String {
+        match self {
+            Self::Replace { pattern, replacement, anchor_start, anchor_end, global } => {
+                match (anchor_start, anchor_end) {
+                    (true, true) => match path == pattern {
+                        true => replacement.clone(),
+                        false => path.to_string()
+                    },
+                    (true, false) => match path.strip_prefix(pattern.as_str()) {
+                        Some(rest) => format!("{}{}", replacement, rest),
+                        None => path.to_string()
+                    },
+                    (false, true) => match path.strip_suffix(pattern.as_str()) {
+                        Some(rest) => format!("{}{}", rest, replacement),
+                        None => path.to_string()
+                    },
+                    (false, false) => match global {
+                        true => path.replace(pattern.as_str(), replacement),
+                        false => path.replacen(pattern.as_str(), replacement, 1)
+                    }
+                }
+            },
+            Self::Custom(f) => f(path)
+        }
+    }
+}
+
+impl std::fmt::Debug for PathTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Replace { pattern, replacement, anchor_start, anchor_end, global } => f.debug_struct("Replace")
+                .field("pattern", pattern)
+                .field("replacement", replacement)
+                .field("anchor_start", anchor_start)
+                .field("anchor_end", anchor_end)
+                .field("global", global)
+                .finish(),
+            Self::Custom(_) => f.write_str("Custom(..)")
+        }
+    }
+}
+
+impl PartialEq for PathTransform {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Replace { pattern, replacement, anchor_start, anchor_end, global },
+                Self::Replace { pattern: o_pattern, replacement: o_replacement, anchor_start: o_start, anchor_end: o_end, global: o_global }
+            ) => pattern == o_pattern && replacement == o_replacement && anchor_start == o_start && anchor_end == o_end && global == o_global,
+            (Self::Custom(a), Self::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false
+        }
+    }
+}
+
+/// Applies a list of transforms in order to a path.
+///
+/// # Arguments
+/// * `transforms` - Transforms to apply.
+/// * `path` - The path to transform.
+///
+/// # Returns
+/// * `Some(String)` - The transformed path.
+/// * `None` - When a transform emptied the path.
+pub(crate) fn apply_transforms(transforms: &[PathTransform], path: &str) -> Option<String> {
+    let mut path = path.to_string();
+    for transform in transforms {
+        path = transform.apply(&path);
+        if path.is_empty() {
+            return None;
+        }
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_apply() {
+        let transform = match PathTransform::parse("s/^usr/opt/") {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to parse transform: {}", e);
+                return;
+            }
+        };
+        assert_eq!("opt/bin/usr", transform.apply("usr/bin/usr"));
+        assert_eq!("bin/usr", transform.apply("bin/usr"));
+
+        let transform = PathTransform::parse("s,a,b,g").unwrap();
+        assert_eq!("bbb/b", transform.apply("aba/a"));
+        let transform = PathTransform::parse("s/a/b/").unwrap();
+        assert_eq!("bba/a", transform.apply("aba/a"));
+        let transform = PathTransform::parse("s/\\.txt$/.md/").unwrap();
+        assert_eq!("a.txt/b.md", transform.apply("a.txt/b.txt"));
+        let transform = PathTransform::parse("s/a\\/b/c/").unwrap();
+        assert_eq!("c/d", transform.apply("a/b/d"));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(PathTransform::parse("x/a/b/").is_err());
+        assert!(PathTransform::parse("s/a/b").is_err());
+        assert!(PathTransform::parse("s/a/b/q").is_err());
+        assert!(PathTransform::parse("s//b/").is_err());
+    }
+
+    #[test]
+    fn apply_in_order() {
+        let transforms = vec![
+            PathTransform::parse("s/^src/lib/").unwrap(),
+            PathTransform::custom(|path| path.to_uppercase()),
+            PathTransform::custom(|path| if path.ends_with(".O") { String::new() } else { path.to_string() })
+        ];
+        assert_eq!(Some("LIB/A.RS".to_string()), apply_transforms(&transforms, "src/a.rs"));
+        assert_eq!(None, apply_transforms(&transforms, "src/a.o"));
+    }
+}
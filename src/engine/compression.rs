@@ -0,0 +1,329 @@
+mod lazy;
+#[cfg(feature = "zstd")]
+mod seekable;
+
+pub use lazy::LazyDecoder;
+#[cfg(feature = "zstd")]
+pub use seekable::{has_seek_table, SeekableFrame, SeekableZstdReader, SeekableZstdWriter, DEFAULT_FRAME_SIZE};
+
+use anyhow::{bail, Result};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::io::Result as IoResult;
+
+/// Compression formats detected from the stream magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// Plain uncompressed TAR.
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2
+}
+
+impl Compression {
+    /// Detects the compression format out of the stream leading bytes.
+    ///
+    /// # Arguments
+    /// * `magic` - The leading bytes of the stream, at least 10 bytes are needed to detect every format.
+    pub fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            return Self::Gzip;
+        }
+        if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            return Self::Zstd;
+        }
+        if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            return Self::Xz;
+        }
+        // the level digit and block magic are checked too since "BZh" is a valid file name start
+        if magic.len() >= 10 && magic.starts_with(b"BZh") && (b'1'..=b'9').contains(&magic[3])
+            && (magic[4..10] == [0x31, 0x41, 0x59, 0x26, 0x53, 0x59] || magic[4..10] == [0x17, 0x72, 0x45, 0x38, 0x50, 0x90]) {
+            return Self::Bzip2;
+        }
+        Self::None
+    }
+
+    /// Sniffs the compression format of a stream, the stream position is restored.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to sniff.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The detected compression.
+    /// * `Err(e)` - If the stream can't be read.
+    pub fn sniff(stream: &mut (impl Read + Seek)) -> Result<Self> {
+        let pos = stream.stream_position()?;
+        let mut magic = [0u8; 10];
+        let mut read = 0;
+        while read < magic.len() {
+            let n = stream.read(&mut magic[read..])?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+        }
+        stream.seek(SeekFrom::Start(pos))?;
+        Ok(Self::detect(&magic[..read]))
+    }
+
+    /// Wraps a reader with the decoder for this compression.
+    ///
+    /// # Arguments
+    /// * `reader` - The compressed reader.
+    ///
+    /// # Returns
+    /// * `Ok(Box<dyn Read>)` - The decoding reader.
+    /// * `Err(e)` - If the compression support is not enabled.
+    pub fn decoder<'a>(&self, reader: impl Read + 'a) -> Result<Box<dyn Read + 'a>> {
+        match self {
+            Self::None => Ok(Box::new(reader)),
+            #[cfg(feature = "gzip")]
+            Self::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(reader))),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(reader)?)),
+            #[cfg(feature = "xz")]
+            Self::Xz => Ok(Box::new(xz2::read::XzDecoder::new_multi_decoder(reader))),
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => Ok(Box::new(bzip2::read::MultiBzDecoder::new(reader))),
+            #[allow(unreachable_patterns)]
+            v => bail!("{:?} compression support is not enabled", v)
+        }
+    }
+}
+
+/// Location of a decompressed offset within a compressed stream made of
+/// independent frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicalOffset {
+    /// Index of the frame holding the offset.
+    pub frame: usize,
+    /// Offset of the frame within the compressed stream.
+    pub compressed_offset: u64,
+    /// Offset within the decompressed frame content.
+    pub frame_offset: u64,
+}
+
+/// Archive stream that is either the plain stream or the decompressed
+/// content of a compressed one.
+#[derive(Debug)]
+pub enum Decoded<T> {
+    /// Uncompressed stream used as is.
+    Plain(T),
+    /// Decompressed content, read only.
+    Buffered(Cursor<Vec<u8>>, Compression),
+    /// Compressed content decompressed on demand as it's read, read only.
+    Streaming(LazyDecoder<T>),
+    /// Zstd seekable format decompressed on demand, read only.
+    #[cfg(feature = "zstd")]
+    Seekable(SeekableZstdReader<T>)
+}
+
+impl<T: Read + Seek> Decoded<T> {
+    /// Sniffs the stream compression and decompresses it when needed.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to decode.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The decoded stream.
+    /// * `Err(e)` - If the stream can't be read or the compression isn't supported.
+    pub fn new(mut stream: T) -> Result<Self> {
+        let compression = Compression::sniff(&mut stream)?;
+        if compression == Compression::None {
+            return Ok(Self::Plain(stream));
+        }
+        #[cfg(feature = "zstd")]
+        if compression == Compression::Zstd && has_seek_table(&mut stream)? {
+            return Ok(Self::Seekable(SeekableZstdReader::open(stream)?));
+        }
+        let mut buf = Vec::new();
+        compression.decoder(&mut stream)?.read_to_end(&mut buf)?;
+        Ok(Self::Buffered(Cursor::new(buf), compression))
+    }
+
+    /// Sniffs the stream compression without inflating it, compressed
+    /// content is decompressed on demand as it's read so memory usage
+    /// doesn't grow with the entry sizes.
+    ///
+    /// # Arguments
+    /// * `stream` - The stream to decode.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The decoded stream.
+    /// * `Err(e)` - If the stream can't be read or the compression isn't supported.
+    pub fn lazy(mut stream: T) -> Result<Self> {
+        let compression = Compression::sniff(&mut stream)?;
+        if compression == Compression::None {
+            return Ok(Self::Plain(stream));
+        }
+        #[cfg(feature = "zstd")]
+        if compression == Compression::Zstd && has_seek_table(&mut stream)? {
+            return Ok(Self::Seekable(SeekableZstdReader::open(stream)?));
+        }
+        Ok(Self::Streaming(LazyDecoder::new(stream, compression)?))
+    }
+
+    /// Returns the compression of the source stream.
+    pub fn compression(&self) -> Compression {
+        match self {
+            Self::Plain(_) => Compression::None,
+            Self::Buffered(_, compression) => *compression,
+            Self::Streaming(v) => v.compression(),
+            #[cfg(feature = "zstd")]
+            Self::Seekable(_) => Compression::Zstd
+        }
+    }
+
+    /// Maps a decompressed offset to its physical location, only streams
+    /// with independent frames have one.
+    ///
+    /// # Arguments
+    /// * `offset` - Decompressed offset.
+    ///
+    /// # Returns
+    /// * `Some(PhysicalOffset)` - The frame location of the offset.
+    /// * `None` - When the stream has no frames or the offset is out of range.
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+    pub fn locate(&self, offset: u64) -> Option<PhysicalOffset> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Seekable(v) => v.locate(offset),
+            _ => None
+        }
+    }
+
+    /// Seeks to a decompressed offset, jumping straight to its frame when
+    /// the physical location is known.
+    ///
+    /// # Arguments
+    /// * `offset` - Decompressed offset.
+    /// * `location` - Physical location of the offset if known.
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - The new position.
+    /// * `Err(e)` - If the stream can't seek.
+    pub fn seek_located(&mut self, offset: u64, location: Option<&PhysicalOffset>) -> IoResult<u64> {
+        match (self, location) {
+            #[cfg(feature = "zstd")]
+            (Self::Seekable(v), Some(location)) => v.seek_physical(location),
+            (v, _) => v.seek(SeekFrom::Start(offset))
+        }
+    }
+}
+
+impl<T: Read + Seek> Read for Decoded<T> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match self {
+            Self::Plain(v) => v.read(buf),
+            Self::Buffered(v, _) => v.read(buf),
+            Self::Streaming(v) => v.read(buf),
+            #[cfg(feature = "zstd")]
+            Self::Seekable(v) => v.read(buf)
+        }
+    }
+}
+
+impl<T: Read + Seek> Seek for Decoded<T> {
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        match self {
+            Self::Plain(v) => v.seek(pos),
+            Self::Buffered(v, _) => v.seek(pos),
+            Self::Streaming(v) => v.seek(pos),
+            #[cfg(feature = "zstd")]
+            Self::Seekable(v) => v.seek(pos)
+        }
+    }
+}
+
+impl<T: Read + Seek + Write> Write for Decoded<T> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        match self {
+            Self::Plain(v) => v.write(buf),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{:?} compressed archives are read only", self.compression())
+            ))
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match self {
+            Self::Plain(v) => v.flush(),
+            _ => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_magic() {
+        assert_eq!(Compression::Gzip, Compression::detect(&[0x1f, 0x8b, 0x08, 0, 0, 0]));
+        assert_eq!(Compression::Zstd, Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0, 0]));
+        assert_eq!(Compression::Xz, Compression::detect(&[0xfd, b'7', b'z', b'X', b'Z', 0]));
+        assert_eq!(Compression::Bzip2, Compression::detect(b"BZh91AY&SY"));
+        assert_eq!(Compression::None, Compression::detect(b"BZh_file.txt"));
+        assert_eq!(Compression::None, Compression::detect(b"file.txt"));
+        assert_eq!(Compression::None, Compression::detect(&[]));
+    }
+
+    #[test]
+    fn sniff_restores_position() {
+        let mut stream = Cursor::new(vec![0x1f, 0x8b, 1, 2]);
+        match Compression::sniff(&mut stream) {
+            Ok(v) => assert_eq!(Compression::Gzip, v),
+            Err(e) => assert!(false, "Failed to sniff: {}", e)
+        }
+        assert_eq!(0, stream.position());
+    }
+
+    #[test]
+    fn plain_passthrough() {
+        let mut decoded = Decoded::new(Cursor::new(b"plain".to_vec())).unwrap();
+        assert_eq!(Compression::None, decoded.compression());
+        decoded.seek(SeekFrom::End(0)).unwrap();
+        decoded.write_all(b"!").unwrap();
+        let mut buf = String::new();
+        decoded.seek(SeekFrom::Start(0)).unwrap();
+        decoded.read_to_string(&mut buf).unwrap();
+        assert_eq!("plain!", buf);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_decoded() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"compressed").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut decoded = Decoded::new(Cursor::new(compressed)).unwrap();
+        assert_eq!(Compression::Gzip, decoded.compression());
+        let mut buf = String::new();
+        decoded.read_to_string(&mut buf).unwrap();
+        assert_eq!("compressed", buf);
+        assert!(decoded.write_all(b"x").is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn seekable_zstd_decoded() {
+        let mut writer = SeekableZstdWriter::new(Vec::new(), 512, 3);
+        writer.write_all(&[7u8; 2048]).unwrap();
+        let compressed = writer.finish().unwrap();
+        let mut decoded = Decoded::new(Cursor::new(compressed)).unwrap();
+        assert!(matches!(decoded, Decoded::Seekable(_)));
+        decoded.seek(SeekFrom::Start(1500)).unwrap();
+        let mut buf = Vec::new();
+        decoded.read_to_end(&mut buf).unwrap();
+        assert_eq!(vec![7u8; 548], buf);
+
+        let location = decoded.locate(1500).unwrap();
+        assert_eq!(2, location.frame);
+        assert_eq!(476, location.frame_offset);
+        assert_eq!(1500, decoded.seek_located(1500, Some(&location)).unwrap());
+        assert_eq!(None, Decoded::new(Cursor::new(Vec::<u8>::new())).unwrap().locate(0));
+    }
+}
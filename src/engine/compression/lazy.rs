@@ -0,0 +1,246 @@
+use super::Compression;
+use anyhow::{bail, Result};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+
+/// Decoder owning the compressed stream so it can be taken back to rewind.
+enum Inflater<T> {
+    Plain(T),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::read::MultiGzDecoder<T>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::read::Decoder<'static, std::io::BufReader<T>>),
+    #[cfg(feature = "xz")]
+    Xz(xz2::read::XzDecoder<T>),
+    #[cfg(feature = "bzip2")]
+    Bzip2(bzip2::read::MultiBzDecoder<T>)
+}
+
+impl<T: Read> Inflater<T> {
+    /// Wraps a compressed stream with its decoder.
+    ///
+    /// # Arguments
+    /// * `compression` - The stream compression.
+    /// * `stream` - The compressed stream.
+    fn new(compression: Compression, stream: T) -> Result<Self> {
+        match compression {
+            Compression::None => Ok(Self::Plain(stream)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Self::Gzip(flate2::read::MultiGzDecoder::new(stream))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Self::Zstd(zstd::stream::read::Decoder::new(stream)?)),
+            #[cfg(feature = "xz")]
+            Compression::Xz => Ok(Self::Xz(xz2::read::XzDecoder::new_multi_decoder(stream))),
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => Ok(Self::Bzip2(bzip2::read::MultiBzDecoder::new(stream))),
+            #[allow(unreachable_patterns)]
+            v => bail!("{:?} compression support is not enabled", v)
+        }
+    }
+
+    /// Drops the decoder returning the compressed stream.
+    fn into_inner(self) -> T {
+        match self {
+            Self::Plain(v) => v,
+            #[cfg(feature = "gzip")]
+            Self::Gzip(v) => v.into_inner(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(v) => v.finish().into_inner(),
+            #[cfg(feature = "xz")]
+            Self::Xz(v) => v.into_inner(),
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2(v) => v.into_inner()
+        }
+    }
+}
+
+impl<T: Read> Read for Inflater<T> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match self {
+            Self::Plain(v) => v.read(buf),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(v) => v.read(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(v) => v.read(buf),
+            #[cfg(feature = "xz")]
+            Self::Xz(v) => v.read(buf),
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2(v) => v.read(buf)
+        }
+    }
+}
+
+/// Reader decompressing the stream on demand as it's read, so only the
+/// decoder state is kept in memory. Forward seeks decompress and discard
+/// up to the target while backward seeks restart the decoder from the
+/// start of the compressed stream.
+pub struct LazyDecoder<T> {
+    /// Decoder over the compressed stream, only missing after a failed rewind.
+    inner: Option<Inflater<T>>,
+    /// The stream compression.
+    compression: Compression,
+    /// Offset of the compressed content within the stream.
+    start: u64,
+    /// Current decompressed position.
+    pos: u64,
+    /// Decompressed size, known once the end was reached.
+    len: Option<u64>,
+}
+
+impl<T: Read + Seek> LazyDecoder<T> {
+    /// Creates a lazy decoder starting at the current stream position.
+    ///
+    /// # Arguments
+    /// * `stream` - The compressed stream.
+    /// * `compression` - The stream compression.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The lazy decoder.
+    /// * `Err(e)` - If the stream can't be read or the compression support is not enabled.
+    pub fn new(mut stream: T, compression: Compression) -> Result<Self> {
+        let start = stream.stream_position()?;
+        Ok(Self {
+            inner: Some(Inflater::new(compression, stream)?),
+            compression,
+            start,
+            pos: 0,
+            len: None
+        })
+    }
+
+    /// Returns the compression of the source stream.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Gets the decoder or fails when a previous rewind lost it.
+    fn inner(&mut self) -> IoResult<&mut Inflater<T>> {
+        match self.inner.as_mut() {
+            Some(v) => Ok(v),
+            None => Err(IoError::other("the decoder was lost by a failed rewind"))
+        }
+    }
+
+    /// Restarts the decoder from the start of the compressed stream.
+    fn rewind_decoder(&mut self) -> IoResult<()> {
+        let mut stream = match self.inner.take() {
+            Some(v) => v.into_inner(),
+            None => return Err(IoError::other("the decoder was lost by a failed rewind"))
+        };
+        stream.seek(SeekFrom::Start(self.start))?;
+        self.inner = Some(Inflater::new(self.compression, stream).map_err(IoError::other)?);
+        self.pos = 0;
+        Ok(())
+    }
+
+    /// Decompresses and discards content until the target or the end is reached.
+    ///
+    /// # Arguments
+    /// * `target` - Decompressed offset to stop at, `u64::MAX` to reach the end.
+    fn skip_to(&mut self, target: u64) -> IoResult<()> {
+        let amount = target - self.pos;
+        let skipped = std::io::copy(&mut self.inner()?.take(amount), &mut std::io::sink())?;
+        self.pos += skipped;
+        if skipped < amount {
+            self.len = Some(self.pos);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read + Seek> Read for LazyDecoder<T> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let n = self.inner()?.read(buf)?;
+        if n < 1 && !buf.is_empty() {
+            self.len = Some(self.pos);
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<T: Read + Seek> Seek for LazyDecoder<T> {
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        let target = match pos {
+            SeekFrom::Start(v) => v as i128,
+            SeekFrom::Current(v) => self.pos as i128 + v as i128,
+            SeekFrom::End(v) => {
+                // the size is only known after decompressing everything once
+                if self.len.is_none() {
+                    self.skip_to(u64::MAX)?;
+                }
+                self.len.unwrap_or(self.pos) as i128 + v as i128
+            }
+        };
+        if target < 0 {
+            return Err(IoError::new(ErrorKind::InvalidInput, "invalid seek to a negative position"));
+        }
+        let target = target as u64;
+        if target < self.pos {
+            self.rewind_decoder()?;
+        }
+        if target > self.pos {
+            self.skip_to(target)?;
+        }
+        Ok(self.pos)
+    }
+}
+
+impl<T> fmt::Debug for LazyDecoder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyDecoder")
+            .field("compression", &self.compression)
+            .field("start", &self.start)
+            .field("pos", &self.pos)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn plain_seeks() {
+        let mut decoder = LazyDecoder::new(Cursor::new(b"0123456789".to_vec()), Compression::None).unwrap();
+        assert_eq!(6, decoder.seek(SeekFrom::Start(6)).unwrap());
+        let mut buf = [0u8; 2];
+        decoder.read_exact(&mut buf).unwrap();
+        assert_eq!(b"67", &buf);
+        assert_eq!(2, decoder.seek(SeekFrom::Current(-6)).unwrap());
+        decoder.read_exact(&mut buf).unwrap();
+        assert_eq!(b"23", &buf);
+        assert_eq!(8, decoder.seek(SeekFrom::End(-2)).unwrap());
+        assert!(decoder.seek(SeekFrom::Current(-9)).is_err());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_on_demand() {
+        use std::io::Write;
+        let data: Vec<u8> = (0..100_000u32).map(|v| (v % 251) as u8).collect();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut decoder = match LazyDecoder::new(Cursor::new(compressed), Compression::Gzip) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to create decoder: {}", e);
+                return;
+            }
+        };
+        let mut buf = [0u8; 16];
+        decoder.seek(SeekFrom::Start(70_000)).unwrap();
+        decoder.read_exact(&mut buf).unwrap();
+        assert_eq!(&data[70_000..70_016], &buf);
+
+        // going back restarts the decoder
+        decoder.seek(SeekFrom::Start(10)).unwrap();
+        decoder.read_exact(&mut buf).unwrap();
+        assert_eq!(&data[10..26], &buf);
+        assert_eq!(100_000, decoder.seek(SeekFrom::End(0)).unwrap());
+    }
+}
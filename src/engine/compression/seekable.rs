@@ -0,0 +1,442 @@
+use super::PhysicalOffset;
+use anyhow::{bail, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+
+/// Magic number of the skippable frame holding the seek table.
+const SKIPPABLE_MAGIC: u32 = 0x184D2A5E;
+
+/// Magic number closing the seek table footer.
+const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+
+/// Seek table footer size: frame count, descriptor and magic.
+const FOOTER_SIZE: u64 = 9;
+
+/// Default uncompressed size of each independent frame.
+pub const DEFAULT_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Frame location within the compressed and decompressed streams.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeekableFrame {
+    /// Compressed frame offset.
+    pub compressed_offset: u64,
+    /// Compressed frame size.
+    pub compressed_size: u64,
+    /// Decompressed frame offset.
+    pub offset: u64,
+    /// Decompressed frame size.
+    pub size: u64,
+}
+
+/// Writer producing the zstd seekable format: the content is split into
+/// independent frames followed by a seek table so readers can decompress
+/// any frame without decompressing from the start.
+pub struct SeekableZstdWriter<W: Write> {
+    /// Compressed output.
+    writer: W,
+    /// Pending uncompressed bytes of the current frame.
+    buf: Vec<u8>,
+    /// Uncompressed size of each frame.
+    frame_size: usize,
+    /// Compression level.
+    level: i32,
+    /// Written frames as (compressed size, decompressed size).
+    frames: Vec<(u32, u32)>,
+}
+
+impl<W: Write> SeekableZstdWriter<W> {
+    /// Creates a new seekable zstd writer.
+    ///
+    /// # Arguments
+    /// * `writer` - Compressed output.
+    /// * `frame_size` - Uncompressed size of each frame, smaller frames allow cheaper random access.
+    /// * `level` - Zstd compression level.
+    pub fn new(writer: W, frame_size: usize, level: i32) -> Self {
+        let frame_size = frame_size.clamp(1, u32::MAX as usize);
+        Self {
+            writer,
+            buf: Vec::with_capacity(frame_size),
+            frame_size,
+            level,
+            frames: Vec::new()
+        }
+    }
+
+    /// Compresses the pending bytes as an independent frame.
+    fn flush_frame(&mut self) -> IoResult<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let compressed = zstd::bulk::compress(&self.buf, self.level)?;
+        if compressed.len() > u32::MAX as usize {
+            return Err(IoError::new(ErrorKind::InvalidData, "compressed frame too large"));
+        }
+        self.writer.write_all(&compressed)?;
+        self.frames.push((compressed.len() as u32, self.buf.len() as u32));
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Writes the last frame and the seek table.
+    ///
+    /// # Returns
+    /// * `Ok(W)` - The inner writer.
+    /// * `Err(e)` - If write fails.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush_frame()?;
+        let mut table = Vec::with_capacity(self.frames.len() * 8 + 17);
+        table.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+        table.extend_from_slice(&((self.frames.len() * 8) as u32 + FOOTER_SIZE as u32).to_le_bytes());
+        for (compressed, decompressed) in self.frames.iter() {
+            table.extend_from_slice(&compressed.to_le_bytes());
+            table.extend_from_slice(&decompressed.to_le_bytes());
+        }
+        table.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        table.push(0);
+        table.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+        self.writer.write_all(&table)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for SeekableZstdWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let n = buf.len().min(self.frame_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() >= self.frame_size {
+            self.flush_frame()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.writer.flush()
+    }
+}
+
+/// Tells whether a stream ends with a zstd seek table, the stream position is restored.
+///
+/// # Arguments
+/// * `reader` - The stream to check.
+pub fn has_seek_table(reader: &mut (impl Read + Seek)) -> Result<bool> {
+    let pos = reader.stream_position()?;
+    let len = reader.seek(SeekFrom::End(0))?;
+    if len < FOOTER_SIZE + 8 {
+        reader.seek(SeekFrom::Start(pos))?;
+        return Ok(false);
+    }
+    let mut magic = [0u8; 4];
+    reader.seek(SeekFrom::End(-4))?;
+    reader.read_exact(&mut magic)?;
+    reader.seek(SeekFrom::Start(pos))?;
+    Ok(u32::from_le_bytes(magic) == SEEKABLE_MAGIC)
+}
+
+/// Random access reader over the zstd seekable format, only the frame
+/// containing the current position is decompressed.
+#[derive(Debug)]
+pub struct SeekableZstdReader<R> {
+    /// Compressed input.
+    reader: R,
+    /// Frames from the seek table.
+    frames: Vec<SeekableFrame>,
+    /// Total decompressed size.
+    len: u64,
+    /// Current decompressed position.
+    pos: u64,
+    /// Decompressed frame cache as (frame index, content).
+    cache: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> SeekableZstdReader<R> {
+    /// Opens a seekable zstd stream reading its seek table.
+    ///
+    /// # Arguments
+    /// * `reader` - Compressed input.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The seekable reader.
+    /// * `Err(e)` - If the stream has no valid seek table.
+    pub fn open(mut reader: R) -> Result<Self> {
+        let stream_len = reader.seek(SeekFrom::End(0))?;
+        if stream_len < FOOTER_SIZE + 8 {
+            bail!("missing zstd seek table");
+        }
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        reader.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        reader.read_exact(&mut footer)?;
+        if u32::from_le_bytes(footer[5..9].try_into()?) != SEEKABLE_MAGIC {
+            bail!("missing zstd seek table");
+        }
+        let count = u32::from_le_bytes(footer[0..4].try_into()?) as u64;
+        let entry_size = if footer[4] & 0x80 != 0 { 12 } else { 8 };
+        let table_size = count * entry_size + FOOTER_SIZE + 8;
+        if table_size > stream_len {
+            bail!("invalid zstd seek table size");
+        }
+
+        // read the seek table entries
+        let table_offset = stream_len - table_size;
+        reader.seek(SeekFrom::Start(table_offset))?;
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        if u32::from_le_bytes(header[0..4].try_into()?) != SKIPPABLE_MAGIC {
+            bail!("invalid zstd seek table frame");
+        }
+        let mut entries = vec![0u8; (count * entry_size) as usize];
+        reader.read_exact(&mut entries)?;
+        let mut frames = Vec::with_capacity(count as usize);
+        let (mut compressed_offset, mut offset) = (0, 0);
+        for entry in entries.chunks(entry_size as usize) {
+            let compressed_size = u32::from_le_bytes(entry[0..4].try_into()?) as u64;
+            let size = u32::from_le_bytes(entry[4..8].try_into()?) as u64;
+            frames.push(SeekableFrame { compressed_offset, compressed_size, offset, size });
+            compressed_offset += compressed_size;
+            offset += size;
+        }
+        if compressed_offset != table_offset {
+            bail!("zstd seek table doesn't match the stream frames");
+        }
+        Ok(Self {
+            reader,
+            frames,
+            len: offset,
+            pos: 0,
+            cache: None
+        })
+    }
+
+    /// Returns the seek table frames.
+    pub fn frames(&self) -> &[SeekableFrame] {
+        &self.frames
+    }
+
+    /// Returns the total decompressed size.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Tells whether the decompressed content is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Maps a decompressed offset to its frame within the compressed stream.
+    ///
+    /// # Arguments
+    /// * `offset` - Decompressed offset.
+    ///
+    /// # Returns
+    /// * `Some(PhysicalOffset)` - The frame location of the offset.
+    /// * `None` - When the offset is past the decompressed content.
+    pub fn locate(&self, offset: u64) -> Option<PhysicalOffset> {
+        let index = self.frames.partition_point(|f| f.offset + f.size <= offset);
+        let frame = self.frames.get(index)?;
+        Some(PhysicalOffset {
+            frame: index,
+            compressed_offset: frame.compressed_offset,
+            frame_offset: offset - frame.offset
+        })
+    }
+
+    /// Seeks straight to a frame location, decompressing the frame without
+    /// looking it up on the seek table.
+    ///
+    /// # Arguments
+    /// * `location` - Frame location as returned by `locate`.
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - The new decompressed position.
+    /// * `Err(e)` - If the frame doesn't exists or can't be read.
+    pub fn seek_physical(&mut self, location: &PhysicalOffset) -> IoResult<u64> {
+        let frame = match self.frames.get(location.frame) {
+            Some(v) => *v,
+            None => return Err(IoError::new(ErrorKind::InvalidInput, "invalid zstd frame index"))
+        };
+        if frame.compressed_offset != location.compressed_offset {
+            return Err(IoError::new(ErrorKind::InvalidInput, "zstd frame location doesn't match the seek table"));
+        }
+        self.load_frame(location.frame)?;
+        self.pos = frame.offset + location.frame_offset;
+        Ok(self.pos)
+    }
+
+    /// Consumes the reader returning the compressed input.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Loads the frame at an index into the cache.
+    fn load_frame(&mut self, index: usize) -> IoResult<()> {
+        if self.cache.as_ref().is_some_and(|(i, _)| *i == index) {
+            return Ok(());
+        }
+        let frame = self.frames[index];
+        let mut compressed = vec![0u8; frame.compressed_size as usize];
+        self.reader.seek(SeekFrom::Start(frame.compressed_offset))?;
+        self.reader.read_exact(&mut compressed)?;
+        let content = zstd::bulk::decompress(&compressed, frame.size as usize)?;
+        if content.len() as u64 != frame.size {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("zstd frame {} decompressed to {} bytes but the seek table says {}", index, content.len(), frame.size)
+            ));
+        }
+        self.cache = Some((index, content));
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableZstdReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        // the cached frame is reused without searching the seek table
+        let index = match &self.cache {
+            Some((i, _)) if self.frames[*i].offset <= self.pos && self.pos < self.frames[*i].offset + self.frames[*i].size => *i,
+            _ => self.frames.partition_point(|f| f.offset + f.size <= self.pos)
+        };
+        self.load_frame(index)?;
+        let frame = self.frames[index];
+        let content = match &self.cache {
+            Some((_, content)) => content,
+            None => return Ok(0)
+        };
+        let start = (self.pos - frame.offset) as usize;
+        let remaining = match content.len().checked_sub(start) {
+            Some(v) if v > 0 => v,
+            _ => return Err(IoError::new(ErrorKind::InvalidData, format!("zstd frame {} is shorter than its seek table size", index)))
+        };
+        let n = buf.len().min(remaining);
+        buf[..n].copy_from_slice(&content[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableZstdReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        let pos = match pos {
+            SeekFrom::Start(v) => v as i128,
+            SeekFrom::End(v) => self.len as i128 + v as i128,
+            SeekFrom::Current(v) => self.pos as i128 + v as i128
+        };
+        if pos < 0 {
+            return Err(IoError::new(ErrorKind::InvalidInput, "invalid seek to a negative position"));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample() -> Vec<u8> {
+        (0..10_000u32).flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn round_trip_frames() {
+        let content = sample();
+        let mut writer = SeekableZstdWriter::new(Vec::new(), 4096, 3);
+        writer.write_all(&content).unwrap();
+        let compressed = match writer.finish() {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to finish: {}", e);
+                return;
+            }
+        };
+        let mut reader = match SeekableZstdReader::open(Cursor::new(compressed)) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open: {}", e);
+                return;
+            }
+        };
+        assert_eq!(10, reader.frames().len());
+        assert_eq!(content.len() as u64, reader.len());
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(content, out);
+    }
+
+    #[test]
+    fn random_access() {
+        let content = sample();
+        let mut writer = SeekableZstdWriter::new(Vec::new(), 1000, 3);
+        writer.write_all(&content).unwrap();
+        let mut reader = SeekableZstdReader::open(Cursor::new(writer.finish().unwrap())).unwrap();
+
+        // read across a frame boundary
+        let mut buf = [0u8; 16];
+        reader.seek(SeekFrom::Start(29_992)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&content[29_992..30_008], &buf);
+        reader.seek(SeekFrom::End(-4)).unwrap();
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(9_999u32.to_le_bytes(), buf);
+        assert_eq!(0, reader.read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn locate_and_seek_physical() {
+        let content = sample();
+        let mut writer = SeekableZstdWriter::new(Vec::new(), 1000, 3);
+        writer.write_all(&content).unwrap();
+        let mut reader = SeekableZstdReader::open(Cursor::new(writer.finish().unwrap())).unwrap();
+        let location = match reader.locate(2_500) {
+            Some(v) => v,
+            None => {
+                assert!(false, "expected a frame location");
+                return;
+            }
+        };
+        assert_eq!(2, location.frame);
+        assert_eq!(500, location.frame_offset);
+        assert_eq!(reader.frames()[2].compressed_offset, location.compressed_offset);
+        assert_eq!(None, reader.locate(content.len() as u64));
+
+        assert_eq!(2_500, reader.seek_physical(&location).unwrap());
+        let mut buf = [0u8; 8];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&content[2_500..2_508], &buf);
+        let invalid = PhysicalOffset { frame: 99, ..location };
+        assert!(reader.seek_physical(&invalid).is_err());
+    }
+
+    #[test]
+    fn reject_short_frames() {
+        let content = sample();
+        let mut writer = SeekableZstdWriter::new(Vec::new(), 1000, 3);
+        writer.write_all(&content).unwrap();
+        let mut compressed = writer.finish().unwrap();
+
+        // the seek table claims a bigger first frame than its content
+        let footer = compressed.len() - FOOTER_SIZE as usize;
+        let count = u32::from_le_bytes(compressed[footer..footer + 4].try_into().unwrap()) as usize;
+        let entry_size = if compressed[footer + 4] & 0x80 != 0 { 12 } else { 8 };
+        let first = footer - count * entry_size;
+        compressed[first + 4..first + 8].copy_from_slice(&1500u32.to_le_bytes());
+        let mut reader = SeekableZstdReader::open(Cursor::new(compressed)).unwrap();
+        reader.seek(SeekFrom::Start(1200)).unwrap();
+        let mut buf = [0u8; 8];
+        match reader.read(&mut buf) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(ErrorKind::InvalidData, e.kind())
+        }
+    }
+
+    #[test]
+    fn reject_plain_zstd() {
+        let compressed = zstd::bulk::compress(b"not seekable", 3).unwrap();
+        assert!(!has_seek_table(&mut Cursor::new(compressed.clone())).unwrap());
+        assert!(SeekableZstdReader::open(Cursor::new(compressed)).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::io::{Error as IoError, ErrorKind};
+use thiserror::Error;
+
+use crate::engine::header::PosixViolation;
+
+/// Errors specific to the TAR engine.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The file was deleted or moved after the sub file handle was opened.
+    #[error("stale handle for '{0}', the file was deleted or moved")]
+    StaleHandle(String),
+    /// An entry with the same path already exists.
+    #[error("entry '{0}' already exists")]
+    AlreadyExists(String),
+    /// An index position past the index end.
+    #[error("index {0} out of bounds")]
+    OutOfBounds(usize),
+    /// A content range starting past the entry content end.
+    #[error("range of {len} bytes at {offset} is past the end of '{path}'")]
+    InvalidRange {
+        /// Entry path.
+        path: String,
+        /// Range start.
+        offset: u64,
+        /// Range length.
+        len: u64,
+    },
+    /// The index or archive structure is corrupted.
+    #[error("corrupted archive: {0}")]
+    Corrupted(String),
+    /// An entry would grow past the configured maximum entry size.
+    #[error("entry '{path}' size {size} exceeds the {limit} bytes limit")]
+    QuotaExceeded {
+        /// Entry path.
+        path: String,
+        /// Size the entry would reach.
+        size: u64,
+        /// Maximum entry size.
+        limit: u64,
+    },
+}
+
+impl Error {
+    /// Gets the IO error kind matching this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::StaleHandle(_) => ErrorKind::StaleNetworkFileHandle,
+            Error::AlreadyExists(_) => ErrorKind::AlreadyExists,
+            Error::OutOfBounds(_) => ErrorKind::InvalidInput,
+            Error::InvalidRange { .. } => ErrorKind::InvalidInput,
+            Error::Corrupted(_) => ErrorKind::InvalidData,
+            Error::QuotaExceeded { .. } => ErrorKind::FileTooLarge,
+        }
+    }
+}
+
+impl From<Error> for IoError {
+    fn from(value: Error) -> Self {
+        IoError::new(value.kind(), value)
+    }
+}
+
+/// Converts an engine error into an IO error, keeping the kind of the
+/// first IO or engine error found on its chain so IO trait impls don't
+/// return plain `Other` errors.
+///
+/// # Arguments
+/// * `err` - The error to convert.
+///
+/// # Returns
+/// * `IoError` - The IO error with the matching kind.
+pub fn to_io_error(err: anyhow::Error) -> IoError {
+    let err = match err.downcast::<IoError>() {
+        Ok(v) => return v,
+        Err(e) => e
+    };
+    let err = match err.downcast::<Error>() {
+        Ok(v) => return v.into(),
+        Err(e) => e
+    };
+    let kind = err.chain().find_map(|cause| {
+        if let Some(v) = cause.downcast_ref::<IoError>() {
+            return Some(v.kind());
+        }
+        if let Some(v) = cause.downcast_ref::<Error>() {
+            return Some(v.kind());
+        }
+        if cause.is::<PosixViolation>() {
+            return Some(ErrorKind::InvalidData);
+        }
+        None
+    });
+    let message = format!("{:#}", err);
+    IoError::new(kind.unwrap_or(ErrorKind::Other), message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn io_error_passthrough() {
+        let err = anyhow::Error::new(IoError::new(ErrorKind::UnexpectedEof, "eof"));
+        let err = to_io_error(err);
+        assert_eq!(ErrorKind::UnexpectedEof, err.kind());
+        assert_eq!("eof", err.to_string());
+    }
+
+    #[test]
+    fn engine_error_kind() {
+        let err = to_io_error(Error::AlreadyExists("a.txt".to_string()).into());
+        assert_eq!(ErrorKind::AlreadyExists, err.kind());
+        let err = to_io_error(Error::OutOfBounds(3).into());
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+        let err = to_io_error(Error::QuotaExceeded { path: "a.txt".to_string(), size: 2, limit: 1 }.into());
+        assert_eq!(ErrorKind::FileTooLarge, err.kind());
+    }
+
+    #[test]
+    fn context_chain_kind() {
+        let err: anyhow::Result<()> = Err(IoError::new(ErrorKind::NotFound, "missing"));
+        let err = to_io_error(err.context("failed to load header").unwrap_err());
+        assert_eq!(ErrorKind::NotFound, err.kind());
+        assert_eq!("failed to load header: missing", err.to_string());
+
+        let err = to_io_error(anyhow::Error::new(PosixViolation::InvalidMagic).context("validation"));
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        assert_eq!(ErrorKind::Other, to_io_error(anyhow::anyhow!("unknown")).kind());
+    }
+}
@@ -1,30 +1,43 @@
-pub mod helper;
+pub mod custom;
+pub mod dump;
+pub(crate) mod helper;
 pub mod ustar;
 pub mod gnu;
+pub mod namespace;
 pub mod pax;
 pub mod v7;
+pub mod validate;
 mod traits;
+#[cfg(test)]
+pub(crate) mod strategy;
 
 pub use traits::{UsedBlocksTrait, IsTypeTrait};
+pub use custom::{register_handler, unregister_handlers, CustomHeader, HeaderHandler, HeaderMatch};
 pub use ustar::{UstarHeader, UstarTypeFlag};
 pub use gnu::{GnuHeader, GnuTypeFlag};
-pub use pax::{Attribute as PaxAttribute, PaxHeader, PaxTypeFlag};
+pub use namespace::{register_namespace, unregister_namespace, Namespace, ValueType};
+pub use pax::{Attribute as PaxAttribute, PaxHeader, PaxTypeFlag, Value as PaxValue};
 pub use v7::{V7Header, V7TypeFlag};
+pub use validate::PosixViolation;
 
 use anyhow::Result;
 use std::io::{Read, Write};
 
 /// Represents any supported TAR header.
+#[non_exhaustive]
 pub enum TarHeader {
     Ustar(UstarHeader),
     Gnu(GnuHeader),
     Pax(PaxHeader),
     V7(V7Header),
+    Custom(CustomHeader),
     Unknown([u8; 512], usize),
 }
 
 impl TarHeader {
-    /// Loads a TAR header from the reader.
+    /// Loads a TAR header from the reader. Blocks the built-in parsers don't
+    /// recognize are dispatched to the registered custom handlers before
+    /// falling back to V7, see `register_handler`.
     ///
     /// # Arguments
     /// * `reader` - Byte reader.
@@ -39,7 +52,19 @@ impl TarHeader {
             // Return read bytes as Unknown
             return Ok(TarHeader::Unknown(buf, readed));
         }
+        Self::load_block(buf, reader)
+    }
 
+    /// Loads a TAR header whose first block was already read.
+    ///
+    /// # Arguments
+    /// * `buf` - The first header block.
+    /// * `reader` - Byte reader positioned after the block.
+    ///
+    /// # Returns
+    /// * `Ok(Self)` - The loaded header.
+    /// * `Err(e)` - If header could not be read or parsed.
+    pub fn load_block(buf: [u8; 512], reader: &mut impl Read) -> Result<Self> {
         // load header from buffer based on its magic and version
         if let Some(header) = GnuHeader::load(&buf, reader)? {
             return Ok(TarHeader::Gnu(header));
@@ -50,6 +75,9 @@ impl TarHeader {
         if let Some(header) = UstarHeader::load(&buf)? {
             return Ok(TarHeader::Ustar(header));
         }
+        if let Some(header) = custom::dispatch(&buf, reader)? {
+            return Ok(TarHeader::Custom(header));
+        }
         if let Some(header) = V7Header::load(&buf)? {
             return Ok(TarHeader::V7(header));
         }
@@ -72,6 +100,7 @@ impl TarHeader {
             TarHeader::Gnu(h) => h.save(writer),
             TarHeader::Pax(h) => h.save(writer),
             TarHeader::V7(h) => h.save(writer),
+            TarHeader::Custom(h) => Ok(writer.write_all(&h.raw)?),
             TarHeader::Unknown(bytes, size) => {
                 if *size > 0 {
                     writer.write_all(&bytes[0..*size])?;
@@ -81,6 +110,18 @@ impl TarHeader {
         }
     }
 
+    /// Formats an unknown header block as an annotated hexdump for diagnostics.
+    ///
+    /// # Returns
+    /// * `Some(String)` - The annotated hexdump when the header is unknown.
+    /// * `None` - When the header was parsed.
+    pub fn hexdump(&self) -> Option<String> {
+        match self {
+            TarHeader::Unknown(bytes, size) => Some(dump::hexdump(&bytes[..*size])),
+            _ => None
+        }
+    }
+
     /// Returns the size of the content in bytes.
     pub fn get_content_size(&self) -> u64 {
         match self {
@@ -88,6 +129,7 @@ impl TarHeader {
             TarHeader::Gnu(h) => h.size,
             TarHeader::Pax(h) => h.size,
             TarHeader::V7(h) => h.size,
+            TarHeader::Custom(h) => h.header.size,
             TarHeader::Unknown(_, _) => 0,
         }
     }
@@ -100,6 +142,7 @@ impl UsedBlocksTrait for TarHeader {
             Self::Gnu(h) => h.get_used_blocks(),
             Self::Pax(h) => h.get_used_blocks(),
             Self::V7(h) => h.get_used_blocks(),
+            Self::Custom(h) => h.used_blocks(),
             Self::Unknown(_, _) => 0,
         }
     }
@@ -110,6 +153,7 @@ impl UsedBlocksTrait for TarHeader {
             Self::Gnu(h) => h.get_saved_blocks(),
             Self::Pax(h) => h.get_saved_blocks(),
             Self::V7(h) => h.get_saved_blocks(),
+            Self::Custom(h) => h.used_blocks(),
             Self::Unknown(_, _) => 0,
         }
     }
@@ -120,6 +164,7 @@ impl UsedBlocksTrait for TarHeader {
             Self::Gnu(h) => h.calc_used_blocks(),
             Self::Pax(h) => h.calc_used_blocks(),
             Self::V7(h) => h.calc_used_blocks(),
+            Self::Custom(h) => h.used_blocks(),
             Self::Unknown(_, _) => 0,
         }
     }
@@ -132,6 +177,7 @@ impl IsTypeTrait for TarHeader {
             Self::Gnu(h) => h.typeflag.is_regular_file(),
             Self::Pax(h) => h.typeflag.is_regular_file(),
             Self::V7(h) => h.typeflag.is_regular_file(),
+            Self::Custom(h) => h.header.typeflag.is_regular_file(),
             Self::Unknown(_, _) => false,
         }
     }
@@ -142,6 +188,7 @@ impl IsTypeTrait for TarHeader {
             Self::Gnu(h) => h.typeflag.is_hard_link(),
             Self::Pax(h) => h.typeflag.is_hard_link(),
             Self::V7(h) => h.typeflag.is_hard_link(),
+            Self::Custom(h) => h.header.typeflag.is_hard_link(),
             Self::Unknown(_, _) => false,
         }
     }
@@ -152,6 +199,7 @@ impl IsTypeTrait for TarHeader {
             Self::Gnu(h) => h.typeflag.is_symbolic_link(),
             Self::Pax(h) => h.typeflag.is_symbolic_link(),
             Self::V7(h) => h.typeflag.is_symbolic_link(),
+            Self::Custom(h) => h.header.typeflag.is_symbolic_link(),
             Self::Unknown(_, _) => false,
         }
     }
@@ -162,6 +210,7 @@ impl IsTypeTrait for TarHeader {
             Self::Gnu(h) => h.typeflag.is_character_special(),
             Self::Pax(h) => h.typeflag.is_character_special(),
             Self::V7(h) => h.typeflag.is_character_special(),
+            Self::Custom(h) => h.header.typeflag.is_character_special(),
             Self::Unknown(_, _) => false,
         }
     }
@@ -172,6 +221,7 @@ impl IsTypeTrait for TarHeader {
             Self::Gnu(h) => h.typeflag.is_block_special(),
             Self::Pax(h) => h.typeflag.is_block_special(),
             Self::V7(h) => h.typeflag.is_block_special(),
+            Self::Custom(h) => h.header.typeflag.is_block_special(),
             Self::Unknown(_, _) => false,
         }
     }
@@ -182,6 +232,7 @@ impl IsTypeTrait for TarHeader {
             Self::Gnu(h) => h.typeflag.is_directory(),
             Self::Pax(h) => h.typeflag.is_directory(),
             Self::V7(h) => h.typeflag.is_directory(),
+            Self::Custom(h) => h.header.typeflag.is_directory(),
             Self::Unknown(_, _) => false,
         }
     }
@@ -192,6 +243,7 @@ impl IsTypeTrait for TarHeader {
             Self::Gnu(h) => h.typeflag.is_fifo(),
             Self::Pax(h) => h.typeflag.is_fifo(),
             Self::V7(h) => h.typeflag.is_fifo(),
+            Self::Custom(h) => h.header.typeflag.is_fifo(),
             Self::Unknown(_, _) => false,
         }
     }
@@ -202,6 +254,7 @@ impl IsTypeTrait for TarHeader {
             Self::Gnu(h) => h.typeflag.is_contiguous_file(),
             Self::Pax(h) => h.typeflag.is_contiguous_file(),
             Self::V7(h) => h.typeflag.is_contiguous_file(),
+            Self::Custom(h) => h.header.typeflag.is_contiguous_file(),
             Self::Unknown(_, _) => false,
         }
     }
@@ -317,4 +370,28 @@ mod tests {
             _ => panic!("Did not round-trip Unknown header"),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn hexdump_unknown() {
+        let mut bytes = [0xFFu8; 512];
+        bytes[257..263].copy_from_slice(b"bogus!");
+        let header = TarHeader::Unknown(bytes, 300);
+        let dump = match header.hexdump() {
+            Some(v) => v,
+            None => {
+                assert!(false, "expected an hexdump for an unknown header");
+                return;
+            }
+        };
+        assert!(dump.starts_with("000  name      ff ff"));
+        assert!(dump.lines().last().unwrap().starts_with("129  gname "));
+
+        let header = TarHeader::Ustar(UstarHeader::load(&{
+            let mut buf = [0u8; 512];
+            buf[257..263].copy_from_slice(b"ustar\0");
+            buf[263..265].copy_from_slice(b"00");
+            buf
+        }).unwrap().unwrap());
+        assert!(header.hexdump().is_none());
+    }
+}
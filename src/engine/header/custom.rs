@@ -0,0 +1,255 @@
+use anyhow::{bail, Result};
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+
+use super::UstarHeader;
+
+/// Installed handlers in registration order.
+static HANDLERS: RwLock<Vec<(HeaderMatch, Arc<dyn HeaderHandler>)>> = RwLock::new(Vec::new());
+
+/// Selects the header blocks a custom handler is asked to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderMatch {
+    /// Blocks with this type flag byte.
+    TypeFlag(u8),
+    /// Blocks with this magic on the USTAR magic field.
+    Magic([u8; 6]),
+}
+
+impl HeaderMatch {
+    /// Tells whether a header block matches.
+    ///
+    /// # Arguments
+    /// * `block` - Raw header block.
+    pub fn matches(&self, block: &[u8; 512]) -> bool {
+        match self {
+            HeaderMatch::TypeFlag(v) => block[156] == *v,
+            HeaderMatch::Magic(v) => block[257..263] == *v
+        }
+    }
+}
+
+/// Parses the headers of a proprietary tar dialect.
+pub trait HeaderHandler: Send + Sync {
+    /// Parses a matching header block. Whether the block is supported must
+    /// be told from the block alone, the reader may not be seekable.
+    ///
+    /// # Arguments
+    /// * `block` - Raw header block.
+    /// * `reader` - Reader positioned after the block, for headers spanning several blocks.
+    ///
+    /// # Returns
+    /// * `Ok(Some(CustomHeader))` - The parsed header.
+    /// * `Ok(None)` - When the handler doesn't support the block, the next handler is tried. Nothing may be read
+    ///   from the reader.
+    /// * `Err(e)` - If the header is corrupted or can't be read.
+    fn load(&self, block: &[u8; 512], reader: &mut dyn Read) -> Result<Option<CustomHeader>>;
+}
+
+/// Header parsed by a custom handler, built with `CustomHeader::new` so
+/// fields can be added later.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct CustomHeader {
+    /// Entry the header describes, its type flag must be a known USTAR one.
+    pub header: UstarHeader,
+    /// Raw header blocks as stored, written back on save.
+    pub raw: Vec<u8>,
+}
+
+impl CustomHeader {
+    /// Creates a custom header.
+    ///
+    /// # Arguments
+    /// * `header` - Entry the header describes.
+    /// * `raw` - Raw header blocks as stored.
+    pub fn new(header: UstarHeader, raw: Vec<u8>) -> Self {
+        Self { header, raw }
+    }
+
+    /// Returns the number of blocks the raw header uses.
+    pub fn used_blocks(&self) -> usize {
+        self.raw.len().div_ceil(512)
+    }
+}
+
+/// Installs a handler for the header blocks the built-in GNU, PAX and USTAR
+/// parsers don't recognize, it's tried before the V7 fallback. Handlers are
+/// process wide and tried in registration order.
+///
+/// # Arguments
+/// * `matcher` - Blocks the handler is asked to parse.
+/// * `handler` - The handler.
+pub fn register_handler(matcher: HeaderMatch, handler: impl HeaderHandler + 'static) {
+    let mut handlers = HANDLERS.write().unwrap_or_else(|e| e.into_inner());
+    handlers.push((matcher, Arc::new(handler)));
+}
+
+/// Removes the handlers installed for a matcher.
+///
+/// # Arguments
+/// * `matcher` - The matcher the handlers were installed for.
+///
+/// # Returns
+/// * `usize` - The amount of removed handlers.
+pub fn unregister_handlers(matcher: HeaderMatch) -> usize {
+    let mut handlers = HANDLERS.write().unwrap_or_else(|e| e.into_inner());
+    let len = handlers.len();
+    handlers.retain(|(v, _)| *v != matcher);
+    len - handlers.len()
+}
+
+/// Dispatches a header block to the matching handlers, a handler declining
+/// the block after reading past it fails since the bytes can't be given
+/// back to the reader.
+///
+/// # Arguments
+/// * `block` - Raw header block.
+/// * `reader` - Reader positioned after the block.
+///
+/// # Returns
+/// * `Ok(Some(CustomHeader))` - The header parsed by the first handler supporting it.
+/// * `Ok(None)` - When no handler supports the block.
+/// * `Err(e)` - If a handler fails or declines the block after reading past it.
+pub(super) fn dispatch(block: &[u8; 512], reader: &mut dyn Read) -> Result<Option<CustomHeader>> {
+    // the handlers are cloned so a handler can register others without a deadlock
+    let handlers: Vec<Arc<dyn HeaderHandler>> = {
+        let handlers = HANDLERS.read().unwrap_or_else(|e| e.into_inner());
+        handlers.iter()
+            .filter(|(matcher, _)| matcher.matches(block))
+            .map(|(_, handler)| handler.clone())
+            .collect()
+    };
+    let mut reader = CountingReader { inner: reader, read: 0 };
+    for handler in handlers {
+        if let Some(mut header) = handler.load(block, &mut reader)? {
+            if header.raw.is_empty() {
+                header.raw = block.to_vec();
+            }
+            return Ok(Some(header));
+        }
+        if reader.read > 0 {
+            bail!("custom header handler declined the block after reading {} bytes past it", reader.read);
+        }
+    }
+    Ok(None)
+}
+
+/// Reader counting the bytes the handlers read past the header block.
+struct CountingReader<'a> {
+    /// The wrapped reader.
+    inner: &'a mut dyn Read,
+    /// Bytes read so far.
+    read: u64,
+}
+
+impl Read for CountingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::header::{IsTypeTrait, TarHeader, UsedBlocksTrait, UstarTypeFlag};
+    use std::io::Cursor;
+
+    /// Dialect storing the name on the block start and the size as a little
+    /// endian integer on a second block.
+    struct Dialect;
+
+    impl HeaderHandler for Dialect {
+        fn load(&self, block: &[u8; 512], reader: &mut dyn Read) -> Result<Option<CustomHeader>> {
+            let mut extra = [0u8; 512];
+            reader.read_exact(&mut extra)?;
+            let mut header = UstarHeader::new(UstarTypeFlag::RegularFile);
+            header.name = String::from_utf8_lossy(&block[..block.iter().position(|b| *b == 0).unwrap_or(100)])
+                .into_owned();
+            header.size = u64::from_le_bytes(extra[..8].try_into().unwrap());
+            Ok(Some(CustomHeader::new(header, [block.as_slice(), extra.as_slice()].concat())))
+        }
+    }
+
+    /// Handler declining every header, peeking the next block first when
+    /// told to.
+    struct Decline(bool);
+
+    impl HeaderHandler for Decline {
+        fn load(&self, _block: &[u8; 512], reader: &mut dyn Read) -> Result<Option<CustomHeader>> {
+            if self.0 {
+                let mut extra = [0u8; 512];
+                reader.read_exact(&mut extra)?;
+            }
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn declined_header() {
+        let mut buf = vec![0u8; 1024];
+        buf[..5].copy_from_slice(b"b.txt");
+        buf[156] = b'0';
+        buf[257..263].copy_from_slice(b"rtdcl\0");
+
+        // the V7 fallback sees a non seekable reader right after the header block
+        register_handler(HeaderMatch::Magic(*b"rtdcl\0"), Decline(false));
+        let mut stream = &buf[..];
+        let header = match TarHeader::load(&mut stream) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to load header: {}", e);
+                return;
+            }
+        };
+        assert!(matches!(header, TarHeader::V7(_)));
+        assert_eq!(512, stream.len());
+
+        // declining after reading past the block fails instead of losing the bytes
+        register_handler(HeaderMatch::Magic(*b"rtdcl\0"), Decline(true));
+        let result = TarHeader::load(&mut Cursor::new(buf));
+        assert_eq!(2, unregister_handlers(HeaderMatch::Magic(*b"rtdcl\0")));
+        match result {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert!(e.to_string().contains("declined"))
+        }
+    }
+
+    #[test]
+    fn dispatch_custom_header() {
+        let mut buf = vec![0u8; 1024];
+        buf[..5].copy_from_slice(b"a.txt");
+        buf[156] = b'0';
+        buf[257..263].copy_from_slice(b"rtcst\0");
+        buf[512..520].copy_from_slice(&3u64.to_le_bytes());
+        buf.extend_from_slice(b"abc");
+
+        // without the handler the block looks like a V7 header
+        assert!(matches!(TarHeader::load(&mut Cursor::new(buf.clone())).unwrap(), TarHeader::V7(_)));
+
+        register_handler(HeaderMatch::Magic(*b"rtcst\0"), Dialect);
+        let mut stream = Cursor::new(buf.clone());
+        let mut header = match TarHeader::load(&mut stream) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to load header: {}", e);
+                return;
+            }
+        };
+        assert_eq!(1, unregister_handlers(HeaderMatch::Magic(*b"rtcst\0")));
+        assert_eq!(1024, stream.position());
+        match &header {
+            TarHeader::Custom(h) => assert_eq!("a.txt", h.header.name),
+            _ => assert!(false, "Did not dispatch the custom header")
+        }
+        assert_eq!(3, header.get_content_size());
+        assert_eq!(2, header.get_used_blocks());
+        assert!(header.is_regular_file());
+
+        let mut out = Vec::new();
+        header.save(&mut out).unwrap();
+        assert_eq!(buf[..1024], out[..]);
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt::Write;
+
+/// Header field boundaries as `(label, start, end)`, follows the USTAR layout
+/// which GNU, PAX and V7 headers share for the common fields.
+const FIELDS: [(&str, usize, usize); 17] = [
+    ("name", 0, 100),
+    ("mode", 100, 108),
+    ("uid", 108, 116),
+    ("gid", 116, 124),
+    ("size", 124, 136),
+    ("mtime", 136, 148),
+    ("chksum", 148, 156),
+    ("typeflag", 156, 157),
+    ("linkname", 157, 257),
+    ("magic", 257, 263),
+    ("version", 263, 265),
+    ("uname", 265, 297),
+    ("gname", 297, 329),
+    ("devmajor", 329, 337),
+    ("devminor", 337, 345),
+    ("prefix", 345, 500),
+    ("pad", 500, 512)
+];
+
+/// Bytes displayed per hexdump line.
+const LINE_SIZE: usize = 16;
+
+/// Formats a header block as a hexdump annotated with the header field
+/// boundaries. Each field starts on a new line labeled with its name, long
+/// fields continue on the following lines. Meant for diagnostics and bug
+/// reports about unparseable headers.
+///
+/// # Arguments
+/// * `block` - The header block bytes, shorter blocks are dumped up to their length.
+///
+/// # Returns
+/// * `String` - The annotated hexdump.
+pub fn hexdump(block: &[u8]) -> String {
+    let mut out = String::new();
+    for (label, start, end) in FIELDS {
+        if start >= block.len() {
+            break;
+        }
+        let end = end.min(block.len());
+        let mut offset = start;
+        let mut first = true;
+        while offset < end {
+            let line_end = (offset + LINE_SIZE).min(end);
+            let bytes = &block[offset..line_end];
+            let label = if first { label } else { "" };
+            let _ = write!(out, "{:03x}  {:<8}  ", offset, label);
+            for i in 0..LINE_SIZE {
+                match bytes.get(i) {
+                    Some(b) => { let _ = write!(out, "{:02x} ", b); },
+                    None => out.push_str("   ")
+                }
+            }
+            out.push_str(" |");
+            for b in bytes {
+                out.push(if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' });
+            }
+            out.push_str("|\n");
+            offset = line_end;
+            first = false;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_labels_fields() {
+        let mut block = [0u8; 512];
+        block[0..8].copy_from_slice(b"file.txt");
+        block[156] = b'Z';
+        block[257..263].copy_from_slice(b"bogus!");
+        let dump = hexdump(&block);
+        let lines: Vec<&str> = dump.lines().collect();
+
+        // name uses 7 lines, every other field starts on its own line
+        assert_eq!(
+            lines[0],
+            "000  name      66 69 6c 65 2e 74 78 74 00 00 00 00 00 00 00 00  |file.txt........|"
+        );
+        assert!(lines[1].starts_with("010            00 00 "));
+        assert!(lines.iter().any(|l| l.starts_with("09c  typeflag  5a ") && l.ends_with("|Z|")));
+        assert!(lines.iter().any(|l| l.starts_with("101  magic     62 6f 67 75 73 21 ") && l.ends_with("|bogus!|")));
+        assert!(lines.last().unwrap().starts_with("1f4  pad "));
+    }
+
+    #[test]
+    fn dump_short_block() {
+        let dump = hexdump(b"abc");
+        assert_eq!(dump.lines().count(), 1);
+        assert!(dump.ends_with("|abc|\n"));
+    }
+}
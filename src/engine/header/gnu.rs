@@ -2,6 +2,7 @@ use anyhow::{bail, Result};
 use std::io::{Read, Write};
 
 use super::helper::*;
+use crate::format::{checksum, long_name, parse_sparse};
 use super::{UsedBlocksTrait, UstarTypeFlag, IsTypeTrait};
 
 /// PAX header type flag.
@@ -294,10 +295,7 @@ impl GnuHeader {
     pub fn read_long_header(buf: &[u8; 512], reader: &mut impl Read) -> Result<String> {
         // Validate checksum
         let chksum = parse_octal::<u32>(&buf[148..156])?;
-        let mut chksum_bytes = buf.clone();
-        let mut new_chksum: u32 = 0;
-        chksum_bytes[148..156].fill(b' ');
-        for i in 0..512 { new_chksum = new_chksum.wrapping_add(chksum_bytes[i] as u32); }
+        let new_chksum = checksum(buf);
         if chksum != new_chksum {
             bail!("Invalid long name checksum: expected {}, got {}", chksum, new_chksum);
         }
@@ -313,7 +311,7 @@ impl GnuHeader {
             data.extend_from_slice(&block[..n as usize]);
             size -= n;
         }
-        Ok(std::str::from_utf8(&data)?.trim_end_matches('\0').to_string())
+        Ok(std::str::from_utf8(long_name(&data))?.to_string())
     }
 
     /// Loads a GNU long name records.
@@ -396,16 +394,9 @@ impl GnuHeader {
 
         // GNU extensions:
         // Sparse entries: 4 x (offset: 12, numbytes: 12) = 96 bytes (500..596)
-        for i in 0..4 {
-            let offset = 386 + i * 24;
-            let offset_buff = &buf[offset..offset+12];
-            let numbytes_buff = &buf[offset+12..offset+24];
-            if offset_buff != &[0u8; 12] && numbytes_buff != &[0u8; 12] {
-                let offset = parse_octal::<u64>(offset_buff)?;
-                let numbytes = parse_octal::<u64>(numbytes_buff)?;
-                self.sparse.push(SparseEntry { offset, numbytes });
-            }
-        }
+        let mut sparse = Vec::new();
+        parse_sparse(&buf[386..482], &mut sparse)?;
+        self.sparse.extend(sparse.drain(..).map(|(offset, numbytes)| SparseEntry { offset, numbytes }));
     
         // Incremental dump fields (not always present, e.g. 369..500)
         self.incremental = if self.sparse.len() < 1 && &buf[369..500] != &[0u8; 131] {
@@ -422,17 +413,8 @@ impl GnuHeader {
         while next {
             let mut buf = [0u8; 512];
             reader.read_exact(&mut buf)?;
-            let mut offset = 0;
-            while offset < 504 {
-                let offset_buff = &buf[offset..offset+12];
-                let numbytes_buff = &buf[offset+12..offset+24];
-                if offset_buff != &[0u8; 12] && numbytes_buff != &[0u8; 12] {
-                    let offset = parse_octal::<u64>(offset_buff)?;
-                    let numbytes = parse_octal::<u64>(numbytes_buff)?;
-                    self.sparse.push(SparseEntry { offset, numbytes });
-                }
-                offset += 24;
-            }
+            parse_sparse(&buf[..504], &mut sparse)?;
+            self.sparse.extend(sparse.drain(..).map(|(offset, numbytes)| SparseEntry { offset, numbytes }));
             next = buf[504] == b'1';
         }
         Ok(())
@@ -458,26 +440,33 @@ impl GnuHeader {
             return Ok(None);
         }
 
-        // load each header in it's order
+        // load the long name and linkname records in any order, a repeated
+        // record overrides the previous one
         let mut skip_name = false;
         let mut skip_linkname = false;
-        let mut buffer = buf;
-        let mut buf: [u8; 512] = [0u8; 512];
+        let mut superseded = 0;
+        let mut buf = *buf;
         let mut header = GnuHeader::new(typeflag);
-        if typeflag == GnuTypeFlag::LongName {
-            header.load_long_name(buffer, reader)?;
-            reader.read_exact(&mut buf)?;
-            buffer = &buf;
-            skip_name = true;
-        }
-        if typeflag == GnuTypeFlag::LongLinkName {
-            header.load_long_link(buffer, reader)?;
+        loop {
+            let (repeated, name) = match GnuTypeFlag::from(buf[156]) {
+                GnuTypeFlag::LongName => (skip_name, true),
+                GnuTypeFlag::LongLinkName => (skip_linkname, false),
+                _ => break
+            };
+            if repeated {
+                superseded += 1 + parse_octal::<u64>(&buf[124..136])?.div_ceil(512) as usize;
+            }
+            if name {
+                header.load_long_name(&buf, reader)?;
+                skip_name = true;
+            } else {
+                header.load_long_link(&buf, reader)?;
+                skip_linkname = true;
+            }
             reader.read_exact(&mut buf)?;
-            buffer = &buf;
-            skip_linkname = true;
         }
-        header.load_standard(buffer, reader, skip_name, skip_linkname)?;
-        header.saved_blocks = header.get_used_blocks();
+        header.load_standard(&buf, reader, skip_name, skip_linkname)?;
+        header.saved_blocks = header.get_used_blocks() + superseded;
         Ok(Some(header))
     }
 
@@ -490,7 +479,7 @@ impl GnuHeader {
     ///
     /// # Returns
     /// * `Ok(bool)` - Whether the header was saved.
-    /// * `Err(e)` - If write fails.
+    /// * `Err(e)` - If a value doesn't fit its field or write fails.
     pub fn save_long_header(&self, writer: &mut impl Write, typeflag: u8, value: &str) -> Result<bool> {
         // validate value size to be lower than 100 bytes
         let value_bytes = value.as_bytes();
@@ -505,7 +494,7 @@ impl GnuHeader {
         buf[100..108].copy_from_slice(b"0000000\0"); // mode
         buf[108..116].copy_from_slice(b"0000000\0"); // uid
         buf[116..124].copy_from_slice(b"0000000\0"); // gid
-        put_octal(&mut buf[124..136], value_bytes_len as u64); // size
+        put_octal(&mut buf[124..136], value_bytes_len as u64)?; // size
         buf[136..148].copy_from_slice(b"00000000000\0"); // mtime
         buf[148..156].fill(b' '); // chksum
         buf[156] = typeflag; // typeflag
@@ -513,15 +502,14 @@ impl GnuHeader {
         buf[263..265].copy_from_slice(b" \0"); // version
 
         // compute checksum
-        let mut chksum: u32 = 0;
-        for i in 0..512 { chksum = chksum.wrapping_add(buf[i] as u32); }
+        let chksum = checksum(&buf);
         let chksum_str = format!("{:06o}\0 ", chksum);
         let chksum_bytes = chksum_str.as_bytes();
         buf[148..148+chksum_bytes.len()].copy_from_slice(chksum_bytes);
         writer.write_all(&buf)?;
         let value_bytes = value.as_bytes();
         writer.write_all(value_bytes)?;
-        writer.write_all(&vec![0u8; 512 - value_bytes_len])?;
+        writer.write_all(&vec![0u8; value_bytes_len.div_ceil(512) * 512 - value_bytes_len])?;
         Ok(true)
     }
 
@@ -556,22 +544,24 @@ impl GnuHeader {
     ///
     /// # Returns
     /// * `Ok(())` - On success.
-    /// * `Err(e)` - If write fails.
+    /// * `Err(e)` - If a value doesn't fit its field or write fails.
     pub fn save(&mut self, writer: &mut impl Write) -> Result<()> {
-        // write the possible GNU long headers when needed
-        let skip_name = self.save_long_name(writer)?;
-        let skip_linkname = self.save_long_link(writer)?;
+        // the blocks are only written once every field is validated, so a
+        // failed save doesn't leave orphan long name records behind
+        let mut out = Vec::with_capacity(512);
+        let skip_name = self.save_long_name(&mut out)?;
+        let skip_linkname = self.save_long_link(&mut out)?;
 
         // Set buffer default bytes to spaces so the checksum field is correct before computing checksum (TAR spec)
         let mut buf = [0u8; 512];
         if !skip_name {
             put_str(&mut buf[0..100], &self.name);
         }
-        put_octal(&mut buf[100..108], self.mode);
-        put_octal(&mut buf[108..116], self.uid);
-        put_octal(&mut buf[116..124], self.gid);
-        put_octal(&mut buf[124..136], self.size);
-        put_octal(&mut buf[136..148], self.mtime);
+        put_octal(&mut buf[100..108], self.mode)?;
+        put_octal(&mut buf[108..116], self.uid)?;
+        put_octal(&mut buf[116..124], self.gid)?;
+        put_octal(&mut buf[124..136], self.size)?;
+        put_octal(&mut buf[136..148], self.mtime)?;
         // chksum is written after calculating
         buf[156] = self.typeflag.into();
         if !skip_linkname {
@@ -581,8 +571,8 @@ impl GnuHeader {
         put_str(&mut buf[263..265], &self.version);
         put_str(&mut buf[265..297], &self.uname);
         put_str(&mut buf[297..329], &self.gname);
-        put_octal(&mut buf[329..337], self.devmajor);
-        put_octal(&mut buf[337..345], self.devminor);
+        put_octal(&mut buf[329..337], self.devmajor)?;
+        put_octal(&mut buf[337..345], self.devminor)?;
 
         // GNU extra
         buf[500..512].copy_from_slice(&self.gnu_extra);
@@ -594,8 +584,8 @@ impl GnuHeader {
         // Write first 4 sparse entries into header
         let mut off = 386;
         for entry in self.sparse.iter().take(4) {
-            put_octal(&mut buf[off..off+12], entry.offset);
-            put_octal(&mut buf[off+12..off+24], entry.numbytes);
+            put_octal(&mut buf[off..off+12], entry.offset)?;
+            put_octal(&mut buf[off+12..off+24], entry.numbytes)?;
             off += 24;
         }
 
@@ -609,17 +599,17 @@ impl GnuHeader {
 
         // Write realsize if present
         match self.realsize {
-            Some(realsize) => put_octal(&mut buf[483..495], realsize),
+            Some(realsize) => put_octal(&mut buf[483..495], realsize)?,
             None => buf[483..495].fill(0)
         }
 
         // Write atime/ctime if present
         match self.atime {
-            Some(atime) => put_octal(&mut buf[345..357], atime),
+            Some(atime) => put_octal(&mut buf[345..357], atime)?,
             None => buf[345..357].fill(0)
         }
         match self.ctime {
-            Some(ctime) => put_octal(&mut buf[357..369], ctime),
+            Some(ctime) => put_octal(&mut buf[357..369], ctime)?,
             None => buf[357..369].fill(0)
         }
 
@@ -638,15 +628,13 @@ impl GnuHeader {
 
         // Write checksum
         buf[148..156].fill(b' ');
-        let mut chksum: u32 = 0;
-        for i in 0..512 { chksum = chksum.wrapping_add(buf[i] as u32); }
+        let chksum = checksum(&buf);
         let chksum_str = format!("{:06o}\0 ", chksum);
         let chksum_bytes = chksum_str.as_bytes();
         buf[148..148+chksum_bytes.len()].copy_from_slice(chksum_bytes);
-        self.chksum = chksum;
 
         // Write standard header
-        writer.write_all(&buf)?;
+        out.extend_from_slice(&buf);
 
         // Write extended sparse headers if needed
         if isextended {
@@ -663,17 +651,19 @@ impl GnuHeader {
                         break;
                     }
                     let entry = &self.sparse[processed];
-                    put_octal(&mut block[offset..offset+12], entry.offset);
-                    put_octal(&mut block[offset+12..offset+24], entry.numbytes);
+                    put_octal(&mut block[offset..offset+12], entry.offset)?;
+                    put_octal(&mut block[offset+12..offset+24], entry.numbytes)?;
                     offset += 24;
                     processed += 1;
                 }
 
                 // Set isextended flag for this block
                 block[504] = if processed < total { b'1' } else { b'0' };
-                writer.write_all(&block)?;
+                out.extend_from_slice(&block);
             }
         }
+        writer.write_all(&out)?;
+        self.chksum = chksum;
 
         // update the saved blocks
         self.saved_blocks = self.get_used_blocks();
@@ -687,10 +677,10 @@ impl UsedBlocksTrait for GnuHeader {
         let name_length = self.name.len();
         let linkname_length = self.linkname.len();
         if name_length > 100 {
-            used_blocks += 1 + (name_length - 100) / 512 + if (name_length - 100) % 512 > 0 {1} else {0};
+            used_blocks += 1 + name_length.div_ceil(512);
         }
         if linkname_length > 100 {
-            used_blocks += 1 + (linkname_length - 100) / 512 + if (linkname_length - 100) % 512 > 0 {1} else {0};
+            used_blocks += 1 + linkname_length.div_ceil(512);
         }
         let sparse_length = self.sparse.len();
         if sparse_length > 4 {
@@ -1603,6 +1593,70 @@ mod tests {
         assert_eq!(buf[1024+156], b'0'); // next header is standard header
     }
 
+    #[test]
+    fn gnu_long_name_multiple_blocks() {
+        let mut header = sample_header();
+        header.typeflag = GnuTypeFlag::Ustar(UstarTypeFlag::RegularFile);
+        header.name = "a".repeat(600);
+
+        // the record holds the whole name so it spans two blocks
+        let mut stream = Vec::new();
+        match header.save(&mut stream) {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to save header: {}", e);
+                return;
+            }
+        }
+        assert_eq!(4 * 512, stream.len());
+        assert_eq!(4, header.calc_used_blocks());
+        let mut reader = Cursor::new(stream);
+        let mut buf = [0u8; 512];
+        reader.read_exact(&mut buf).unwrap();
+        let loaded = GnuHeader::load(&buf, &mut reader).unwrap().unwrap();
+        assert_eq!(header.name, loaded.name);
+        assert_eq!(4 * 512, reader.position());
+    }
+
+    #[test]
+    fn load_long_records_any_order() {
+        let mut header = sample_header();
+        header.typeflag = GnuTypeFlag::Ustar(UstarTypeFlag::SymbolicLink);
+        header.name = "a".repeat(150);
+        header.linkname = "b".repeat(120);
+        let mut stale = header.clone();
+        stale.name = "c".repeat(130);
+        let mut short = header.clone();
+        short.name = "short".to_string();
+        short.linkname = "short".to_string();
+
+        // stale long name, long linkname, long name and then the real header
+        let mut stream = Vec::new();
+        assert!(stale.save_long_name(&mut stream).unwrap());
+        assert!(header.save_long_link(&mut stream).unwrap());
+        assert!(header.save_long_name(&mut stream).unwrap());
+        short.save(&mut stream).unwrap();
+        let mut reader = Cursor::new(stream.clone());
+        let mut buf = [0u8; 512];
+        reader.read_exact(&mut buf).unwrap();
+        let loaded = match GnuHeader::load(&buf, &mut reader) {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                assert!(false, "expected a GNU header");
+                return;
+            },
+            Err(e) => {
+                assert!(false, "Failed to load header: {}", e);
+                return;
+            }
+        };
+        assert_eq!(header.name, loaded.name);
+        assert_eq!(header.linkname, loaded.linkname);
+        assert_eq!(GnuTypeFlag::Ustar(UstarTypeFlag::SymbolicLink), loaded.typeflag);
+        assert_eq!(stream.len(), loaded.get_saved_blocks() * 512);
+        assert_eq!(stream.len() as u64, reader.position());
+    }
+
     #[test]
     fn calc_used_blocks_default() {
         let header = sample_header();
@@ -1614,13 +1668,13 @@ mod tests {
         assert_eq!(1, header.calc_used_blocks());
         header.name = std::str::from_utf8(&[42u8; 101] as &[u8]).unwrap().to_string();
         assert_eq!(3, header.calc_used_blocks());
-        header.name = std::str::from_utf8(&[42u8; 612] as &[u8]).unwrap().to_string();
+        header.name = std::str::from_utf8(&[42u8; 512] as &[u8]).unwrap().to_string();
         assert_eq!(3, header.calc_used_blocks());
-        header.name = std::str::from_utf8(&[42u8; 613] as &[u8]).unwrap().to_string();
+        header.name = std::str::from_utf8(&[42u8; 513] as &[u8]).unwrap().to_string();
         assert_eq!(4, header.calc_used_blocks());
-        header.name = std::str::from_utf8(&[42u8; 1124] as &[u8]).unwrap().to_string();
+        header.name = std::str::from_utf8(&[42u8; 1024] as &[u8]).unwrap().to_string();
         assert_eq!(4, header.calc_used_blocks());
-        header.name = std::str::from_utf8(&[42u8; 1125] as &[u8]).unwrap().to_string();
+        header.name = std::str::from_utf8(&[42u8; 1025] as &[u8]).unwrap().to_string();
         assert_eq!(5, header.calc_used_blocks());
     }
 
@@ -1630,13 +1684,13 @@ mod tests {
         assert_eq!(1, header.calc_used_blocks());
         header.linkname = std::str::from_utf8(&[42u8; 101] as &[u8]).unwrap().to_string();
         assert_eq!(3, header.calc_used_blocks());
-        header.linkname = std::str::from_utf8(&[42u8; 612] as &[u8]).unwrap().to_string();
+        header.linkname = std::str::from_utf8(&[42u8; 512] as &[u8]).unwrap().to_string();
         assert_eq!(3, header.calc_used_blocks());
-        header.linkname = std::str::from_utf8(&[42u8; 613] as &[u8]).unwrap().to_string();
+        header.linkname = std::str::from_utf8(&[42u8; 513] as &[u8]).unwrap().to_string();
         assert_eq!(4, header.calc_used_blocks());
-        header.linkname = std::str::from_utf8(&[42u8; 1124] as &[u8]).unwrap().to_string();
+        header.linkname = std::str::from_utf8(&[42u8; 1024] as &[u8]).unwrap().to_string();
         assert_eq!(4, header.calc_used_blocks());
-        header.linkname = std::str::from_utf8(&[42u8; 1125] as &[u8]).unwrap().to_string();
+        header.linkname = std::str::from_utf8(&[42u8; 1025] as &[u8]).unwrap().to_string();
         assert_eq!(5, header.calc_used_blocks());
     }
 
@@ -1799,4 +1853,19 @@ mod tests {
         assert_eq!(header.get_used_blocks(), 3);
         assert!(header.updated_used_blocks, "used_blocks should be updated");
     }
+
+    #[test]
+    fn save_overflow_writes_nothing() {
+        let mut header = sample_header();
+        header.name = "n".repeat(200);
+        header.linkname = "l".repeat(200);
+        header.size = 0o100000000000;
+        let mut buf = Vec::new();
+        match header.save(&mut buf) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(e.to_string(), "value too large for field: 100000000000 doesn't fit in 11 octal digits")
+        }
+        assert!(buf.is_empty());
+        assert_eq!(0, header.saved_blocks);
+    }
 }
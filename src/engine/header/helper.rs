@@ -1,10 +1,29 @@
 use anyhow::{bail, Result as AnyResult};
+use std::ffi::OsString;
 use std::string::FromUtf8Error;
 
 // Helper to extract and trim null-terminated strings
 pub(crate) fn get_str(buf: &[u8]) -> Result<String, FromUtf8Error> {
-    let nul = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
-    String::from_utf8(buf[..nul].to_vec())
+    String::from_utf8(get_bytes(buf).to_vec())
+}
+
+// Helper to extract the raw bytes of a null-terminated field
+pub(crate) fn get_bytes(buf: &[u8]) -> &[u8] {
+    crate::format::field_bytes(buf)
+}
+
+// Helper to extract null-terminated fields as OS strings, keeping non UTF-8 bytes on unix
+pub(crate) fn get_os_str(buf: &[u8]) -> OsString {
+    let bytes = get_bytes(buf);
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        std::ffi::OsStr::from_bytes(bytes).to_os_string()
+    }
+    #[cfg(not(unix))]
+    {
+        OsString::from(String::from_utf8_lossy(bytes).into_owned())
+    }
 }
 
 // Helper to extract and trim null-terminated strings
@@ -13,19 +32,12 @@ pub(crate) fn get_str_with_min_size(buf: &[u8], min_size: usize) -> Result<Strin
     String::from_utf8(buf[..nul].to_vec())
 }
 
-// Helper to parse octal strings
-pub(crate) fn parse_octal<T: std::str::FromStr>(buf: &[u8]) -> AnyResult<T>
-where
-    T: num_traits::Num + std::fmt::Debug,
-{
-    let binding = String::from_utf8(buf.to_vec())?;
-    let s = binding.trim_matches(|c| c == char::from(0) || c == ' ').trim();
-    if s.is_empty() {
-        return Ok(T::zero());
-    }
-    match T::from_str_radix(s, 8) {
+// Helper to parse octal strings, the field parsing is shared with the format core
+pub(crate) fn parse_octal<T: TryFrom<u64>>(buf: &[u8]) -> AnyResult<T> {
+    let value = crate::format::parse_octal(buf)?;
+    match T::try_from(value) {
         Ok(v) => Ok(v),
-        Err(_) => bail!("Invalid octal: {}", s)
+        Err(_) => bail!("octal value {:o} doesn't fit its type", value)
     }
 }
 
@@ -39,13 +51,9 @@ pub(crate) fn put_str(dst: &mut [u8], value: &str) {
         dst[len..].fill(0);
     }
 }
-// Helper to write octal numbers as space-padded strings
-pub(crate) fn put_octal<T: itoa::Integer + std::fmt::Octal>(dst: &mut [u8], value: T) {
-    let s = format!("{:0width$o}", value, width = dst.len() - 1); // leave space for null
-    let bytes = s.as_bytes();
-    let len = bytes.len().min(dst.len() - 1);
-    dst[..len].copy_from_slice(&bytes[..len]);
-    dst[len] = b'\0';
+// Helper to write octal numbers as null-terminated strings, fails when the value doesn't fit
+pub(crate) fn put_octal<T: Into<u64>>(dst: &mut [u8], value: T) -> AnyResult<()> {
+    Ok(crate::format::put_octal(dst, value.into())?)
 }
 
 #[cfg(test)]
@@ -124,7 +132,7 @@ mod tests {
     #[test]
     fn test_put_octal_u32() {
         let mut buf = [0u8; 8];
-        put_octal(&mut buf, 0o644u32);
+        put_octal(&mut buf, 0o644u32).unwrap();
         // Should be 0000644\0
         assert_eq!(&buf[..7], b"0000644");
         assert_eq!(buf[7], 0);
@@ -132,8 +140,38 @@ mod tests {
     #[test]
     fn test_put_octal_u64() {
         let mut buf = [0u8; 12];
-        put_octal(&mut buf, 0o1234u64);
+        put_octal(&mut buf, 0o1234u64).unwrap();
         assert_eq!(&buf[..11], b"00000001234");
         assert_eq!(buf[11], 0);
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_put_octal_max_fits() {
+        let mut buf = [0u8; 8];
+        put_octal(&mut buf, 0o7777777u32).unwrap();
+        assert_eq!(&buf[..7], b"7777777");
+        assert_eq!(buf[7], 0);
+    }
+    #[test]
+    fn test_put_octal_overflow() {
+        let mut buf = [0u8; 8];
+        match put_octal(&mut buf, 0o10000000u32) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(e.to_string(), "value too large for field: 10000000 doesn't fit in 7 octal digits")
+        }
+        assert_eq!(buf, [0u8; 8]);
+    }
+    #[test]
+    fn test_get_bytes() {
+        assert_eq!(get_bytes(b"na\xffme\0rest"), b"na\xffme");
+        assert_eq!(get_bytes(b"abc"), b"abc");
+        assert_eq!(get_bytes(b"\0abc"), b"");
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_get_os_str_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let value = get_os_str(b"na\xffme\0rest");
+        assert_eq!(value.as_bytes(), b"na\xffme");
+        assert!(get_str(b"na\xffme\0rest").is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use super::pax::{Attribute, Value};
+
+/// Registered namespaces by prefix.
+static NAMESPACES: RwLock<Vec<Namespace>> = RwLock::new(Vec::new());
+
+/// Type declared for a namespace attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    /// Free form string, kept raw as `Value::Default`.
+    String,
+    /// Unsigned integer.
+    U64,
+    /// Signed integer.
+    I64,
+    /// Floating point number.
+    F64,
+    /// `true` or `false`.
+    Bool,
+}
+
+impl ValueType {
+    /// Parses a raw attribute value.
+    ///
+    /// # Arguments
+    /// * `raw` - Raw value.
+    ///
+    /// # Returns
+    /// * `Ok(Value)` - The typed value.
+    /// * `Err(e)` - If the raw value isn't of this type.
+    pub fn parse(&self, raw: &str) -> Result<Value> {
+        Ok(match self {
+            ValueType::String => Value::Default,
+            ValueType::U64 => Value::U64(raw.parse()?),
+            ValueType::I64 => Value::I64(raw.parse()?),
+            ValueType::F64 => Value::F64(raw.parse()?),
+            ValueType::Bool => Value::Bool(raw.parse()?)
+        })
+    }
+}
+
+/// Schema of a vendor PAX attribute namespace, the `MYAPP.*` keys for a
+/// `MYAPP` prefix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Namespace {
+    /// Namespace prefix without the trailing dot.
+    prefix: String,
+    /// Declared types by key name, without the prefix.
+    keys: HashMap<String, ValueType>,
+    /// Whether undeclared keys are rejected.
+    strict: bool,
+}
+
+impl Namespace {
+    /// Creates an empty namespace schema.
+    ///
+    /// # Arguments
+    /// * `prefix` - Namespace prefix without the trailing dot.
+    pub fn new(prefix: &str) -> Self {
+        Self { prefix: prefix.to_string(), keys: HashMap::new(), strict: false }
+    }
+
+    /// Declares a key type.
+    ///
+    /// # Arguments
+    /// * `name` - Key name without the prefix.
+    /// * `kind` - Value type.
+    pub fn key(mut self, name: &str, kind: ValueType) -> Self {
+        self.keys.insert(name.to_string(), kind);
+        self
+    }
+
+    /// Sets whether undeclared keys are rejected, otherwise they're kept as
+    /// strings.
+    ///
+    /// # Arguments
+    /// * `strict` - Whether undeclared keys are rejected.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Gets the namespace prefix.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Gets the type of a full attribute key.
+    ///
+    /// # Arguments
+    /// * `key` - Attribute key including the prefix.
+    ///
+    /// # Returns
+    /// * `Ok(Some(ValueType))` - The declared type when the key is on this namespace.
+    /// * `Ok(None)` - When the key isn't on this namespace.
+    /// * `Err(e)` - If the key is undeclared on a strict namespace.
+    fn type_of(&self, key: &str) -> Result<Option<ValueType>> {
+        let name = match key.strip_prefix(self.prefix.as_str()).and_then(|v| v.strip_prefix('.')) {
+            Some(v) => v,
+            None => return Ok(None)
+        };
+        match self.keys.get(name) {
+            Some(v) => Ok(Some(*v)),
+            None if self.strict => bail!("undeclared attribute '{}' on namespace '{}'", key, self.prefix),
+            None => Ok(Some(ValueType::String))
+        }
+    }
+}
+
+/// Registers a namespace schema, replacing the one with the same prefix.
+/// Namespaces are process wide.
+///
+/// # Arguments
+/// * `namespace` - The namespace schema.
+pub fn register_namespace(namespace: Namespace) {
+    let mut namespaces = NAMESPACES.write().unwrap_or_else(|e| e.into_inner());
+    namespaces.retain(|v| v.prefix != namespace.prefix);
+    namespaces.push(namespace);
+}
+
+/// Removes a namespace schema.
+///
+/// # Arguments
+/// * `prefix` - Namespace prefix without the trailing dot.
+///
+/// # Returns
+/// * `bool` - Whether the namespace was registered.
+pub fn unregister_namespace(prefix: &str) -> bool {
+    let mut namespaces = NAMESPACES.write().unwrap_or_else(|e| e.into_inner());
+    let len = namespaces.len();
+    namespaces.retain(|v| v.prefix != prefix);
+    len != namespaces.len()
+}
+
+/// Gets the declared type of an attribute key.
+///
+/// # Arguments
+/// * `key` - Attribute key.
+///
+/// # Returns
+/// * `Ok(Some(ValueType))` - The declared type when a namespace holds the key.
+/// * `Ok(None)` - When no namespace holds the key.
+/// * `Err(e)` - If the key is undeclared on a strict namespace.
+fn lookup(key: &str) -> Result<Option<ValueType>> {
+    let namespaces = NAMESPACES.read().unwrap_or_else(|e| e.into_inner());
+    for namespace in namespaces.iter() {
+        if let Some(kind) = namespace.type_of(key)? {
+            return Ok(Some(kind));
+        }
+    }
+    Ok(None)
+}
+
+/// Parses a namespaced attribute loaded from a header. Values that don't
+/// match the schema are left to the caller to keep raw, so archives written
+/// by other tools still load; use `PaxHeader::schema_violations` to report them.
+///
+/// # Arguments
+/// * `key` - Attribute key.
+/// * `raw` - Raw value.
+///
+/// # Returns
+/// * `Some(Attribute)` - The typed attribute when a namespace holds the key and the value matches.
+/// * `None` - When no namespace holds the key or the value doesn't match the declared type.
+pub(super) fn parse_attribute(key: &str, raw: &str) -> Option<Attribute> {
+    let kind = match lookup(key) {
+        Ok(Some(v)) => v,
+        _ => return None
+    };
+    match kind.parse(raw) {
+        Ok(value) => Some(Attribute { value, raw: raw.to_string() }),
+        Err(_) => None
+    }
+}
+
+/// Checks a namespaced attribute before it's saved, its raw value must
+/// parse into its typed value.
+///
+/// # Arguments
+/// * `key` - Attribute key.
+/// * `attr` - The attribute.
+///
+/// # Returns
+/// * `Ok(())` - When the attribute matches its declared type or no namespace holds the key.
+/// * `Err(e)` - If the attribute doesn't match the declared type.
+pub(super) fn validate_attribute(key: &str, attr: &Attribute) -> Result<()> {
+    let kind = match lookup(key)? {
+        Some(v) => v,
+        None => return Ok(())
+    };
+    match kind.parse(&attr.raw) {
+        Ok(v) if v == attr.value => Ok(()),
+        _ => bail!("attribute '{}' value '{}' isn't a valid {:?}", key, attr.raw, kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::header::{PaxHeader, PaxTypeFlag};
+    use std::io::Cursor;
+
+    #[test]
+    fn typed_namespace() {
+        register_namespace(Namespace::new("RTNS")
+            .key("build", ValueType::U64)
+            .key("delta", ValueType::I64)
+            .key("signed", ValueType::Bool)
+            .strict(true));
+
+        let mut header = PaxHeader::new(PaxTypeFlag::Extended);
+        header.set_attr("RTNS.build", Attribute::from_str("42".to_string()));
+        header.set_attr("RTNS.delta", Attribute::from_str("-3".to_string()));
+        header.set_attr("RTNS.signed", Attribute::from_str("true".to_string()));
+        header.set_attr("RTNSX.other", Attribute::from_str("raw".to_string()));
+
+        // untyped values don't pass the save validation
+        assert!(header.save(&mut Vec::new()).is_err());
+        header.set_attr("RTNS.build", Attribute { value: Value::U64(42), raw: "42".to_string() });
+        header.set_attr("RTNS.delta", Attribute::from_i64(-3));
+        header.set_attr("RTNS.signed", Attribute::from_bool(true));
+        let mut buf = Vec::new();
+        if let Err(e) = header.save(&mut buf) {
+            assert!(false, "Failed to save header: {}", e);
+            return;
+        }
+
+        let block: [u8; 512] = buf[..512].try_into().unwrap();
+        let loaded = match PaxHeader::load(&block, &mut Cursor::new(&buf[512..])) {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                assert!(false, "expected a PAX header");
+                return;
+            },
+            Err(e) => {
+                assert!(false, "Failed to load header: {}", e);
+                return;
+            }
+        };
+        assert_eq!(Value::U64(42), loaded.get_attr("RTNS.build").unwrap().value);
+        assert_eq!(Value::I64(-3), loaded.get_attr("RTNS.delta").unwrap().value);
+        assert_eq!(Value::Bool(true), loaded.get_attr("RTNS.signed").unwrap().value);
+        assert_eq!(Value::Default, loaded.get_attr("RTNSX.other").unwrap().value);
+
+        // undeclared keys are rejected by strict namespaces
+        header.set_attr("RTNS.missing", Attribute::from_str("1".to_string()));
+        assert!(header.save(&mut Vec::new()).is_err());
+        assert!(unregister_namespace("RTNS"));
+        assert!(!unregister_namespace("RTNS"));
+        assert!(header.save(&mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn foreign_values_load_raw() {
+        // written by a tool that doesn't know the schema
+        let mut header = PaxHeader::new(PaxTypeFlag::Extended);
+        header.set_attr("RTFV.build", Attribute::from_str("nightly".to_string()));
+        header.set_attr("RTFV.extra", Attribute::from_str("1".to_string()));
+        let mut buf = Vec::new();
+        if let Err(e) = header.save(&mut buf) {
+            assert!(false, "Failed to save header: {}", e);
+            return;
+        }
+
+        register_namespace(Namespace::new("RTFV")
+            .key("build", ValueType::U64)
+            .strict(true));
+        let block: [u8; 512] = buf[..512].try_into().unwrap();
+        let mut loaded = match PaxHeader::load(&block, &mut Cursor::new(&buf[512..])) {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                assert!(false, "expected a PAX header");
+                return;
+            },
+            Err(e) => {
+                assert!(false, "Failed to load header: {}", e);
+                return;
+            }
+        };
+        assert_eq!(Value::Default, loaded.get_attr("RTFV.build").unwrap().value);
+        assert_eq!("nightly", loaded.get_attr("RTFV.build").unwrap().raw);
+        assert_eq!(Value::Default, loaded.get_attr("RTFV.extra").unwrap().value);
+        assert_eq!(2, loaded.schema_violations().len());
+
+        // the schema still applies on save
+        assert!(loaded.save(&mut Vec::new()).is_err());
+        assert!(unregister_namespace("RTFV"));
+        assert!(loaded.schema_violations().is_empty());
+    }
+}
@@ -3,8 +3,9 @@ use std::io::{Read, Write};
 
 /// Represents a PAX TAR header.
 use indexmap::IndexMap;
-use dhfarm_engine::db::field::Value;
 use super::helper::*;
+use crate::format::checksum;
+use super::namespace;
 use super::{UsedBlocksTrait, IsTypeTrait, UstarTypeFlag};
 
 /// PAX header type flag.
@@ -12,6 +13,8 @@ use super::{UsedBlocksTrait, IsTypeTrait, UstarTypeFlag};
 pub enum PaxTypeFlag {
     Extended,
     Global,
+    /// Extended header written by older Solaris/SunOS pax, same layout as `Extended`.
+    SolarisExtended,
     Ustar(UstarTypeFlag)
 }
 
@@ -20,6 +23,7 @@ impl From<u8> for PaxTypeFlag {
         match value {
             b'x' => Self::Extended,
             b'g' => Self::Global,
+            b'X' => Self::SolarisExtended,
             v => Self::Ustar(UstarTypeFlag::from(v)),
         }
     }
@@ -30,6 +34,7 @@ impl From<PaxTypeFlag> for u8 {
         match value {
             PaxTypeFlag::Extended => b'x',
             PaxTypeFlag::Global => b'g',
+            PaxTypeFlag::SolarisExtended => b'X',
             PaxTypeFlag::Ustar(v) => u8::from(v),
         }
     }
@@ -93,6 +98,19 @@ impl IsTypeTrait for PaxTypeFlag {
     }
 }
 
+/// Parsed value of a PAX attribute.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Free form string values, only the raw value is kept.
+    Default,
+    U64(u64),
+    F64(f64),
+    /// Signed integer, only parsed for namespace attributes declared as such.
+    I64(i64),
+    /// Boolean, only parsed for namespace attributes declared as such.
+    Bool(bool),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Attribute {
     /// The value of the attribute unless it is a string then it will be Value::Default
@@ -123,6 +141,28 @@ impl Attribute {
             raw: s
         }
     }
+
+    /// Creates a signed integer attribute.
+    ///
+    /// # Arguments
+    /// * `value` - The value.
+    pub fn from_i64(value: i64) -> Self {
+        Self {
+            value: Value::I64(value),
+            raw: value.to_string()
+        }
+    }
+
+    /// Creates a boolean attribute.
+    ///
+    /// # Arguments
+    /// * `value` - The value.
+    pub fn from_bool(value: bool) -> Self {
+        Self {
+            value: Value::Bool(value),
+            raw: value.to_string()
+        }
+    }
 }
 
 impl std::fmt::Display for Attribute {
@@ -361,6 +401,18 @@ impl PaxHeader {
         self.set_attr("ctime", Attribute{value: Value::F64(ctime), raw: ctime.to_string()});
     }
 
+    /// Checks the attributes against the registered namespaces, loading keeps
+    /// mismatched values raw instead of failing.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - A message per attribute that wouldn't pass the save validation.
+    pub fn schema_violations(&self) -> Vec<String> {
+        self.attributes.iter()
+            .filter_map(|(k, v)| namespace::validate_attribute(k, v).err())
+            .map(|e| e.to_string())
+            .collect()
+    }
+
     /// Returns the PAX attribute if present.
     /// 
     /// # Arguments
@@ -496,14 +548,14 @@ impl PaxHeader {
     /// * `reader` - Reader positioned at the start of a header block. Supports reading long name/link records.
     ///
     /// # Returns
-    /// * `Ok(Self)` - The loaded PAX header.
+    /// * `Ok(Self)` - The loaded PAX header, namespace attributes are parsed into their declared types.
     /// * `Err(e)` - If header could not be read or parsed.
     pub fn load(buf: &[u8; 512], reader: &mut impl Read) -> Result<Option<Self>> {
         // validate headers
         if &buf[257..262] != b"ustar"
             || (buf[262] != b' ' && buf[262] != b'\0')
             || (&buf[263..265] != b"00" && &buf[263..265] != b" \0")
-            || (buf[156] != b'x' && buf[156] != b'g') {
+            || (buf[156] != b'x' && buf[156] != b'g' && buf[156] != b'X') {
             return Ok(None);
         }
         let typeflag = buf[156].into();
@@ -607,7 +659,10 @@ impl PaxHeader {
                                 "atime" => Attribute::from_f64(value_raw),
                                 "ctime" => Attribute::from_f64(value_raw),
                                 "size" => Attribute::from_u64(value_raw),
-                                _ => Attribute::from_str(value_raw)
+                                _ => match namespace::parse_attribute(&key, &value_raw) {
+                                    Some(v) => v,
+                                    None => Attribute::from_str(value_raw)
+                                }
                             };
                             line_buf = Vec::new();
                             lookup_index = 0;
@@ -669,29 +724,30 @@ impl PaxHeader {
     ///
     /// # Returns
     /// * `Ok(())` - On success.
-    /// * `Err(e)` - If write fails.
+    /// * `Err(e)` - If a value doesn't fit its field, a namespace attribute doesn't match its schema or write fails.
     pub fn save(&mut self, writer: &mut impl Write) -> anyhow::Result<()> {
         let mut buf = [0u8; 512];
         put_str(&mut buf[0..100], &self.name);
-        put_octal(&mut buf[100..108], self.mode);
-        put_octal(&mut buf[108..116], self.uid);
-        put_octal(&mut buf[116..124], self.gid);
+        put_octal(&mut buf[100..108], self.mode)?;
+        put_octal(&mut buf[108..116], self.uid)?;
+        put_octal(&mut buf[116..124], self.gid)?;
 
         // Calculate PAX attribute data block size
         let mut pax_size = 0u64;
         for (k, v) in &self.attributes {
+            namespace::validate_attribute(k, v)?;
             pax_size += Self::calc_line_size(k, v);
         }
-        put_octal(&mut buf[124..136], pax_size);
-        put_octal(&mut buf[136..148], self.mtime);
+        put_octal(&mut buf[124..136], pax_size)?;
+        put_octal(&mut buf[136..148], self.mtime)?;
         buf[156] = self.typeflag.into();
         put_str(&mut buf[157..257], &self.linkname);
         put_str(&mut buf[257..263], &self.magic);
         put_str(&mut buf[263..265], &self.version);
         put_str(&mut buf[265..297], &self.uname);
         put_str(&mut buf[297..329], &self.gname);
-        put_octal(&mut buf[329..337], self.devmajor);
-        put_octal(&mut buf[337..345], self.devminor);
+        put_octal(&mut buf[329..337], self.devmajor)?;
+        put_octal(&mut buf[337..345], self.devminor)?;
 
         // Only write the prefix field (filename prefix)
         put_str(&mut buf[345..500], &self.prefix);
@@ -700,8 +756,7 @@ impl PaxHeader {
         buf[148..156].fill(b' ');
 
         // Compute and write checksum
-        let mut chksum: u32 = 0;
-        for i in 0..512 { chksum = chksum.wrapping_add(buf[i] as u32); }
+        let chksum = checksum(&buf);
         let chksum_str = format!("{:06o}\0 ", chksum);
         let chksum_bytes = chksum_str.as_bytes();
         buf[148..148+chksum_bytes.len()].copy_from_slice(chksum_bytes);
@@ -727,6 +782,12 @@ impl PaxHeader {
     pub fn is_global(&self) -> bool {
         self.typeflag == PaxTypeFlag::Global
     }
+
+    /// Returns true if this PAX header is an extended header (applies to the next file),
+    /// including the Solaris `X` variant.
+    pub fn is_extended(&self) -> bool {
+        matches!(self.typeflag, PaxTypeFlag::Extended | PaxTypeFlag::SolarisExtended)
+    }
 }
 
 impl UsedBlocksTrait for PaxHeader {
@@ -856,6 +917,35 @@ mod tests {
         assert!(!h.is_global());
     }
 
+    #[test]
+    fn solaris_extended() {
+        let mut header = sample_header();
+        header.typeflag = PaxTypeFlag::SolarisExtended;
+        let mut stream = Cursor::new(Vec::new());
+        header.save(&mut stream).unwrap();
+        stream.rewind().unwrap();
+        let mut buf = [0u8; 512];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(b'X', buf[156]);
+        let loaded = match PaxHeader::load(&buf, &mut stream) {
+            Ok(Some(h)) => h,
+            Ok(None) => {
+                assert!(false, "expected a PAX header");
+                return;
+            },
+            Err(e) => {
+                assert!(false, "Failed to load header: {}", e);
+                return;
+            }
+        };
+        assert!(loaded.is_extended());
+        assert!(!loaded.is_global());
+        assert_eq!(header.attributes.len(), loaded.attributes.len());
+        for (k, v) in &header.attributes {
+            assert_eq!(loaded.attributes.get(k), Some(v));
+        }
+    }
+
     #[test]
     fn save_sets_name_field() {
         let mut header = sample_header();
@@ -872,7 +962,7 @@ mod tests {
         let mut buf = [0u8; 2048];
         header.save(&mut (&mut buf as &mut [u8])).expect("save");
         let mut expected = [0u8; 8];
-        put_octal(&mut expected, header.mode);
+        put_octal(&mut expected, header.mode).unwrap();
         assert_eq!(&buf[100..108], &expected);
     }
 
@@ -882,7 +972,7 @@ mod tests {
         let mut buf = [0u8; 2048];
         header.save(&mut (&mut buf as &mut [u8])).expect("save");
         let mut expected = [0u8; 8];
-        put_octal(&mut expected, header.uid);
+        put_octal(&mut expected, header.uid).unwrap();
         assert_eq!(&buf[108..116], &expected);
     }
 
@@ -892,7 +982,7 @@ mod tests {
         let mut buf = [0u8; 2048];
         header.save(&mut (&mut buf as &mut [u8])).expect("save");
         let mut expected = [0u8; 8];
-        put_octal(&mut expected, header.gid);
+        put_octal(&mut expected, header.gid).unwrap();
         assert_eq!(&buf[116..124], &expected);
     }
 
@@ -917,7 +1007,7 @@ mod tests {
             }
         }
         let mut expected = [0u8; 12];
-        put_octal(&mut expected, pax_size);
+        put_octal(&mut expected, pax_size).unwrap();
         assert_eq!(&buf[124..136], &expected);
     }
 
@@ -927,7 +1017,7 @@ mod tests {
         let mut buf = [0u8; 2048];
         header.save(&mut (&mut buf as &mut [u8])).expect("save");
         let mut expected = [0u8; 12];
-        put_octal(&mut expected, header.mtime);
+        put_octal(&mut expected, header.mtime).unwrap();
         assert_eq!(&buf[136..148], &expected);
     }
 
@@ -995,7 +1085,7 @@ mod tests {
         let mut buf = [0u8; 2048];
         header.save(&mut (&mut buf as &mut [u8])).expect("save");
         let mut expected = [0u8; 8];
-        put_octal(&mut expected, header.devmajor);
+        put_octal(&mut expected, header.devmajor).unwrap();
         assert_eq!(&buf[329..337], &expected);
     }
 
@@ -1005,7 +1095,7 @@ mod tests {
         let mut buf = [0u8; 2048];
         header.save(&mut (&mut buf as &mut [u8])).expect("save");
         let mut expected = [0u8; 8];
-        put_octal(&mut expected, header.devminor);
+        put_octal(&mut expected, header.devminor).unwrap();
         assert_eq!(&buf[337..345], &expected);
     }
 
@@ -0,0 +1,263 @@
+use proptest::prelude::*;
+use proptest::string::string_regex;
+
+use super::gnu::SparseEntry;
+use super::{GnuHeader, GnuTypeFlag, PaxAttribute, PaxHeader, PaxTypeFlag, UstarHeader, UstarTypeFlag};
+
+/// Characters used for generated names, valid on any header field.
+const NAME_CHARS: &str = "[a-zA-Z0-9._/-]";
+
+/// Generates numbers that fit an octal field of `digits` digits, favoring
+/// zero and the max value.
+///
+/// # Arguments
+/// * `digits` - Octal digits available on the field.
+pub(crate) fn octal(digits: u32) -> impl Strategy<Value = u64> {
+    let max = 8u64.pow(digits) - 1;
+    prop_oneof![
+        1 => Just(0u64),
+        1 => Just(max),
+        4 => 0..=max
+    ]
+}
+
+/// Generates an octal value for the 8 bytes fields (mode, ids and devices).
+fn octal_u32() -> impl Strategy<Value = u32> {
+    octal(7).prop_map(|v| v as u32)
+}
+
+/// Generates names up to `max` bytes, favoring the exact max length.
+///
+/// # Arguments
+/// * `max` - Max name length in bytes.
+pub(crate) fn name(max: usize) -> impl Strategy<Value = String> {
+    prop_oneof![
+        1 => string_regex(&format!("{}{{{}}}", NAME_CHARS, max)).unwrap(),
+        3 => string_regex(&format!("{}{{0,{}}}", NAME_CHARS, max)).unwrap()
+    ]
+}
+
+/// Generates any known USTAR type flag.
+pub(crate) fn ustar_typeflag() -> impl Strategy<Value = UstarTypeFlag> {
+    prop_oneof![
+        Just(UstarTypeFlag::RegularFile),
+        Just(UstarTypeFlag::HardLink),
+        Just(UstarTypeFlag::SymbolicLink),
+        Just(UstarTypeFlag::CharacterSpecial),
+        Just(UstarTypeFlag::BlockSpecial),
+        Just(UstarTypeFlag::Directory),
+        Just(UstarTypeFlag::FIFO),
+        Just(UstarTypeFlag::ContiguousFile)
+    ]
+}
+
+/// Generates valid USTAR headers.
+pub(crate) fn ustar_header() -> impl Strategy<Value = UstarHeader> {
+    (
+        (name(100), name(155), name(100), ustar_typeflag()),
+        (octal_u32(), octal_u32(), octal_u32(), octal(11), octal(11)),
+        (name(32), name(32), octal_u32(), octal_u32())
+    ).prop_map(|((name, prefix, linkname, typeflag), (mode, uid, gid, size, mtime), (uname, gname, devmajor, devminor))| {
+        let mut header = UstarHeader::new(typeflag);
+        header.name = name;
+        header.prefix = prefix;
+        header.linkname = linkname;
+        header.mode = mode;
+        header.uid = uid;
+        header.gid = gid;
+        header.size = size;
+        header.mtime = mtime;
+        header.uname = uname;
+        header.gname = gname;
+        header.devmajor = devmajor;
+        header.devminor = devminor;
+        header
+    })
+}
+
+/// Generates USTAR headers with a single numeric field one past its max value.
+pub(crate) fn near_valid_ustar_header() -> impl Strategy<Value = UstarHeader> {
+    (ustar_header(), 0..7usize).prop_map(|(mut header, field)| {
+        let (max_u32, max_u64) = (8u32.pow(7), 8u64.pow(11));
+        match field {
+            0 => header.mode = max_u32,
+            1 => header.uid = max_u32,
+            2 => header.gid = max_u32,
+            3 => header.size = max_u64,
+            4 => header.mtime = max_u64,
+            5 => header.devmajor = max_u32,
+            _ => header.devminor = max_u32
+        }
+        header
+    })
+}
+
+/// Generates GNU sparse maps, from none up to several extended blocks.
+pub(crate) fn sparse_map() -> impl Strategy<Value = Vec<SparseEntry>> {
+    let entry = (octal(11), 1..=8u64.pow(11) - 1).prop_map(|(offset, numbytes)| SparseEntry { offset, numbytes });
+    prop_oneof![
+        2 => prop::collection::vec(entry.clone(), 0..=4),
+        1 => prop::collection::vec(entry, 5..=200)
+    ]
+}
+
+/// Generates valid GNU headers including long names, long link names and
+/// sparse maps.
+pub(crate) fn gnu_header() -> impl Strategy<Value = GnuHeader> {
+    let typeflag = prop_oneof![
+        4 => ustar_typeflag().prop_map(GnuTypeFlag::Ustar),
+        1 => Just(GnuTypeFlag::Sparse)
+    ];
+    (
+        (name(1200), name(1200), typeflag, sparse_map()),
+        (octal_u32(), octal_u32(), octal_u32(), octal(11), octal(11)),
+        (name(32), name(32), octal_u32(), octal_u32()),
+        (proptest::option::of(octal(11)), proptest::option::of(octal(11)), proptest::option::of(octal(11)), any::<[u8; 12]>())
+    ).prop_map(|((name, linkname, typeflag, sparse), (mode, uid, gid, size, mtime), (uname, gname, devmajor, devminor), (realsize, atime, ctime, gnu_extra))| {
+        let mut header = GnuHeader::new(typeflag);
+        header.set_name(name);
+        header.set_linkname(linkname);
+        header.mode = mode;
+        header.uid = uid;
+        header.gid = gid;
+        header.size = size;
+        header.mtime = mtime;
+        header.uname = uname;
+        header.gname = gname;
+        header.devmajor = devmajor;
+        header.devminor = devminor;
+        header.atime = atime;
+        header.ctime = ctime;
+        header.gnu_extra = gnu_extra;
+
+        // the real size and extended flag only make sense on sparse maps
+        header.isextended = sparse.len() > 4;
+        if !sparse.is_empty() {
+            header.realsize = realsize;
+        }
+        for entry in sparse {
+            header.push_sparse(entry);
+        }
+        header
+    })
+}
+
+/// Generates PAX attributes, both well known numeric ones and free form
+/// strings long enough to span several blocks.
+pub(crate) fn pax_attribute() -> impl Strategy<Value = (String, PaxAttribute)> {
+    let text = string_regex("[^\n\0]{0,700}").unwrap();
+    prop_oneof![
+        (prop_oneof![Just("uid"), Just("gid"), Just("size")], any::<u64>())
+            .prop_map(|(key, value)| (key.to_string(), PaxAttribute::from_u64(value.to_string()))),
+        (prop_oneof![Just("mtime"), Just("atime"), Just("ctime")], any::<u32>(), 0..1_000_000_000u32)
+            .prop_map(|(key, secs, nanos)| (key.to_string(), PaxAttribute::from_f64(format!("{}.{:09}", secs, nanos)))),
+        (prop_oneof![Just("path"), Just("linkpath"), Just("uname"), Just("gname")], text.clone())
+            .prop_map(|(key, value)| (key.to_string(), PaxAttribute::from_str(value))),
+        (string_regex("[A-Z]{2,10}\\.[a-z.]{1,20}").unwrap(), text)
+            .prop_map(|(key, value)| (key, PaxAttribute::from_str(value)))
+    ]
+}
+
+/// Generates valid PAX extended and global headers.
+pub(crate) fn pax_header() -> impl Strategy<Value = PaxHeader> {
+    let typeflag = prop_oneof![Just(PaxTypeFlag::Extended), Just(PaxTypeFlag::Global), Just(PaxTypeFlag::SolarisExtended)];
+    (
+        (name(100), name(155), typeflag, prop::collection::vec(pax_attribute(), 0..20)),
+        (octal_u32(), octal_u32(), octal_u32(), octal(11)),
+        (name(32), name(32))
+    ).prop_map(|((name, prefix, typeflag, attributes), (mode, uid, gid, mtime), (uname, gname))| {
+        let mut header = PaxHeader::new(typeflag);
+        header.name = name;
+        header.prefix = prefix;
+        header.mode = mode;
+        header.uid = uid;
+        header.gid = gid;
+        header.mtime = mtime;
+        header.uname = uname;
+        header.gname = gname;
+        for (key, value) in attributes {
+            header.set_attr(&key, value);
+        }
+        header
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{TarHeader, UsedBlocksTrait};
+    use std::io::Cursor;
+
+    /// Pads a saved header to the next block boundary.
+    fn pad(mut buf: Vec<u8>) -> Vec<u8> {
+        buf.resize(buf.len().div_ceil(512) * 512, 0);
+        buf
+    }
+
+    proptest! {
+        #[test]
+        fn ustar_round_trip(mut header in ustar_header()) {
+            let mut buf = Vec::new();
+            prop_assert!(header.save(&mut buf).is_ok());
+            prop_assert_eq!(512, buf.len());
+            let block: [u8; 512] = buf[..].try_into().unwrap();
+            let mut loaded = match UstarHeader::load(&block) {
+                Ok(Some(v)) => v,
+                Ok(None) => return Err(TestCaseError::fail("expected a USTAR header")),
+                Err(e) => return Err(TestCaseError::fail(format!("Failed to load: {}", e)))
+            };
+            loaded.chksum = header.chksum;
+            prop_assert_eq!(header, loaded);
+        }
+
+        #[test]
+        fn ustar_field_overflow(mut header in near_valid_ustar_header()) {
+            let mut buf = Vec::new();
+            prop_assert!(header.save(&mut buf).is_err());
+        }
+
+        #[test]
+        fn gnu_round_trip(mut header in gnu_header()) {
+            let mut buf = Vec::new();
+            prop_assert!(header.save(&mut buf).is_ok());
+            prop_assert_eq!(buf.len(), header.get_saved_blocks() * 512);
+            let mut reader = Cursor::new(&buf[512..]);
+            let block: [u8; 512] = buf[..512].try_into().unwrap();
+            let loaded = match GnuHeader::load(&block, &mut reader) {
+                Ok(Some(v)) => v,
+                Ok(None) => return Err(TestCaseError::fail("expected a GNU header")),
+                Err(e) => return Err(TestCaseError::fail(format!("Failed to load: {}", e)))
+            };
+            prop_assert_eq!(buf.len() - 512, reader.position() as usize);
+            prop_assert_eq!(header, loaded);
+        }
+
+        #[test]
+        fn pax_round_trip(mut header in pax_header()) {
+            let mut buf = Vec::new();
+            prop_assert!(header.save(&mut buf).is_ok());
+            let buf = pad(buf);
+            prop_assert_eq!(buf.len(), header.get_saved_blocks() * 512);
+            let mut reader = Cursor::new(&buf[512..]);
+            let block: [u8; 512] = buf[..512].try_into().unwrap();
+            let loaded = match PaxHeader::load(&block, &mut reader) {
+                Ok(Some(v)) => v,
+                Ok(None) => return Err(TestCaseError::fail("expected a PAX header")),
+                Err(e) => return Err(TestCaseError::fail(format!("Failed to load: {}", e)))
+            };
+            header.size = loaded.size;
+            prop_assert_eq!(header, loaded);
+        }
+
+        #[test]
+        fn tar_header_detects_format(mut header in gnu_header()) {
+            let mut buf = Vec::new();
+            prop_assert!(header.save(&mut buf).is_ok());
+            match TarHeader::load(&mut Cursor::new(buf)) {
+                Ok(TarHeader::Gnu(_)) => {},
+                Ok(_) => return Err(TestCaseError::fail("expected a GNU header")),
+                Err(e) => return Err(TestCaseError::fail(format!("Failed to load: {}", e)))
+            }
+        }
+    }
+}
@@ -3,6 +3,7 @@ use std::io::Write;
 
 /// Represents a USTAR TAR header.
 use super::helper::*;
+use crate::format::checksum;
 use super::{UsedBlocksTrait, IsTypeTrait};
 
 /// USTAR header type flag.
@@ -220,31 +221,30 @@ impl UstarHeader {
     ///
     /// # Returns
     /// * `Ok(())` - On success.
-    /// * `Err(e)` - If write fails.
+    /// * `Err(e)` - If a value doesn't fit its field or write fails.
     pub fn save(&mut self, writer: &mut impl Write) -> anyhow::Result<()> {
         let mut buf = [0u8; 512];
         put_str(&mut buf[0..100], &self.name);
-        put_octal(&mut buf[100..108], self.mode);
-        put_octal(&mut buf[108..116], self.uid);
-        put_octal(&mut buf[116..124], self.gid);
-        put_octal(&mut buf[124..136], self.size);
-        put_octal(&mut buf[136..148], self.mtime);
+        put_octal(&mut buf[100..108], self.mode)?;
+        put_octal(&mut buf[108..116], self.uid)?;
+        put_octal(&mut buf[116..124], self.gid)?;
+        put_octal(&mut buf[124..136], self.size)?;
+        put_octal(&mut buf[136..148], self.mtime)?;
         buf[156] = self.typeflag.into();
         put_str(&mut buf[157..257], &self.linkname);
         put_str(&mut buf[257..263], &self.magic);
         put_str(&mut buf[263..265], &self.version);
         put_str(&mut buf[265..297], &self.uname);
         put_str(&mut buf[297..329], &self.gname);
-        put_octal(&mut buf[329..337], self.devmajor);
-        put_octal(&mut buf[337..345], self.devminor);
+        put_octal(&mut buf[329..337], self.devmajor)?;
+        put_octal(&mut buf[337..345], self.devminor)?;
         put_str(&mut buf[345..500], &self.prefix);
 
         // Set checksum field to spaces before computing checksum (TAR spec)
         for b in &mut buf[148..156] { *b = b' '; }
 
         // Compute and write checksum
-        let mut chksum: u32 = 0;
-        for i in 0..512 { chksum = chksum.wrapping_add(buf[i] as u32); }
+        let chksum = checksum(&buf);
         let chksum_str = format!("{:06o}\0 ", chksum);
         let chksum_bytes = chksum_str.as_bytes();
         buf[148..148+chksum_bytes.len()].copy_from_slice(chksum_bytes);
@@ -380,4 +380,17 @@ mod tests {
         assert_eq!(header.name, loaded.name);
         assert_eq!(header.size, loaded.size);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn save_size_overflow() {
+        let mut header = sample_header();
+        header.size = 0o100000000000;
+        let mut buf = Vec::new();
+        match header.save(&mut buf) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(e.to_string(), "value too large for field: 100000000000 doesn't fit in 11 octal digits")
+        }
+        assert!(buf.is_empty());
+        assert_eq!(0, header.saved_blocks);
+    }
+}
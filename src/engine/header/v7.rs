@@ -2,6 +2,7 @@ use anyhow::Result;
 use std::io::Write;
 
 use super::helper::*;
+use crate::format::checksum;
 use super::{UsedBlocksTrait, IsTypeTrait};
 
 /// V7 header type flag.
@@ -172,15 +173,15 @@ impl V7Header {
     ///
     /// # Returns
     /// * `Ok(())` - On success.
-    /// * `Err(e)` - If write fails.
+    /// * `Err(e)` - If a value doesn't fit its field or write fails.
     pub fn save(&mut self, writer: &mut impl Write) -> anyhow::Result<()> {
         let mut buf = [0u8; 512];
         put_str(&mut buf[0..100], &self.name);
-        put_octal(&mut buf[100..108], self.mode);
-        put_octal(&mut buf[108..116], self.uid);
-        put_octal(&mut buf[116..124], self.gid);
-        put_octal(&mut buf[124..136], self.size);
-        put_octal(&mut buf[136..148], self.mtime);
+        put_octal(&mut buf[100..108], self.mode)?;
+        put_octal(&mut buf[108..116], self.uid)?;
+        put_octal(&mut buf[116..124], self.gid)?;
+        put_octal(&mut buf[124..136], self.size)?;
+        put_octal(&mut buf[136..148], self.mtime)?;
 
         // chksum is written after calculating
         buf[156] = self.typeflag.into();
@@ -190,8 +191,7 @@ impl V7Header {
         buf[148..156].fill(b' ');
         
         // Compute and write checksum
-        let mut chksum: u32 = 0;
-        for i in 0..512 { chksum = chksum.wrapping_add(buf[i] as u32); }
+        let chksum = checksum(&buf);
         let chksum_str = format!("{:06o}\0 ", chksum);
         let chksum_bytes = chksum_str.as_bytes();
         buf[148..148+chksum_bytes.len()].copy_from_slice(chksum_bytes);
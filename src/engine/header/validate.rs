@@ -0,0 +1,422 @@
+use thiserror::Error;
+
+use crate::format::{checksum, parse_octal};
+
+use super::helper::get_bytes;
+use super::{PaxHeader, PaxTypeFlag, TarHeader, UstarHeader, UstarTypeFlag};
+
+/// Numeric header fields as `(label, start, end)`.
+const NUMERIC_FIELDS: [(&str, usize, usize); 8] = [
+    ("mode", 100, 108),
+    ("uid", 108, 116),
+    ("gid", 116, 124),
+    ("size", 124, 136),
+    ("mtime", 136, 148),
+    ("chksum", 148, 156),
+    ("devmajor", 329, 337),
+    ("devminor", 337, 345)
+];
+
+/// Keywords defined by POSIX, any other keyword made of lowercase letters
+/// only is reserved.
+const PAX_KEYWORDS: [&str; 13] = [
+    "atime", "charset", "comment", "ctime", "gid", "gname", "hdrcharset",
+    "linkpath", "mtime", "path", "size", "uid", "uname"
+];
+
+/// Strict POSIX (ustar and pax) conformance violation.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[non_exhaustive]
+pub enum PosixViolation {
+    /// The header uses a non POSIX format such as GNU or V7.
+    #[error("non POSIX {0} header")]
+    NonPosixFormat(&'static str),
+    /// The header block is shorter than 512 bytes.
+    #[error("truncated header block of {0} bytes")]
+    TruncatedBlock(usize),
+    /// The magic field isn't `ustar\0`.
+    #[error("invalid magic, expected 'ustar\\0'")]
+    InvalidMagic,
+    /// The version field isn't `00`.
+    #[error("invalid version, expected '00'")]
+    InvalidVersion,
+    /// The type flag isn't defined by POSIX.
+    #[error("non POSIX type flag {0:?}")]
+    InvalidTypeflag(char),
+    /// The stored checksum doesn't match the header bytes.
+    #[error("checksum mismatch, stored {stored:o} but computed {computed:o}")]
+    ChecksumMismatch { stored: u32, computed: u32 },
+    /// A text field exceeds its max length.
+    #[error("{field} is {len} bytes long, max is {max}")]
+    FieldTooLong { field: &'static str, len: usize, max: usize },
+    /// A field lacks its required NUL or space terminator.
+    #[error("{0} is not terminated")]
+    MissingNul(&'static str),
+    /// A numeric field has non octal digits.
+    #[error("{0} is not a valid octal number")]
+    InvalidNumber(&'static str),
+    /// A numeric value doesn't fit its field.
+    #[error("{0} doesn't fit its field")]
+    NumberOutOfRange(&'static str),
+    /// A PAX extended record is malformed.
+    #[error("invalid PAX record at {offset}: {reason}")]
+    InvalidRecord { offset: usize, reason: String },
+    /// A PAX attribute has an invalid key or value.
+    #[error("invalid PAX attribute '{key}': {reason}")]
+    InvalidAttribute { key: String, reason: String },
+}
+
+/// Validates a raw header block against the strict ustar rules: magic and
+/// version, octal numeric fields with their terminators, NUL terminated user
+/// and group names, POSIX type flags and the checksum.
+///
+/// # Arguments
+/// * `block` - Raw header block.
+///
+/// # Returns
+/// * `Vec<PosixViolation>` - The violations found, empty when the block conforms.
+pub fn validate_block(block: &[u8]) -> Vec<PosixViolation> {
+    if block.len() < 512 {
+        return vec![PosixViolation::TruncatedBlock(block.len())];
+    }
+    let mut violations = Vec::new();
+    let (magic, version) = (&block[257..263], &block[263..265]);
+    if magic == b"ustar " && version == b" \0" {
+        violations.push(PosixViolation::NonPosixFormat("GNU"));
+    } else if block[257..265].iter().all(|b| *b == 0) {
+        violations.push(PosixViolation::NonPosixFormat("V7"));
+    } else {
+        if magic != b"ustar\0" {
+            violations.push(PosixViolation::InvalidMagic);
+        }
+        if version != b"00" {
+            violations.push(PosixViolation::InvalidVersion);
+        }
+    }
+    match block[156] {
+        b'0'..=b'7' | b'\0' | b'x' | b'g' => {},
+        v => violations.push(PosixViolation::InvalidTypeflag(v as char))
+    }
+    for (field, start, end) in NUMERIC_FIELDS {
+        let raw = &block[start..end];
+        if raw[raw.len() - 1] != 0 && raw[raw.len() - 1] != b' ' {
+            violations.push(PosixViolation::MissingNul(field));
+        }
+        let digits = raw.iter().take_while(|b| **b != 0 && **b != b' ');
+        if digits.clone().any(|b| !(b'0'..=b'7').contains(b)) {
+            violations.push(PosixViolation::InvalidNumber(field));
+        }
+    }
+    for (field, start, end) in [("uname", 265, 297), ("gname", 297, 329)] {
+        if !block[start..end].contains(&0) {
+            violations.push(PosixViolation::MissingNul(field));
+        }
+    }
+
+    // the checksum is computed with its own field filled with spaces
+    let computed = checksum(block[..512].try_into().unwrap());
+    let digits = get_bytes(&block[148..156]);
+    let stored = match digits.is_empty() {
+        true => None,
+        false => parse_octal(digits).ok().map(|v| v as u32)
+    };
+    match stored {
+        Some(stored) if stored != computed => violations.push(PosixViolation::ChecksumMismatch { stored, computed }),
+        _ => {}
+    }
+    violations
+}
+
+/// Validates the PAX extended records data, each record must follow the
+/// `"%d %s=%s\n"` layout where the length includes the whole record.
+///
+/// # Arguments
+/// * `data` - The extended header data without the block padding.
+///
+/// # Returns
+/// * `Vec<PosixViolation>` - The violations found, empty when the records conform.
+pub fn validate_records(data: &[u8]) -> Vec<PosixViolation> {
+    let mut violations = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let start = offset;
+        let invalid = move |reason: &str| PosixViolation::InvalidRecord { offset: start, reason: reason.to_string() };
+        let rest = &data[offset..];
+        let space = match rest.iter().position(|b| *b == b' ') {
+            Some(v) => v,
+            None => {
+                violations.push(invalid("missing length separator"));
+                break;
+            }
+        };
+        let len = match std::str::from_utf8(&rest[..space]).ok().and_then(|v| v.parse::<usize>().ok()) {
+            Some(v) if v > space + 1 && v <= rest.len() => v,
+            _ => {
+                violations.push(invalid("invalid record length"));
+                break;
+            }
+        };
+        let record = &rest[space + 1..len];
+        offset += len;
+        if record.last() != Some(&b'\n') {
+            violations.push(invalid("record doesn't end with a newline"));
+            continue;
+        }
+        let record = &record[..record.len() - 1];
+        let equal = match record.iter().position(|b| *b == b'=') {
+            Some(v) => v,
+            None => {
+                violations.push(invalid("missing '=' separator"));
+                continue;
+            }
+        };
+        let (key, value) = match (std::str::from_utf8(&record[..equal]), std::str::from_utf8(&record[equal + 1..])) {
+            (Ok(key), Ok(value)) => (key, value),
+            _ => {
+                violations.push(invalid("record is not valid UTF-8"));
+                continue;
+            }
+        };
+        if let Some(v) = validate_attribute(key, value) {
+            violations.push(v);
+        }
+    }
+    violations
+}
+
+/// Validates a PAX attribute key and value.
+///
+/// # Arguments
+/// * `key` - Attribute keyword.
+/// * `value` - Raw attribute value.
+///
+/// # Returns
+/// * `Some(PosixViolation)` - The violation found.
+/// * `None` - When the attribute conforms.
+pub fn validate_attribute(key: &str, value: &str) -> Option<PosixViolation> {
+    let invalid = |reason: &str| Some(PosixViolation::InvalidAttribute { key: key.to_string(), reason: reason.to_string() });
+    if key.is_empty() {
+        return invalid("empty keyword");
+    }
+    let is_decimal = |v: &str| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit());
+    match key {
+        "size" | "uid" | "gid" if !is_decimal(value) => invalid("expected a decimal number"),
+        "atime" | "ctime" | "mtime" => {
+            let unsigned = value.strip_prefix('-').unwrap_or(value);
+            let mut parts = unsigned.splitn(2, '.');
+            let secs = parts.next().unwrap_or_default();
+            match parts.next() {
+                Some(fraction) if !is_decimal(secs) || !is_decimal(fraction) => invalid("expected a decimal timestamp"),
+                None if !is_decimal(secs) => invalid("expected a decimal timestamp"),
+                _ => None
+            }
+        },
+        "hdrcharset" if value != "ISO-IR 10646 2000 UTF-8" && value != "BINARY" => invalid("unknown header charset"),
+        key if !PAX_KEYWORDS.contains(&key) && key.bytes().all(|b| b.is_ascii_lowercase()) => invalid("reserved keyword"),
+        _ => None
+    }
+}
+
+/// Validates the common ustar text and numeric fields of a parsed header.
+fn validate_fields(violations: &mut Vec<PosixViolation>, header: &UstarHeader) {
+    let text = [
+        ("name", &header.name, 100),
+        ("linkname", &header.linkname, 100),
+        ("prefix", &header.prefix, 155),
+        ("uname", &header.uname, 31),
+        ("gname", &header.gname, 31)
+    ];
+    for (field, value, max) in text {
+        if value.len() > max {
+            violations.push(PosixViolation::FieldTooLong { field, len: value.len(), max });
+        }
+    }
+    if header.magic != "ustar\0" {
+        violations.push(PosixViolation::InvalidMagic);
+    }
+    if header.version != "00" {
+        violations.push(PosixViolation::InvalidVersion);
+    }
+    let (max_short, max_long) = (0o7777777u64, 0o77777777777u64);
+    let numbers = [
+        ("mode", header.mode as u64, max_short),
+        ("uid", header.uid as u64, max_short),
+        ("gid", header.gid as u64, max_short),
+        ("size", header.size, max_long),
+        ("mtime", header.mtime, max_long),
+        ("devmajor", header.devmajor as u64, max_short),
+        ("devminor", header.devminor as u64, max_short)
+    ];
+    for (field, value, max) in numbers {
+        if value > max {
+            violations.push(PosixViolation::NumberOutOfRange(field));
+        }
+    }
+    if let UstarTypeFlag::Unknown(v) = header.typeflag {
+        violations.push(PosixViolation::InvalidTypeflag(v as char));
+    }
+}
+
+/// Validates a PAX header fields by checking them as an ustar header, plus
+/// its attributes.
+fn validate_pax(violations: &mut Vec<PosixViolation>, header: &PaxHeader) {
+    let mut ustar = UstarHeader::new(UstarTypeFlag::RegularFile);
+    ustar.name = header.name.clone();
+    ustar.linkname = header.linkname.clone();
+    ustar.prefix = header.prefix.clone();
+    ustar.uname = header.uname.clone();
+    ustar.gname = header.gname.clone();
+    ustar.magic = header.magic.clone();
+    ustar.version = header.version.clone();
+    ustar.mode = header.mode;
+    ustar.uid = header.uid;
+    ustar.gid = header.gid;
+    ustar.size = header.size;
+    ustar.mtime = header.mtime;
+    ustar.devmajor = header.devmajor;
+    ustar.devminor = header.devminor;
+    validate_fields(violations, &ustar);
+    if let PaxTypeFlag::Ustar(v) = header.typeflag {
+        violations.push(PosixViolation::InvalidTypeflag(u8::from(v) as char));
+    }
+    for (key, value) in header.iter_attr() {
+        if let Some(v) = validate_attribute(key, &value.raw) {
+            violations.push(v);
+        }
+    }
+}
+
+/// Computes the checksum a header would be saved with.
+///
+/// # Returns
+/// * `Some(u32)` - The checksum.
+/// * `None` - When the header can't be saved.
+fn saved_checksum(mut header: TarHeader) -> Option<u32> {
+    let mut buf = Vec::new();
+    header.save(&mut buf).ok()?;
+    buf.get(..512).map(checksum)
+}
+
+impl TarHeader {
+    /// Validates the header against strict POSIX ustar and pax rules: field
+    /// lengths, magic and version, numeric ranges, type flags, PAX attribute
+    /// syntax and the checksum of loaded headers. Unknown headers are
+    /// validated from their raw block, see `validate_block`.
+    ///
+    /// # Returns
+    /// * `Vec<PosixViolation>` - The violations found, empty when the header conforms.
+    pub fn validate_posix(&self) -> Vec<PosixViolation> {
+        let mut violations = Vec::new();
+        let stored = match self {
+            TarHeader::Gnu(_) => return vec![PosixViolation::NonPosixFormat("GNU")],
+            TarHeader::V7(_) => return vec![PosixViolation::NonPosixFormat("V7")],
+            TarHeader::Custom(_) => return vec![PosixViolation::NonPosixFormat("custom")],
+            TarHeader::Unknown(buf, size) => return validate_block(&buf[..*size]),
+            TarHeader::Ustar(h) => {
+                validate_fields(&mut violations, h);
+                h.chksum
+            },
+            TarHeader::Pax(h) => {
+                validate_pax(&mut violations, h);
+                h.chksum
+            }
+        };
+
+        // built headers have no checksum until they are saved
+        if stored != 0 && violations.is_empty() {
+            let header = match self {
+                TarHeader::Ustar(h) => TarHeader::Ustar(h.clone()),
+                TarHeader::Pax(h) => TarHeader::Pax(h.clone()),
+                _ => return violations
+            };
+            match saved_checksum(header) {
+                Some(computed) if computed != stored => violations.push(PosixViolation::ChecksumMismatch { stored, computed }),
+                _ => {}
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::header::{GnuHeader, GnuTypeFlag, PaxAttribute};
+
+    fn saved_block(header: &mut UstarHeader) -> [u8; 512] {
+        let mut buf = Vec::new();
+        header.save(&mut buf).unwrap();
+        buf[..512].try_into().unwrap()
+    }
+
+    #[test]
+    fn valid_block() {
+        let mut header = UstarHeader::new(UstarTypeFlag::RegularFile);
+        header.name = "a.txt".to_string();
+        let block = saved_block(&mut header);
+        assert_eq!(Vec::<PosixViolation>::new(), validate_block(&block));
+    }
+
+    #[test]
+    fn invalid_block() {
+        let mut header = UstarHeader::new(UstarTypeFlag::RegularFile);
+        header.uname = "u".repeat(32);
+        let mut block = saved_block(&mut header);
+        block[100] = b'9';
+        block[156] = b'L';
+        let violations = validate_block(&block);
+        assert!(violations.contains(&PosixViolation::InvalidNumber("mode")));
+        assert!(violations.contains(&PosixViolation::InvalidTypeflag('L')));
+        assert!(violations.contains(&PosixViolation::MissingNul("uname")));
+        assert!(violations.iter().any(|v| matches!(v, PosixViolation::ChecksumMismatch { .. })));
+        assert_eq!(vec![PosixViolation::TruncatedBlock(10)], validate_block(&block[..10]));
+    }
+
+    #[test]
+    fn records() {
+        assert!(validate_records(b"14 path=a.txt\n").is_empty());
+        assert!(validate_records(b"").is_empty());
+        assert_eq!(1, validate_records(b"15 path=a.txt\n").len());
+        assert_eq!(1, validate_records(b"11 uid=abc\n").len());
+        assert_eq!(1, validate_records(b"11 novalue\n").len());
+        assert_eq!(1, validate_records(b"x path=a\n").len());
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(None, validate_attribute("mtime", "1600000000.5"));
+        assert_eq!(None, validate_attribute("mtime", "-12"));
+        assert_eq!(None, validate_attribute("SCHILY.xattr.user.a", "x"));
+        assert!(validate_attribute("mtime", "1.").is_some());
+        assert!(validate_attribute("size", "").is_some());
+        assert!(validate_attribute("hdrcharset", "latin1").is_some());
+        assert!(validate_attribute("custom", "x").is_some());
+    }
+
+    #[test]
+    fn parsed_headers() {
+        let mut header = UstarHeader::new(UstarTypeFlag::RegularFile);
+        header.name = "n".repeat(101);
+        header.mode = 0o10000000;
+        let violations = TarHeader::Ustar(header).validate_posix();
+        assert_eq!(vec![
+            PosixViolation::FieldTooLong { field: "name", len: 101, max: 100 },
+            PosixViolation::NumberOutOfRange("mode")
+        ], violations);
+
+        let mut header = PaxHeader::new(PaxTypeFlag::Extended);
+        header.set_attr("uid", PaxAttribute::from_str("x".to_string()));
+        assert_eq!(1, TarHeader::Pax(header).validate_posix().len());
+
+        let header = GnuHeader::new(GnuTypeFlag::Ustar(UstarTypeFlag::RegularFile));
+        assert_eq!(vec![PosixViolation::NonPosixFormat("GNU")], TarHeader::Gnu(header).validate_posix());
+
+        // loaded headers get their checksum checked
+        let mut header = UstarHeader::new(UstarTypeFlag::RegularFile);
+        let block = saved_block(&mut header);
+        let mut loaded = UstarHeader::load(&block).unwrap().unwrap();
+        assert!(TarHeader::Ustar(loaded.clone()).validate_posix().is_empty());
+        loaded.chksum += 1;
+        assert_eq!(1, TarHeader::Ustar(loaded).validate_posix().len());
+    }
+}
@@ -1,8 +1,12 @@
+mod export;
 mod file;
 mod page;
+mod superblock;
 
-pub use file::FileEntry;
+pub use export::{ExportTrailer, EXPORT_MAGIC, EXPORT_PATH};
+pub use file::{FileEntry, FileMeta};
 pub use page::{Page, RECORD_COUNT as PAGE_RECORD_COUNT};
+pub use superblock::{Superblock, SUPERBLOCK_SIZE};
 
 use anyhow::{bail, Result};
 use std::io::{Read, Seek, SeekFrom, Write};
@@ -13,19 +17,38 @@ use indexmap::IndexMap;
 use std::collections::HashMap;
 use std::marker::PhantomData;
 
-use crate::engine::{header::{IsTypeTrait, PaxHeader, PaxTypeFlag, TarHeader, UsedBlocksTrait, UstarTypeFlag}, index::file::FileMeta};
+use crate::engine::archive::padded_size;
+use crate::engine::error::Error;
+use crate::engine::header::{IsTypeTrait, PaxHeader, PaxTypeFlag, TarHeader, UsedBlocksTrait, UstarTypeFlag};
 
 pub const PAGE_SIZE: u64 = 1024 * 1024;
 
+/// Entries stored on every page, the first record of a page points to the
+/// next page.
+const PAGE_ENTRY_COUNT: usize = PAGE_RECORD_COUNT as usize - 1;
+
 pub(crate) struct Index {
-    pub first_page: usize,
     pub pages: Vec<Page>,
 
+    /// Index layout stored before the first page table.
+    superblock: Superblock,
+
+    /// Whether the index was written before superblocks existed, its first
+    /// page table starts right after the page header.
+    legacy: bool,
+
     /// Files in the page.
     entries: IndexMap<String, FileEntry>,
 
     /// Modified entries.
     modified: HashMap<usize, PhantomData<()>>,
+
+    /// Last generation assigned to an entry.
+    generation: u64,
+
+    /// Highest entry position ever stored, used to know how many records to
+    /// soft remove from the pages.
+    max_index: usize,
 }
 
 impl Index {
@@ -38,18 +61,20 @@ impl Index {
         let mut entries = IndexMap::new();
         entries.shift_insert(0, "".to_string(), FileEntry::default());
         Self {
-            first_page: 0,
             pages: Vec::new(),
+            superblock: Superblock::new(0),
+            legacy: false,
             entries,
-            modified: HashMap::new()
+            modified: HashMap::new(),
+            generation: 0,
+            max_index: 0
         }
     }
 
-    pub fn read_headers(stream: impl Read + Seek) -> Result<()> {
-        unimplemented!()
-    }
-
-    /// Opens an index file and loads all pages into memory.
+    /// Opens an index file and loads all pages into memory, indexes written
+    /// before superblocks existed are loaded by scanning their page chain.
+    /// Entries stored before the first page are skipped, tars created over
+    /// an empty stream write their first files before it.
     ///
     /// # Arguments
     ///
@@ -60,14 +85,19 @@ impl Index {
     /// * `IoResult<Self>`: The result of the open operation.
     pub fn open(stream: &mut (impl Read + Seek + Write)) -> Result<Self> {
         let mut offset;
-        let mut pages = Vec::new();
+        let mut generation = 0;
+        let mut pages: Vec<Page> = Vec::new();
+        let mut superblock = Superblock::new(0);
+        let mut legacy = false;
         let mut entries = IndexMap::new();
         entries.insert(String::default(), FileEntry::default());
+        Self::seek_first_page(stream)?;
 
         // read pages
         loop {
             // read page header
-            let mut header = TarHeader::load(stream)?;
+            let header_offset = stream.stream_position()?;
+            let header = TarHeader::load(stream)?;
             if !header.is_regular_file() {
                 bail!("expected regular file");
             }
@@ -76,9 +106,29 @@ impl Index {
                 bail!("invalid index page size");
             }
 
-            // read page data
+            // validate the superblock before parsing any page so foreign data fails fast
             offset = stream.stream_position()?;
-            let mut segment = Segment::new_unsafe(stream, offset, size)?;
+            if pages.is_empty() && !Superblock::probe(stream)? {
+                legacy = true;
+                superblock = Superblock::new(header_offset);
+            }
+            let table_offset = match pages.is_empty() && !legacy {
+                true => {
+                    superblock = Superblock::load(stream)?;
+                    if superblock.first_page != header_offset {
+                        bail!(Error::Corrupted("index superblock doesn't match its page offset".to_string()));
+                    }
+                    offset + SUPERBLOCK_SIZE
+                },
+                false => offset
+            };
+
+            // read page data
+            let table_size = match legacy {
+                true => PAGE_SIZE,
+                false => Self::table_size_at(pages.len())
+            };
+            let mut segment = Segment::new_unsafe(stream, table_offset, table_size)?;
             match Page::load(&mut segment) {
                 Ok(mut page) => {
                     // validate table
@@ -87,35 +137,31 @@ impl Index {
                     }
 
                     // record page offsets
-                    page.offset = offset;
-                    page.table_offset = offset + 512 * header.get_used_blocks() as u64;
-
-                    // first record is always the offset of the next page unless 0
-                    let record = match page.table.record_from(&mut segment, 0)? {
-                        Some(record) => record,
-                        None => bail!("expected record 0 to exists")
-                    };
-                    
+                    page.offset = header_offset;
+                    page.table_offset = table_offset;
 
                     // add page records to the index
                     let iter = page.iter(&mut segment)?;
                     let mut is_first = true;
                     for record in iter {
-                        // handle the first entry, this one contains the offset of the next page
+                        // first record is always the offset of the next page unless 0
                         if is_first {
                             offset = match record.get("offset") {
                                 Some(v) => v.try_into()?,
                                 None => bail!("expected record 0 to contain 'offset' field")
                             };
+                            is_first = false;
                             continue;
                         }
 
                         // handle the other entries
-                        let entry = FileEntry::from_record(&record)?;
+                        let mut entry = FileEntry::from_record(&record)?;
                         if entry.meta.offset < 1 {
                             // exit whenever the offset is 0, this will mark us the first empty record
                             break;
                         }
+                        generation += 1;
+                        entry.generation = generation;
                         entries.insert(entry.meta.path.clone(), entry);
                     }
 
@@ -126,46 +172,115 @@ impl Index {
                     if offset < 1 {
                         break;
                     }
+                    stream.seek(SeekFrom::Start(offset))?;
                 }
                 Err(_) => {
                     // exit as error when the index positions are corrupted
-                    bail!("page not found, the index is corrupted, please fallback to scan mode");
+                    bail!(Error::Corrupted("index page not found, please fallback to scan mode".to_string()));
                 },
             }
         }
+        if legacy {
+            superblock.last_page = pages.last().map(|page| page.offset).unwrap_or(superblock.first_page);
+        } else if pages.last().map(|page| page.offset) != Some(superblock.last_page) {
+            bail!(Error::Corrupted("index superblock last page doesn't match the page chain".to_string()));
+        }
+        let max_index = entries.len() - 1;
         Ok(Self{
-            first_page: 0,
             pages,
+            superblock,
+            legacy,
             entries,
-            modified: HashMap::new()
+            modified: HashMap::new(),
+            generation,
+            max_index
         })
     }
 
-    /// Adds a new page to the index.
+    /// Moves the stream to the header of the first index page, found by its
+    /// superblock. Indexes written before superblocks existed are expected at
+    /// the stream position.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - Stream positioned at the first entry header.
+    fn seek_first_page(stream: &mut (impl Read + Seek)) -> Result<()> {
+        let start = stream.stream_position()?;
+        let mut offset = start;
+        let mut legacy = false;
+        loop {
+            // stop at the end of archive marker or the stream end
+            let mut block = [0u8; 512];
+            stream.seek(SeekFrom::Start(offset))?;
+            match stream.read_exact(&mut block) {
+                Ok(_) => {},
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into())
+            }
+            if block.iter().all(|b| *b == 0) {
+                break;
+            }
+            let header = TarHeader::load_block(block, stream)?;
+            let content = stream.stream_position()?;
+            match header {
+                TarHeader::Unknown(_, _) => break,
+
+                // extended and global header records are read along their header
+                TarHeader::Pax(_) => {
+                    offset = content;
+                    continue;
+                },
+                _ => {}
+            }
+            let size = header.get_content_size();
+            if header.is_regular_file() && size == PAGE_SIZE {
+                if Superblock::probe(stream)? {
+                    stream.seek(SeekFrom::Start(offset))?;
+                    return Ok(());
+                }
+                legacy |= offset == start;
+            }
+            offset = content + padded_size(size);
+        }
+        if !legacy {
+            bail!(Error::Corrupted("index page not found, please fallback to scan mode".to_string()));
+        }
+        stream.seek(SeekFrom::Start(start))?;
+        Ok(())
+    }
+
+    /// Adds a new page to the index followed by the end of archive marker.
     /// 
     /// # Arguments
     /// 
-    /// * `segment` - Segment to write the page into.
-    /// * `offset` - Offset of the new page.
+    /// * `stream` - Stream to write the page into.
+    /// * `page_offset` - Offset of the new page header, usually the archive data end.
     /// * `path` - Path of the new page.
-    pub fn add_page(&mut self, stream: &mut (impl Read + Seek + Write), path: &str) -> Result<&mut Page> {
-        // seek up to the end of the TAR
-        stream.seek(SeekFrom::End(1024))?;
-        let page_offset = stream.stream_position()?;
+    pub fn add_page(&mut self, stream: &mut (impl Read + Seek + Write), page_offset: u64, path: &str) -> Result<&mut Page> {
+        stream.seek(SeekFrom::Start(page_offset))?;
 
         // save new page
         let mut header = PaxHeader::new(PaxTypeFlag::Ustar(UstarTypeFlag::RegularFile));
         header.set_attr_path(path);
         header.set_attr_size(PAGE_SIZE);
         header.save(stream)?;
-        let table_offset = page_offset + 512 * header.get_used_blocks() as u64;
-        let mut segment = Segment::new_unsafe(stream, table_offset, PAGE_SIZE)?;
+        let mut table_offset = page_offset + 512 * header.get_used_blocks() as u64;
+
+        // the first page starts with the superblock
+        if self.pages.is_empty() {
+            self.superblock = Superblock::new(page_offset);
+            self.superblock.save(stream)?;
+            table_offset += SUPERBLOCK_SIZE;
+        }
+        let table_size = self.table_size(self.pages.len());
+        let mut segment = Segment::new_unsafe(stream, table_offset, table_size)?;
         let mut page = Page::new(&mut segment)?;
         page.offset = page_offset;
         page.table_offset = table_offset;
 
-        // write TAR end
-        stream.write(&[0u8; 1024])?;
+        // write TAR end right after the page content
+        stream.seek(SeekFrom::Start(table_offset + table_size))?;
+        stream.write_all(&[0u8; 1024])?;
         stream.flush()?;
 
         // update the last page to point to the new page
@@ -175,8 +290,19 @@ impl Index {
             let mut record = last_page.table.header.record.new_record()?;
             record.set("offset", page_offset.into());
             record.set("path", path.into());
-            let mut last_segment = Segment::new_unsafe(stream, last_page.table_offset, PAGE_SIZE)?;
+            let last_size = match self.legacy {
+                true => PAGE_SIZE,
+                false => Self::table_size_at(page_count - 1)
+            };
+            let mut last_segment = Segment::new_unsafe(stream, last_page.table_offset, last_size)?;
             last_page.table.save_record_into(&mut last_segment, 0, &record)?;
+
+            // point the superblock to the new last page, legacy indexes have none
+            self.superblock.last_page = page_offset;
+            if !self.legacy {
+                stream.seek(SeekFrom::Start(self.pages[0].table_offset - SUPERBLOCK_SIZE))?;
+                self.superblock.save(stream)?;
+            }
         }
 
         // save new page into the page array
@@ -184,15 +310,64 @@ impl Index {
         Ok(self.pages.last_mut().unwrap())
     }
 
-    /// Gets the number of entries in the page.
+    /// Gets the index layout stored before the first page table.
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    /// Gets the table size of a page, the first page table follows the
+    /// superblock unless the index is legacy.
+    ///
+    /// # Arguments
+    ///
+    /// * `page` - Position of the page within the chain.
+    pub fn table_size(&self, page: usize) -> u64 {
+        match self.legacy {
+            true => PAGE_SIZE,
+            false => Self::table_size_at(page)
+        }
+    }
+
+    /// Gets the table size of a page on indexes with a superblock.
+    ///
+    /// # Arguments
+    ///
+    /// * `page` - Position of the page within the chain.
+    fn table_size_at(page: usize) -> u64 {
+        match page {
+            0 => PAGE_SIZE - SUPERBLOCK_SIZE,
+            _ => PAGE_SIZE
+        }
+    }
+
+    /// Gets the number of pages needed to store every entry.
+    pub fn pages_needed(&self) -> usize {
+        self.len().div_ceil(PAGE_ENTRY_COUNT).max(1)
+    }
+
+    /// Locates the page and record storing an entry position.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Entry position within the entries map, the empty entry at 0 excluded.
+    fn locate_record(index: usize) -> (usize, u64) {
+        ((index - 1) / PAGE_ENTRY_COUNT, ((index - 1) % PAGE_ENTRY_COUNT + 1) as u64)
+    }
+
+    /// Gets the number of entries in the index.
     /// 
     /// # Returns
     /// 
-    /// * `usize` - The number of entries in the page.
+    /// * `usize` - The number of entries in the index.
     pub fn len(&self) -> usize {
         self.entries.len() - 1
     }
 
+    /// Tells whether the index has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() < 1
+    }
+
     /// Remove an entry from the page.
     /// 
     /// # Arguments
@@ -208,7 +383,7 @@ impl Index {
         let index = index + 1;
         let len = self.entries.len();
         if index > len - 1 {
-            return Err(anyhow::anyhow!("index out of bounds"));
+            bail!(Error::OutOfBounds(index - 1));
         }
 
         // rearrange when entry to be removed is not the last one
@@ -261,29 +436,86 @@ impl Index {
     /// 
     /// * `Result<()>` - The result of the flush operation.
     pub fn flush(&mut self, writer: &mut (impl Read + Seek + Write)) -> Result<()> {
-        // TODO: finish the entries logic movement from src/engine/index/page.rs to src/engine/index.rs
+        self.flush_dirty(writer, usize::MAX)?;
+        Ok(())
+    }
 
+    /// Gets the number of modified entries waiting to be flushed.
+    pub fn dirty_len(&self) -> usize {
+        self.modified.len()
+    }
+
+    /// Marks every modified entry as flushed without writing it, for indexes
+    /// stored elsewhere than on their pages.
+    pub fn discard_dirty(&mut self) {
+        self.modified.clear();
+    }
+
+    /// Flushes up to `limit` modified entries to the writer, so large dirty
+    /// sets can be flushed in batches. Entries that fail to be written are
+    /// kept as modified.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `writer` - The writer to use for writing the page.
+    /// * `limit` - Max amount of modified entries to flush.
+    /// 
+    /// # Returns
+    /// 
+    /// * `Result<usize>` - The amount of entries flushed.
+    pub fn flush_dirty(&mut self, writer: &mut (impl Read + Seek + Write), limit: usize) -> Result<usize> {
         // update modified entry records
         let length = self.entries.len();
-        for index in self.modified.keys() {
+        let batch: Vec<usize> = self.modified.keys().take(limit).copied().collect();
+        for (i, index) in batch.iter().enumerate() {
             let index = *index;
-            if index < length {
-                let record = match self.entries.get_index(index) {
-                    Some((_, entry)) => entry.as_record(&self.table)?,
-                    None => continue
-                };
-                self.table.save_record_into(writer, index as u64, &record)?;
+            if index < 1 || index >= length {
+                continue;
             }
+            let result = self.save_record(writer, index, false);
+            if let Err(e) = result {
+                for flushed in batch[..i].iter() {
+                    self.modified.remove(flushed);
+                }
+                return Err(e);
+            }
+        }
+        for index in batch.iter() {
+            self.modified.remove(index);
         }
 
-        // soft delete empty records
-        if length < self.max_index {
-            let empty_record = self.table.header.record.new_record()?;
-            for index in length..self.max_index {
-                self.table.save_record_into(writer, index as u64, &empty_record)?;
+        // soft delete empty records once every entry is flushed
+        if self.modified.is_empty() && length <= self.max_index {
+            for index in length..=self.max_index {
+                self.save_record(writer, index, true)?;
             }
+            self.max_index = length - 1;
         }
         writer.flush()?;
+        Ok(batch.len())
+    }
+
+    /// Saves an entry record into its page.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The writer to use for writing the page.
+    /// * `index` - Entry position within the entries map.
+    /// * `empty` - Whether an empty record is saved instead of the entry.
+    fn save_record(&self, writer: &mut (impl Read + Seek + Write), index: usize, empty: bool) -> Result<()> {
+        let (page_index, record_index) = Self::locate_record(index);
+        let page = match self.pages.get(page_index) {
+            Some(v) => v,
+            // soft deleted records past the stored pages were never written
+            None if empty => return Ok(()),
+            None => bail!(Error::Corrupted(format!("index page {} doesn't exists", page_index)))
+        };
+        let record = match (empty, self.entries.get_index(index)) {
+            (false, Some((_, entry))) => entry.as_record(&page.table)?,
+            _ => page.table.header.record.new_record()?
+        };
+        let mut segment = Segment::new_unsafe(writer, page.table_offset, self.table_size(page_index))?;
+        page.table.save_record_into(&mut segment, record_index, &record)?;
         Ok(())
     }
 
@@ -299,15 +531,17 @@ impl Index {
     pub fn append(&mut self, entry: FileMeta, prev_part: usize, next_part: usize) -> Result<()> {
         let length = self.entries.len();
         if self.entries.contains_key(&entry.path) {
-            return Err(anyhow::anyhow!("entry already exists"));
+            bail!(Error::AlreadyExists(entry.path.clone()));
         }
+        self.generation += 1;
         self.entries.insert(entry.path.clone(), FileEntry {
             meta: entry,
             next_part: next_part,
-            prev_part: prev_part
+            prev_part: prev_part,
+            generation: self.generation
         });
         self.modified.insert(length, PhantomData::default());
-        self.max_index = length;
+        self.max_index = self.max_index.max(length);
         Ok(())
     }
 
@@ -337,10 +571,160 @@ impl Index {
         self.entries.get_mut(path)
     }
 
-    /// Gets an entry by index.
+    /// Returns an iterator over the entries.
+    /// 
+    /// # Returns
+    /// 
+    /// * `impl Iterator<Item = &FileEntry>` - The entries in index order.
+    pub fn iter(&self) -> impl Iterator<Item = &FileEntry> {
+        self.entries.values().skip(1)
+    }
+
+    /// Gets an entry and its index by path.
     /// 
     /// # Arguments
     /// 
+    /// * `path` - The path of the entry to get.
+    /// 
+    /// # Returns
+    /// 
+    /// * `Option<(usize, &FileEntry)>` - The entry index and entry if found, otherwise None.
+    pub fn get_full(&self, path: &str) -> Option<(usize, &FileEntry)> {
+        match self.entries.get_full(path) {
+            Some((index, _, entry)) if index > 0 => Some((index - 1, entry)),
+            _ => None
+        }
+    }
+
+    /// Gets the partition chain of an entry, starting from the entry itself and
+    /// following the next_part references.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `index` - The index of the first partition entry.
+    /// 
+    /// # Returns
+    /// 
+    /// * `Vec<(usize, &FileEntry)>` - The partitions index and entry in order, empty if the entry doesn't exists.
+    pub fn get_parts(&self, index: usize) -> Vec<(usize, &FileEntry)> {
+        let mut parts = Vec::new();
+        let mut next = index + 1;
+        while next > 0 {
+            // stop on corrupted chains referencing back to a visited entry
+            if parts.len() >= self.entries.len() {
+                break;
+            }
+            match self.entries.get_index(next) {
+                Some((_, entry)) => {
+                    parts.push((next - 1, entry));
+                    next = entry.next_part;
+                },
+                None => break
+            }
+        }
+        parts
+    }
+
+    /// Renames an entry keeping its position, a new generation is assigned to
+    /// the entry so open handles become stale.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `index` - The index of the entry to rename.
+    /// * `path` - The new path of the entry.
+    /// 
+    /// # Returns
+    /// 
+    /// * `Result<()>` - The result of the rename operation.
+    pub fn rename(&mut self, index: usize, path: &str) -> Result<()> {
+        let index = index + 1;
+        if index > self.entries.len() - 1 {
+            bail!(Error::OutOfBounds(index - 1));
+        }
+        if self.entries.contains_key(path) {
+            bail!(Error::AlreadyExists(path.to_string()));
+        }
+        let (_, mut entry) = self.entries.shift_remove_index(index).unwrap();
+        entry.meta.path = path.to_string();
+        self.generation += 1;
+        entry.generation = self.generation;
+        self.entries.shift_insert(index, path.to_string(), entry);
+        self.modified.insert(index, PhantomData::default());
+        Ok(())
+    }
+
+    /// Sets the content size of an entry marking it as modified.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `index` - The index of the entry to resize.
+    /// * `size` - The new content size.
+    /// 
+    /// # Returns
+    /// 
+    /// * `Result<()>` - The result of the resize operation.
+    pub fn set_size(&mut self, index: usize, size: u64) -> Result<()> {
+        let index = index + 1;
+        match self.entries.get_index_mut(index) {
+            Some((_, entry)) => entry.meta.size = size,
+            None => bail!(Error::OutOfBounds(index - 1))
+        }
+        self.modified.insert(index, PhantomData::default());
+        Ok(())
+    }
+
+    /// Sets the content offset of an entry marking it as modified.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the entry to move.
+    /// * `offset` - The new content offset.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - The result of the move operation.
+    pub fn set_offset(&mut self, index: usize, offset: u64) -> Result<()> {
+        let index = index + 1;
+        match self.entries.get_index_mut(index) {
+            Some((_, entry)) => entry.meta.offset = offset,
+            None => bail!(Error::OutOfBounds(index - 1))
+        }
+        self.modified.insert(index, PhantomData::default());
+        Ok(())
+    }
+
+    /// Links an entry as the next partition of other entry, both are marked
+    /// as partitioned and modified.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the partition to link after.
+    /// * `next` - The index of the new next partition.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - The result of the link operation.
+    pub fn link_part(&mut self, index: usize, next: usize) -> Result<()> {
+        let (index, next) = (index + 1, next + 1);
+        let len = self.entries.len();
+        if index >= len || next >= len || index == next {
+            bail!(Error::OutOfBounds(index.max(next) - 1));
+        }
+        let entry = &mut self.entries[index];
+        entry.next_part = next;
+        entry.meta.parted = true;
+        let entry = &mut self.entries[next];
+        entry.prev_part = index;
+        entry.meta.parted = true;
+        self.modified.insert(index, PhantomData::default());
+        self.modified.insert(next, PhantomData::default());
+        Ok(())
+    }
+
+    /// Gets an entry by index.
+    ///
+    /// # Arguments
+    /// 
     /// * `index` - The index of the entry to get.
     /// 
     /// # Returns
@@ -373,4 +757,142 @@ impl Index {
 #[cfg(test)]
 mod tests {
     use super::*;
-}
\ No newline at end of file
+    use std::io::Cursor;
+
+    /// Builds an index holding a file split in two partitions around other file.
+    fn sample() -> Index {
+        let mut index = Index::new();
+        index.append(FileMeta { offset: 512, path: "a.0".to_string(), parted: true, size: 10 }, 0, 3).unwrap();
+        index.append(FileMeta { offset: 1536, path: "b".to_string(), parted: false, size: 5 }, 0, 0).unwrap();
+        index.append(FileMeta { offset: 2560, path: "a.1".to_string(), parted: true, size: 5 }, 1, 0).unwrap();
+        index
+    }
+
+    #[test]
+    fn append_and_get() {
+        let mut index = sample();
+        assert_eq!(3, index.len());
+        assert_eq!(3, index.dirty_len());
+        assert_eq!(Some(1), index.get_full("b").map(|(i, _)| i));
+        assert_eq!(1536, index.get_index(1).unwrap().meta.offset);
+        assert_eq!(vec![0, 2], index.get_parts(0).iter().map(|(i, _)| *i).collect::<Vec<_>>());
+        match index.append(FileMeta { path: "b".to_string(), ..Default::default() }, 0, 0) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert!(matches!(e.downcast_ref::<Error>(), Some(Error::AlreadyExists(_))))
+        }
+        index.get_index_mut(1).unwrap().meta.size = 7;
+        assert_eq!(7, index.get("b").unwrap().meta.size);
+    }
+
+    #[test]
+    fn remove_rearrange() {
+        let mut index = sample();
+
+        // the last entry is moved into the removed position keeping its chain
+        if let Err(e) = index.remove(1) {
+            assert!(false, "Failed to remove entry: {}", e);
+            return;
+        }
+        assert_eq!(2, index.len());
+        assert_eq!("a.1", index.get_index(1).unwrap().meta.path);
+        assert_eq!(2, index.get_index(0).unwrap().next_part);
+        assert_eq!(15, index.get_parts(0).iter().map(|(_, part)| part.meta.size).sum::<u64>());
+
+        // removing a partition unlinks it from the chain
+        index.remove(1).unwrap();
+        assert_eq!(0, index.get_index(0).unwrap().next_part);
+        assert!(index.remove(5).is_err());
+    }
+
+    #[test]
+    fn link_part() {
+        let mut index = sample();
+        index.append(FileMeta { offset: 3584, path: "b.1".to_string(), parted: false, size: 7 }, 0, 0).unwrap();
+        if let Err(e) = index.link_part(1, 3) {
+            assert!(false, "Failed to link partition: {}", e);
+            return;
+        }
+        let parts = index.get_parts(1);
+        assert_eq!(vec![1, 3], parts.iter().map(|(id, _)| *id).collect::<Vec<usize>>());
+        assert!(parts.iter().all(|(_, part)| part.meta.parted));
+        assert_eq!(2, index.get_index(3).unwrap().prev_part);
+        assert!(index.link_part(1, 9).is_err());
+        assert!(index.link_part(1, 1).is_err());
+    }
+
+    #[test]
+    fn locate_records() {
+        assert_eq!((0, 1), Index::locate_record(1));
+        assert_eq!((0, PAGE_RECORD_COUNT - 1), Index::locate_record(PAGE_ENTRY_COUNT));
+        assert_eq!((1, 1), Index::locate_record(PAGE_ENTRY_COUNT + 1));
+        assert_eq!(1, Index::new().pages_needed());
+        assert_eq!(1, sample().pages_needed());
+    }
+
+    #[test]
+    fn flush_and_open() {
+        let mut stream = Cursor::new(Vec::new());
+        let mut index = sample();
+        if let Err(e) = index.add_page(&mut stream, 0, ".0.rhindex") {
+            assert!(false, "Failed to add page: {}", e);
+            return;
+        }
+        assert_eq!(3, index.flush_dirty(&mut stream, usize::MAX).unwrap());
+        assert_eq!(0, index.dirty_len());
+
+        stream.set_position(0);
+        let loaded = match Index::open(&mut stream) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open index: {}", e);
+                return;
+            }
+        };
+        assert_eq!(3, loaded.len());
+        assert_eq!(0, loaded.superblock().first_page);
+        assert_eq!(2560, loaded.get("a.1").unwrap().meta.offset);
+        assert_eq!(vec![0, 2], loaded.get_parts(0).iter().map(|(i, _)| *i).collect::<Vec<_>>());
+
+        // removed entries are soft deleted from the page
+        index.remove(2).unwrap();
+        index.flush(&mut stream).unwrap();
+        stream.set_position(0);
+        assert_eq!(2, Index::open(&mut stream).unwrap().len());
+    }
+
+    #[test]
+    fn open_legacy() {
+        let mut stream = Cursor::new(Vec::new());
+        let mut index = sample();
+        index.add_page(&mut stream, 0, ".0.rhindex").unwrap();
+        index.flush_dirty(&mut stream, usize::MAX).unwrap();
+
+        // drop the superblock moving the table to the page content start
+        let table_offset = index.pages[0].table_offset as usize;
+        let bytes = stream.into_inner();
+        let mut legacy = bytes[..table_offset - SUPERBLOCK_SIZE as usize].to_vec();
+        legacy.extend_from_slice(&bytes[table_offset..table_offset + Index::table_size_at(0) as usize]);
+        legacy.extend_from_slice(&[0u8; SUPERBLOCK_SIZE as usize]);
+        legacy.extend_from_slice(&bytes[table_offset + Index::table_size_at(0) as usize..]);
+        let mut stream = Cursor::new(legacy);
+        let mut loaded = match Index::open(&mut stream) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open legacy index: {}", e);
+                return;
+            }
+        };
+        assert!(loaded.legacy);
+        assert_eq!(PAGE_SIZE, loaded.table_size(0));
+        assert_eq!(3, loaded.len());
+        assert_eq!(2560, loaded.get("a.1").unwrap().meta.offset);
+
+        // legacy indexes keep their layout when flushed
+        loaded.remove(2).unwrap();
+        loaded.flush(&mut stream).unwrap();
+        stream.set_position(0);
+        let reopened = Index::open(&mut stream).unwrap();
+        assert!(reopened.legacy);
+        assert_eq!(2, reopened.len());
+    }
+}
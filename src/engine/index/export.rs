@@ -0,0 +1,244 @@
+use anyhow::{bail, Result};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+
+use super::{FileEntry, FileMeta, Index, Superblock};
+use crate::engine::error::Error;
+
+/// Path of the entry holding an exported index.
+pub const EXPORT_PATH: &str = ".rhindex";
+
+/// Magic opening the exported index trailer block.
+pub const EXPORT_MAGIC: &[u8; 8] = b"rtar-idx";
+
+/// Magic opening the exported index content.
+const CONTENT_MAGIC: &[u8; 8] = b"rhindex1";
+
+/// Block written as the last archive block after the end of archive marker,
+/// it locates the exported index content. The block holds the magic, the
+/// content offset and the content length as little endian integers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportTrailer {
+    /// Offset of the exported index content.
+    pub offset: u64,
+    /// Length of the exported index content.
+    pub len: u64,
+}
+
+impl ExportTrailer {
+    /// Parses an exported index trailer block.
+    ///
+    /// # Arguments
+    /// * `block` - Raw block.
+    ///
+    /// # Returns
+    /// * `Some(ExportTrailer)` - The trailer.
+    /// * `None` - When the block isn't an exported index trailer.
+    pub fn parse(block: &[u8; 512]) -> Option<Self> {
+        if block[..8] != *EXPORT_MAGIC {
+            return None;
+        }
+        let offset = u64::from_le_bytes(block[8..16].try_into().unwrap());
+        let len = u64::from_le_bytes(block[16..24].try_into().unwrap());
+        Some(Self { offset, len })
+    }
+
+    /// Encodes the trailer block.
+    pub fn encode(&self) -> [u8; 512] {
+        let mut block = [0u8; 512];
+        block[..8].copy_from_slice(EXPORT_MAGIC);
+        block[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        block[16..24].copy_from_slice(&self.len.to_le_bytes());
+        block
+    }
+}
+
+impl Index {
+    /// Serializes every entry in index order, partitions included, so the
+    /// index can be stored as a single entry.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u8>` - The serialized index.
+    pub fn export(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(CONTENT_MAGIC);
+        buf.extend_from_slice(&(self.entries.len() as u64 - 1).to_le_bytes());
+        for (key, entry) in self.entries.iter().skip(1) {
+            buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            buf.extend_from_slice(key.as_bytes());
+            buf.extend_from_slice(&(entry.meta.path.len() as u32).to_le_bytes());
+            buf.extend_from_slice(entry.meta.path.as_bytes());
+            buf.extend_from_slice(&entry.meta.offset.to_le_bytes());
+            buf.extend_from_slice(&entry.meta.size.to_le_bytes());
+            buf.push(entry.meta.parted as u8);
+            buf.extend_from_slice(&(entry.prev_part as u64).to_le_bytes());
+            buf.extend_from_slice(&(entry.next_part as u64).to_le_bytes());
+        }
+        buf
+    }
+
+    /// Rebuilds an index from its serialized form, the index has no pages
+    /// so it's meant for reading cold archives.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The serialized index.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self>` - The index, a corrupted error when the data is malformed.
+    pub fn import(data: &[u8]) -> Result<Self> {
+        let mut reader = ExportReader { data, pos: 0 };
+        if reader.take(8)? != CONTENT_MAGIC {
+            bail!(Error::Corrupted("invalid exported index magic".to_string()));
+        }
+        let count = reader.u64()?;
+        let mut entries = IndexMap::new();
+        entries.insert(String::default(), FileEntry::default());
+        let mut generation = 0;
+        for _ in 0..count {
+            let key = reader.string()?;
+            let path = reader.string()?;
+            let offset = reader.u64()?;
+            let size = reader.u64()?;
+            let parted = reader.take(1)?[0] != 0;
+            let prev_part = reader.u64()? as usize;
+            let next_part = reader.u64()? as usize;
+            if prev_part as u64 > count || next_part as u64 > count {
+                bail!(Error::Corrupted(format!("exported index entry '{}' links a partition out of bounds", key)));
+            }
+            generation += 1;
+            let meta = FileMeta { offset, path, parted, size };
+            if entries.insert(key.clone(), FileEntry { meta, next_part, prev_part, generation }).is_some() {
+                bail!(Error::Corrupted(format!("duplicated exported index entry '{}'", key)));
+            }
+        }
+        if reader.pos != data.len() {
+            bail!(Error::Corrupted("unexpected data after the exported index".to_string()));
+        }
+
+        let mut index = Self::new();
+        index.entries = entries;
+        index.modified = HashMap::new();
+        index.generation = generation;
+        index.superblock = Superblock::new(0);
+        Ok(index)
+    }
+
+    /// Loads the index exported at the end of an archive.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The archive stream.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<(Self, ExportTrailer)>>` - The exported index and its trailer, none when the archive doesn't
+    ///   end with an exported index trailer.
+    pub fn open_exported(stream: &mut (impl Read + Seek)) -> Result<Option<(Self, ExportTrailer)>> {
+        let len = stream.seek(SeekFrom::End(0))?;
+        if len < 512 {
+            return Ok(None);
+        }
+        let mut block = [0u8; 512];
+        stream.seek(SeekFrom::Start(len - 512))?;
+        stream.read_exact(&mut block)?;
+        let trailer = match ExportTrailer::parse(&block) {
+            Some(v) => v,
+            None => return Ok(None)
+        };
+        if trailer.offset.checked_add(trailer.len).is_none_or(|end| end > len - 512) {
+            bail!(Error::Corrupted("the exported index trailer points past the archive end".to_string()));
+        }
+        let mut data = vec![0u8; trailer.len as usize];
+        stream.seek(SeekFrom::Start(trailer.offset))?;
+        stream.read_exact(&mut data)?;
+        Ok(Some((Self::import(&data)?, trailer)))
+    }
+}
+
+/// Cursor over serialized index data.
+struct ExportReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ExportReader<'a> {
+    /// Takes the next bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - Amount of bytes to take.
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = match self.pos.checked_add(len) {
+            Some(v) if v <= self.data.len() => v,
+            _ => bail!(Error::Corrupted("truncated exported index".to_string()))
+        };
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Takes the next little endian integer.
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Takes the next length prefixed string.
+    fn string(&mut self) -> Result<String> {
+        let len = u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize;
+        Ok(std::str::from_utf8(self.take(len)?)?.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn export_roundtrip() {
+        let mut index = Index::new();
+        for (i, path) in ["a.txt", "b.txt"].iter().enumerate() {
+            let meta = FileMeta { offset: 512 + i as u64 * 1024, path: path.to_string(), parted: false, size: 3 };
+            index.append(meta, 0, 0).unwrap();
+        }
+        let data = index.export();
+
+        let mut buf = vec![0u8; 2048];
+        let trailer = ExportTrailer { offset: buf.len() as u64, len: data.len() as u64 };
+        buf.extend_from_slice(&data);
+        buf.extend_from_slice(&trailer.encode());
+        let loaded = match Index::open_exported(&mut Cursor::new(buf)) {
+            Ok(Some((v, found))) => {
+                assert_eq!(trailer, found);
+                v
+            },
+            Ok(None) => {
+                assert!(false, "expected an exported index");
+                return;
+            },
+            Err(e) => {
+                assert!(false, "Failed to open exported index: {}", e);
+                return;
+            }
+        };
+        assert_eq!(2, loaded.len());
+        assert_eq!(1536, loaded.get("b.txt").unwrap().meta.offset);
+        assert_eq!(0, loaded.dirty_len());
+
+        assert!(Index::open_exported(&mut Cursor::new(vec![0u8; 1024])).unwrap().is_none());
+        assert!(Index::import(&data[..data.len() - 1]).is_err());
+
+        // partition links past the entry count are rejected
+        let mut broken = data.clone();
+        let len = broken.len();
+        broken[len - 8..].copy_from_slice(&3u64.to_le_bytes());
+        match Index::import(&broken) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert!(matches!(e.downcast_ref::<Error>(), Some(Error::Corrupted(_))))
+        }
+    }
+}
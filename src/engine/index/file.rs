@@ -39,7 +39,11 @@ impl Default for FileMeta {
 pub struct FileEntry {
     pub meta: FileMeta,
     pub next_part: usize,
-    pub prev_part: usize
+    pub prev_part: usize,
+
+    /// In-memory generation, changes whenever the entry is moved so open
+    /// handles can detect they are stale. It isn't persisted.
+    pub generation: u64
 }
 
 impl FileEntry {
@@ -52,6 +56,7 @@ impl FileEntry {
         self.meta.copy_from(&entry.meta);
         self.next_part = entry.next_part;
         self.prev_part = entry.prev_part;
+        self.generation = entry.generation;
     }
 
     pub fn as_record(&self, table: &Table) -> Result<Record> {
@@ -95,7 +100,8 @@ impl FileEntry {
         Ok(Self {
             meta,
             next_part: next_part.into(),
-            prev_part: prev_part.into()
+            prev_part: prev_part.into(),
+            generation: 0
         })
     }
 }
@@ -105,7 +111,8 @@ impl Default for FileEntry {
         Self {
             meta: FileMeta::default(),
             next_part: 0,
-            prev_part: 0
+            prev_part: 0,
+            generation: 0
         }
     }
 }
\ No newline at end of file
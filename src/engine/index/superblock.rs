@@ -0,0 +1,191 @@
+use anyhow::{bail, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::engine::error::Error;
+use super::{PAGE_RECORD_COUNT, PAGE_SIZE};
+
+/// Bytes reserved for the superblock at the start of the first page content.
+pub const SUPERBLOCK_SIZE: u64 = 512;
+
+/// Magic identifying an rtar index.
+pub const MAGIC: [u8; 8] = *b"RTARIDX\0";
+
+/// Latest index format version.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Self describing header stored before the first index page table, so an
+/// index can be told apart from foreign data before parsing any page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Superblock {
+    /// Index format version.
+    pub version: u16,
+    /// Content size of every index page.
+    pub page_size: u64,
+    /// Records of every index page.
+    pub record_count: u64,
+    /// Offset of the first page header.
+    pub first_page: u64,
+    /// Offset of the last page header.
+    pub last_page: u64,
+}
+
+impl Superblock {
+    /// Creates a superblock for an index with a single page.
+    ///
+    /// # Arguments
+    ///
+    /// * `first_page` - Offset of the first page header.
+    pub fn new(first_page: u64) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            page_size: PAGE_SIZE,
+            record_count: PAGE_RECORD_COUNT,
+            first_page,
+            last_page: first_page
+        }
+    }
+
+    /// Encodes the superblock, numbers are stored little endian.
+    ///
+    /// # Returns
+    ///
+    /// * `[u8; 512]` - The encoded superblock.
+    pub fn encode(&self) -> [u8; SUPERBLOCK_SIZE as usize] {
+        let mut buf = [0u8; SUPERBLOCK_SIZE as usize];
+        buf[0..8].copy_from_slice(&MAGIC);
+        buf[8..10].copy_from_slice(&self.version.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.page_size.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.record_count.to_le_bytes());
+        buf[32..40].copy_from_slice(&self.first_page.to_le_bytes());
+        buf[40..48].copy_from_slice(&self.last_page.to_le_bytes());
+        buf
+    }
+
+    /// Decodes and validates a superblock.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - The encoded superblock.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self>` - The superblock, a corrupted error when it isn't an rtar index or its layout isn't supported.
+    pub fn decode(buf: &[u8; SUPERBLOCK_SIZE as usize]) -> Result<Self> {
+        if buf[0..8] != MAGIC {
+            bail!(Error::Corrupted("not an rtar index, please fallback to scan mode".to_string()));
+        }
+        let u64_at = |start: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&buf[start..start + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let superblock = Self {
+            version: u16::from_le_bytes([buf[8], buf[9]]),
+            page_size: u64_at(16),
+            record_count: u64_at(24),
+            first_page: u64_at(32),
+            last_page: u64_at(40)
+        };
+        if superblock.version < 1 || superblock.version > FORMAT_VERSION {
+            bail!(Error::Corrupted(format!("unsupported index format version {}", superblock.version)));
+        }
+        if superblock.page_size != PAGE_SIZE {
+            bail!(Error::Corrupted(format!("unsupported index page size {}", superblock.page_size)));
+        }
+        if superblock.record_count != PAGE_RECORD_COUNT {
+            bail!(Error::Corrupted(format!("unsupported index page record count {}", superblock.record_count)));
+        }
+        if superblock.last_page < superblock.first_page {
+            bail!(Error::Corrupted("index last page is before the first page".to_string()));
+        }
+        Ok(superblock)
+    }
+
+    /// Loads and validates a superblock from a reader.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Reader positioned at the start of the first page content.
+    pub fn load(reader: &mut impl Read) -> Result<Self> {
+        let mut buf = [0u8; SUPERBLOCK_SIZE as usize];
+        reader.read_exact(&mut buf)?;
+        Self::decode(&buf)
+    }
+
+    /// Checks whether a superblock magic is found at the reader position,
+    /// the position isn't moved. Indexes written before superblocks existed
+    /// start their first page table right away.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Reader positioned at the start of the first page content.
+    pub fn probe(reader: &mut (impl Read + Seek)) -> Result<bool> {
+        let start = reader.stream_position()?;
+        let mut buf = [0u8; MAGIC.len()];
+        let found = match reader.read_exact(&mut buf) {
+            Ok(_) => buf == MAGIC,
+            Err(_) => false
+        };
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(found)
+    }
+
+    /// Saves the superblock into a writer.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Writer positioned at the start of the first page content.
+    pub fn save(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&self.encode())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn roundtrip() {
+        let mut superblock = Superblock::new(1024);
+        superblock.last_page = 4096;
+        let mut stream = Cursor::new(Vec::new());
+        superblock.save(&mut stream).unwrap();
+        assert_eq!(SUPERBLOCK_SIZE, stream.get_ref().len() as u64);
+        stream.set_position(0);
+        match Superblock::load(&mut stream) {
+            Ok(v) => assert_eq!(superblock, v),
+            Err(e) => assert!(false, "Failed to load superblock: {}", e)
+        }
+    }
+
+    #[test]
+    fn rejects_foreign_data() {
+        let err = Superblock::decode(&[b'a'; SUPERBLOCK_SIZE as usize]).unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::Corrupted(_))));
+
+        let mut buf = Superblock::new(0).encode();
+        buf[8] = 9;
+        assert!(Superblock::decode(&buf).is_err());
+        let mut buf = Superblock::new(0).encode();
+        buf[16..24].copy_from_slice(&512u64.to_le_bytes());
+        assert!(Superblock::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn probe() {
+        let mut stream = Cursor::new(Superblock::new(0).encode().to_vec());
+        stream.set_position(0);
+        match Superblock::probe(&mut stream) {
+            Ok(v) => assert!(v),
+            Err(e) => {
+                assert!(false, "Failed to probe superblock: {}", e);
+                return;
+            }
+        }
+        assert_eq!(0, stream.position());
+        assert!(!Superblock::probe(&mut Cursor::new(vec![0u8; 512])).unwrap());
+        assert!(!Superblock::probe(&mut Cursor::new(Vec::new())).unwrap());
+    }
+}
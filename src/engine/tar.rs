@@ -1,50 +1,144 @@
 use dhfarm_engine::traits::DataTrait;
-use dhfarm_engine::{Data, Segment};
-use indexmap::IndexMap;
-use tokio::sync::Mutex;
-use std::default;
-use std::fs::OsFile;
+use dhfarm_engine::Data;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::collections::HashMap;
+use std::fs::File;
 use std::io::{Read, Seek, SeekFrom, Write, Error as IoError};
 use std::io::Result as IoResult;
 use std::path::PathBuf;
-use crate::engine::index::{Index, PAGE_SIZE};
+use crate::engine::DEFAULT_BUFFER_SIZE;
+use crate::format;
+use crate::engine::archive::{pad_region, padded_size, part_path, EntryKind, Metadata};
+use crate::engine::error::{to_io_error, Error};
+use crate::engine::header::TarHeader;
+use crate::engine::index::{FileMeta, Index, PAGE_SIZE};
+use lock::LockTable;
+use region::RegionTable;
+
+#[cfg(feature = "async")]
+mod async_sub_file;
+mod batch;
+#[cfg(feature = "async")]
+mod entries;
+mod export;
+#[cfg(feature = "async")]
+mod flusher;
+#[cfg(feature = "async")]
+mod stream;
+mod lock;
+mod region;
+mod sub_file;
+
+#[cfg(feature = "async")]
+pub use async_sub_file::AsyncSubFile;
+pub use batch::Batch;
+#[cfg(feature = "async")]
+pub use entries::{BuilderEntries, BuilderEntry};
+#[cfg(feature = "async")]
+pub use flusher::{FlushOptions, IndexFlusher};
+#[cfg(feature = "async")]
+pub use stream::{copy_bounded, StreamOptions};
+pub use sub_file::{SubFile, SubFileMetadata};
 
 const BLOCK_SIZE: u64 = 512;
 
-struct Tar<T: Read + Write + Seek> {
+/// Index backed tar engine, its API is blocking so it can be shared between
+/// threads behind a `std::sync::Mutex`, the `async` feature adds tokio based
+/// handles over an `Arc<tokio::sync::Mutex<Tar>>`.
+///
+/// Every sub file keeps its own cursor and the tar is only locked to seek and
+/// write the stream, so several handles can write the same entry at once.
+/// Writers coordinate through region locks, a write into a region locked by
+/// other handle fails with a would block error.
+pub struct Tar<T: Read + Write + Seek> {
     stream: Data<T>,
     index: Index,
     need_closing: bool,
-    end_fake_id: usize
+    need_flush: bool,
+    end_fake_id: usize,
+    /// End of the deleted content the stored index may still point at, it
+    /// isn't overwritten until the index is flushed.
+    retired_end: u64,
+    /// Maximum logical size of a file.
+    max_file_size: Option<u64>,
+    /// Whether the index is exported as a trailing entry on close instead of flushed into its pages.
+    export_index: bool,
+    /// Freed regions as `(offset, len)`, sorted and merged.
+    free: Vec<(u64, u64)>,
+    /// Amount of entries referencing a partition content, only the offsets
+    /// shared by copy-on-write copies are kept.
+    shared: HashMap<u64, usize>,
+    /// Advisory locks of the entries.
+    locks: LockTable,
+    /// Write region locks and written ranges of the entries.
+    regions: RegionTable,
+    /// Wakes the async handles waiting for a lock.
+    #[cfg(feature = "async")]
+    pub(crate) unlocked: std::sync::Arc<tokio::sync::Notify>
+}
+
+impl Tar<File> {
+    /// Creates a new tar file with its first index page.
+    /// 
+    /// # Arguments
+    /// * `path`: The path to create the tar file at.
+    /// 
+    /// # Returns
+    /// * `IoResult<Self>`: The result of the create operation.
+    pub fn create_new(path: PathBuf) -> IoResult<Self> {
+        let file = File::create_new(path)?;
+        let mut myself = Self::new(file);
+        myself.inner_add_page()?;
+        Ok(myself)
+    }
 }
 
-impl<'tar, T: Read + Write + Seek> Tar<T> {
-    /// Creates a new tar instance.
+impl<T: Read + Write + Seek> Tar<T> {
+    /// Creates a new tar instance with an empty index over an empty stream,
+    /// the index pages are written past the files on the first flush.
+    /// 
+    /// # Arguments
+    /// * `stream`: The stream to create the tar from.
+    /// 
+    /// # Returns
+    /// * `Self`: The created tar instance.
+    pub fn new(stream: T) -> Self {
+        Self::with_index(stream, Index::new())
+    }
+
+    /// Creates a new tar instance from a loaded index.
     /// 
     /// # Arguments
-    /// * `file`: The file to create the tar from.
-    /// * `index`: The index to create the tar from.
+    /// * `stream`: The stream to create the tar from.
+    /// * `index`: The index of the tar files.
     /// 
     /// # Returns
     /// * `Self`: The created tar instance.
-    fn new(stream: T) -> Self {
-        let index = Index::new();
+    fn with_index(stream: T, index: Index) -> Self {
+        let mut shared: HashMap<u64, usize> = HashMap::new();
+        for entry in index.iter() {
+            *shared.entry(entry.meta.offset).or_default() += 1;
+        }
+        shared.retain(|_, count| *count > 1);
         Self{
             stream: Data::new(stream, false),
             index,
             need_closing: false,
-            end_fake_id: 0
+            need_flush: false,
+            end_fake_id: 0,
+            retired_end: 0,
+            max_file_size: None,
+            export_index: false,
+            free: Vec::new(),
+            shared,
+            locks: LockTable::default(),
+            regions: RegionTable::default(),
+            #[cfg(feature = "async")]
+            unlocked: std::sync::Arc::new(tokio::sync::Notify::new())
         }
     }
 
-    fn last_file(&'tar self) -> Option<&'tar SubFile> {
-        let file = match self.files.get_index(self.end_fake_id) {
-            Some((_, file)) => file,
-            None => return None
-        };
-        Some(file)
-    }
-
     /// Pads the stream with zeroes to the next block size.
     /// 
     /// # Arguments
@@ -62,66 +156,225 @@ impl<'tar, T: Read + Write + Seek> Tar<T> {
         Ok(())
     }
 
-    /// Creates a new tar file.
+    /// Adds an index page past the data end.
+    fn inner_add_page(&mut self) -> IoResult<()> {
+        let offset = self.data_end();
+        let path = format!(".{}.rhindex", self.index.pages.len());
+        self.index.add_page(&mut self.stream, offset, &path).map_err(to_io_error)?;
+        self.need_flush = true;
+        Ok(())
+    }
+
+    /// Gets the offset right after the last entry or index page content,
+    /// deleted content not yet dropped from the stored index counts too.
+    fn data_end(&self) -> u64 {
+        let entries = self.index.iter().map(|entry| entry.meta.offset + padded_size(entry.meta.size));
+        let pages = self.index.pages.iter().enumerate().map(|(i, page)| page.table_offset + self.index.table_size(i));
+        entries.chain(pages).max().unwrap_or(0).max(self.retired_end)
+    }
+
+    /// Writes a new file header past the data end, the file isn't registered
+    /// on the index until `inner_commit_file` is called so an interrupted
+    /// write leaves it past the data end where the next file reclaims it.
     /// 
     /// # Arguments
-    /// * `path`: The path to create the tar file at.
+    /// * `path`: The path of the file to create.
+    /// * `len`: The content size of the file.
     /// 
     /// # Returns
-    /// * `IoResult<Self>`: The result of the create operation.
-    pub async fn create_new(path: PathBuf) -> IoResult<Self> {
-        let file = match OsFile::create_new(path) {
-            Ok(file) => file,
-            Err(err) => Err(err)?
-        };
-        let index = Index::new();
-        let mut myself = Self::new(file, index);
-
-        // create index header file
-        let stream = &mut myself.stream;
-        let mut header = tar::Header::new_gnu();
-        header.set_path(".0.rhindex")?;
-        header.set_size(512);
-        header.set_cksum();
-        stream.write_all(header.as_bytes())?;
-
-        // write index page
-        let page = myself.index.add_page();
-        page.write_all(stream)?;
-        Self::pad_zeroes(stream, PAGE_SIZE as u64)?;
-        drop(lock);
-        Ok(myself)
+    /// * `IoResult<(u64, Metadata)>`: The content offset and header information of the file.
+    fn inner_begin_file(&mut self, path: &str, len: u64) -> IoResult<(u64, Metadata)> {
+        if self.index.get(path).is_some() {
+            return Err(IoError::new(std::io::ErrorKind::AlreadyExists, format!("file '{}' already exists", path)));
+        }
+        let offset = self.data_end();
+        self.inner_write_header(offset, path, len)
+    }
+
+    /// Writes a new file header at an offset without registering the file.
+    /// 
+    /// # Arguments
+    /// * `offset`: The header offset.
+    /// * `path`: The path of the file.
+    /// * `len`: The content size of the file.
+    /// 
+    /// # Returns
+    /// * `IoResult<(u64, Metadata)>`: The content offset and header information of the file.
+    fn inner_write_header(&mut self, offset: u64, path: &str, len: u64) -> IoResult<(u64, Metadata)> {
+        self.check_size(path, len)?;
+        self.move_to(offset)?;
+        let mut meta = Metadata::new(path, EntryKind::RegularFile);
+        meta.size = len;
+        let header_size = match meta.save_headers(&mut self.stream) {
+            Ok(v) => v,
+            Err(e) => return Err(to_io_error(e))
+        };
+        self.need_flush = true;
+        Ok((offset + header_size, meta))
+    }
+
+    /// Pads the content of a file started by `inner_begin_file`, closes the
+    /// tar and registers the file on the index. The data is flushed before
+    /// the index record is created so the stored index never points at
+    /// content that wasn't written.
+    /// 
+    /// # Arguments
+    /// * `meta`: The header information of the file.
+    /// * `offset`: The content offset of the file.
+    /// 
+    /// # Returns
+    /// * `IoResult<SubFile>`: The registered sub file with its cursor at the start.
+    fn inner_commit_file(&mut self, meta: Metadata, offset: u64) -> IoResult<SubFile> {
+        Self::pad_zeroes(&mut self.stream, meta.size)?;
+        self.stream.write_all(&[0u8; 2 * BLOCK_SIZE as usize])?;
+        self.need_flush = true;
+        self.inner_flush()?;
+        self.inner_register_file(meta, offset)
+    }
+
+    /// Registers a fully written file on the index without closing the tar,
+    /// its content must be flushed already.
+    /// 
+    /// # Arguments
+    /// * `meta`: The header information of the file.
+    /// * `offset`: The content offset of the file.
+    /// 
+    /// # Returns
+    /// * `IoResult<SubFile>`: The registered sub file with its cursor at the start.
+    fn inner_register_file(&mut self, meta: Metadata, offset: u64) -> IoResult<SubFile> {
+        let entry = FileMeta {
+            offset,
+            path: meta.path.clone(),
+            parted: false,
+            size: meta.size
+        };
+        if let Err(e) = self.index.append(entry.clone(), 0, 0) {
+            return Err(to_io_error(e));
+        }
+        self.end_fake_id = self.index.len() - 1;
+        let generation = match self.index.get_index(self.end_fake_id) {
+            Some(v) => v.generation,
+            None => 0
+        };
+        let handle = self.locks.next_handle();
+        Ok(SubFile::new(self.end_fake_id, entry, generation, meta, handle))
+    }
+
+    /// Creates a new file with its whole content reserved up front and filled
+    /// with zeroes, so random writes within `len` never trigger partitioning.
+    /// 
+    /// # Arguments
+    /// * `path`: The path of the file to create.
+    /// * `len`: The content size to reserve.
+    /// 
+    /// # Returns
+    /// * `IoResult<SubFile>`: The created sub file with its cursor at the start.
+    pub fn create_with_size(&mut self, path: &str, len: u64) -> IoResult<SubFile> {
+        let (offset, meta) = self.inner_begin_file(path, len)?;
+
+        // reserve the content
+        Self::write_zeroes(&mut self.stream, len)?;
+        self.inner_commit_file(meta, offset)
+    }
+
+    /// Appends a new file streaming `len` bytes of content from an async
+    /// reader.
+    /// 
+    /// This is cancellation safe: the file is registered on the index only
+    /// after its content is fully written, so dropping the future mid-way
+    /// leaves the partial data past the data end, where the next file or the
+    /// closing tag overwrites it.
+    /// 
+    /// # Arguments
+    /// * `path`: The path of the file to append.
+    /// * `len`: The content size of the file.
+    /// * `reader`: The reader to stream the content from.
+    /// 
+    /// # Returns
+    /// * `IoResult<SubFile>`: The appended sub file with its cursor at the start.
+    #[cfg(feature = "async")]
+    pub async fn append_from<R: AsyncRead + Unpin>(&mut self, path: &str, len: u64, reader: &mut R) -> IoResult<SubFile> {
+        let (offset, meta) = self.inner_begin_file(path, len)?;
+        let mut buf = vec![0u8; DEFAULT_BUFFER_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(DEFAULT_BUFFER_SIZE as u64) as usize;
+            let read = reader.read(&mut buf[..n]).await?;
+            if read < 1 {
+                return Err(IoError::new(std::io::ErrorKind::UnexpectedEof, format!("content of '{}' ended {} bytes early", path, remaining)));
+            }
+            self.stream.write_all(&buf[..read])?;
+            remaining -= read as u64;
+        }
+        self.inner_commit_file(meta, offset)
+    }
+
+    /// Extracts a sub file content from its cursor into an async writer.
+    /// 
+    /// This is cancellation safe on chunk boundaries: the sub file cursor
+    /// only advances after a chunk is fully written, so after dropping the
+    /// future it points to the first byte not known to be written and the
+    /// extraction can be resumed from it.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to extract.
+    /// * `writer`: The writer to extract the content into.
+    /// 
+    /// # Returns
+    /// * `IoResult<u64>`: The amount of bytes extracted.
+    #[cfg(feature = "async")]
+    pub async fn extract_to<W: AsyncWrite + Unpin>(&mut self, file: &mut SubFile, writer: &mut W) -> IoResult<u64> {
+        let mut buf = vec![0u8; DEFAULT_BUFFER_SIZE];
+        let mut total = 0;
+        loop {
+            let mut cursor = file.clone();
+            let read = self.inner_read(&mut cursor, &mut buf)?;
+            if read < 1 {
+                break;
+            }
+            writer.write_all(&buf[..read]).await?;
+            file.pos = cursor.pos;
+            total += read as u64;
+        }
+        writer.flush().await?;
+        Ok(total)
     }
 
-    /// Opens a tar file and loads the files.
+    /// Opens a tar and loads its index, the index exported at the archive
+    /// end is preferred over the index pages. A tar opened from an exported
+    /// index keeps exporting it on close since it has no index pages, the
+    /// exported index isn't overwritten until a new one replaces it.
     /// 
     /// # Arguments
-    /// * `file`: The file to open the tar from.
+    /// * `stream`: The stream to open the tar from.
     /// 
     /// # Returns
     /// * `IoResult<Self>`: The result of the open operation.
-    pub async fn open(mut file: OsFile) -> IoResult<Self> {
-        let index = Index::open(&mut file)?;
-        let mut tar = Self::new(file, index);
-        let lock = tar.mutex.lock().await;
-        let stream = &mut tar.stream;
-
-        for page in tar.index.pages.iter() {
-            for entry in page.iter() {
-                let entry = *entry;
-                if entry == 1 {
-                    break;
-                }
+    pub fn open(mut stream: T) -> IoResult<Self> {
+        let (index, exported) = match Index::open_exported(&mut stream).map_err(to_io_error)? {
+            Some((index, trailer)) => (index, Some(trailer)),
+            None => {
+                stream.seek(SeekFrom::Start(0))?;
+                (Index::open(&mut stream).map_err(to_io_error)?, None)
             }
+        };
+        let mut tar = Self::with_index(stream, index);
+        if let Some(trailer) = exported {
+            tar.export_index = true;
+            tar.retired_end = trailer.offset + padded_size(trailer.len);
         }
-        drop(lock);
 
-        // TODO: Read all sub files
+        // the file closest to the data end is the one able to grow in place
+        tar.end_fake_id = tar.index.iter()
+            .enumerate()
+            .max_by_key(|(_, entry)| entry.meta.offset)
+            .map(|(id, _)| id)
+            .unwrap_or(0);
         Ok(tar)
     }
 
     // Flush any non flushed data into the tar.
-    fn inner_flush(&mut self) -> IoResult<()> {
+    pub(crate) fn inner_flush(&mut self) -> IoResult<()> {
         if !self.need_flush {
             return Ok(());
         }
@@ -130,114 +383,1677 @@ impl<'tar, T: Read + Write + Seek> Tar<T> {
         Ok(())
     }
 
-    /// Write this tar's closing tag when needed.
+    /// Flushes up to `limit` dirty index entries into the tar.
+    ///
+    /// # Arguments
+    /// * `limit` - Max dirty entries to flush.
+    ///
+    /// # Returns
+    /// * `IoResult<usize>` - The number of flushed entries.
+    pub(crate) fn inner_flush_index(&mut self, limit: usize) -> IoResult<usize> {
+        // the exported index is written on close, deleted content is kept until it replaces the previous one
+        if self.export_index {
+            self.index.discard_dirty();
+            return Ok(0);
+        }
+        while self.index.pages.len() < self.index.pages_needed() {
+            self.inner_add_page()?;
+        }
+        let flushed = self.index.flush_dirty(&mut self.stream, limit).map_err(to_io_error)?;
+
+        // deleted content can be reused once no stored record points at it
+        if self.index.dirty_len() < 1 {
+            self.retired_end = 0;
+        }
+        Ok(flushed)
+    }
+
+    /// Flushes the index and writes this tar's closing tag when needed.
     fn inner_close(&mut self) -> IoResult<()> {
         self.inner_flush()?;
+
+        // the exported index discards its dirty records and is written as a whole instead
+        self.inner_flush_index(usize::MAX)?;
+        if self.export_index {
+            self.export_index()?;
+            self.need_closing = false;
+            return Ok(());
+        }
         if !self.need_closing {
             return Ok(());
         }
 
-        // look for the end of the file and write the tar end tag
-        let pos = match self.last_file() {
-            Some(file) => file.pos + file.entry.size,
-            None => return Err(IoError::new(std::io::ErrorKind::NotFound, "last file index doesn't exists"))
-        };
-        self.stream.seek(SeekFrom::Start(pos))?;
-        self.stream.write(&[0;1024])?;
+        // write the tar end tag right after the last file or index page
+        let pos = self.data_end();
+        self.move_to(pos)?;
+        self.stream.write_all(&[0u8; 2 * BLOCK_SIZE as usize])?;
+        self.need_flush = true;
+        self.inner_flush()?;
         self.need_closing = false;
         Ok(())
     }
 
-    /// Moves the stream position to the sub file position if different.
-    pub(crate) async fn move_to(&mut self, file: &SubFile) -> IoResult<()> {
-        let pos = self.stream.stream_position()?;
-        if pos != file.pos {
-            if self.need_flush {
-                self.inner_flush();
-            }
-            self.stream.seek(SeekFrom::Start(file.pos))?;
+    /// Opens a file by path with its cursor at the start.
+    /// 
+    /// # Arguments
+    /// * `path`: The path of the file to open.
+    /// 
+    /// # Returns
+    /// * `IoResult<SubFile>`: The opened sub file.
+    pub fn open_file(&mut self, path: &str) -> IoResult<SubFile> {
+        let (fake_id, entry, generation) = match self.index.get_full(path) {
+            Some((fake_id, entry)) => (fake_id, entry.meta.clone(), entry.generation),
+            None => return Err(IoError::new(std::io::ErrorKind::NotFound, format!("file '{}' not found", path)))
+        };
+        let meta = self.load_metadata(fake_id, &entry)?;
+        let handle = self.locks.next_handle();
+        Ok(SubFile::new(fake_id, entry, generation, meta, handle))
+    }
+
+    /// Loads the header information of a file from the header block right
+    /// before its content, files without a readable header get the default
+    /// metadata.
+    /// 
+    /// # Arguments
+    /// * `fake_id`: Index position of the file first partition.
+    /// * `entry`: First partition entry.
+    /// 
+    /// # Returns
+    /// * `IoResult<Metadata>`: The file header information with its logical size.
+    fn load_metadata(&mut self, fake_id: usize, entry: &FileMeta) -> IoResult<Metadata> {
+        let mut meta = None;
+        if entry.offset >= BLOCK_SIZE {
+            self.move_to(entry.offset - BLOCK_SIZE)?;
+            meta = match TarHeader::load(&mut self.stream) {
+                Ok(header) => Metadata::from_headers(&header, None).ok(),
+                Err(_) => None
+            };
         }
-        Ok(())
+        let mut meta = meta.unwrap_or_else(|| Metadata::new(&entry.path, EntryKind::RegularFile));
+        meta.path = entry.path.clone();
+        meta.size = self.index.get_parts(fake_id).iter().map(|(_, part)| part.meta.size).sum();
+        Ok(meta)
     }
 
-    pub(crate) async fn inner_read(&mut self, file: &mut SubFile, buf: &mut [u8]) -> IoResult<usize> {
-        self.move_to(file).await?;
-        let read = self.stream.read(buf)?;
-        file.pos += read as u64;
-        Ok(read)
+    /// Duplicates a sub file handle with an independent cursor starting at
+    /// the same position, both handles keep working on the same entry.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to duplicate.
+    /// 
+    /// # Returns
+    /// * `IoResult<SubFile>`: The new handle, a stale handle error when the file was deleted or moved.
+    pub fn try_clone_file(&mut self, file: &SubFile) -> IoResult<SubFile> {
+        let fake_id = self.validate(file)?;
+        let mut clone = file.clone();
+        clone.fake_id = fake_id;
+        clone.handle = self.locks.next_handle();
+        Ok(clone)
     }
 
+    /// Copies a file without copying its content, the new entry points to the
+    /// same data region and the first write to either copy relocates the
+    /// written partition, so large entries can be snapshotted cheaply.
+    ///
+    /// # Arguments
+    /// * `src`: The path of the file to copy.
+    /// * `dst`: The path of the copy.
+    ///
+    /// # Returns
+    /// * `IoResult<SubFile>`: The copy with its cursor at the start.
+    pub fn cow_copy(&mut self, src: &str, dst: &str) -> IoResult<SubFile> {
+        let (fake_id, mut entry) = match self.index.get_full(src) {
+            Some((fake_id, entry)) => (fake_id, entry.meta.clone()),
+            None => return Err(IoError::new(std::io::ErrorKind::NotFound, format!("file '{}' not found", src)))
+        };
+        if entry.parted {
+            return Err(IoError::new(std::io::ErrorKind::Unsupported, "copying partitioned files isn't supported"));
+        }
+        let mut meta = self.load_metadata(fake_id, &entry)?;
+        entry.path = dst.to_string();
+        meta.path = dst.to_string();
+        if let Err(e) = self.index.append(entry.clone(), 0, 0) {
+            return Err(to_io_error(e));
+        }
+        *self.shared.entry(entry.offset).or_insert(1) += 1;
+        let fake_id = self.index.len() - 1;
+        let generation = match self.index.get_index(fake_id) {
+            Some(v) => v.generation,
+            None => 0
+        };
+        let handle = self.locks.next_handle();
+        Ok(SubFile::new(fake_id, entry, generation, meta, handle))
+    }
 
-    pub(crate) async fn inner_write(&mut self, file: &mut SubFile, buf: &[u8]) -> IoResult<usize> {
-        //self.ensure_index().await?;
-        self.move_to(file).await?;
-        let written = self.stream.write(buf)?;
-        file.pos += written as u64;
-        self.need_flush = true;
-        Ok(written)
+    /// Tells whether a partition content is shared with other entries.
+    ///
+    /// # Arguments
+    /// * `offset`: The partition content offset.
+    fn is_shared(&self, offset: u64) -> bool {
+        self.shared.contains_key(&offset)
+    }
+
+    /// Drops a reference to a partition content shared by copy-on-write
+    /// copies.
+    ///
+    /// # Arguments
+    /// * `offset`: The partition content offset.
+    ///
+    /// # Returns
+    /// * `bool`: Whether other entries still reference the content.
+    fn release_shared(&mut self, offset: u64) -> bool {
+        match self.shared.get_mut(&offset) {
+            Some(count) if *count > 2 => {
+                *count -= 1;
+                true
+            },
+            Some(_) => {
+                self.shared.remove(&offset);
+                true
+            },
+            None => false
+        }
     }
 
-    pub async fn flush(&'tar mut self) -> IoResult<()> {
-        let _lock = self.mutex.get_mut();
-        self.inner_flush();
+    /// Relocates a partition shared by copy-on-write copies past the data
+    /// end so it can be modified without affecting the other copies, nothing
+    /// is done when the partition isn't shared.
+    ///
+    /// # Arguments
+    /// * `file`: The sub file owning the partition.
+    /// * `id`: Index position of the partition.
+    fn inner_unshare(&mut self, file: &mut SubFile, id: usize) -> IoResult<()> {
+        let part = match self.index.get_index(id) {
+            Some(entry) => entry.meta.clone(),
+            None => return Err(Error::OutOfBounds(id).into())
+        };
+        if !self.is_shared(part.offset) {
+            return Ok(());
+        }
+
+        // write the new header keeping the original header information
+        let mut meta = self.load_metadata(id, &part)?;
+        meta.size = part.size;
+        let offset = self.inner_relocate(id, &part, meta)?;
+        self.release_shared(part.offset);
+        if id == file.fake_id {
+            file.entry.offset = offset;
+        }
         Ok(())
     }
 
-    pub(crate) async fn auto_partition(&mut self, file: &mut SubFile, bytes_to_write: u64) -> IoResult<()> {
-        // do nothing if the bytes to be written fits the file
-        if file.pos + bytes_to_write < file.entry.size {
-            return Ok(())
+    /// Copies a partition content past the data end behind new headers and
+    /// points its index entry at the copy, the tar is closed afterwards.
+    ///
+    /// # Arguments
+    /// * `id`: Index position of the partition.
+    /// * `part`: The partition entry.
+    /// * `meta`: Header information written before the copy.
+    ///
+    /// # Returns
+    /// * `IoResult<u64>`: The new partition content offset.
+    fn inner_relocate(&mut self, id: usize, part: &FileMeta, meta: Metadata) -> IoResult<u64> {
+        let offset = self.data_end();
+        self.move_to(offset)?;
+        let header_size = match meta.save_headers(&mut self.stream) {
+            Ok(v) => v,
+            Err(e) => return Err(to_io_error(e))
+        };
+        self.need_flush = true;
+
+        // copy the content and close the tar
+        let mut buf = vec![0u8; DEFAULT_BUFFER_SIZE];
+        let mut copied = 0;
+        while copied < part.size {
+            let n = (part.size - copied).min(DEFAULT_BUFFER_SIZE as u64) as usize;
+            self.move_to(part.offset + copied)?;
+            self.stream.read_exact(&mut buf[..n])?;
+            self.move_to(offset + header_size + copied)?;
+            self.stream.write_all(&buf[..n])?;
+            copied += n as u64;
         }
+        Self::pad_zeroes(&mut self.stream, part.size)?;
+        self.stream.write_all(&[0u8; 2 * BLOCK_SIZE as usize])?;
+        self.inner_flush()?;
+        self.index.set_offset(id, offset + header_size).map_err(to_io_error)?;
+        self.end_fake_id = id;
+        Ok(offset + header_size)
+    }
+
+    /// Tries to acquire a shared advisory lock on a sub file entry, an
+    /// exclusive lock held by the same handle is downgraded.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to lock.
+    /// 
+    /// # Returns
+    /// * `IoResult<()>`: A would block error when other handle holds an exclusive lock.
+    pub fn try_lock_shared(&mut self, file: &SubFile) -> IoResult<()> {
+        self.validate(file)?;
+        self.locks.try_lock_shared(&file.entry.path, file.handle)
+    }
 
-        // partition when isn't the last file
-        if self.end_fake_id != file.fake_id {
-            // TODO: handle partitioning after header fixes
+    /// Tries to acquire an exclusive advisory lock on a sub file entry, a
+    /// shared lock held only by the same handle is upgraded.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to lock.
+    /// 
+    /// # Returns
+    /// * `IoResult<()>`: A would block error when other handle holds a lock.
+    pub fn try_lock_exclusive(&mut self, file: &SubFile) -> IoResult<()> {
+        self.validate(file)?;
+        self.locks.try_lock_exclusive(&file.entry.path, file.handle)
+    }
 
+    /// Releases the advisory lock held by a sub file handle.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to unlock.
+    pub fn unlock(&mut self, file: &SubFile) {
+        if self.locks.unlock(&file.entry.path, file.handle) {
+            #[cfg(feature = "async")]
+            self.unlocked.notify_waiters();
         }
+    }
 
-        // handle file when last partition
-        self.need_closing = true;
-        Ok(())
+    /// Closes a sub file handle releasing its advisory lock and every region
+    /// it still holds. Sub files are plain cursors so dropping them keeps
+    /// their locks, async sub files close their handle when dropped.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to close.
+    pub fn close_file(&mut self, file: SubFile) {
+        let locked = self.locks.unlock(&file.entry.path, file.handle);
+        let regions = self.regions.release(&file.entry.path, file.handle);
+        if locked || regions {
+            #[cfg(feature = "async")]
+            self.unlocked.notify_waiters();
+        }
     }
-}
 
-impl Drop for Tar {
-    fn drop(&mut self) {
-        self.inner_close().unwrap();
+    /// Tries to lock a region of a sub file for writing, other handles can't
+    /// write into it until it's unlocked. Regions held by the same handle
+    /// may overlap.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to lock.
+    /// * `offset`: Logical position where the region starts.
+    /// * `len`: Region length.
+    /// 
+    /// # Returns
+    /// * `IoResult<()>`: A would block error when other handle holds an overlapping region.
+    pub fn try_lock_region(&mut self, file: &SubFile, offset: u64, len: u64) -> IoResult<()> {
+        self.validate(file)?;
+        self.regions.try_lock(&file.entry.path, file.handle, offset, offset.saturating_add(len))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Releases a region locked by a sub file handle.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file owning the region.
+    /// * `offset`: Logical position where the region starts.
+    /// * `len`: Region length.
+    pub fn unlock_region(&mut self, file: &SubFile, offset: u64, len: u64) {
+        if self.regions.unlock(&file.entry.path, file.handle, offset, offset.saturating_add(len)) {
+            #[cfg(feature = "async")]
+            self.unlocked.notify_waiters();
+        }
+    }
 
-    #[test]
-    fn test_new_tar() {
-        let file = OsFile::create("test.tar").unwrap();
-        let tar = Tar::new(file);
-        assert!(tar.files.is_empty());
+    /// Gets the logical ranges of a sub file written since the last flush as
+    /// `(start, end)`, sorted and merged.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to get the ranges from.
+    pub fn dirty_regions(&self, file: &SubFile) -> Vec<(u64, u64)> {
+        self.regions.dirty(&file.entry.path).to_vec()
     }
 
-    #[test]
-    fn test_open_tar_standard() {}
+    /// Gets the logical size of a sub file, adding up all its partitions.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to get the size from.
+    pub fn file_size(&self, file: &SubFile) -> u64 {
+        match self.validate(file) {
+            Ok(fake_id) => self.index.get_parts(fake_id).iter().map(|(_, part)| part.meta.size).sum(),
+            Err(_) => 0
+        }
+    }
 
-    #[test]
-    fn test_open_tar_good() {}
+    /// Validates the sub file still points to the same index entry it was
+    /// opened from. Entries are matched by path and generation since other
+    /// deletes may move them to a different index position.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to validate.
+    /// 
+    /// # Returns
+    /// * `IoResult<usize>`: The current index position of the file, a stale handle error when the file was deleted
+    ///   or renamed.
+    pub(crate) fn validate(&self, file: &SubFile) -> IoResult<usize> {
+        match self.index.get_full(&file.entry.path) {
+            Some((fake_id, entry)) if entry.generation == file.generation => Ok(fake_id),
+            _ => Err(Error::StaleHandle(file.entry.path.clone()).into())
+        }
+    }
 
-    #[test]
-    fn test_open_tar_corrupted() {}
+    /// Validates a sub file and updates its index position.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to refresh.
+    /// 
+    /// # Returns
+    /// * `IoResult<()>`: A stale handle error when the file was deleted or renamed.
+    fn refresh(&self, file: &mut SubFile) -> IoResult<()> {
+        file.fake_id = self.validate(file)?;
+        Ok(())
+    }
 
-    #[test]
-    fn test_ensure_index(){}
+    /// Deletes a file and all its partitions from the index, open handles to
+    /// the file become stale.
+    /// 
+    /// # Arguments
+    /// * `path`: The path of the file to delete.
+    pub fn delete_file(&mut self, path: &str) -> IoResult<()> {
+        let fake_id = match self.index.get_full(path) {
+            Some((fake_id, _)) => fake_id,
+            None => return Err(IoError::new(std::io::ErrorKind::NotFound, format!("file '{}' not found", path)))
+        };
 
-    #[test]
-    fn test_auto_partition_fits() {
-        
+        // the content is kept until the stored index drops the records, content shared with copies stays in use
+        let parts: Vec<(usize, FileMeta)> = self.index.get_parts(fake_id).into_iter()
+            .map(|(id, entry)| (id, entry.meta.clone()))
+            .collect();
+        for (_, part) in parts.iter() {
+            if !self.release_shared(part.offset) {
+                self.retired_end = self.retired_end.max(part.offset + padded_size(part.size));
+            }
+        }
+
+        // remove from the highest index so swapped entries are never part of the chain
+        let mut ids: Vec<usize> = parts.iter().map(|(id, _)| *id).collect();
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        for id in ids {
+            if let Err(e) = self.index.remove(id) {
+                return Err(to_io_error(e));
+            }
+        }
+        self.end_fake_id = self.index.len().saturating_sub(1);
+        self.locks.remove(path);
+        self.regions.remove(path);
+        #[cfg(feature = "async")]
+        self.unlocked.notify_waiters();
+        Ok(())
     }
 
-    #[test]
-    fn test_auto_partition_append() {}
+    /// Truncates or extends a sub file, the cursor isn't moved. Shrinking
+    /// drops the trailing partitions and marks their blocks free, growing
+    /// fills the new content with zeroes.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to resize.
+    /// * `len`: The new logical size.
+    pub fn set_len(&mut self, file: &mut SubFile, len: u64) -> IoResult<()> {
+        self.refresh(file)?;
+        let size = self.file_size(file);
+        if len > size {
+            self.check_size(&file.entry.path, len)?;
+        }
+        if len != size {
+            // the partition about to change can't be shared with other copies
+            if let Some(id) = self.locate_part(file, len.min(size).saturating_sub(1)) {
+                self.inner_unshare(file, id)?;
+            }
+        }
+        if len < size {
+            self.inner_shrink(file, len)?;
+        } else if len > size {
+            self.inner_grow(file, size, len)?;
+        }
+        file.metadata.header.size = len;
+        Ok(())
+    }
 
-    #[test]
-    fn test_auto_partition_partition() {}
-}
\ No newline at end of file
+    /// Shrinks a sub file keeping the partitions holding its first `len`
+    /// bytes. The freed blocks and the headers of the dropped partitions are
+    /// overwritten with padding headers so sequential readers skip them.
+    fn inner_shrink(&mut self, file: &mut SubFile, len: u64) -> IoResult<()> {
+        let parts: Vec<(usize, FileMeta)> = self.index.get_parts(file.fake_id).into_iter()
+            .map(|(id, entry)| (id, entry.meta.clone()))
+            .collect();
+
+        // find the partition where the file now ends
+        let mut remaining = len;
+        let mut cut = 0;
+        for (i, (_, part)) in parts.iter().enumerate() {
+            cut = i;
+            if remaining <= part.size {
+                break;
+            }
+            remaining -= part.size;
+        }
+        let (id, part) = &parts[cut];
+        self.inner_update_header_size(part.offset, remaining)?;
+        self.inner_pad(part.offset + padded_size(remaining), padded_size(part.size) - padded_size(remaining))?;
+        self.index.set_size(*id, remaining).map_err(to_io_error)?;
+        if cut == 0 {
+            file.entry.size = remaining;
+        }
+
+        // drop the trailing partitions from the highest index so swapped entries are never part of the chain
+        let mut dropped: Vec<&(usize, FileMeta)> = parts[cut + 1..].iter().collect();
+        dropped.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+        for (id, part) in dropped {
+            // content shared with copies stays in use, the stored index may still point at the rest
+            if !self.release_shared(part.offset) {
+                let start = match self.inner_load_header(part.offset)? {
+                    Some(_) => part.offset.saturating_sub(Self::part_header_size(&part.path, part.size)?),
+                    None => part.offset
+                };
+                let end = part.offset + padded_size(part.size);
+                self.inner_pad(start, end - start)?;
+                self.retired_end = self.retired_end.max(end);
+            }
+            self.index.remove(*id).map_err(to_io_error)?;
+        }
+        self.inner_flush()?;
+        if cut + 1 < parts.len() {
+            self.end_fake_id = self.index.len().saturating_sub(1);
+
+            // the first partition may have been swapped so refresh the handle
+            if let Some((fake_id, entry)) = self.index.get_full(&file.entry.path) {
+                file.fake_id = fake_id;
+                file.generation = entry.generation;
+            }
+        }
+        self.need_closing = true;
+        Ok(())
+    }
+
+    /// Grows a sub file from `size` to `len` bytes filling it with zeroes.
+    /// The last partition grows in place when it's the last content of the
+    /// stream, otherwise it only fills its block padding and the rest goes
+    /// into a new partition past the data end.
+    fn inner_grow(&mut self, file: &mut SubFile, size: u64, len: u64) -> IoResult<()> {
+        let (id, part) = match self.index.get_parts(file.fake_id).last() {
+            Some((id, entry)) => (*id, entry.meta.clone()),
+            None => return Err(IoError::new(std::io::ErrorKind::NotFound, "file doesn't exists on the index"))
+        };
+        let is_last = part.offset + padded_size(part.size) >= self.data_end();
+        let grow = len - size;
+        let in_place = if is_last { grow } else { grow.min(padded_size(part.size) - part.size) };
+
+        // fill the new content and close the tar when it was the last file
+        if in_place > 0 {
+            let new_size = part.size + in_place;
+            self.move_to(part.offset + part.size)?;
+            Self::write_zeroes(&mut self.stream, in_place)?;
+            if is_last {
+                Self::pad_zeroes(&mut self.stream, new_size)?;
+                self.stream.write_all(&[0u8; 2 * BLOCK_SIZE as usize])?;
+                self.end_fake_id = id;
+            }
+            self.need_flush = true;
+            self.inner_update_header_size(part.offset, new_size)?;
+            self.inner_flush()?;
+            self.index.set_size(id, new_size).map_err(to_io_error)?;
+            if id == file.fake_id {
+                file.entry.size = new_size;
+            }
+        }
+        if grow > in_place {
+            self.inner_add_part(file, id, grow - in_place)?;
+        }
+        self.need_closing = true;
+        Ok(())
+    }
+
+    /// Appends a zero filled partition past the data end and links it after
+    /// the last partition of a sub file, the data is flushed before the index
+    /// records are updated.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to extend.
+    /// * `last`: Index position of the file last partition.
+    /// * `len`: The new partition size.
+    fn inner_add_part(&mut self, file: &mut SubFile, last: usize, len: u64) -> IoResult<()> {
+        let count = self.index.get_parts(file.fake_id).len();
+        let path = part_path(&file.entry.path, count);
+        let offset = self.data_end();
+        let (data_offset, meta) = self.inner_write_header(offset, &path, len)?;
+        Self::write_zeroes(&mut self.stream, len)?;
+        Self::pad_zeroes(&mut self.stream, meta.size)?;
+        self.stream.write_all(&[0u8; 2 * BLOCK_SIZE as usize])?;
+        self.need_flush = true;
+        self.inner_flush()?;
+
+        // register and link the partition once its content is written
+        let entry = FileMeta { offset: data_offset, path, parted: true, size: len };
+        self.index.append(entry, 0, 0).map_err(to_io_error)?;
+        let id = self.index.len() - 1;
+        self.index.link_part(last, id).map_err(to_io_error)?;
+        self.end_fake_id = id;
+        file.entry.parted = true;
+        file.metadata.parted = true;
+        Ok(())
+    }
+
+    /// Writes an amount of zero bytes into a writer.
+    /// 
+    /// # Arguments
+    /// * `writer`: The writer to write to.
+    /// * `len`: Amount of zero bytes to write.
+    fn write_zeroes(writer: &mut impl Write, len: u64) -> IoResult<()> {
+        let buf = [0u8; DEFAULT_BUFFER_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(DEFAULT_BUFFER_SIZE as u64) as usize;
+            writer.write_all(&buf[..n])?;
+            remaining -= n as u64;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the size field of the header block right before a partition
+    /// content, nothing is done when the block isn't a valid header.
+    /// 
+    /// # Arguments
+    /// * `offset`: The partition content offset.
+    /// * `size`: The new partition size.
+    fn inner_update_header_size(&mut self, offset: u64, size: u64) -> IoResult<()> {
+        let mut block = match self.inner_load_header(offset)? {
+            Some(v) => v,
+            None => return Ok(())
+        };
+        if let Err(e) = format::put_octal(&mut block[124..136], size) {
+            return Err(IoError::new(std::io::ErrorKind::InvalidInput, e));
+        }
+        format::set_checksum(&mut block);
+        self.move_to(offset - BLOCK_SIZE)?;
+        self.stream.write_all(&block)?;
+        self.need_flush = true;
+        Ok(())
+    }
+
+    /// Loads the header block right before a partition content.
+    /// 
+    /// # Arguments
+    /// * `offset`: The partition content offset.
+    /// 
+    /// # Returns
+    /// * `IoResult<Option<[u8; 512]>>`: The header block, `None` when it isn't a valid header.
+    fn inner_load_header(&mut self, offset: u64) -> IoResult<Option<[u8; BLOCK_SIZE as usize]>> {
+        if offset < BLOCK_SIZE {
+            return Ok(None);
+        }
+        let mut block = [0u8; BLOCK_SIZE as usize];
+        self.move_to(offset - BLOCK_SIZE)?;
+        self.stream.read_exact(&mut block)?;
+        match format::parse_octal(&block[148..156]) {
+            Ok(v) if v == format::checksum(&block) as u64 => Ok(Some(block)),
+            _ => Ok(None)
+        }
+    }
+
+    /// Overwrites a freed region with padding headers and marks it free.
+    /// 
+    /// # Arguments
+    /// * `offset`: The region offset, block aligned.
+    /// * `len`: The region length, block aligned.
+    fn inner_pad(&mut self, offset: u64, len: u64) -> IoResult<()> {
+        if len < 1 {
+            return Ok(());
+        }
+        self.move_to(offset)?;
+        pad_region(&mut self.stream, offset, len).map_err(to_io_error)?;
+        self.need_flush = true;
+        self.mark_free(offset, len);
+        Ok(())
+    }
+
+    /// Gets the size of the headers written before a partition content.
+    /// 
+    /// # Arguments
+    /// * `path`: The partition path.
+    /// * `size`: The partition content size.
+    fn part_header_size(path: &str, size: u64) -> IoResult<u64> {
+        let mut meta = Metadata::new(path, EntryKind::RegularFile);
+        meta.size = size;
+        meta.save_headers(&mut std::io::sink()).map_err(to_io_error)
+    }
+
+    /// Marks a region as free merging it with its adjacent free regions.
+    /// 
+    /// # Arguments
+    /// * `offset`: The region offset.
+    /// * `len`: The region length.
+    fn mark_free(&mut self, offset: u64, len: u64) {
+        if len < 1 {
+            return;
+        }
+        let index = self.free.partition_point(|(v, _)| *v < offset);
+        self.free.insert(index, (offset, len));
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(self.free.len());
+        for (offset, len) in self.free.drain(..) {
+            match merged.last_mut() {
+                Some(last) if last.0 + last.1 >= offset => last.1 = last.1.max(offset + len - last.0),
+                _ => merged.push((offset, len))
+            }
+        }
+        self.free = merged;
+    }
+
+    /// Limits the logical size of every file, creating or growing a file
+    /// past it fails with a quota error instead of partitioning it. Files
+    /// already larger can still be read and written within their size.
+    /// 
+    /// # Arguments
+    /// * `limit`: Maximum file size in bytes, `None` removes the limit.
+    pub fn set_max_file_size(&mut self, limit: Option<u64>) {
+        self.max_file_size = limit;
+    }
+
+    /// Checks a file size against the maximum file size.
+    /// 
+    /// # Arguments
+    /// * `path`: The path of the file.
+    /// * `size`: The size the file would reach.
+    /// 
+    /// # Returns
+    /// * `IoResult<()>`: A file too large error when the size exceeds the limit.
+    fn check_size(&self, path: &str, size: u64) -> IoResult<()> {
+        match self.max_file_size {
+            Some(limit) if size > limit => Err(Error::QuotaExceeded { path: path.to_string(), size, limit }.into()),
+            _ => Ok(())
+        }
+    }
+
+    /// Gets the amount of bytes freed by shrunk files.
+    pub fn free_space(&self) -> u64 {
+        self.free.iter().map(|(_, len)| len).sum()
+    }
+
+    /// Renames a file, open handles to the file become stale. The header
+    /// before the file content is rewritten in place when the new name fits
+    /// the same header blocks, otherwise the first partition is copied past
+    /// the data end behind the new header.
+    /// 
+    /// # Arguments
+    /// * `path`: The path of the file to rename.
+    /// * `new_path`: The new path of the file.
+    pub fn rename_file(&mut self, path: &str, new_path: &str) -> IoResult<()> {
+        let (fake_id, part) = match self.index.get_full(path) {
+            Some((fake_id, entry)) => (fake_id, entry.meta.clone()),
+            None => return Err(IoError::new(std::io::ErrorKind::NotFound, format!("file '{}' not found", path)))
+        };
+        if self.index.get(new_path).is_some() {
+            return Err(Error::AlreadyExists(new_path.to_string()).into());
+        }
+        self.inner_rename_header(fake_id, &part, new_path)?;
+        let ids: Vec<usize> = self.index.get_parts(fake_id).iter().map(|(id, _)| *id).collect();
+        if let Err(e) = self.index.rename(fake_id, new_path) {
+            return Err(to_io_error(e));
+        }
+
+        // the following partitions are named after the file
+        for (n, id) in ids.into_iter().enumerate().skip(1) {
+            self.index.rename(id, &part_path(new_path, n)).map_err(to_io_error)?;
+        }
+        self.locks.rename(path, new_path);
+        self.regions.rename(path, new_path);
+        Ok(())
+    }
+
+    /// Rewrites the header of a file first partition with a new path.
+    /// 
+    /// # Arguments
+    /// * `fake_id`: Index position of the file first partition.
+    /// * `part`: First partition entry.
+    /// * `new_path`: The new path of the file.
+    fn inner_rename_header(&mut self, fake_id: usize, part: &FileMeta, new_path: &str) -> IoResult<()> {
+        let mut meta = self.load_metadata(fake_id, part)?;
+        meta.size = part.size;
+        let mut old = Vec::new();
+        meta.save_headers(&mut old).map_err(to_io_error)?;
+        meta.path = new_path.to_string();
+        let mut new = Vec::new();
+        meta.save_headers(&mut new).map_err(to_io_error)?;
+
+        // a single header block is rewritten in place unless other copies share it
+        let in_place = old.len() == new.len() && new.len() as u64 == BLOCK_SIZE;
+        if in_place && part.offset >= BLOCK_SIZE && !self.is_shared(part.offset) {
+            self.move_to(part.offset - BLOCK_SIZE)?;
+            self.stream.write_all(&new)?;
+            self.need_flush = true;
+            return self.inner_flush();
+        }
+        self.inner_relocate(fake_id, part, meta)?;
+        self.release_shared(part.offset);
+        Ok(())
+    }
+
+    /// Locates the stream offset of a sub file logical position by walking the
+    /// partition chain.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to locate the position for.
+    /// * `pos`: Logical position within the sub file.
+    /// 
+    /// # Returns
+    /// * `Some((u64, u64))`: The stream offset and the remaining bytes on that partition.
+    /// * `None`: When the position is at or after the end of the last partition.
+    fn locate(&self, file: &SubFile, pos: u64) -> Option<(u64, u64)> {
+        let mut pos = pos;
+        for (_, part) in self.index.get_parts(file.fake_id) {
+            if pos < part.meta.size {
+                return Some((part.meta.offset + pos, part.meta.size - pos));
+            }
+            pos -= part.meta.size;
+        }
+        None
+    }
+
+    /// Gets the index position of the partition holding a sub file logical
+    /// position, writes past the end land on the last partition.
+    ///
+    /// # Arguments
+    /// * `file`: The sub file to locate the position for.
+    /// * `pos`: Logical position within the sub file.
+    fn locate_part(&self, file: &SubFile, pos: u64) -> Option<usize> {
+        let mut pos = pos;
+        let parts = self.index.get_parts(file.fake_id);
+        for (id, part) in parts.iter() {
+            if pos < part.meta.size {
+                return Some(*id);
+            }
+            pos -= part.meta.size;
+        }
+        parts.last().map(|(id, _)| *id)
+    }
+
+    /// Moves the stream position to the target offset if different.
+    pub(crate) fn move_to(&mut self, offset: u64) -> IoResult<()> {
+        let pos = self.stream.stream_position()?;
+        if pos != offset {
+            if self.need_flush {
+                self.inner_flush()?;
+            }
+            self.stream.seek(SeekFrom::Start(offset))?;
+        }
+        Ok(())
+    }
+
+    /// Reads from the sub file cursor, crossing partition boundaries as needed
+    /// so partitioned files read as a single continuous file.
+    pub(crate) fn inner_read(&mut self, file: &mut SubFile, buf: &mut [u8]) -> IoResult<usize> {
+        self.refresh(file)?;
+        let mut total = 0;
+        while total < buf.len() {
+            let (offset, available) = match self.locate(file, file.pos) {
+                Some(v) => v,
+                None => break
+            };
+            let len = (buf.len() - total).min(available.min(usize::MAX as u64) as usize);
+            self.move_to(offset)?;
+            let read = self.stream.read(&mut buf[total..total + len])?;
+            if read < 1 {
+                break;
+            }
+            file.pos += read as u64;
+            total += read;
+        }
+        Ok(total)
+    }
+
+    /// Writes at the sub file cursor up to the end of the current partition,
+    /// writes past the end of the file grow it first, partitioning it when it
+    /// can't grow in place.
+    pub(crate) fn inner_write(&mut self, file: &mut SubFile, buf: &[u8]) -> IoResult<usize> {
+        self.refresh(file)?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(id) = self.locate_part(file, file.pos) {
+            self.inner_unshare(file, id)?;
+        }
+        let size = self.file_size(file);
+        let end = file.pos + buf.len() as u64;
+        if end > size {
+            self.check_size(&file.entry.path, end)?;
+            self.regions.check(&file.entry.path, file.handle, file.pos, end)?;
+            self.inner_grow(file, size, end)?;
+        }
+        let (offset, len) = match self.locate(file, file.pos) {
+            Some((offset, available)) => (offset, buf.len().min(available.min(usize::MAX as u64) as usize)),
+            None => return Ok(0)
+        };
+        let end = file.pos + len as u64;
+        self.regions.check(&file.entry.path, file.handle, file.pos, end)?;
+        self.move_to(offset)?;
+        let written = self.stream.write(&buf[..len])?;
+        self.regions.mark_dirty(&file.entry.path, file.pos, file.pos + written as u64);
+        file.pos += written as u64;
+        self.need_flush = true;
+        Ok(written)
+    }
+
+    /// Reads from a sub file into the buffer advancing its cursor.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to read from.
+    /// * `buf`: The buffer to read into.
+    /// 
+    /// # Returns
+    /// * `IoResult<usize>`: The amount of bytes read, 0 at the end of the file.
+    pub fn read(&mut self, file: &mut SubFile, buf: &mut [u8]) -> IoResult<usize> {
+        self.inner_read(file, buf)
+    }
+
+    /// Writes the buffer into a sub file advancing its cursor.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to write into.
+    /// * `buf`: The buffer to write.
+    /// 
+    /// # Returns
+    /// * `IoResult<usize>`: The amount of bytes written.
+    pub fn write(&mut self, file: &mut SubFile, buf: &[u8]) -> IoResult<usize> {
+        self.inner_write(file, buf)
+    }
+
+    /// Reads from a logical position of a sub file without moving its
+    /// cursor, like `pread`.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to read from.
+    /// * `buf`: The buffer to read into.
+    /// * `offset`: Logical position to read from.
+    /// 
+    /// # Returns
+    /// * `IoResult<usize>`: The amount of bytes read, 0 at the end of the file.
+    pub fn read_at(&mut self, file: &SubFile, buf: &mut [u8], offset: u64) -> IoResult<usize> {
+        let mut cursor = file.clone();
+        cursor.pos = offset;
+        self.inner_read(&mut cursor, buf)
+    }
+
+    /// Writes into a logical position of a sub file without moving its
+    /// cursor, like `pwrite`.
+    /// 
+    /// # Arguments
+    /// * `file`: The sub file to write into.
+    /// * `buf`: The buffer to write.
+    /// * `offset`: Logical position to write at.
+    /// 
+    /// # Returns
+    /// * `IoResult<usize>`: The amount of bytes written.
+    pub fn write_at(&mut self, file: &SubFile, buf: &[u8], offset: u64) -> IoResult<usize> {
+        let mut cursor = file.clone();
+        cursor.pos = offset;
+        self.inner_write(&mut cursor, buf)
+    }
+
+    /// Flushes any pending data and the modified index entries into the
+    /// tar, the written ranges are forgotten once flushed.
+    pub fn flush(&mut self) -> IoResult<()> {
+        self.inner_flush()?;
+        self.inner_flush_index(usize::MAX)?;
+        self.regions.clear_dirty();
+        Ok(())
+    }
+
+    pub(crate) fn auto_partition(&mut self, file: &mut SubFile, bytes_to_write: u64) -> IoResult<()> {
+        self.inner_auto_partition(file, bytes_to_write)
+    }
+
+    /// Makes room for the bytes about to be written at the sub file cursor,
+    /// partitioning the file when it can't grow in place.
+    pub(crate) fn inner_auto_partition(&mut self, file: &mut SubFile, bytes_to_write: u64) -> IoResult<()> {
+        self.refresh(file)?;
+
+        // do nothing if the bytes to be written fits the file
+        let size = self.file_size(file);
+        let end = file.pos + bytes_to_write;
+        if end <= size {
+            return Ok(())
+        }
+        self.check_size(&file.entry.path, end)?;
+        self.inner_grow(file, size, end)
+    }
+}
+
+impl<T: Read + Write + Seek> Drop for Tar<T> {
+    fn drop(&mut self) {
+        // errors can't be reported from drop, a failed close leaves the
+        // previous end tag in place
+        let _ = self.inner_close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    #[cfg(feature = "async")]
+    use tokio_test::assert_pending;
+
+    #[test]
+    fn test_new_tar() {
+        let tar = Tar::new(Cursor::new(Vec::new()));
+        assert!(tar.index.is_empty());
+        assert!(tar.index.pages.is_empty());
+    }
+
+    #[test]
+    fn test_open_tar_good() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 10).unwrap();
+        tar.write(&mut file, b"hello").unwrap();
+        tar.create_with_size("b.bin", 600).unwrap();
+        tar.inner_close().unwrap();
+        let mut bytes = Vec::new();
+        tar.stream.seek(SeekFrom::Start(0)).unwrap();
+        tar.stream.read_to_end(&mut bytes).unwrap();
+        drop(tar);
+
+        let mut tar = match Tar::open(Cursor::new(bytes)) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open tar: {}", e);
+                return;
+            }
+        };
+        assert_eq!(2, tar.index.len());
+        assert_eq!(1, tar.end_fake_id);
+        let mut file = tar.open_file("a.bin").unwrap();
+        let mut buf = [0u8; 5];
+        assert_eq!(5, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"hello", &buf);
+    }
+
+    #[test]
+    fn flush_persists_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.tar");
+        let mut tar = Tar::create_new(path.clone()).unwrap();
+        let mut file = tar.create_with_size("a.bin", 5).unwrap();
+        tar.write(&mut file, b"hello").unwrap();
+        if let Err(e) = tar.flush() {
+            assert!(false, "Failed to flush tar: {}", e);
+            return;
+        }
+        tar.create_with_size("b.bin", 600).unwrap();
+        drop(tar);
+
+        // entries flushed explicitly and the ones flushed on drop are both found
+        let stream = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let mut tar = match Tar::open(stream) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open tar: {}", e);
+                return;
+            }
+        };
+        let mut file = tar.open_file("a.bin").unwrap();
+        let mut buf = [0u8; 5];
+        assert_eq!(5, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"hello", &buf);
+        let file = tar.open_file("b.bin").unwrap();
+        assert_eq!(600, tar.file_size(&file));
+    }
+
+    #[test]
+    fn test_open_tar_corrupted() {
+        let stream = Cursor::new(vec![b'x'; 2048]);
+        assert!(Tar::open(stream).is_err());
+    }
+
+    #[test]
+    fn test_auto_partition_fits() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 100).unwrap();
+        tar.create_with_size("b.bin", 10).unwrap();
+        file.pos = 90;
+        if let Err(e) = tar.auto_partition(&mut file, 10) {
+            assert!(false, "Failed to auto partition: {}", e);
+            return;
+        }
+        assert_eq!(100, tar.file_size(&file));
+        assert!(!file.metadata().is_parted());
+    }
+
+    #[test]
+    fn test_auto_partition_append() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 10).unwrap();
+        let mut file = tar.create_with_size("b.bin", 10).unwrap();
+        file.pos = 10;
+        tar.auto_partition(&mut file, 1000).unwrap();
+        assert_eq!(1010, tar.file_size(&file));
+        assert_eq!(1010, tar.index.get("b.bin").unwrap().meta.size);
+        assert!(!file.metadata().is_parted());
+        assert!(tar.need_closing);
+    }
+
+    #[test]
+    fn test_auto_partition_partition() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 10).unwrap();
+        tar.create_with_size("b.bin", 10).unwrap();
+        file.pos = 10;
+        tar.auto_partition(&mut file, 1000).unwrap();
+        assert_eq!(1010, tar.file_size(&file));
+        assert!(file.metadata().is_parted());
+
+        // the padding of the first partition is used before the new partition
+        let parts = tar.index.get_parts(file.fake_id);
+        assert_eq!(vec![512, 498], parts.iter().map(|(_, part)| part.meta.size).collect::<Vec<u64>>());
+        assert_eq!("a.bin.1.rhpart", parts[1].1.meta.path);
+        assert_eq!(parts[1].0, tar.end_fake_id);
+    }
+
+    #[test]
+    fn write_past_end() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 5).unwrap();
+        let mut other = tar.create_with_size("b.bin", 5).unwrap();
+        tar.write(&mut other, b"other").unwrap();
+        file.pos = 600;
+
+        // the gap is zero filled and the next file header is left untouched
+        let mut written = 0;
+        while written < 5 {
+            written += match tar.write(&mut file, &b"hello"[written..]) {
+                Ok(v) => v,
+                Err(e) => {
+                    assert!(false, "Failed to write past the end: {}", e);
+                    return;
+                }
+            };
+        }
+        assert_eq!(605, tar.file_size(&file));
+        assert_eq!(605, tar.index.get_parts(file.fake_id).iter().map(|(_, part)| part.meta.size).sum::<u64>());
+        let mut buf = vec![0xffu8; 605];
+        assert_eq!(605, tar.read_at(&file, &mut buf, 0).unwrap());
+        assert!(buf[..600].iter().all(|b| *b == 0));
+        assert_eq!(b"hello", &buf[600..]);
+        let mut other = tar.open_file("b.bin").unwrap();
+        let mut buf = [0u8; 5];
+        assert_eq!(5, tar.read(&mut other, &mut buf).unwrap());
+        assert_eq!(b"other", &buf);
+    }
+
+    #[test]
+    fn read_across_partitions() {
+        let mut stream = vec![0u8; 1024];
+        stream[0..5].copy_from_slice(b"hello");
+        stream[512..518].copy_from_slice(b" world");
+        let mut tar = Tar::new(Cursor::new(stream));
+        tar.index.append(FileMeta { offset: 0, path: "a.part1".to_string(), parted: true, size: 5 }, 0, 2).unwrap();
+        tar.index.append(FileMeta { offset: 512, path: "a.part2".to_string(), parted: true, size: 6 }, 1, 0).unwrap();
+        let mut file = match tar.open_file("a.part1") {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open file: {}", e);
+                return;
+            }
+        };
+        assert_eq!(11, tar.file_size(&file));
+        assert_eq!(11, file.metadata().len());
+        assert!(file.metadata().is_parted());
+        let mut buf = [0u8; 16];
+        let read = tar.read(&mut file, &mut buf).unwrap();
+        assert_eq!(b"hello world", &buf[..read]);
+        assert_eq!(11, file.position());
+        assert_eq!(0, tar.read(&mut file, &mut buf).unwrap());
+    }
+
+    #[test]
+    fn read_partition_boundary() {
+        let mut stream = vec![0u8; 1024];
+        stream[0..5].copy_from_slice(b"hello");
+        stream[512..518].copy_from_slice(b" world");
+        let mut tar = Tar::new(Cursor::new(stream));
+        tar.index.append(FileMeta { offset: 0, path: "a.part1".to_string(), parted: true, size: 5 }, 0, 2).unwrap();
+        tar.index.append(FileMeta { offset: 512, path: "a.part2".to_string(), parted: true, size: 6 }, 1, 0).unwrap();
+        let mut file = tar.open_file("a.part1").unwrap();
+        let mut buf = [0u8; 3];
+        assert_eq!(3, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"hel", &buf);
+        assert_eq!(3, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"lo ", &buf);
+        assert_eq!(3, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"wor", &buf);
+    }
+
+    #[test]
+    fn create_with_size() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = match tar.create_with_size("db.bin", 2000) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to create file: {}", e);
+                return;
+            }
+        };
+        assert_eq!(2000, tar.file_size(&file));
+        assert_eq!(512, file.entry.offset);
+        file.pos = 1500;
+        assert_eq!(5, tar.write(&mut file, b"hello").unwrap());
+        tar.flush().unwrap();
+        let mut file = tar.open_file("db.bin").unwrap();
+        assert_eq!(2000, file.metadata().len());
+        assert_eq!(0o644, file.metadata().mode());
+        assert_eq!("db.bin", file.metadata().header().path);
+        assert!(!file.metadata().is_parted());
+        let mut buf = vec![0u8; 2000];
+        assert_eq!(2000, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"hello", &buf[1500..1505]);
+        assert!(buf[..1500].iter().all(|b| *b == 0));
+        match tar.create_with_size("db.bin", 10) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::AlreadyExists, e.kind())
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn append_from_cancelled() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 10).unwrap();
+        let end = tar.data_end();
+
+        // the reader stalls mid content so the append is dropped while pending
+        let (mut reader, mut writer) = tokio::io::duplex(64);
+        writer.write_all(b"partial").await.unwrap();
+        let mut task = tokio_test::task::spawn(tar.append_from("b.bin", 100, &mut reader));
+        assert_pending!(task.poll());
+        drop(task);
+        assert!(tar.index.get("b.bin").is_none());
+        assert_eq!(end, tar.data_end());
+
+        // the next append reclaims the partial data
+        let mut file = match tar.append_from("b.bin", 5, &mut &b"hello"[..]).await {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to append file: {}", e);
+                return;
+            }
+        };
+        assert_eq!(end + 512, file.entry.offset);
+        let mut buf = [0u8; 5];
+        assert_eq!(5, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"hello", &buf);
+        match tar.append_from("c.bin", 10, &mut &b"short"[..]).await {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::UnexpectedEof, e.kind())
+        }
+        assert!(tar.index.get("c.bin").is_none());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn extract_to_cancelled() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.append_from("a.bin", 11, &mut &b"hello world"[..]).await.unwrap();
+
+        // the writer is smaller than the content so the extraction stalls
+        let (mut writer, _reader) = tokio::io::duplex(4);
+        let mut task = tokio_test::task::spawn(tar.extract_to(&mut file, &mut writer));
+        assert_pending!(task.poll());
+        drop(task);
+        assert_eq!(0, file.position());
+
+        let mut buf = Vec::new();
+        assert_eq!(11, tar.extract_to(&mut file, &mut buf).await.unwrap());
+        assert_eq!(b"hello world", &buf[..]);
+        assert_eq!(11, file.position());
+    }
+
+    #[test]
+    fn auto_partition_reserved() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("db.bin", 1024).unwrap();
+        file.pos = 1000;
+        tar.auto_partition(&mut file, 24).unwrap();
+        assert!(!tar.need_closing);
+    }
+
+    #[test]
+    fn deleted_content_kept_until_index_flush() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 10).unwrap();
+        let last = tar.create_with_size("b.bin", 600).unwrap();
+        let last_end = last.entry.offset + padded_size(600);
+        tar.delete_file("b.bin").unwrap();
+
+        // the stored index may still point at b.bin so its blocks aren't reused
+        let file = tar.create_with_size("c.bin", 10).unwrap();
+        assert!(file.entry.offset > last_end);
+        assert_eq!(last_end, tar.retired_end);
+    }
+
+    #[test]
+    fn stale_handle_on_delete() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file_a = tar.create_with_size("a.bin", 10).unwrap();
+        let mut file_b = tar.create_with_size("b.bin", 10).unwrap();
+        tar.delete_file("a.bin").unwrap();
+        let mut buf = [0u8; 4];
+        match tar.read(&mut file_a, &mut buf) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::StaleNetworkFileHandle, e.kind())
+        }
+
+        // b.bin was moved into a.bin index position but its handle keeps working
+        match tar.write(&mut file_b, b"data") {
+            Ok(v) => assert_eq!(4, v),
+            Err(e) => {
+                assert!(false, "Failed to write moved file: {}", e);
+                return;
+            }
+        }
+        assert_eq!(0, file_b.fake_id);
+        assert_eq!(4, tar.read_at(&file_b, &mut buf, 0).unwrap());
+        assert_eq!(b"data", &buf);
+    }
+
+    #[test]
+    fn stale_handle_on_rename() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 10).unwrap();
+        tar.rename_file("a.bin", "c.bin").unwrap();
+        let mut buf = [0u8; 4];
+        match tar.read(&mut file, &mut buf) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::StaleNetworkFileHandle, e.kind())
+        }
+        let mut file = tar.open_file("c.bin").unwrap();
+        assert_eq!(4, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!("c.bin", file.metadata().header().path);
+    }
+
+    #[test]
+    fn rename_rewrites_header() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 5).unwrap();
+        tar.write(&mut file, b"hello").unwrap();
+        tar.create_with_size("b.bin", 10).unwrap();
+        let offset = file.entry.offset;
+        if let Err(e) = tar.rename_file("a.bin", "c.bin") {
+            assert!(false, "Failed to rename file: {}", e);
+            return;
+        }
+        let mut block = [0u8; BLOCK_SIZE as usize];
+        tar.stream.seek(SeekFrom::Start(offset - BLOCK_SIZE)).unwrap();
+        tar.stream.read_exact(&mut block).unwrap();
+        let header = format::RawHeader::decode(&block).unwrap();
+        assert_eq!(b"c.bin".to_vec(), header.name);
+        assert_eq!(format::checksum(&block), header.chksum);
+
+        // a name needing extended headers moves the content behind new headers
+        let long = format!("dir/{}", "d".repeat(150));
+        tar.rename_file("c.bin", &long).unwrap();
+        let file = tar.open_file(&long).unwrap();
+        assert!(file.entry.offset > offset);
+        tar.stream.seek(SeekFrom::Start(0)).unwrap();
+        let mut buf = Vec::new();
+        tar.stream.read_to_end(&mut buf).unwrap();
+        let mut archive = crate::engine::archive::Archive::open(Cursor::new(buf)).unwrap();
+        let mut out = Vec::new();
+        archive.read_to(&long, &mut out).unwrap();
+        assert_eq!(b"hello".to_vec(), out);
+    }
+
+    #[test]
+    fn rename_error_kind() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 10).unwrap();
+        tar.create_with_size("b.bin", 10).unwrap();
+        match tar.rename_file("a.bin", "b.bin") {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::AlreadyExists, e.kind())
+        }
+    }
+
+    #[test]
+    fn set_len_shrink() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 2000).unwrap();
+        tar.create_with_size("b.bin", 10).unwrap();
+
+        // writing past the block padding adds a second partition after b.bin
+        file.pos = 2048;
+        tar.write(&mut file, b"world").unwrap();
+        assert_eq!(2, tar.index.get_parts(file.fake_id).len());
+        file.pos = 1500;
+        match tar.set_len(&mut file, 100) {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to shrink file: {}", e);
+                return;
+            }
+        }
+        assert_eq!(1500, file.position());
+        assert_eq!(100, tar.file_size(&file));
+        assert_eq!(100, file.metadata().len());
+        assert_eq!(1536 + 1024, tar.free_space());
+
+        // the header size is rewritten with a valid checksum
+        let mut block = [0u8; 512];
+        tar.move_to(file.entry.offset - 512).unwrap();
+        tar.stream.read_exact(&mut block).unwrap();
+        let header = format::RawHeader::decode(&block).unwrap();
+        assert_eq!(100, header.size);
+        assert_eq!(format::checksum(&block), header.chksum);
+
+        // sequential readers skip the freed blocks and the dropped partition
+        tar.flush().unwrap();
+        let mut bytes = Vec::new();
+        tar.stream.seek(SeekFrom::Start(0)).unwrap();
+        tar.stream.read_to_end(&mut bytes).unwrap();
+        drop(tar);
+        let archive = match crate::engine::archive::Archive::open(Cursor::new(bytes.clone())) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open shrunk archive: {}", e);
+                return;
+            }
+        };
+        assert_eq!(100, archive.get("a.bin").unwrap().meta.size);
+        assert!(archive.get("b.bin").is_some());
+        assert!(archive.entries().all(|entry| !entry.meta.path.ends_with(".rhpart")));
+        let mut tar = match Tar::open(Cursor::new(bytes)) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open shrunk tar: {}", e);
+                return;
+            }
+        };
+        let file = tar.open_file("a.bin").unwrap();
+        assert_eq!(100, tar.file_size(&file));
+        assert_eq!(2, tar.index.len());
+    }
+
+    #[test]
+    fn set_len_shrink_partitions() {
+        let mut stream = vec![0u8; 1024];
+        stream[0..5].copy_from_slice(b"hello");
+        stream[512..518].copy_from_slice(b" world");
+        let mut tar = Tar::new(Cursor::new(stream));
+        tar.index.append(FileMeta { offset: 0, path: "a.part1".to_string(), parted: true, size: 5 }, 0, 2).unwrap();
+        tar.index.append(FileMeta { offset: 512, path: "a.part2".to_string(), parted: true, size: 6 }, 1, 0).unwrap();
+        let mut file = tar.open_file("a.part1").unwrap();
+        tar.set_len(&mut file, 3).unwrap();
+        assert_eq!(1, tar.index.len());
+        assert_eq!(3, tar.file_size(&file));
+        let mut buf = [0u8; 8];
+        assert_eq!(3, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"hel", &buf[..3]);
+    }
+
+    #[test]
+    fn max_file_size() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.set_max_file_size(Some(16));
+        match tar.create_with_size("a.bin", 32) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::FileTooLarge, e.kind())
+        }
+        let mut file = tar.create_with_size("b.bin", 10).unwrap();
+        file.pos = 10;
+        match tar.write(&mut file, &[1u8; 10]) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::FileTooLarge, e.kind())
+        }
+        assert!(tar.set_len(&mut file, 20).is_err());
+        tar.set_len(&mut file, 16).unwrap();
+        assert_eq!(16, tar.file_size(&file));
+    }
+
+    #[test]
+    fn set_len_grow() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file_a = tar.create_with_size("a.bin", 100).unwrap();
+        let mut file_b = tar.create_with_size("b.bin", 10).unwrap();
+        assert_eq!(5, tar.write(&mut file_b, b"hello").unwrap());
+        tar.set_len(&mut file_b, 600).unwrap();
+        assert_eq!(600, tar.file_size(&file_b));
+        file_b.pos = 0;
+        let mut buf = vec![0xffu8; 600];
+        assert_eq!(600, tar.read(&mut file_b, &mut buf).unwrap());
+        assert_eq!(b"hello", &buf[..5]);
+        assert!(buf[5..].iter().all(|b| *b == 0));
+
+        // files before the end grow within their blocks and then partition
+        tar.set_len(&mut file_a, 400).unwrap();
+        assert_eq!(400, tar.file_size(&file_a));
+        assert!(!file_a.metadata().is_parted());
+        match tar.set_len(&mut file_a, 1000) {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to grow file: {}", e);
+                return;
+            }
+        }
+        assert_eq!(1000, tar.file_size(&file_a));
+        assert!(file_a.metadata().is_parted());
+        let mut buf = vec![0xffu8; 1000];
+        assert_eq!(1000, tar.read_at(&file_a, &mut buf, 0).unwrap());
+        assert!(buf.iter().all(|b| *b == 0));
+        file_b.pos = 0;
+        assert_eq!(5, tar.read(&mut file_b, &mut buf[..5]).unwrap());
+        assert_eq!(b"hello", &buf[..5]);
+    }
+
+    #[test]
+    fn try_clone_file() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 10).unwrap();
+        file.pos = 4;
+        let mut other = match tar.try_clone_file(&file) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to clone file: {}", e);
+                return;
+            }
+        };
+        assert_eq!(4, other.position());
+
+        // cursors move independently
+        assert_eq!(2, tar.write(&mut file, b"ab").unwrap());
+        other.pos = 0;
+        assert_eq!(2, tar.write(&mut other, b"cd").unwrap());
+        assert_eq!(6, file.position());
+        assert_eq!(2, other.position());
+        let mut buf = [0u8; 10];
+        other.pos = 0;
+        tar.read(&mut other, &mut buf).unwrap();
+        assert_eq!(b"cd\0\0ab\0\0\0\0", &buf);
+
+        tar.delete_file("a.bin").unwrap();
+        match tar.try_clone_file(&file) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::StaleNetworkFileHandle, e.kind())
+        }
+    }
+
+    #[test]
+    fn positional_io() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 10).unwrap();
+        file.pos = 2;
+        match tar.write_at(&file, b"world", 5) {
+            Ok(v) => assert_eq!(5, v),
+            Err(e) => {
+                assert!(false, "Failed to write: {}", e);
+                return;
+            }
+        }
+        assert_eq!(5, tar.write_at(&file, b"hello", 0).unwrap());
+        assert_eq!(2, file.position());
+
+        let mut buf = [0u8; 5];
+        assert_eq!(5, tar.read_at(&file, &mut buf, 5).unwrap());
+        assert_eq!(b"world", &buf);
+        assert_eq!(0, tar.read_at(&file, &mut buf, 10).unwrap());
+        assert_eq!(2, file.position());
+        assert_eq!(5, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"llowo", &buf);
+    }
+
+    #[test]
+    fn advisory_locks() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let file = tar.create_with_size("a.bin", 10).unwrap();
+        let other = tar.open_file("a.bin").unwrap();
+        match tar.try_lock_exclusive(&file) {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to lock: {}", e);
+                return;
+            }
+        }
+        match tar.try_lock_shared(&other) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::WouldBlock, e.kind())
+        }
+        tar.unlock(&file);
+        tar.try_lock_shared(&other).unwrap();
+        tar.try_lock_shared(&file).unwrap();
+        assert!(tar.try_lock_exclusive(&file).is_err());
+
+        // locks follow renames and the clones are separate handles
+        tar.rename_file("a.bin", "b.bin").unwrap();
+        let file = tar.open_file("b.bin").unwrap();
+        let clone = tar.try_clone_file(&file).unwrap();
+        tar.try_lock_shared(&file).unwrap();
+        assert!(tar.try_lock_exclusive(&clone).is_err());
+    }
+
+    #[test]
+    fn cow_copy() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 600).unwrap();
+        tar.write(&mut file, b"hello").unwrap();
+        let end = tar.data_end();
+        let mut copy = match tar.cow_copy("a.bin", "b.bin") {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to copy file: {}", e);
+                return;
+            }
+        };
+        assert_eq!(file.entry.offset, copy.entry.offset);
+        assert_eq!(end, tar.data_end());
+        assert_eq!(600, copy.metadata().len());
+
+        // the first write relocates the copy and leaves the source untouched
+        assert_eq!(5, tar.write(&mut copy, b"world").unwrap());
+        assert_ne!(file.entry.offset, copy.entry.offset);
+        let mut buf = [0u8; 5];
+        assert_eq!(5, tar.read_at(&file, &mut buf, 0).unwrap());
+        assert_eq!(b"hello", &buf);
+        assert_eq!(5, tar.read_at(&copy, &mut buf, 0).unwrap());
+        assert_eq!(b"world", &buf);
+        assert_eq!(600, tar.file_size(&copy));
+
+        // once relocated the source is written in place
+        let offset = file.entry.offset;
+        assert_eq!(3, tar.write_at(&file, b"abc", 0).unwrap());
+        assert_eq!(offset, file.entry.offset);
+        assert_eq!(5, tar.read_at(&copy, &mut buf, 0).unwrap());
+        assert_eq!(b"world", &buf);
+
+        match tar.cow_copy("a.bin", "b.bin") {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::AlreadyExists, e.kind())
+        }
+    }
+
+    #[test]
+    fn cow_copy_outlives_source() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.bin", 600).unwrap();
+        tar.write(&mut file, b"hello").unwrap();
+        let copy = tar.cow_copy("a.bin", "b.bin").unwrap();
+        let other = tar.cow_copy("a.bin", "c.bin").unwrap();
+        assert!(tar.is_shared(file.entry.offset));
+
+        // deleting the source keeps the content shared by the copies
+        if let Err(e) = tar.delete_file("a.bin") {
+            assert!(false, "Failed to delete file: {}", e);
+            return;
+        }
+        assert!(tar.is_shared(copy.entry.offset));
+        assert_eq!(0, tar.retired_end);
+        let copy = tar.open_file("b.bin").unwrap();
+        let mut buf = [0u8; 5];
+        assert_eq!(5, tar.read_at(&copy, &mut buf, 0).unwrap());
+        assert_eq!(b"hello", &buf);
+
+        // the last copy owns the content and writes it in place
+        tar.delete_file("c.bin").unwrap();
+        assert!(!tar.is_shared(other.entry.offset));
+        let offset = copy.entry.offset;
+        assert_eq!(5, tar.write_at(&copy, b"world", 0).unwrap());
+        assert_eq!(offset, tar.open_file("b.bin").unwrap().entry.offset);
+    }
+
+    #[test]
+    fn region_locks() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut head = tar.create_with_size("a.bin", 20).unwrap();
+        let mut tail = tar.open_file("a.bin").unwrap();
+        match tar.try_lock_region(&head, 0, 10) {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to lock region: {}", e);
+                return;
+            }
+        }
+        tar.try_lock_region(&tail, 10, 10).unwrap();
+
+        // writers on disjoint regions interleave
+        tail.pos = 10;
+        assert_eq!(5, tar.write(&mut head, b"hello").unwrap());
+        assert_eq!(5, tar.write(&mut tail, b"world").unwrap());
+        assert_eq!(5, tar.write(&mut head, b"01234").unwrap());
+        match tar.write(&mut head, b"x") {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::WouldBlock, e.kind())
+        }
+        assert_eq!(vec![(0, 15)], tar.dirty_regions(&tail));
+        let mut buf = [0u8; 15];
+        assert_eq!(15, tar.read_at(&head, &mut buf, 0).unwrap());
+        assert_eq!(b"hello01234world", &buf);
+
+        // unlocked regions are writable again and flushing forgets the written ranges
+        tar.unlock_region(&tail, 10, 10);
+        assert_eq!(1, tar.write(&mut head, b"x").unwrap());
+        tar.flush().unwrap();
+        assert!(tar.dirty_regions(&head).is_empty());
+    }
+
+    #[test]
+    fn close_file_releases_locks() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let file = tar.create_with_size("a.bin", 20).unwrap();
+        let mut other = tar.open_file("a.bin").unwrap();
+        tar.try_lock_exclusive(&file).unwrap();
+        tar.try_lock_region(&file, 0, 10).unwrap();
+        tar.try_lock_region(&file, 10, 10).unwrap();
+        assert!(tar.try_lock_shared(&other).is_err());
+
+        tar.close_file(file);
+        match tar.try_lock_exclusive(&other) {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to lock: {}", e);
+                return;
+            }
+        }
+        assert_eq!(5, tar.write(&mut other, b"hello").unwrap());
+    }
+}
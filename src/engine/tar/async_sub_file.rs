@@ -0,0 +1,528 @@
+use std::future::Future;
+use std::io::{Read, Seek, SeekFrom, Write, Error as IoError, ErrorKind};
+use std::io::Result as IoResult;
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use super::{copy_bounded, StreamOptions, SubFile, Tar};
+
+/// Future acquiring the shared tar lock.
+type LockFuture<T> = Pin<Box<dyn Future<Output = OwnedMutexGuard<Tar<T>>> + Send>>;
+
+/// Sub file handle implementing the tokio async IO traits over a shared tar,
+/// so entries can be served directly as async streams. Every operation locks
+/// the tar, moves its stream to the sub file cursor and partitions the file
+/// when a write doesn't fit.
+///
+/// Operations are cancellation safe, the tar work runs synchronously once
+/// the lock is acquired so dropping a pending operation only drops its lock
+/// acquisition. Dropping the handle releases its advisory and region locks.
+pub struct AsyncSubFile<T: Read + Write + Seek + Send + 'static> {
+    /// Shared tar the file belongs to.
+    tar: Arc<Mutex<Tar<T>>>,
+    /// File handle with the logical cursor.
+    file: SubFile,
+    /// Pending lock acquisition.
+    lock: Option<LockFuture<T>>,
+    /// Pending seek started by `start_seek`.
+    seek: Option<SeekFrom>,
+}
+
+impl<T: Read + Write + Seek + Send + 'static> AsyncSubFile<T> {
+    /// Opens a file by path with its cursor at the start.
+    ///
+    /// # Arguments
+    /// * `tar` - Shared tar the file belongs to.
+    /// * `path` - The path of the file to open.
+    ///
+    /// # Returns
+    /// * `IoResult<Self>` - The opened async sub file.
+    pub async fn open(tar: Arc<Mutex<Tar<T>>>, path: &str) -> IoResult<Self> {
+        let file = tar.lock().await.open_file(path)?;
+        Ok(Self::new(tar, file))
+    }
+
+    /// Wraps an already open sub file.
+    ///
+    /// # Arguments
+    /// * `tar` - Shared tar the file belongs to.
+    /// * `file` - The open sub file.
+    pub(crate) fn new(tar: Arc<Mutex<Tar<T>>>, file: SubFile) -> Self {
+        Self {
+            tar,
+            file,
+            lock: None,
+            seek: None
+        }
+    }
+
+    /// Returns the logical cursor position.
+    pub fn position(&self) -> u64 {
+        self.file.position()
+    }
+
+    /// Returns the file path.
+    pub fn path(&self) -> &str {
+        self.file.path()
+    }
+
+    /// Duplicates the handle with an independent cursor starting at the same
+    /// position, both handles share the tar so they can be used from
+    /// different tasks on different regions of the same entry.
+    ///
+    /// # Returns
+    /// * `IoResult<Self>` - The new handle, a stale handle error when the file was deleted or moved.
+    pub async fn try_clone(&self) -> IoResult<Self> {
+        let file = self.tar.lock().await.try_clone_file(&self.file)?;
+        Ok(Self::new(self.tar.clone(), file))
+    }
+
+    /// Reads from a logical position without moving the cursor, the handle
+    /// is borrowed immutably so concurrent operations can share it.
+    ///
+    /// # Arguments
+    /// * `buf` - The buffer to read into.
+    /// * `offset` - Logical position to read from.
+    ///
+    /// # Returns
+    /// * `IoResult<usize>` - The amount of bytes read, 0 at the end of the file.
+    pub async fn read_at(&self, buf: &mut [u8], offset: u64) -> IoResult<usize> {
+        self.tar.lock().await.read_at(&self.file, buf, offset)
+    }
+
+    /// Writes into a logical position without moving the cursor, the handle
+    /// is borrowed immutably so concurrent operations can share it.
+    ///
+    /// # Arguments
+    /// * `buf` - The buffer to write.
+    /// * `offset` - Logical position to write at.
+    ///
+    /// # Returns
+    /// * `IoResult<usize>` - The amount of bytes written.
+    pub async fn write_at(&self, buf: &[u8], offset: u64) -> IoResult<usize> {
+        let mut tar = self.tar.lock().await;
+        let mut cursor = self.file.clone();
+        cursor.pos = offset;
+        tar.inner_auto_partition(&mut cursor, buf.len() as u64)?;
+        tar.write_at(&self.file, buf, offset)
+    }
+
+    /// Acquires a shared advisory lock on the entry, waiting while other
+    /// handle holds an exclusive lock.
+    pub async fn lock_shared(&self) -> IoResult<()> {
+        self.wait_lock(|tar, file| tar.try_lock_shared(file)).await
+    }
+
+    /// Acquires an exclusive advisory lock on the entry, waiting while other
+    /// handles hold a lock.
+    pub async fn lock_exclusive(&self) -> IoResult<()> {
+        self.wait_lock(|tar, file| tar.try_lock_exclusive(file)).await
+    }
+
+    /// Releases the advisory lock held by this handle, it's also released
+    /// when the handle is dropped.
+    pub async fn unlock(&self) {
+        self.tar.lock().await.unlock(&self.file);
+    }
+
+    /// Locks a region of the file for writing, waiting while other handle
+    /// holds an overlapping region.
+    ///
+    /// # Arguments
+    /// * `offset` - Logical position where the region starts.
+    /// * `len` - Region length.
+    pub async fn lock_region(&self, offset: u64, len: u64) -> IoResult<()> {
+        self.wait_lock(|tar, file| tar.try_lock_region(file, offset, len)).await
+    }
+
+    /// Releases a region locked by this handle.
+    ///
+    /// # Arguments
+    /// * `offset` - Logical position where the region starts.
+    /// * `len` - Region length.
+    pub async fn unlock_region(&self, offset: u64, len: u64) {
+        self.tar.lock().await.unlock_region(&self.file, offset, len);
+    }
+
+    /// Retries a lock attempt every time a lock is released until it doesn't
+    /// block.
+    ///
+    /// # Arguments
+    /// * `try_lock` - Lock attempt.
+    async fn wait_lock(&self, try_lock: impl Fn(&mut Tar<T>, &SubFile) -> IoResult<()>) -> IoResult<()> {
+        loop {
+            let unlocked = self.tar.lock().await.unlocked.clone();
+
+            // register before trying so a release in between isn't missed
+            let mut notified = pin!(unlocked.notified());
+            notified.as_mut().enable();
+            match try_lock(&mut *self.tar.lock().await, &self.file) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => notified.await,
+                result => return result
+            }
+        }
+    }
+
+    /// Truncates or extends the file, the cursor isn't moved.
+    ///
+    /// # Arguments
+    /// * `len` - The new logical size.
+    pub async fn set_len(&mut self, len: u64) -> IoResult<()> {
+        self.tar.lock().await.set_len(&mut self.file, len)
+    }
+
+    /// Writes a reader content at the cursor through a bounded ring of
+    /// blocks, so a slow reader never makes the data pile up in memory.
+    ///
+    /// # Arguments
+    /// * `reader` - The reader to copy from.
+    /// * `options` - Ring settings.
+    ///
+    /// # Returns
+    /// * `IoResult<u64>` - The amount of bytes written.
+    pub async fn write_from<R: AsyncRead + Unpin>(&mut self, reader: &mut R, options: StreamOptions) -> IoResult<u64> {
+        copy_bounded(reader, self, options).await
+    }
+
+    /// Reads from the cursor into a writer through a bounded ring of blocks,
+    /// so a slow writer applies backpressure instead of buffering the file.
+    ///
+    /// # Arguments
+    /// * `writer` - The writer to copy into.
+    /// * `options` - Ring settings.
+    ///
+    /// # Returns
+    /// * `IoResult<u64>` - The amount of bytes read.
+    pub async fn read_into<W: AsyncWrite + Unpin>(&mut self, writer: &mut W, options: StreamOptions) -> IoResult<u64> {
+        copy_bounded(self, writer, options).await
+    }
+
+    /// Consumes the handle returning the inner sub file, its locks are kept
+    /// until the sub file is closed.
+    pub fn into_inner(mut self) -> SubFile {
+        let file = self.file.clone();
+
+        // handle 0 is never assigned so the drop releases nothing
+        self.file.handle = 0;
+        file
+    }
+
+    /// Polls the shared tar lock, the pending acquisition is kept across
+    /// polls so the waker is registered on the mutex queue.
+    fn poll_lock(&mut self, cx: &mut Context<'_>) -> Poll<OwnedMutexGuard<Tar<T>>> {
+        let tar = self.tar.clone();
+        let lock = self.lock.get_or_insert_with(|| Box::pin(tar.lock_owned()));
+        let guard = ready!(lock.as_mut().poll(cx));
+        self.lock = None;
+        Poll::Ready(guard)
+    }
+}
+
+impl<T: Read + Write + Seek + Send + 'static> Drop for AsyncSubFile<T> {
+    fn drop(&mut self) {
+        if self.file.handle == 0 {
+            return;
+        }
+        let file = self.file.clone();
+        if let Ok(mut tar) = self.tar.try_lock() {
+            tar.close_file(file);
+            return;
+        }
+
+        // the tar is busy, release from a task or block when outside a runtime
+        let tar = self.tar.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move { tar.lock().await.close_file(file) });
+            },
+            Err(_) => tar.blocking_lock().close_file(file)
+        }
+    }
+}
+
+impl<T: Read + Write + Seek + Send + 'static> AsyncRead for AsyncSubFile<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<IoResult<()>> {
+        let this = self.get_mut();
+        let mut tar = ready!(this.poll_lock(cx));
+        let read = tar.inner_read(&mut this.file, buf.initialize_unfilled())?;
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<T: Read + Write + Seek + Send + 'static> futures_io::AsyncRead for AsyncSubFile<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<IoResult<usize>> {
+        let this = self.get_mut();
+        let mut tar = ready!(this.poll_lock(cx));
+        Poll::Ready(tar.inner_read(&mut this.file, buf))
+    }
+}
+
+impl<T: Read + Write + Seek + Send + 'static> AsyncWrite for AsyncSubFile<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        let this = self.get_mut();
+        let mut tar = ready!(this.poll_lock(cx));
+        tar.inner_auto_partition(&mut this.file, buf.len() as u64)?;
+        Poll::Ready(tar.inner_write(&mut this.file, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let this = self.get_mut();
+        let mut tar = ready!(this.poll_lock(cx));
+        Poll::Ready(tar.flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl<T: Read + Write + Seek + Send + 'static> AsyncSeek for AsyncSubFile<T> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> IoResult<()> {
+        let this = self.get_mut();
+        if this.seek.is_some() {
+            return Err(IoError::other("other seek operation is pending"));
+        }
+        this.seek = Some(position);
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<u64>> {
+        let this = self.get_mut();
+        let position = match this.seek {
+            Some(v) => v,
+            None => return Poll::Ready(Ok(this.file.pos))
+        };
+        let tar = ready!(this.poll_lock(cx));
+        this.seek = None;
+        tar.validate(&this.file)?;
+        let pos = match position {
+            SeekFrom::Start(v) => v as i128,
+            SeekFrom::End(v) => tar.file_size(&this.file) as i128 + v as i128,
+            SeekFrom::Current(v) => this.file.pos as i128 + v as i128
+        };
+        if pos < 0 {
+            return Poll::Ready(Err(IoError::new(std::io::ErrorKind::InvalidInput, "invalid seek to a negative position")));
+        }
+        this.file.pos = pos as u64;
+        Poll::Ready(Ok(this.file.pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::index::FileMeta;
+    use std::io::Cursor;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn write_seek_read() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 16).unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let mut file = match AsyncSubFile::open(tar.clone(), "a.bin").await {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open file: {}", e);
+                return;
+            }
+        };
+        file.write_all(b"hello").await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(5, file.position());
+        assert_eq!(11, file.seek(SeekFrom::End(-5)).await.unwrap());
+        file.write_all(b"world").await.unwrap();
+
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(16, buf.len());
+        assert_eq!(b"hello", &buf[..5]);
+        assert_eq!(b"world", &buf[11..]);
+        assert!(file.seek(SeekFrom::Current(-100)).await.is_err());
+    }
+
+    #[cfg(feature = "futures-io")]
+    #[tokio::test]
+    async fn futures_io_read() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.txt", 5).unwrap();
+        tar.write(&mut file, b"hello").unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let file = AsyncSubFile::open(tar.clone(), "a.txt").await.unwrap();
+        let mut file = pin!(file);
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let mut buf = [0u8; 16];
+        let read = loop {
+            match futures_io::AsyncRead::poll_read(file.as_mut(), &mut cx, &mut buf) {
+                Poll::Ready(v) => break v.unwrap(),
+                Poll::Pending => tokio::task::yield_now().await
+            }
+        };
+        assert_eq!(b"hello", &buf[..read]);
+    }
+
+    #[tokio::test]
+    async fn read_across_partitions() {
+        let mut stream = vec![0u8; 1024];
+        stream[0..5].copy_from_slice(b"hello");
+        stream[512..518].copy_from_slice(b" world");
+        let mut tar = Tar::new(Cursor::new(stream));
+        tar.index.append(FileMeta { offset: 0, path: "a.part1".to_string(), parted: true, size: 5 }, 0, 2).unwrap();
+        tar.index.append(FileMeta { offset: 512, path: "a.part2".to_string(), parted: true, size: 6 }, 1, 0).unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let mut file = AsyncSubFile::open(tar.clone(), "a.part1").await.unwrap();
+        let mut buf = String::new();
+        file.read_to_string(&mut buf).await.unwrap();
+        assert_eq!("hello world", buf);
+
+        // concurrent handles share the tar stream through the lock
+        let mut other = AsyncSubFile::open(tar, "a.part1").await.unwrap();
+        other.seek(SeekFrom::Start(6)).await.unwrap();
+        let mut buf = [0u8; 5];
+        other.read_exact(&mut buf).await.unwrap();
+        assert_eq!(b"world", &buf);
+    }
+
+    #[tokio::test]
+    async fn stale_handle() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 10).unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let mut file = AsyncSubFile::open(tar.clone(), "a.bin").await.unwrap();
+        tar.lock().await.delete_file("a.bin").unwrap();
+        let mut buf = [0u8; 4];
+        match file.read(&mut buf).await {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(std::io::ErrorKind::StaleNetworkFileHandle, e.kind())
+        }
+    }
+
+    #[tokio::test]
+    async fn try_clone_concurrent() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 8).unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let mut writer = AsyncSubFile::open(tar.clone(), "a.bin").await.unwrap();
+        writer.write_all(b"head").await.unwrap();
+        let mut reader = match writer.try_clone().await {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to clone file: {}", e);
+                return;
+            }
+        };
+        reader.seek(SeekFrom::Start(0)).await.unwrap();
+
+        // one task reads the head while the other writes the tail
+        let read = tokio::spawn(async move {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf).await.map(|_| buf)
+        });
+        writer.write_all(b"tail").await.unwrap();
+        assert_eq!(b"head", &read.await.unwrap().unwrap());
+        assert_eq!(8, writer.position());
+    }
+
+    #[tokio::test]
+    async fn positional_io_shared() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 8).unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let file = AsyncSubFile::open(tar, "a.bin").await.unwrap();
+
+        // both writes borrow the same handle concurrently
+        let (head, tail) = tokio::join!(file.write_at(b"head", 0), file.write_at(b"tail", 4));
+        assert_eq!(4, head.unwrap());
+        assert_eq!(4, tail.unwrap());
+        let mut buf = [0u8; 8];
+        assert_eq!(8, file.read_at(&mut buf, 0).await.unwrap());
+        assert_eq!(b"headtail", &buf);
+        assert_eq!(0, file.position());
+    }
+
+    #[tokio::test]
+    async fn wait_for_lock() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 8).unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let file = AsyncSubFile::open(tar.clone(), "a.bin").await.unwrap();
+        let other = AsyncSubFile::open(tar, "a.bin").await.unwrap();
+        match file.lock_exclusive().await {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to lock: {}", e);
+                return;
+            }
+        }
+
+        // the shared lock waits until the exclusive one is released
+        let mut waiting = tokio_test::task::spawn(other.lock_shared());
+        tokio_test::assert_pending!(waiting.poll());
+        file.unlock().await;
+        assert!(waiting.is_woken());
+        tokio_test::assert_ready_ok!(waiting.poll());
+        drop(waiting);
+        assert!(file.lock_shared().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn drop_releases_locks() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 8).unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let file = AsyncSubFile::open(tar.clone(), "a.bin").await.unwrap();
+        let other = AsyncSubFile::open(tar.clone(), "a.bin").await.unwrap();
+        file.lock_exclusive().await.unwrap();
+        file.lock_region(0, 4).await.unwrap();
+
+        // the waiting lock is granted once the holder is dropped
+        let mut waiting = tokio_test::task::spawn(other.lock_exclusive());
+        tokio_test::assert_pending!(waiting.poll());
+        drop(file);
+        assert!(waiting.is_woken());
+        tokio_test::assert_ready_ok!(waiting.poll());
+        drop(waiting);
+        match other.write_at(b"head", 0).await {
+            Ok(v) => assert_eq!(4, v),
+            Err(e) => {
+                assert!(false, "Failed to write: {}", e);
+                return;
+            }
+        }
+
+        // the busy tar releases the locks from a task
+        let guard = tar.clone().lock_owned().await;
+        drop(other);
+        drop(guard);
+        let last = AsyncSubFile::open(tar, "a.bin").await.unwrap();
+        tokio::task::yield_now().await;
+        assert!(last.lock_exclusive().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn bounded_stream() {
+        let data: Vec<u8> = (0..3000u32).map(|v| v as u8).collect();
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", data.len() as u64).unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let mut file = AsyncSubFile::open(tar, "a.bin").await.unwrap();
+        let options = StreamOptions { block_size: 256, max_in_flight: 2 };
+        match file.write_from(&mut Cursor::new(data.clone()), options).await {
+            Ok(v) => assert_eq!(data.len() as u64, v),
+            Err(e) => {
+                assert!(false, "Failed to stream into the file: {}", e);
+                return;
+            }
+        }
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(data.len() as u64, file.read_into(&mut buf, options).await.unwrap());
+        assert_eq!(data, buf);
+    }
+}
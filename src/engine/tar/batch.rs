@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind, Read, Seek, Write};
+use std::io::Result as IoResult;
+
+use super::{Tar, BLOCK_SIZE};
+use crate::engine::archive::{is_whiteout, normalize_path, padded_size, whiteout_targets, Archive, Entry, EntryKind};
+use crate::engine::error::to_io_error;
+
+/// Operation queued on a batch.
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    /// Appends a new file with its content.
+    Append(String, Vec<u8>),
+    /// Deletes a file.
+    Delete(String),
+    /// Renames a file.
+    Rename(String, String),
+}
+
+/// Appends, deletes and renames applied together by `Tar::apply_batch`, the
+/// files are written back to back and the tar is closed and flushed once.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Batch {
+    /// Queued operations in order.
+    ops: Vec<Op>,
+}
+
+impl Batch {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a new file.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the file to append.
+    /// * `data` - The file content.
+    pub fn append(&mut self, path: &str, data: impl Into<Vec<u8>>) -> &mut Self {
+        self.ops.push(Op::Append(path.to_string(), data.into()));
+        self
+    }
+
+    /// Queues a file deletion.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the file to delete.
+    pub fn delete(&mut self, path: &str) -> &mut Self {
+        self.ops.push(Op::Delete(path.to_string()));
+        self
+    }
+
+    /// Queues a file rename.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the file to rename.
+    /// * `new_path` - The new path of the file.
+    pub fn rename(&mut self, path: &str, new_path: &str) -> &mut Self {
+        self.ops.push(Op::Rename(path.to_string(), new_path.to_string()));
+        self
+    }
+
+    /// Gets the amount of queued operations.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Tells whether no operation is queued.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Replays the operations over the paths existence so conflicts are
+    /// found before anything is written.
+    ///
+    /// # Arguments
+    /// * `exists` - Tells whether a path exists before the batch.
+    fn validate(&self, exists: impl Fn(&str) -> bool) -> IoResult<()> {
+        let mut changed: HashMap<&str, bool> = HashMap::new();
+        let is_present = |changed: &HashMap<&str, bool>, path: &str| match changed.get(path) {
+            Some(v) => *v,
+            None => exists(path)
+        };
+        for op in self.ops.iter() {
+            match op {
+                Op::Append(path, _) => {
+                    if is_present(&changed, path) {
+                        return Err(already_exists(path));
+                    }
+                    changed.insert(path, true);
+                },
+                Op::Delete(path) => {
+                    if !is_present(&changed, path) {
+                        return Err(not_found(path));
+                    }
+                    changed.insert(path, false);
+                },
+                Op::Rename(path, new_path) => {
+                    if !is_present(&changed, path) {
+                        return Err(not_found(path));
+                    }
+                    if is_present(&changed, new_path) {
+                        return Err(already_exists(new_path));
+                    }
+                    changed.insert(path, false);
+                    changed.insert(new_path, true);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read + Write + Seek> Tar<T> {
+    /// Applies a batch in order. The operations are checked up front so a
+    /// conflicting batch changes nothing, then the new files are written
+    /// back to back, the tar is closed and flushed once and only then the
+    /// index is updated and flushed, so the stored index never points at
+    /// unwritten content. Handles opened before the batch to deleted, renamed or moved
+    /// files become stale.
+    ///
+    /// # Arguments
+    /// * `batch` - The operations to apply.
+    ///
+    /// # Returns
+    /// * `IoResult<()>` - A not found or already exists error when the batch conflicts with the files.
+    pub fn apply_batch(&mut self, batch: Batch) -> IoResult<()> {
+        batch.validate(|path| self.index.get(path).is_some())?;
+
+        // write the new files content
+        let mut offset = self.data_end();
+        let mut written = Vec::new();
+        for op in batch.ops.iter() {
+            if let Op::Append(path, data) = op {
+                let (data_offset, meta) = self.inner_write_header(offset, path, data.len() as u64)?;
+                self.stream.write_all(data)?;
+                Self::pad_zeroes(&mut self.stream, meta.size)?;
+                offset = data_offset + padded_size(meta.size);
+                written.push((meta, data_offset));
+            }
+        }
+        if !written.is_empty() {
+            self.stream.write_all(&[0u8; 2 * BLOCK_SIZE as usize])?;
+            self.need_flush = true;
+            self.inner_flush()?;
+        }
+
+        // update the index once the content is flushed
+        let mut written = written.into_iter();
+        for op in batch.ops {
+            match op {
+                Op::Append(..) => {
+                    if let Some((meta, data_offset)) = written.next() {
+                        self.inner_register_file(meta, data_offset)?;
+                    }
+                },
+                Op::Delete(path) => self.delete_file(&path)?,
+                Op::Rename(path, new_path) => self.rename_file(&path, &new_path)?
+            }
+        }
+        self.inner_flush_index(usize::MAX)?;
+        Ok(())
+    }
+}
+
+impl<T: Read + Write + Seek> Tar<T> {
+    /// Applies a delta archive in place through the index: regular entries
+    /// add or overwrite files while whiteout entries (`.wh.<name>`) delete
+    /// them and opaque markers (`.wh..wh..opq`) clear their directory.
+    /// Whiteouts are applied before any addition so they only affect the
+    /// previous content. The changes go through a single batch so the index
+    /// is updated once, other entry kinds are skipped as only files are stored.
+    ///
+    /// # Arguments
+    /// * `delta` - The delta archive to apply.
+    ///
+    /// # Returns
+    /// * `IoResult<()>` - An error when the delta can't be read or the tar written.
+    pub fn apply<D: Read + Seek>(&mut self, delta: &mut Archive<D>) -> IoResult<()> {
+        let entries: Vec<Entry> = delta.entries().cloned().collect();
+        let mut present: Vec<String> = self.index.iter()
+            .filter(|entry| entry.prev_part == 0)
+            .map(|entry| entry.meta.path.clone())
+            .collect();
+        let mut batch = Batch::new();
+
+        // apply whiteouts
+        let removed = whiteout_targets(&entries, present.iter());
+        for path in removed.iter() {
+            batch.delete(path);
+        }
+        present.retain(|path| !removed.contains(path));
+
+        // apply additions and overwrites
+        for entry in entries {
+            if entry.meta.kind != EntryKind::RegularFile || is_whiteout(&entry.meta.path) {
+                continue;
+            }
+            let path = normalize_path(&entry.meta.path);
+            present.retain(|k| {
+                if normalize_path(k) != path {
+                    return true;
+                }
+                batch.delete(k);
+                false
+            });
+            let mut data = Vec::with_capacity(entry.meta.size as usize);
+            delta.entry_reader(&entry.meta.path).map_err(to_io_error)?.read_to_end(&mut data)?;
+            batch.append(&entry.meta.path, data);
+            present.push(entry.meta.path.clone());
+        }
+        self.apply_batch(batch)
+    }
+}
+
+/// Builds the error of a missing file.
+fn not_found(path: &str) -> IoError {
+    IoError::new(ErrorKind::NotFound, format!("file '{}' not found", path))
+}
+
+/// Builds the error of a file that already exists.
+fn already_exists(path: &str) -> IoError {
+    IoError::new(ErrorKind::AlreadyExists, format!("file '{}' already exists", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::Metadata;
+    use std::io::Cursor;
+
+    #[test]
+    fn apply_batch() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 10).unwrap();
+        let mut batch = Batch::new();
+        batch.append("b.bin", b"bee".as_slice())
+            .append("c.bin", b"sea".as_slice())
+            .delete("a.bin")
+            .rename("b.bin", "d.bin");
+        assert_eq!(4, batch.len());
+        if let Err(e) = tar.apply_batch(batch) {
+            assert!(false, "Failed to apply batch: {}", e);
+            return;
+        }
+        assert!(tar.open_file("a.bin").is_err());
+        assert!(tar.open_file("b.bin").is_err());
+        let mut buf = [0u8; 3];
+        let mut file = tar.open_file("d.bin").unwrap();
+        assert_eq!(3, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"bee", &buf);
+        let mut file = tar.open_file("c.bin").unwrap();
+        assert_eq!(3, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"sea", &buf);
+        assert_eq!(0, tar.index.dirty_len());
+
+        // a conflicting batch changes nothing
+        let mut batch = Batch::new();
+        batch.append("e.bin", b"new".as_slice()).delete("missing");
+        match tar.apply_batch(batch) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(ErrorKind::NotFound, e.kind())
+        }
+        assert!(tar.open_file("e.bin").is_err());
+        let mut batch = Batch::new();
+        batch.rename("c.bin", "d.bin");
+        match tar.apply_batch(batch) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(ErrorKind::AlreadyExists, e.kind())
+        }
+    }
+
+    #[test]
+    fn reopen_after_batch() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 10).unwrap();
+        let mut batch = Batch::new();
+        batch.append("b.bin", b"bee".as_slice()).delete("a.bin").rename("b.bin", "c.bin");
+        tar.apply_batch(batch).unwrap();
+        let mut bytes = Vec::new();
+        tar.stream.seek(std::io::SeekFrom::Start(0)).unwrap();
+        tar.stream.read_to_end(&mut bytes).unwrap();
+        drop(tar);
+
+        // the index was flushed by the batch, no close needed
+        let mut tar = match Tar::open(Cursor::new(bytes)) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open tar: {}", e);
+                return;
+            }
+        };
+        assert!(tar.open_file("a.bin").is_err());
+        assert!(tar.open_file("b.bin").is_err());
+        let mut file = tar.open_file("c.bin").unwrap();
+        let mut buf = [0u8; 3];
+        assert_eq!(3, tar.read(&mut file, &mut buf).unwrap());
+        assert_eq!(b"bee", &buf);
+    }
+
+    #[test]
+    fn apply_delta() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut batch = Batch::new();
+        for path in ["keep.txt", "change.txt", "gone.txt", "dir/a.txt", "dir/b.txt"] {
+            batch.append(path, path.as_bytes());
+        }
+        tar.apply_batch(batch).unwrap();
+        let mut delta = Archive::open(Cursor::new(Vec::new())).unwrap();
+        for (path, content) in [
+            ("change.txt", b"new".as_slice()),
+            (".wh.gone.txt", b"".as_slice()),
+            ("dir/.wh..wh..opq", b"".as_slice()),
+            ("dir/c.txt", b"c".as_slice())
+        ] {
+            delta.append_data(path, Metadata::new(path, EntryKind::RegularFile), content).unwrap();
+        }
+        if let Err(e) = tar.apply(&mut delta) {
+            assert!(false, "Failed to apply delta: {}", e);
+            return;
+        }
+        for path in ["gone.txt", "dir/a.txt", "dir/b.txt"] {
+            assert!(tar.open_file(path).is_err());
+        }
+        for (path, expected) in [("keep.txt", b"keep.txt".as_slice()), ("change.txt", b"new"), ("dir/c.txt", b"c")] {
+            let mut file = tar.open_file(path).unwrap();
+            let mut buf = vec![0u8; expected.len()];
+            assert_eq!(expected.len(), tar.read(&mut file, &mut buf).unwrap());
+            assert_eq!(expected, buf.as_slice());
+        }
+    }
+}
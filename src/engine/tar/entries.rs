@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::io::{Read, Seek, Write, Error as IoError, ErrorKind};
+use std::io::Result as IoResult;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::engine::archive::Metadata;
+use crate::engine::error::to_io_error;
+use crate::format::{set_checksum, BLOCK_SIZE};
+use super::{AsyncSubFile, Tar};
+
+/// Largest value of an 11 digit octal field.
+const MAX_OCTAL_11: u64 = 0o77777777777;
+
+/// Largest value of a 7 digit octal field.
+const MAX_OCTAL_7: u64 = 0o7777777;
+
+/// Entry ready to be appended by an async-tar or tokio-tar builder, its
+/// header is a plain USTAR block those crates load with
+/// `Header::from_byte_slice` and its data is an async reader:
+///
+/// `builder.append_data(&mut Header::from_byte_slice(&entry.header).clone(), entry.path(), entry.data)`
+///
+/// async-tar reads the data through `futures-io`, enable the `futures-io`
+/// feature for it.
+pub struct BuilderEntry<T: Read + Write + Seek + Send + 'static> {
+    /// USTAR header block. Paths that don't fit are left empty for the
+    /// builder to store from `path()`, numbers too large for their octal
+    /// field use the GNU base-256 form.
+    pub header: [u8; 512],
+    /// Entry content with its cursor at the start.
+    pub data: AsyncSubFile<T>,
+}
+
+impl<T: Read + Write + Seek + Send + 'static> BuilderEntry<T> {
+    /// Returns the entry path.
+    pub fn path(&self) -> &str {
+        self.data.path()
+    }
+}
+
+/// Entries of a shared tar in index order, so services built around
+/// async-tar or tokio-tar builders can source their data from an rtar
+/// archive. Paths are captured when created, entries deleted afterwards are
+/// skipped.
+pub struct BuilderEntries<T: Read + Write + Seek + Send + 'static> {
+    /// Shared tar the entries belong to.
+    tar: Arc<Mutex<Tar<T>>>,
+    /// Paths of the entries left.
+    paths: VecDeque<String>,
+}
+
+impl<T: Read + Write + Seek + Send + 'static> BuilderEntries<T> {
+    /// Captures the entry paths of a shared tar, partitions are seen as part
+    /// of their first partition entry.
+    ///
+    /// # Arguments
+    /// * `tar` - Shared tar to list the entries from.
+    pub async fn new(tar: Arc<Mutex<Tar<T>>>) -> Self {
+        let paths = tar.lock().await.index.iter()
+            .filter(|entry| entry.prev_part < 1)
+            .map(|entry| entry.meta.path.clone())
+            .collect();
+        Self {
+            tar,
+            paths
+        }
+    }
+
+    /// Opens the next entry, like `tokio::fs::ReadDir::next_entry`.
+    ///
+    /// # Returns
+    /// * `IoResult<Option<BuilderEntry<T>>>` - The next entry, `None` once all entries were returned.
+    pub async fn next_entry(&mut self) -> IoResult<Option<BuilderEntry<T>>> {
+        while let Some(path) = self.paths.pop_front() {
+            let file = match self.tar.lock().await.open_file(&path) {
+                Ok(v) => v,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e)
+            };
+            let header = builder_header(file.metadata().header())?;
+            return Ok(Some(BuilderEntry {
+                header,
+                data: AsyncSubFile::new(self.tar.clone(), file)
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Returns the amount of entries left.
+    pub fn remaining(&self) -> usize {
+        self.paths.len()
+    }
+}
+
+/// Builds the header block handed to builders, failing when a field
+/// only a PAX extended header could hold is set.
+///
+/// # Arguments
+/// * `meta` - Entry metadata.
+///
+/// # Returns
+/// * `Ok([u8; 512])` - The header block.
+/// * `Err(e)` - If the link target or owner names don't fit the block.
+fn builder_header(meta: &Metadata) -> IoResult<[u8; BLOCK_SIZE]> {
+    if meta.linkname.len() > 100 || meta.uname.len() > 32 || meta.gname.len() > 32 {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!("'{}' has a link target or owner name too long for a builder header", meta.path)
+        ));
+    }
+    let mut ustar = meta.build_ustar();
+    let stored = match ustar.prefix.is_empty() {
+        true => ustar.name.clone(),
+        false => format!("{}/{}", ustar.prefix, ustar.name)
+    };
+    if stored != meta.path {
+        ustar.name.clear();
+        ustar.prefix.clear();
+    }
+    let mut buf = Vec::with_capacity(BLOCK_SIZE);
+    if let Err(e) = ustar.save(&mut buf) {
+        return Err(to_io_error(e));
+    }
+    let mut block = [0u8; BLOCK_SIZE];
+    block.copy_from_slice(&buf[..BLOCK_SIZE]);
+    if meta.uid > MAX_OCTAL_7 {
+        put_base256(&mut block[108..116], meta.uid);
+    }
+    if meta.gid > MAX_OCTAL_7 {
+        put_base256(&mut block[116..124], meta.gid);
+    }
+    if meta.size > MAX_OCTAL_11 {
+        put_base256(&mut block[124..136], meta.size);
+    }
+    if meta.mtime > MAX_OCTAL_11 {
+        put_base256(&mut block[136..148], meta.mtime);
+    }
+    set_checksum(&mut block);
+    Ok(block)
+}
+
+/// Writes a number on the GNU base-256 form, the leading byte flags it and
+/// the value is stored big endian on the remaining bytes.
+///
+/// # Arguments
+/// * `dst` - Numeric field.
+/// * `value` - Value to write.
+fn put_base256(dst: &mut [u8], value: u64) {
+    dst.fill(0);
+    dst[0] = 0x80;
+    let bytes = value.to_be_bytes();
+    let len = bytes.len().min(dst.len() - 1);
+    let start = dst.len() - len;
+    dst[start..].copy_from_slice(&bytes[bytes.len() - len..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::archive::EntryKind;
+    use crate::format::RawHeader;
+    use std::io::Cursor;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn builder_entries() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        let mut file = tar.create_with_size("a.txt", 5).unwrap();
+        tar.write(&mut file, b"hello").unwrap();
+        tar.create_with_size("b.txt", 0).unwrap();
+        tar.create_with_size("c.txt", 1).unwrap();
+        tar.delete_file("c.txt").unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let mut entries = BuilderEntries::new(tar.clone()).await;
+        assert_eq!(2, entries.remaining());
+        tar.lock().await.delete_file("b.txt").unwrap();
+
+        let mut entry = match entries.next_entry().await {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                assert!(false, "expected an entry");
+                return;
+            },
+            Err(e) => {
+                assert!(false, "Failed to get entry: {}", e);
+                return;
+            }
+        };
+        assert_eq!("a.txt", entry.path());
+        let header = RawHeader::decode(&entry.header).unwrap();
+        assert!(header.is_ustar());
+        assert_eq!(5, header.size);
+        assert_eq!(b"a.txt", header.name.as_slice());
+        let mut buf = Vec::new();
+        entry.data.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(b"hello", buf.as_slice());
+
+        // deleted entries are skipped
+        assert!(entries.next_entry().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn builder_header_extensions() {
+        let path = format!("{}/a.txt", "d".repeat(300));
+        let mut meta = Metadata::new(&path, EntryKind::RegularFile);
+        meta.size = 9 * 1024 * 1024 * 1024;
+        let block = match builder_header(&meta) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to build header: {}", e);
+                return;
+            }
+        };
+        assert_eq!([0u8; 100], block[0..100]);
+        assert_eq!(0x80, block[124]);
+        assert_eq!(meta.size.to_be_bytes(), block[128..136]);
+
+        meta.linkname = "l".repeat(101);
+        match builder_header(&meta) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(ErrorKind::InvalidInput, e.kind())
+        }
+    }
+}
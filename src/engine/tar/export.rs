@@ -0,0 +1,149 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::Result as IoResult;
+
+use super::{Tar, BLOCK_SIZE};
+use crate::engine::archive::{padded_size, EntryKind, Metadata};
+use crate::engine::error::to_io_error;
+use crate::engine::index::{ExportTrailer, EXPORT_PATH};
+
+impl<T: Read + Write + Seek> Tar<T> {
+    /// Enables or disables the cold archive mode. While enabled the index
+    /// isn't flushed into its pages, instead the whole index is exported on
+    /// close as a final `.rhindex` entry located by a trailer block, so read
+    /// only archives skip the interleaved index pages. Meant to be enabled
+    /// before any file is written.
+    ///
+    /// # Arguments
+    /// * `enabled`: Whether the index is exported on close.
+    pub fn set_export_index(&mut self, enabled: bool) {
+        self.export_index = enabled;
+    }
+
+    /// Tells whether the index is exported on close.
+    pub fn is_export_index(&self) -> bool {
+        self.export_index
+    }
+
+    /// Writes the whole index as a `.rhindex` entry past the data end,
+    /// followed by the end of archive marker and the trailer block locating
+    /// it. The trailer is always the last stream block so a previous export
+    /// left further away is overwritten. The exported index and the content
+    /// it points at are kept until the next export replaces the trailer.
+    ///
+    /// # Returns
+    /// * `IoResult<u64>`: The exported index content offset.
+    pub fn export_index(&mut self) -> IoResult<u64> {
+        let data = self.index.export();
+        let offset = self.data_end();
+        self.move_to(offset)?;
+        let mut meta = Metadata::new(EXPORT_PATH, EntryKind::RegularFile);
+        meta.size = data.len() as u64;
+        let header_size = meta.save_headers(&mut self.stream).map_err(to_io_error)?;
+        self.stream.write_all(&data)?;
+        Self::pad_zeroes(&mut self.stream, meta.size)?;
+        self.stream.write_all(&[0u8; 2 * BLOCK_SIZE as usize])?;
+
+        // zero any stale data up to the trailer position
+        let pos = self.stream.stream_position()?;
+        let len = self.stream.seek(SeekFrom::End(0))?;
+        let trailer_pos = pos.max(len.saturating_sub(BLOCK_SIZE));
+        self.stream.seek(SeekFrom::Start(pos))?;
+        let zeroes = [0u8; BLOCK_SIZE as usize];
+        let mut remaining = trailer_pos - pos;
+        while remaining > 0 {
+            let n = remaining.min(BLOCK_SIZE);
+            self.stream.write_all(&zeroes[..n as usize])?;
+            remaining -= n;
+        }
+
+        let trailer = ExportTrailer { offset: offset + header_size, len: meta.size };
+        self.stream.write_all(&trailer.encode())?;
+        self.need_flush = true;
+        self.inner_flush()?;
+        self.retired_end = trailer.offset + padded_size(trailer.len);
+        Ok(trailer.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::index::Index;
+    use std::io::Cursor;
+
+    #[test]
+    fn export_index_on_close() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.set_export_index(true);
+        tar.create_with_size("a.bin", 10).unwrap();
+        tar.create_with_size("b.bin", 600).unwrap();
+        tar.flush().unwrap();
+        let first = match tar.export_index() {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to export index: {}", e);
+                return;
+            }
+        };
+
+        // a second export after a delete is written past the first one and replaces the trailer
+        tar.delete_file("b.bin").unwrap();
+        tar.flush().unwrap();
+        assert!(tar.retired_end > first);
+        let second = tar.export_index().unwrap();
+        assert!(second > first);
+        let index = match Index::open_exported(&mut tar.stream) {
+            Ok(Some((v, _))) => v,
+            Ok(None) => {
+                assert!(false, "expected an exported index");
+                return;
+            },
+            Err(e) => {
+                assert!(false, "Failed to open exported index: {}", e);
+                return;
+            }
+        };
+        assert_eq!(1, index.len());
+        assert!(index.get("a.bin").is_some());
+        assert!(index.get("b.bin").is_none());
+    }
+
+    #[test]
+    fn reopen_exported() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.set_export_index(true);
+        let mut file = tar.create_with_size("a.bin", 5).unwrap();
+        tar.write(&mut file, b"hello").unwrap();
+        tar.inner_close().unwrap();
+        let first = tar.retired_end;
+        let mut buf = Cursor::new(Vec::new());
+        tar.stream.seek(SeekFrom::Start(0)).unwrap();
+        std::io::copy(&mut tar.stream, &mut buf).unwrap();
+        drop(tar);
+
+        // the reopened tar keeps exporting and doesn't overwrite the exported index
+        let mut tar = match Tar::open(buf) {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to open tar: {}", e);
+                return;
+            }
+        };
+        assert!(tar.is_export_index());
+        assert_eq!(first, tar.data_end());
+        tar.create_with_size("b.bin", 10).unwrap();
+        tar.inner_close().unwrap();
+        let index = match Index::open_exported(&mut tar.stream) {
+            Ok(Some((v, _))) => v,
+            _ => {
+                assert!(false, "expected an exported index");
+                return;
+            }
+        };
+        assert_eq!(2, index.len());
+        let mut file = tar.open_file("a.bin").unwrap();
+        let mut out = [0u8; 5];
+        assert_eq!(5, tar.read(&mut file, &mut out).unwrap());
+        assert_eq!(b"hello", &out);
+    }
+}
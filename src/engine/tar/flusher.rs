@@ -0,0 +1,210 @@
+use std::io::{Read, Seek, Write, Error as IoError};
+use std::io::Result as IoResult;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+
+use super::Tar;
+
+/// Background index flushing settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlushOptions {
+    /// Dirty entries that wake the background task.
+    pub threshold: usize,
+    /// Max dirty entries, index updates wait for room beyond it.
+    pub max_dirty: usize,
+    /// Entries flushed per lock acquisition so foreground operations can
+    /// interleave with a large flush.
+    pub batch: usize,
+}
+
+impl Default for FlushOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 64,
+            max_dirty: 1024,
+            batch: 64
+        }
+    }
+}
+
+/// State shared between the flusher handle and its task.
+#[derive(Debug, Default)]
+struct Shared {
+    /// Wakes the background task.
+    wake: Notify,
+    /// Wakes the operations waiting for dirty set room.
+    drained: Notify,
+    /// Asks the background task to stop once the index is clean.
+    stop: AtomicBool,
+    /// Set when a background flush failed.
+    failed: AtomicBool,
+}
+
+/// Handle to a background task flushing the index of a shared tar, so index
+/// maintenance doesn't stall foreground writes. The dirty set is bounded by
+/// `FlushOptions::max_dirty`, index updates should call `reserve` first to
+/// wait for room when the task falls behind.
+pub struct IndexFlusher<T: Read + Write + Seek + Send + 'static> {
+    /// Shared tar whose index is flushed.
+    tar: Arc<Mutex<Tar<T>>>,
+    /// Flushing settings.
+    options: FlushOptions,
+    /// State shared with the task.
+    shared: Arc<Shared>,
+    /// Background task handle.
+    handle: Option<JoinHandle<IoResult<()>>>,
+}
+
+impl<T: Read + Write + Seek + Send + 'static> IndexFlusher<T> {
+    /// Spawns the background flushing task on the current tokio runtime.
+    ///
+    /// # Arguments
+    /// * `tar` - Shared tar whose index is flushed.
+    /// * `options` - Flushing settings.
+    pub fn spawn(tar: Arc<Mutex<Tar<T>>>, options: FlushOptions) -> Self {
+        let shared = Arc::new(Shared::default());
+        let handle = tokio::spawn(run(tar.clone(), shared.clone(), options.batch.max(1)));
+        Self {
+            tar,
+            options,
+            shared,
+            handle: Some(handle)
+        }
+    }
+
+    /// Returns the flushing settings.
+    pub fn options(&self) -> FlushOptions {
+        self.options
+    }
+
+    /// Waits until the dirty set has room for more index updates.
+    ///
+    /// # Returns
+    /// * `IoResult<()>` - An error when the background task failed.
+    pub async fn reserve(&self) -> IoResult<()> {
+        loop {
+            // register before checking so a flush finishing in between isn't missed
+            let mut drained = pin!(self.shared.drained.notified());
+            drained.as_mut().enable();
+            if self.shared.failed.load(Ordering::Acquire) {
+                return Err(IoError::other("background index flush failed"));
+            }
+            if self.tar.lock().await.index.dirty_len() < self.options.max_dirty {
+                return Ok(());
+            }
+            self.shared.wake.notify_one();
+            drained.await;
+        }
+    }
+
+    /// Tells the background task the index was updated, it's woken once the
+    /// dirty set reaches the threshold.
+    pub async fn notify(&self) {
+        if self.tar.lock().await.index.dirty_len() >= self.options.threshold {
+            self.shared.wake.notify_one();
+        }
+    }
+
+    /// Flushes the remaining dirty entries and stops the background task.
+    ///
+    /// # Returns
+    /// * `IoResult<()>` - An error when a flush failed.
+    pub async fn shutdown(mut self) -> IoResult<()> {
+        self.shared.stop.store(true, Ordering::Release);
+        self.shared.wake.notify_one();
+        match self.handle.take() {
+            Some(handle) => match handle.await {
+                Ok(result) => result,
+                Err(e) => Err(IoError::other(e))
+            },
+            None => Ok(())
+        }
+    }
+}
+
+impl<T: Read + Write + Seek + Send + 'static> Drop for IndexFlusher<T> {
+    fn drop(&mut self) {
+        // let the task flush what's left on its own
+        if self.handle.is_some() {
+            self.shared.stop.store(true, Ordering::Release);
+            self.shared.wake.notify_one();
+        }
+    }
+}
+
+/// Background flushing loop, flushes in batches releasing the tar lock
+/// between them.
+async fn run<T: Read + Write + Seek + Send + 'static>(tar: Arc<Mutex<Tar<T>>>, shared: Arc<Shared>, batch: usize) -> IoResult<()> {
+    loop {
+        shared.wake.notified().await;
+        loop {
+            let result = {
+                let mut tar = tar.lock().await;
+                tar.inner_flush_index(batch).map(|_| tar.index.dirty_len())
+            };
+            let remaining = match result {
+                Ok(v) => v,
+                Err(e) => {
+                    shared.failed.store(true, Ordering::Release);
+                    shared.drained.notify_waiters();
+                    return Err(e);
+                }
+            };
+            shared.drained.notify_waiters();
+            if remaining < 1 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        if shared.stop.load(Ordering::Acquire) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn flush_in_background() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        for i in 0..5 {
+            tar.create_with_size(&format!("{}.bin", i), 10).unwrap();
+        }
+        assert_eq!(5, tar.index.dirty_len());
+        let tar = Arc::new(Mutex::new(tar));
+        let options = FlushOptions { threshold: 2, max_dirty: 4, batch: 2 };
+        let flusher = IndexFlusher::spawn(tar.clone(), options);
+
+        // the dirty set is over its bound so reserving waits for the flush
+        match flusher.reserve().await {
+            Ok(_) => {},
+            Err(e) => {
+                assert!(false, "Failed to reserve: {}", e);
+                return;
+            }
+        }
+        assert!(tar.lock().await.index.dirty_len() < 4);
+        flusher.shutdown().await.unwrap();
+        assert_eq!(0, tar.lock().await.index.dirty_len());
+    }
+
+    #[tokio::test]
+    async fn notify_below_threshold() {
+        let mut tar = Tar::new(Cursor::new(Vec::new()));
+        tar.create_with_size("a.bin", 10).unwrap();
+        let tar = Arc::new(Mutex::new(tar));
+        let flusher = IndexFlusher::spawn(tar.clone(), FlushOptions::default());
+        flusher.notify().await;
+        flusher.reserve().await.unwrap();
+        tokio::task::yield_now().await;
+        assert_eq!(1, tar.lock().await.index.dirty_len());
+        flusher.shutdown().await.unwrap();
+        assert_eq!(0, tar.lock().await.index.dirty_len());
+    }
+}
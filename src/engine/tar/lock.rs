@@ -0,0 +1,146 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Error as IoError, ErrorKind};
+use std::io::Result as IoResult;
+
+/// Advisory lock held on an entry.
+#[derive(Debug, Clone, PartialEq)]
+enum EntryLock {
+    /// Handles sharing the lock.
+    Shared(HashSet<u64>),
+    /// Handle owning the lock.
+    Exclusive(u64),
+}
+
+/// Per entry advisory locks, cooperating components in the same process use
+/// them to coordinate access to individual entries. Locks aren't enforced on
+/// reads or writes.
+#[derive(Debug, Default)]
+pub(crate) struct LockTable {
+    /// Locks by entry path.
+    locks: HashMap<String, EntryLock>,
+    /// Last assigned handle ID.
+    last_handle: u64,
+}
+
+impl LockTable {
+    /// Assigns a new handle ID.
+    pub fn next_handle(&mut self) -> u64 {
+        self.last_handle += 1;
+        self.last_handle
+    }
+
+    /// Tries to acquire a shared lock, an exclusive lock held by the same
+    /// handle is downgraded.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `handle` - Handle ID.
+    ///
+    /// # Returns
+    /// * `IoResult<()>` - A would block error when other handle holds an exclusive lock.
+    pub fn try_lock_shared(&mut self, path: &str, handle: u64) -> IoResult<()> {
+        match self.locks.get_mut(path) {
+            Some(EntryLock::Shared(handles)) => {
+                handles.insert(handle);
+            },
+            Some(EntryLock::Exclusive(owner)) if *owner != handle => return Err(would_block(path)),
+            _ => {
+                self.locks.insert(path.to_string(), EntryLock::Shared(HashSet::from([handle])));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tries to acquire an exclusive lock, a shared lock held only by the
+    /// same handle is upgraded.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `handle` - Handle ID.
+    ///
+    /// # Returns
+    /// * `IoResult<()>` - A would block error when other handle holds a lock.
+    pub fn try_lock_exclusive(&mut self, path: &str, handle: u64) -> IoResult<()> {
+        match self.locks.get(path) {
+            Some(EntryLock::Shared(handles)) if handles.iter().any(|v| *v != handle) => return Err(would_block(path)),
+            Some(EntryLock::Exclusive(owner)) if *owner != handle => return Err(would_block(path)),
+            _ => {}
+        }
+        self.locks.insert(path.to_string(), EntryLock::Exclusive(handle));
+        Ok(())
+    }
+
+    /// Releases the lock held by a handle.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `handle` - Handle ID.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the handle was holding a lock.
+    pub fn unlock(&mut self, path: &str, handle: u64) -> bool {
+        let (released, empty) = match self.locks.get_mut(path) {
+            Some(EntryLock::Shared(handles)) => (handles.remove(&handle), handles.is_empty()),
+            Some(EntryLock::Exclusive(owner)) if *owner == handle => (true, true),
+            _ => (false, false)
+        };
+        if empty {
+            self.locks.remove(path);
+        }
+        released
+    }
+
+    /// Drops the locks of an entry.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    pub fn remove(&mut self, path: &str) {
+        self.locks.remove(path);
+    }
+
+    /// Moves the locks of an entry to its new path.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `new_path` - New entry path.
+    pub fn rename(&mut self, path: &str, new_path: &str) {
+        if let Some(lock) = self.locks.remove(path) {
+            self.locks.insert(new_path.to_string(), lock);
+        }
+    }
+}
+
+/// Builds the error returned when a lock is held by other handle.
+fn would_block(path: &str) -> IoError {
+    IoError::new(ErrorKind::WouldBlock, format!("entry '{}' is locked by other handle", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_and_exclusive() {
+        let mut table = LockTable::default();
+        let (a, b) = (table.next_handle(), table.next_handle());
+        assert_ne!(a, b);
+        table.try_lock_shared("a.bin", a).unwrap();
+        table.try_lock_shared("a.bin", b).unwrap();
+        match table.try_lock_exclusive("a.bin", a) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(ErrorKind::WouldBlock, e.kind())
+        }
+        assert!(table.unlock("a.bin", b));
+        assert!(!table.unlock("a.bin", b));
+
+        // the only shared holder upgrades and then blocks others
+        table.try_lock_exclusive("a.bin", a).unwrap();
+        assert!(table.try_lock_shared("a.bin", b).is_err());
+        assert!(table.try_lock_exclusive("b.bin", b).is_ok());
+        table.rename("a.bin", "c.bin");
+        assert!(table.try_lock_shared("a.bin", b).is_ok());
+        assert!(table.try_lock_shared("c.bin", b).is_err());
+        table.remove("c.bin");
+        assert!(table.try_lock_shared("c.bin", b).is_ok());
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind};
+use std::io::Result as IoResult;
+
+/// Byte range locked by a handle as `[start, end)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Region {
+    /// Handle owning the region.
+    handle: u64,
+    /// Region start.
+    start: u64,
+    /// Region end, exclusive.
+    end: u64,
+}
+
+/// Per entry write regions, lets several handles write the same entry at
+/// once as long as their regions don't overlap, and tracks the ranges
+/// written since the last flush.
+#[derive(Debug, Default)]
+pub(crate) struct RegionTable {
+    /// Locked regions by entry path.
+    locks: HashMap<String, Vec<Region>>,
+    /// Written ranges by entry path as `(start, end)`, sorted and merged.
+    dirty: HashMap<String, Vec<(u64, u64)>>,
+}
+
+impl RegionTable {
+    /// Tries to lock a region for a handle, regions held by the same handle
+    /// may overlap.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `handle` - Handle ID.
+    /// * `start` - Region start.
+    /// * `end` - Region end, exclusive.
+    ///
+    /// # Returns
+    /// * `IoResult<()>` - A would block error when other handle holds an overlapping region.
+    pub fn try_lock(&mut self, path: &str, handle: u64, start: u64, end: u64) -> IoResult<()> {
+        self.check(path, handle, start, end)?;
+        if start < end {
+            self.locks.entry(path.to_string()).or_default().push(Region { handle, start, end });
+        }
+        Ok(())
+    }
+
+    /// Checks no other handle holds a region overlapping the range.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `handle` - Handle ID.
+    /// * `start` - Range start.
+    /// * `end` - Range end, exclusive.
+    ///
+    /// # Returns
+    /// * `IoResult<()>` - A would block error when other handle holds an overlapping region.
+    pub fn check(&self, path: &str, handle: u64, start: u64, end: u64) -> IoResult<()> {
+        let overlaps = self.locks.get(path).is_some_and(|regions| regions.iter().any(|region| {
+            region.handle != handle && region.start < end && start < region.end
+        }));
+        if overlaps {
+            return Err(IoError::new(
+                ErrorKind::WouldBlock,
+                format!("region {}..{} of entry '{}' is locked by other handle", start, end, path)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Releases a region previously locked by a handle.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `handle` - Handle ID.
+    /// * `start` - Region start.
+    /// * `end` - Region end, exclusive.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the region was locked by the handle.
+    pub fn unlock(&mut self, path: &str, handle: u64, start: u64, end: u64) -> bool {
+        let regions = match self.locks.get_mut(path) {
+            Some(v) => v,
+            None => return false
+        };
+        let position = regions.iter().position(|region| *region == Region { handle, start, end });
+        if let Some(index) = position {
+            regions.swap_remove(index);
+        }
+        if regions.is_empty() {
+            self.locks.remove(path);
+        }
+        position.is_some()
+    }
+
+    /// Releases every region locked by a handle.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `handle` - Handle ID.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the handle was holding any region.
+    pub fn release(&mut self, path: &str, handle: u64) -> bool {
+        let regions = match self.locks.get_mut(path) {
+            Some(v) => v,
+            None => return false
+        };
+        let count = regions.len();
+        regions.retain(|region| region.handle != handle);
+        let released = regions.len() != count;
+        if regions.is_empty() {
+            self.locks.remove(path);
+        }
+        released
+    }
+
+    /// Records a written range merging it with the adjacent ones.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `start` - Range start.
+    /// * `end` - Range end, exclusive.
+    pub fn mark_dirty(&mut self, path: &str, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        let ranges = self.dirty.entry(path.to_string()).or_default();
+        let index = ranges.partition_point(|(v, _)| *v < start);
+        ranges.insert(index, (start, end));
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if last.1 >= start => last.1 = last.1.max(end),
+                _ => merged.push((start, end))
+            }
+        }
+        *ranges = merged;
+    }
+
+    /// Gets the ranges of an entry written since the last flush.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    pub fn dirty(&self, path: &str) -> &[(u64, u64)] {
+        self.dirty.get(path).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    /// Forgets the written ranges once they were flushed.
+    pub fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+
+    /// Drops the regions and written ranges of an entry.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    pub fn remove(&mut self, path: &str) {
+        self.locks.remove(path);
+        self.dirty.remove(path);
+    }
+
+    /// Moves the regions and written ranges of an entry to its new path.
+    ///
+    /// # Arguments
+    /// * `path` - Entry path.
+    /// * `new_path` - New entry path.
+    pub fn rename(&mut self, path: &str, new_path: &str) {
+        if let Some(regions) = self.locks.remove(path) {
+            self.locks.insert(new_path.to_string(), regions);
+        }
+        if let Some(ranges) = self.dirty.remove(path) {
+            self.dirty.insert(new_path.to_string(), ranges);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disjoint_regions() {
+        let mut table = RegionTable::default();
+        table.try_lock("a.bin", 1, 0, 10).unwrap();
+        table.try_lock("a.bin", 2, 10, 20).unwrap();
+        table.try_lock("a.bin", 1, 5, 8).unwrap();
+        match table.try_lock("a.bin", 2, 9, 11) {
+            Ok(_) => assert!(false, "expected error but got success"),
+            Err(e) => assert_eq!(ErrorKind::WouldBlock, e.kind())
+        }
+        assert!(table.check("b.bin", 2, 0, 10).is_ok());
+        assert!(table.unlock("a.bin", 1, 0, 10));
+        assert!(!table.unlock("a.bin", 1, 0, 10));
+        assert!(table.check("a.bin", 2, 0, 5).is_ok());
+        assert!(table.check("a.bin", 2, 5, 6).is_err());
+
+        table.mark_dirty("a.bin", 10, 20);
+        table.mark_dirty("a.bin", 0, 4);
+        table.mark_dirty("a.bin", 4, 10);
+        assert_eq!(&[(0, 20)], table.dirty("a.bin"));
+        table.rename("a.bin", "c.bin");
+        assert!(table.dirty("a.bin").is_empty());
+        assert!(table.check("c.bin", 2, 5, 6).is_err());
+        table.clear_dirty();
+        assert!(table.dirty("c.bin").is_empty());
+    }
+}
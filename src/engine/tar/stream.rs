@@ -0,0 +1,133 @@
+use std::io::Error as IoError;
+use std::io::Result as IoResult;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::engine::DEFAULT_BUFFER_SIZE;
+
+/// Bounded streaming settings, memory usage is capped to
+/// `block_size * max_in_flight` regardless of the source and sink speeds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamOptions {
+    /// Size of every block of the ring.
+    pub block_size: usize,
+    /// Blocks read ahead of the writer, the reader waits once all of them
+    /// are in flight.
+    pub max_in_flight: usize,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BUFFER_SIZE * 16,
+            max_in_flight: 4
+        }
+    }
+}
+
+/// Copies a reader into a writer through a fixed ring of blocks, reading
+/// and writing run concurrently so a slow side only stalls the other once
+/// every block is in flight.
+///
+/// # Arguments
+/// * `reader` - The reader to copy from.
+/// * `writer` - The writer to copy into, flushed at the end.
+/// * `options` - Ring settings.
+///
+/// # Returns
+/// * `IoResult<u64>` - The amount of bytes copied.
+pub async fn copy_bounded<R, W>(reader: &mut R, writer: &mut W, options: StreamOptions) -> IoResult<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin
+{
+    let block_size = options.block_size.max(1);
+    let in_flight = options.max_in_flight.max(1);
+    let (filled_tx, mut filled_rx) = mpsc::channel::<(Vec<u8>, usize)>(in_flight);
+    let (free_tx, mut free_rx) = mpsc::channel::<Vec<u8>>(in_flight);
+    for _ in 0..in_flight {
+        let _ = free_tx.try_send(vec![0u8; block_size]);
+    }
+
+    // fill free blocks until the reader ends, the writer ending early drops the free blocks sender
+    let read = async move {
+        while let Some(mut block) = free_rx.recv().await {
+            let mut len = 0;
+            while len < block.len() {
+                let n = reader.read(&mut block[len..]).await?;
+                if n < 1 {
+                    break;
+                }
+                len += n;
+            }
+            if len < 1 || filled_tx.send((block, len)).await.is_err() {
+                break;
+            }
+        }
+        Ok::<(), IoError>(())
+    };
+
+    // drain filled blocks returning them to the ring
+    let write = async move {
+        let mut total = 0u64;
+        while let Some((block, len)) = filled_rx.recv().await {
+            writer.write_all(&block[..len]).await?;
+            total += len as u64;
+            let _ = free_tx.send(block).await;
+        }
+        writer.flush().await?;
+        Ok::<u64, IoError>(total)
+    };
+    let (read, write) = tokio::join!(read, write);
+    read?;
+    write
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Writer accepting a few bytes per call to simulate a slow sink.
+    struct SlowWriter {
+        data: Vec<u8>,
+    }
+
+    impl AsyncWrite for SlowWriter {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+            let n = buf.len().min(7);
+            self.get_mut().data.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn copy_through_ring() {
+        let data: Vec<u8> = (0..10_000u32).map(|v| v as u8).collect();
+        let mut reader = Cursor::new(data.clone());
+        let mut writer = SlowWriter { data: Vec::new() };
+        let options = StreamOptions { block_size: 512, max_in_flight: 2 };
+        match copy_bounded(&mut reader, &mut writer, options).await {
+            Ok(v) => assert_eq!(10_000, v),
+            Err(e) => {
+                assert!(false, "Failed to copy: {}", e);
+                return;
+            }
+        }
+        assert_eq!(data, writer.data);
+
+        // empty readers copy nothing
+        let mut writer = SlowWriter { data: Vec::new() };
+        assert_eq!(0, copy_bounded(&mut tokio::io::empty(), &mut writer, options).await.unwrap());
+    }
+}
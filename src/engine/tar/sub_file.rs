@@ -0,0 +1,105 @@
+use crate::engine::archive::Metadata;
+use crate::engine::index::FileMeta;
+
+/// Represents an open file within the TAR, partitioned files are seen as a
+/// single logical file whose content spans every partition in the chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubFile {
+    /// Index position of the file first partition.
+    pub(crate) fake_id: usize,
+    /// First partition entry, its offset points to the partition content.
+    pub(crate) entry: FileMeta,
+    /// Logical cursor position across all partitions.
+    pub(crate) pos: u64,
+    /// Index entry generation at the time the file was opened.
+    pub(crate) generation: u64,
+    /// Handle ID used to track advisory locks.
+    pub(crate) handle: u64,
+    /// Header information captured when the file was opened.
+    pub(crate) metadata: SubFileMetadata,
+}
+
+/// Header information of an open sub file, captured when the file was opened
+/// so it's available without an index lookup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubFileMetadata {
+    /// Header information with the logical size across all partitions.
+    pub(crate) header: Metadata,
+    /// Whether the file is split into partitions.
+    pub(crate) parted: bool,
+}
+
+impl SubFileMetadata {
+    /// Returns the logical size across all partitions.
+    pub fn len(&self) -> u64 {
+        self.header.size
+    }
+
+    /// Tells whether the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.header.size < 1
+    }
+
+    /// Returns the file mode.
+    pub fn mode(&self) -> u32 {
+        self.header.mode
+    }
+
+    /// Returns the modification time (seconds since epoch).
+    pub fn mtime(&self) -> u64 {
+        self.header.mtime
+    }
+
+    /// Tells whether the file is split into partitions.
+    pub fn is_parted(&self) -> bool {
+        self.parted
+    }
+
+    /// Returns the full header information.
+    pub fn header(&self) -> &Metadata {
+        &self.header
+    }
+}
+
+impl SubFile {
+    /// Creates a new sub file with its cursor at the start.
+    ///
+    /// # Arguments
+    /// * `fake_id` - Index position of the file first partition.
+    /// * `entry` - First partition entry.
+    /// * `generation` - Index entry generation.
+    /// * `header` - File header information with its logical size.
+    /// * `handle` - Handle ID used to track advisory locks.
+    ///
+    /// # Returns
+    /// * `Self` - The created sub file.
+    pub(crate) fn new(fake_id: usize, entry: FileMeta, generation: u64, header: Metadata, handle: u64) -> Self {
+        let metadata = SubFileMetadata {
+            parted: entry.parted,
+            header
+        };
+        Self {
+            fake_id,
+            entry,
+            pos: 0,
+            generation,
+            handle,
+            metadata
+        }
+    }
+
+    /// Returns the logical cursor position.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Returns the file path.
+    pub fn path(&self) -> &str {
+        &self.entry.path
+    }
+
+    /// Returns the file header information, similar to `File::metadata`.
+    pub fn metadata(&self) -> &SubFileMetadata {
+        &self.metadata
+    }
+}
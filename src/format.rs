@@ -0,0 +1,454 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+/// TAR block size in bytes.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Errors found while parsing or serializing raw header blocks.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum FormatError {
+    /// A numeric field doesn't hold a valid octal number.
+    InvalidOctal,
+    /// A numeric value doesn't fit its field.
+    FieldOverflow { value: u64, digits: usize },
+    /// A text value doesn't fit its field.
+    FieldTooLong { len: usize, max: usize },
+    /// A PAX record at the given data offset is malformed.
+    InvalidRecord(usize),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::InvalidOctal => write!(f, "invalid octal number"),
+            FormatError::FieldOverflow { value, digits } => write!(f, "value too large for field: {:o} doesn't fit in {} octal digits", value, digits),
+            FormatError::FieldTooLong { len, max } => write!(f, "value too long for field: {} bytes, max {}", len, max),
+            FormatError::InvalidRecord(offset) => write!(f, "invalid PAX record at offset {}", offset)
+        }
+    }
+}
+
+impl core::error::Error for FormatError {}
+
+/// Gets the bytes of a nul terminated field.
+///
+/// # Arguments
+/// * `buf` - Raw field.
+pub fn field_bytes(buf: &[u8]) -> &[u8] {
+    let nul = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    &buf[..nul]
+}
+
+/// Writes a text field, nul padding the remaining space.
+///
+/// # Arguments
+/// * `dst` - Raw field.
+/// * `value` - The value to write.
+///
+/// # Returns
+/// * `Result<(), FormatError>` - An error when the value doesn't fit.
+pub fn put_field(dst: &mut [u8], value: &[u8]) -> Result<(), FormatError> {
+    if value.len() > dst.len() {
+        return Err(FormatError::FieldTooLong { len: value.len(), max: dst.len() });
+    }
+    dst[..value.len()].copy_from_slice(value);
+    dst[value.len()..].fill(0);
+    Ok(())
+}
+
+/// Parses an octal numeric field, nul and space padding is ignored and empty
+/// fields are zero.
+///
+/// # Arguments
+/// * `buf` - Raw field.
+///
+/// # Returns
+/// * `Result<u64, FormatError>` - The parsed number.
+pub fn parse_octal(buf: &[u8]) -> Result<u64, FormatError> {
+    let start = buf.iter().position(|&b| b != b' ' && b != 0).unwrap_or(buf.len());
+    let end = buf.iter().rposition(|&b| b != b' ' && b != 0).map_or(start, |i| i + 1);
+    let mut value: u64 = 0;
+    for &b in &buf[start..end] {
+        if !(b'0'..=b'7').contains(&b) {
+            return Err(FormatError::InvalidOctal);
+        }
+        value = match value.checked_mul(8) {
+            Some(v) => v + (b - b'0') as u64,
+            None => return Err(FormatError::InvalidOctal)
+        };
+    }
+    Ok(value)
+}
+
+/// Writes a zero padded, nul terminated octal numeric field.
+///
+/// # Arguments
+/// * `dst` - Raw field.
+/// * `value` - The value to write.
+///
+/// # Returns
+/// * `Result<(), FormatError>` - An error when the value doesn't fit.
+pub fn put_octal(dst: &mut [u8], value: u64) -> Result<(), FormatError> {
+    let digits = dst.len() - 1;
+    if digits < 22 && value >> (3 * digits) > 0 {
+        return Err(FormatError::FieldOverflow { value, digits });
+    }
+    let mut rest = value;
+    for b in dst[..digits].iter_mut().rev() {
+        *b = b'0' + (rest & 7) as u8;
+        rest >>= 3;
+    }
+    dst[digits] = 0;
+    Ok(())
+}
+
+/// Computes a header block checksum, the checksum field counts as spaces.
+///
+/// # Arguments
+/// * `block` - Raw header block.
+pub fn checksum(block: &[u8; BLOCK_SIZE]) -> u32 {
+    block.iter().enumerate()
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' as u32 } else { *b as u32 })
+        .sum()
+}
+
+/// Writes the checksum field of a header block.
+///
+/// # Arguments
+/// * `block` - Raw header block.
+pub fn set_checksum(block: &mut [u8; BLOCK_SIZE]) {
+    let mut value = checksum(block);
+    for b in block[148..154].iter_mut().rev() {
+        *b = b'0' + (value & 7) as u8;
+        value >>= 3;
+    }
+    block[154] = 0;
+    block[155] = b' ';
+}
+
+/// Fields of a raw ustar, GNU or v7 header block, text fields are kept as
+/// bytes so no encoding is assumed.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RawHeader {
+    pub name: Vec<u8>,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub mtime: u64,
+    pub chksum: u32,
+    pub typeflag: u8,
+    pub linkname: Vec<u8>,
+    pub magic: [u8; 6],
+    pub version: [u8; 2],
+    pub uname: Vec<u8>,
+    pub gname: Vec<u8>,
+    pub devmajor: u32,
+    pub devminor: u32,
+    pub prefix: Vec<u8>,
+}
+
+impl RawHeader {
+    /// Decodes a header block.
+    ///
+    /// # Arguments
+    /// * `block` - Raw header block.
+    ///
+    /// # Returns
+    /// * `Result<Self, FormatError>` - The decoded header.
+    pub fn decode(block: &[u8; BLOCK_SIZE]) -> Result<Self, FormatError> {
+        let number = |start: usize, end: usize| parse_octal(&block[start..end]).map(|v| v as u32);
+        let mut magic = [0u8; 6];
+        magic.copy_from_slice(&block[257..263]);
+        let mut version = [0u8; 2];
+        version.copy_from_slice(&block[263..265]);
+        Ok(Self {
+            name: field_bytes(&block[0..100]).to_vec(),
+            mode: number(100, 108)?,
+            uid: number(108, 116)?,
+            gid: number(116, 124)?,
+            size: parse_octal(&block[124..136])?,
+            mtime: parse_octal(&block[136..148])?,
+            chksum: number(148, 156)?,
+            typeflag: block[156],
+            linkname: field_bytes(&block[157..257]).to_vec(),
+            magic,
+            version,
+            uname: field_bytes(&block[265..297]).to_vec(),
+            gname: field_bytes(&block[297..329]).to_vec(),
+            devmajor: number(329, 337)?,
+            devminor: number(337, 345)?,
+            prefix: field_bytes(&block[345..500]).to_vec()
+        })
+    }
+
+    /// Encodes the header into a block computing its checksum.
+    ///
+    /// # Returns
+    /// * `Result<[u8; 512], FormatError>` - The encoded block.
+    pub fn encode(&self) -> Result<[u8; BLOCK_SIZE], FormatError> {
+        let mut block = [0u8; BLOCK_SIZE];
+        put_field(&mut block[0..100], &self.name)?;
+        put_octal(&mut block[100..108], self.mode as u64)?;
+        put_octal(&mut block[108..116], self.uid as u64)?;
+        put_octal(&mut block[116..124], self.gid as u64)?;
+        put_octal(&mut block[124..136], self.size)?;
+        put_octal(&mut block[136..148], self.mtime)?;
+        block[156] = self.typeflag;
+        put_field(&mut block[157..257], &self.linkname)?;
+        block[257..263].copy_from_slice(&self.magic);
+        block[263..265].copy_from_slice(&self.version);
+        put_field(&mut block[265..297], &self.uname)?;
+        put_field(&mut block[297..329], &self.gname)?;
+        put_octal(&mut block[329..337], self.devmajor as u64)?;
+        put_octal(&mut block[337..345], self.devminor as u64)?;
+        put_field(&mut block[345..500], &self.prefix)?;
+        set_checksum(&mut block);
+        Ok(block)
+    }
+
+    /// Checks for the POSIX ustar magic and version.
+    pub fn is_ustar(&self) -> bool {
+        &self.magic == b"ustar\0" && &self.version == b"00"
+    }
+
+    /// Checks for the GNU magic and version.
+    pub fn is_gnu(&self) -> bool {
+        &self.magic == b"ustar " && &self.version == b" \0"
+    }
+}
+
+/// Iterates the `"<len> <key>=<value>\n"` records of PAX extended data.
+pub struct PaxRecords<'a> {
+    /// Extended data.
+    data: &'a [u8],
+    /// Offset of the next record.
+    offset: usize,
+}
+
+impl<'a> PaxRecords<'a> {
+    /// Creates a record iterator, values may hold nul bytes and trailing nul
+    /// padding after the last record is ignored.
+    ///
+    /// # Arguments
+    /// * `data` - Extended data.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for PaxRecords<'a> {
+    type Item = Result<(&'a [u8], &'a [u8]), FormatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = &self.data[self.offset..];
+        if data.iter().all(|&b| b == 0) {
+            return None;
+        }
+        let start = self.offset;
+
+        // stop iterating after the first malformed record
+        self.offset = self.data.len();
+        let space = data.iter().position(|&b| b == b' ').unwrap_or(0);
+        let len = match parse_decimal(&data[..space]) {
+            Some(v) if v > space + 1 && v <= data.len() && data[v - 1] == b'\n' => v,
+            _ => return Some(Err(FormatError::InvalidRecord(start)))
+        };
+        let record = &data[space + 1..len - 1];
+        let eq = match record.iter().position(|&b| b == b'=') {
+            Some(v) if v > 0 => v,
+            _ => return Some(Err(FormatError::InvalidRecord(start)))
+        };
+        self.offset = start + len;
+        Some(Ok((&record[..eq], &record[eq + 1..])))
+    }
+}
+
+/// Parses an ASCII decimal number.
+fn parse_decimal(buf: &[u8]) -> Option<usize> {
+    if buf.is_empty() {
+        return None;
+    }
+    buf.iter().try_fold(0usize, |acc, &b| {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        acc.checked_mul(10)?.checked_add((b - b'0') as usize)
+    })
+}
+
+/// Appends a PAX record, the length prefix counts its own digits.
+///
+/// # Arguments
+/// * `out` - Extended data to append into.
+/// * `key` - Record keyword.
+/// * `value` - Record value.
+pub fn put_record(out: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    let base = key.len() + value.len() + 3;
+    let mut len = base + 1;
+    while base + digits(len) != len {
+        len = base + digits(len);
+    }
+    let mut number = [0u8; 20];
+    let n = digits(len);
+    let mut rest = len;
+    for b in number[..n].iter_mut().rev() {
+        *b = b'0' + (rest % 10) as u8;
+        rest /= 10;
+    }
+    out.extend_from_slice(&number[..n]);
+    out.push(b' ');
+    out.extend_from_slice(key);
+    out.push(b'=');
+    out.extend_from_slice(value);
+    out.push(b'\n');
+}
+
+/// Gets the name stored as the content of a GNU long name or long link
+/// entry, trailing nul padding is removed.
+///
+/// # Arguments
+/// * `data` - Entry content.
+pub fn long_name(data: &[u8]) -> &[u8] {
+    let end = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    &data[..end]
+}
+
+/// Parses GNU sparse map entries, each one an octal offset and size of 12
+/// bytes, entries with an empty field are skipped.
+///
+/// Old GNU headers hold 4 entries at `block[386..482]` and extension blocks
+/// hold 21 entries at `block[..504]`.
+///
+/// # Arguments
+/// * `buf` - Raw sparse entries.
+/// * `out` - List to append the `(offset, numbytes)` pairs into.
+///
+/// # Returns
+/// * `Result<(), FormatError>` - An error when a field isn't valid octal.
+pub fn parse_sparse(buf: &[u8], out: &mut Vec<(u64, u64)>) -> Result<(), FormatError> {
+    for entry in buf.chunks_exact(24) {
+        let (offset, numbytes) = entry.split_at(12);
+        if offset.iter().all(|&b| b == 0) || numbytes.iter().all(|&b| b == 0) {
+            continue;
+        }
+        out.push((parse_octal(offset)?, parse_octal(numbytes)?));
+    }
+    Ok(())
+}
+
+/// Counts the decimal digits of a number.
+fn digits(mut value: usize) -> usize {
+    let mut count = 1;
+    while value >= 10 {
+        value /= 10;
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn octal_fields() {
+        assert_eq!(Ok(0o755), parse_octal(b"0000755\0"));
+        assert_eq!(Ok(0o17), parse_octal(b"  17 \0"));
+        assert_eq!(Ok(0), parse_octal(b"\0\0\0\0"));
+        assert_eq!(Err(FormatError::InvalidOctal), parse_octal(b"0000855\0"));
+
+        let mut buf = [0u8; 8];
+        assert_eq!(Ok(()), put_octal(&mut buf, 0o644));
+        assert_eq!(b"0000644\0", &buf);
+        assert_eq!(Ok(()), put_octal(&mut buf, 0o7777777));
+        assert_eq!(Err(FormatError::FieldOverflow { value: 0o10000000, digits: 7 }), put_octal(&mut buf, 0o10000000));
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = RawHeader {
+            name: b"dir/file.txt".to_vec(),
+            mode: 0o644,
+            uid: 1000,
+            gid: 1000,
+            size: 1234,
+            mtime: 1_600_000_000,
+            typeflag: b'0',
+            magic: *b"ustar\0",
+            version: *b"00",
+            uname: b"user".to_vec(),
+            gname: b"group".to_vec(),
+            ..Default::default()
+        };
+        let block = match header.encode() {
+            Ok(v) => v,
+            Err(e) => {
+                assert!(false, "Failed to encode: {}", e);
+                return;
+            }
+        };
+        let decoded = RawHeader::decode(&block).unwrap();
+        assert_eq!(checksum(&block), decoded.chksum);
+        assert!(decoded.is_ustar());
+        assert!(!decoded.is_gnu());
+        assert_eq!(RawHeader { chksum: decoded.chksum, ..header }, decoded);
+
+        let long = RawHeader { name: vec![b'a'; 101], ..Default::default() };
+        assert_eq!(Err(FormatError::FieldTooLong { len: 101, max: 100 }), long.encode());
+    }
+
+    #[test]
+    fn pax_records() {
+        let mut data = Vec::new();
+        put_record(&mut data, b"path", b"a.txt");
+        put_record(&mut data, b"comment", &[b'x'; 90]);
+        assert_eq!(b"14 path=a.txt\n", &data[..14]);
+        assert_eq!(b"103 comment=", &data[14..26]);
+        assert_eq!(117, data.len());
+        data.resize(512, 0);
+        let records: Vec<_> = PaxRecords::new(&data).collect();
+        assert_eq!(2, records.len());
+        assert_eq!(Ok((&b"path"[..], &b"a.txt"[..])), records[0]);
+        assert_eq!(Ok((&b"comment"[..], &[b'x'; 90][..])), records[1]);
+
+        let records: Vec<_> = PaxRecords::new(b"14 path=a.txt\n15 path=a.txt\n").collect();
+        assert_eq!(Err(FormatError::InvalidRecord(14)), records[1]);
+        assert_eq!(1, PaxRecords::new(b"11 novalue\n").count());
+        assert!(PaxRecords::new(b"11 novalue\n").all(|v| v.is_err()));
+
+        let mut data = Vec::new();
+        put_record(&mut data, b"SCHILY.xattr.user.bin", b"a\0b");
+        put_record(&mut data, b"path", b"a.txt");
+        data.resize(512, 0);
+        let records: Vec<_> = PaxRecords::new(&data).collect();
+        assert_eq!(2, records.len());
+        assert_eq!(Ok((&b"SCHILY.xattr.user.bin"[..], &b"a\0b"[..])), records[0]);
+        assert_eq!(Ok((&b"path"[..], &b"a.txt"[..])), records[1]);
+
+        let records: Vec<_> = PaxRecords::new(b"14 path=a.txt\npath=b.txt\n").collect();
+        assert_eq!(2, records.len());
+        assert_eq!(Err(FormatError::InvalidRecord(14)), records[1]);
+    }
+
+    #[test]
+    fn gnu_fields() {
+        assert_eq!(b"dir/long.txt", long_name(b"dir/long.txt\0\0\0"));
+        assert_eq!(b"", long_name(b"\0\0"));
+
+        let mut block = [0u8; BLOCK_SIZE];
+        put_octal(&mut block[386..398], 0).unwrap();
+        put_octal(&mut block[398..410], 512).unwrap();
+        put_octal(&mut block[410..422], 4096).unwrap();
+        put_octal(&mut block[422..434], 100).unwrap();
+        let mut sparse = Vec::new();
+        assert_eq!(Ok(()), parse_sparse(&block[386..482], &mut sparse));
+        assert_eq!(vec![(0, 512), (4096, 100)], sparse);
+
+        block[434..446].copy_from_slice(b"00000000009\0");
+        block[446..458].copy_from_slice(b"00000000001\0");
+        assert_eq!(Err(FormatError::InvalidOctal), parse_sparse(&block[386..482], &mut sparse));
+    }
+}
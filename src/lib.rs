@@ -1 +1,34 @@
-pub mod engine;
\ No newline at end of file
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod format;
+#[cfg(feature = "std")]
+mod engine;
+
+pub use format::FormatError;
+
+#[cfg(feature = "std")]
+pub use engine::archive::{
+    AppendOptions, Archive, ChangedFilePolicy, Codepage, ConflictPolicy, DumpDir, DumpMember, DumpMemberKind, Embedded,
+    Entry, EntryKind, EntrySpec, ExcludePattern, ExtractOptions, FileFlags, GlobalHeader, Metadata, ModeMask,
+    OwnerOverride, PathTransform, RepairReport, SalvageReport, SymlinkMode, Trailer, TransformFn, COMMENT_KEY,
+    CREATIONTIME_KEY, FFLAGS_KEY, MAX_NESTED_DEPTH, METADATA_PREFIX, NESTED_SEPARATOR, SELINUX_KEY,
+    SELINUX_XATTR_KEY, TRAILER_MAGIC
+};
+#[cfg(feature = "std")]
+pub use engine::compression::{Compression, Decoded, LazyDecoder, PhysicalOffset};
+#[cfg(feature = "std")]
+pub use engine::error::Error;
+#[cfg(feature = "std")]
+pub use engine::header::{
+    register_handler, register_namespace, unregister_handlers, unregister_namespace, CustomHeader, GnuHeader,
+    GnuTypeFlag, HeaderHandler, HeaderMatch, IsTypeTrait, Namespace, PaxAttribute, PaxHeader, PaxTypeFlag, PaxValue,
+    PosixViolation, TarHeader, UsedBlocksTrait, UstarHeader, UstarTypeFlag, V7Header, V7TypeFlag, ValueType
+};
+#[cfg(feature = "async")]
+pub use engine::tar::{
+    copy_bounded, AsyncSubFile, BuilderEntries, BuilderEntry, FlushOptions, IndexFlusher, StreamOptions
+};
+#[cfg(feature = "index")]
+pub use engine::tar::{Batch, SubFile, SubFileMetadata, Tar};